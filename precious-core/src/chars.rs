@@ -1,40 +1,111 @@
-#[derive(Debug, Eq, PartialEq)]
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::borrow::Cow;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Chars {
-    pub ring: &'static str,
-    pub tidied: &'static str,
-    pub unchanged: &'static str,
-    pub unknown: &'static str,
-    pub lint_free: &'static str,
-    pub lint_dirty: &'static str,
-    pub empty: &'static str,
-    pub bullet: &'static str,
-    pub execution_error: &'static str,
+    pub ring: Cow<'static, str>,
+    pub tidied: Cow<'static, str>,
+    pub unchanged: Cow<'static, str>,
+    pub unknown: Cow<'static, str>,
+    pub lint_free: Cow<'static, str>,
+    pub lint_dirty: Cow<'static, str>,
+    pub empty: Cow<'static, str>,
+    pub bullet: Cow<'static, str>,
+    pub execution_error: Cow<'static, str>,
 }
 
 pub const FUN_CHARS: Chars = Chars {
-    ring: "💍",
-    tidied: "💧",
-    unchanged: "✨",
+    ring: Cow::Borrowed("💍"),
+    tidied: Cow::Borrowed("💧"),
+    unchanged: Cow::Borrowed("✨"),
     // Person shrugging with medium skin tone - it'd be cool to randomize the
     // skin tone and gender on each run but then this wouldn't be static and
     // the chars wouldn't be constants and I'd have to turn this all into
     // functions.
-    unknown: "🤷🏽",
-    lint_free: "💯",
-    lint_dirty: "💩",
-    empty: "⚫",
-    bullet: "▶",
-    execution_error: "💥",
+    unknown: Cow::Borrowed("🤷🏽"),
+    lint_free: Cow::Borrowed("💯"),
+    lint_dirty: Cow::Borrowed("💩"),
+    empty: Cow::Borrowed("⚫"),
+    bullet: Cow::Borrowed("▶"),
+    execution_error: Cow::Borrowed("💥"),
 };
 
 pub const BORING_CHARS: Chars = Chars {
-    ring: ":",
-    tidied: "*",
-    unchanged: "|",
-    unknown: "?",
-    lint_free: "|",
-    lint_dirty: "*",
-    empty: "_",
-    bullet: "*",
-    execution_error: "!",
+    ring: Cow::Borrowed(":"),
+    tidied: Cow::Borrowed("*"),
+    unchanged: Cow::Borrowed("|"),
+    unknown: Cow::Borrowed("?"),
+    lint_free: Cow::Borrowed("|"),
+    lint_dirty: Cow::Borrowed("*"),
+    empty: Cow::Borrowed("_"),
+    bullet: Cow::Borrowed("*"),
+    execution_error: Cow::Borrowed("!"),
 };
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[schemars(rename_all = "kebab-case")]
+pub struct CharsConfig {
+    ring: Option<String>,
+    tidied: Option<String>,
+    unchanged: Option<String>,
+    unknown: Option<String>,
+    #[serde(alias = "lint-free")]
+    lint_free: Option<String>,
+    #[serde(alias = "lint-dirty")]
+    lint_dirty: Option<String>,
+    empty: Option<String>,
+    bullet: Option<String>,
+    #[serde(alias = "execution-error")]
+    execution_error: Option<String>,
+}
+
+impl Chars {
+    #[must_use]
+    pub fn with_overrides(mut self, config: &CharsConfig) -> Self {
+        let fields = [
+            (&mut self.ring, &config.ring),
+            (&mut self.tidied, &config.tidied),
+            (&mut self.unchanged, &config.unchanged),
+            (&mut self.unknown, &config.unknown),
+            (&mut self.lint_free, &config.lint_free),
+            (&mut self.lint_dirty, &config.lint_dirty),
+            (&mut self.empty, &config.empty),
+            (&mut self.bullet, &config.bullet),
+            (&mut self.execution_error, &config.execution_error),
+        ];
+        for (c, o) in fields {
+            if let Some(o) = o {
+                *c = Cow::Owned(o.clone());
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    #[test]
+    #[parallel]
+    fn with_overrides() -> anyhow::Result<()> {
+        let config: CharsConfig = toml::from_str(
+            r#"
+            lint-dirty = "FAIL"
+            bullet = "-"
+            "#,
+        )?;
+        let chars = BORING_CHARS.with_overrides(&config);
+        assert_eq!(chars.lint_dirty, "FAIL");
+        assert_eq!(chars.bullet, "-");
+        assert_eq!(chars.lint_free, BORING_CHARS.lint_free);
+
+        assert!(toml::from_str::<CharsConfig>(r#"lint-drity = "FAIL""#).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+// Whether to use ANSI color codes in output. This is global because the
+// logger is set up before we have loaded the config file, and the log
+// formatter needs to check it every time it formats a message.
+//
+// The `CLICOLOR_FORCE` env var takes precedence over everything, then
+// `NO_COLOR`, then the `color` key in the config's `[ui]` section. See
+// https://no-color.org/ and https://bixense.com/clicolors/ for details on the
+// env vars.
+use std::{
+    env,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub(crate) fn configure(from_config: Option<bool>) {
+    ENABLED.store(
+        from_env(
+            env::var("CLICOLOR_FORCE").ok().as_deref(),
+            env::var("NO_COLOR").ok().as_deref(),
+        )
+        .or(from_config)
+        .unwrap_or(true),
+        Ordering::Relaxed,
+    );
+}
+
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn from_env(clicolor_force: Option<&str>, no_color: Option<&str>) -> Option<bool> {
+    if clicolor_force.is_some_and(|f| !f.is_empty() && f != "0") {
+        return Some(true);
+    }
+    if no_color.is_some_and(|n| !n.is_empty()) {
+        return Some(false);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case(None, None, None ; "nothing set")]
+    #[test_case(Some("1"), None, Some(true) ; "CLICOLOR_FORCE=1")]
+    #[test_case(Some("0"), None, None ; "CLICOLOR_FORCE=0")]
+    #[test_case(Some(""), None, None ; "CLICOLOR_FORCE is empty")]
+    #[test_case(None, Some("1"), Some(false) ; "NO_COLOR=1")]
+    #[test_case(None, Some(""), None ; "NO_COLOR is empty")]
+    #[test_case(Some("1"), Some("1"), Some(true) ; "CLICOLOR_FORCE wins over NO_COLOR")]
+    #[parallel]
+    fn from_env(clicolor_force: Option<&str>, no_color: Option<&str>, expect: Option<bool>) {
+        assert_eq!(super::from_env(clicolor_force, no_color), expect);
+    }
+}
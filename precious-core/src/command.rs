@@ -1,20 +1,34 @@
-use crate::paths::matcher::{Matcher, MatcherBuilder};
+use crate::{
+    builtin::Builtin,
+    content, patch,
+    paths::matcher::{GlobMatch, Matcher, MatcherBuilder},
+    plugin,
+    record::{self, RecordedInvocation, Recorder},
+    vcs::ChangedLines,
+};
 use anyhow::Result;
+use encoding_rs::Encoding;
 use itertools::Itertools;
 use log::{debug, info};
-use precious_helpers::exec;
+use precious_helpers::exec::{self, Exec};
+use rayon::prelude::*;
 use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     fmt, fs,
-    io::ErrorKind,
+    io::{ErrorKind, Write},
+    iter, mem,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::SystemTime,
 };
+use tempfile::{NamedTempFile, TempDir};
 use thiserror::Error;
+use which::which;
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
 pub enum LintOrTidyCommandType {
     #[serde(rename = "lint")]
     Lint,
@@ -44,7 +58,31 @@ impl fmt::Display for LintOrTidyCommandType {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+// The hash used to tell whether a tidier changed a file when its mtime changed
+// but its size didn't. MD5 is the default, but the others are much faster for
+// big files.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, JsonSchema, PartialEq)]
+pub enum HashAlgorithm {
+    #[default]
+    #[serde(rename = "md5")]
+    Md5,
+    #[serde(rename = "xxh3")]
+    Xxh3,
+    #[serde(rename = "blake3")]
+    Blake3,
+}
+
+impl HashAlgorithm {
+    fn hash(self, content: &[u8]) -> FileHash {
+        match self {
+            HashAlgorithm::Md5 => FileHash::Md5(md5::compute(content)),
+            HashAlgorithm::Xxh3 => FileHash::Xxh3(xxhash_rust::xxh3::xxh3_128(content)),
+            HashAlgorithm::Blake3 => FileHash::Blake3(blake3::hash(content)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
 pub enum Invoke {
     #[serde(rename = "per-file")]
     PerFile,
@@ -73,6 +111,57 @@ impl fmt::Display for Invoke {
     }
 }
 
+// Whether a command runs in CI, locally, or everywhere. This lets one config
+// file have slow checks that only run in CI and quick ones that only run in
+// commit hooks.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum RunWhen {
+    #[serde(rename = "always")]
+    #[default]
+    Always,
+    #[serde(rename = "ci")]
+    Ci,
+    #[serde(rename = "local")]
+    Local,
+}
+
+// The separator to use in the paths passed to a command. Some tools only
+// accept one kind of separator, and a config file shared between Windows and
+// other platforms can't otherwise control this.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum PathStyle {
+    #[serde(rename = "unix")]
+    Unix,
+    #[serde(rename = "windows")]
+    Windows,
+    #[serde(rename = "native")]
+    Native,
+}
+
+impl PathStyle {
+    // On Unix systems a backslash is a valid character in a file name, so
+    // "unix" and "native" only change the separators when running on
+    // Windows.
+    fn apply(self, path: &Path) -> String {
+        let path = path.to_string_lossy();
+        match self {
+            PathStyle::Unix if cfg!(windows) => path.replace('\\', "/"),
+            PathStyle::Windows => path.replace('/', "\\"),
+            PathStyle::Native if cfg!(windows) => path.replace('/', "\\"),
+            PathStyle::Unix | PathStyle::Native => path.into_owned(),
+        }
+    }
+}
+
+// Where a command's stdin comes from. With "file", precious writes the file
+// to the command's stdin, and the command prints a unified diff for it instead
+// of changing the file itself.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum StdinFrom {
+    #[serde(rename = "file")]
+    File,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum ActualInvoke {
     PerFile,
@@ -81,6 +170,15 @@ pub enum ActualInvoke {
 }
 
 impl ActualInvoke {
+    // This is the value of the `PRECIOUS_INVOKE` env var.
+    fn as_str(self) -> &'static str {
+        match self {
+            ActualInvoke::PerFile => "per-file",
+            ActualInvoke::PerDir => "per-dir",
+            ActualInvoke::Once => "once",
+        }
+    }
+
     #[cfg(test)]
     fn as_invoke(&self) -> Invoke {
         match *self {
@@ -112,7 +210,7 @@ impl fmt::Display for WorkingDir {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
 pub enum PathArgs {
     #[serde(rename = "file")]
     File,
@@ -126,6 +224,8 @@ pub enum PathArgs {
     AbsoluteFile,
     #[serde(rename = "absolute-dir")]
     AbsoluteDir,
+    #[serde(rename = "dir-list")]
+    DirList,
 }
 
 impl fmt::Display for PathArgs {
@@ -137,12 +237,55 @@ impl fmt::Display for PathArgs {
             PathArgs::Dot => r#""dot""#,
             PathArgs::AbsoluteFile => r#""absolute-file""#,
             PathArgs::AbsoluteDir => r#""absolute-dir""#,
+            PathArgs::DirList => r#""dir-list""#,
         })
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(tag = "type")]
+pub enum Runner {
+    #[serde(rename = "docker")]
+    Docker { image: String },
+    #[serde(rename = "podman")]
+    Podman { image: String },
+    #[serde(rename = "plugin")]
+    Plugin { name: String },
+}
+
+impl Runner {
+    fn typ(&self) -> &'static str {
+        match self {
+            Runner::Docker { .. } => "docker",
+            Runner::Podman { .. } => "podman",
+            Runner::Plugin { .. } => "plugin",
+        }
+    }
+
+    // Returns the executable and image for a container runner.
+    fn container(&self) -> Option<(&'static str, &str)> {
+        match self {
+            Runner::Docker { image } | Runner::Podman { image } => Some((self.typ(), image)),
+            Runner::Plugin { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Runner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Runner::Docker { image } | Runner::Podman { image } => write!(
+                f,
+                r#"runner = {{ type = "{}", image = "{image}" }}"#,
+                self.typ(),
+            ),
+            Runner::Plugin { name } => write!(f, r#"runner = "plugin:{name}""#),
+        }
+    }
+}
+
 #[derive(Debug, Error, PartialEq, Eq)]
-enum CommandError {
+pub(crate) enum CommandError {
     #[error(
         "You cannot define a command which lints and tidies without lint-flags and/or tidy-flags"
     )]
@@ -160,8 +303,190 @@ enum CommandError {
 
     #[error("Path {path:} should exist but it does not")]
     PathDoesNotExist { path: String },
+
+    #[error(
+        "Could not find \"{exe:}\" for the {command:} command in your path. You can install it by running `{install_cmd:}` or by passing --install-missing"
+    )]
+    ExecutableNotInstalled {
+        exe: String,
+        command: String,
+        install_cmd: String,
+    },
+
+    #[error(
+        "Ran `{install_cmd:}` to install \"{exe:}\" for the {command:} command but it is still not in your path"
+    )]
+    ExecutableStillMissingAfterInstall {
+        exe: String,
+        command: String,
+        install_cmd: String,
+    },
+
+    #[error("The {command:} command has a line-range-flag, which requires invoke = \"per-file\"")]
+    LineRangeFlagRequiresPerFile { command: String },
+
+    #[error(
+        "The diagnostic-regex for the {command:} command must have a capture group named \"line\""
+    )]
+    DiagnosticRegexHasNoLineGroup { command: String },
+
+    #[error("The {command:} command sets {key:} to 0, but it must be at least 1")]
+    InvocationLimitIsZero { command: String, key: &'static str },
+
+    #[error("The {command:} command has check-flags, but only commands with type = \"tidy\" can have check-flags")]
+    CheckFlagsRequiresTidy { command: String },
+
+    #[error("The {command:} command has lint-failure-output, but commands with type = \"tidy\" cannot have lint-failure-output")]
+    LintFailureOutputRequiresLinter { command: String },
+
+    #[error("The {command:} command has read-only, but commands with type = \"tidy\" cannot be read-only")]
+    ReadOnlyRequiresLinter { command: String },
+
+    #[error("The {command:} command has check-flags, which requires lint-failure-exit-codes")]
+    CheckFlagsRequireLintFailureExitCodes { command: String },
+
+    #[error("Cannot check with the {command:} command, which is a tidier that has no check-flags")]
+    CannotCheckWithoutCheckFlags { command: String },
+
+    #[error("The {command:} command sets paths-file, which requires path-args = \"file\", \"dir\", \"absolute-file\", \"absolute-dir\", or \"dir-list\"")]
+    PathsFileRequiresPathArgs { command: String },
+
+    #[error(
+        "The {command:} command sets output-encoding to {encoding:}, which is not a known encoding"
+    )]
+    UnknownOutputEncoding { command: String, encoding: String },
+
+    #[error("The {command:} command sets paths-file, which cannot be used with a runner")]
+    PathsFileCannotBeUsedWithRunner { command: String },
+
+    #[error("The path {path:} contains a newline, so it cannot be written to the paths-file for the {command:} command unless paths-file-null-separated is set")]
+    PathsFilePathHasNewline { command: String, path: String },
+
+    #[error(
+        "The {command:} command sets toolchain, so it cannot also set RUSTUP_TOOLCHAIN in env"
+    )]
+    ToolchainConflictsWithEnv { command: String },
+
+    #[error("The {command:} command sets stdin-from, which requires invoke = \"per-file\"")]
+    StdinFromRequiresPerFile { command: String },
+
+    #[error("The {command:} command sets stdin-from, which cannot be used with redirect-stderr-to-stdout")]
+    StdinFromCannotRedirectStderr { command: String },
+
+    #[error("The {command:} command sets stdin-from, which cannot be used with ignore-stdout")]
+    StdinFromCannotIgnoreStdout { command: String },
+
+    #[error("The {command:} command printed a patch for {} that could not be applied: {error:}", path.display())]
+    InvalidPatch {
+        command: String,
+        path: PathBuf,
+        error: String,
+    },
+
+    #[error("The {command:} command is read-only, but it changed files when it was run on {paths:}. Check that its lint-flags do not tell it to fix files.")]
+    ReadOnlyCommandChangedFiles { command: String, paths: String },
+
+    #[error("The version-cmd for the {command:} command failed: {error:}")]
+    VersionCmdFailed { command: String, error: String },
+
+    #[error("The {command:} command sets max-files-per-invocation or max-bytes-per-invocation, which requires path-args = \"file\" or \"absolute-file\"")]
+    InvocationLimitRequiresFilePathArgs { command: String },
+
+    #[error("The {command:} command sets path-args = \"dir-list\", which requires invoke = \"per-dir\" and dir-include")]
+    DirListRequiresPerDirAndDirInclude { command: String },
+
+    #[error("The {command:} command sets dir-include, which requires path-args = \"dir-list\"")]
+    DirIncludeRequiresDirList { command: String },
+
+    #[error("The {command:} command uses {cmd:}, but there is no builtin command with that name. The builtin commands are {known:}")]
+    UnknownBuiltin {
+        command: String,
+        cmd: String,
+        known: String,
+    },
+
+    #[error("The {command:} command uses a builtin command, which can only be used with type = \"lint\"")]
+    BuiltinRequiresLint { command: String },
+
+    #[error("The {command:} command uses a builtin command, which cannot be used with {key:}")]
+    BuiltinCannotBeUsedWith { command: String, key: &'static str },
+
+    #[error("The {command:} command uses a builtin command, which requires path-args = \"file\", but it sets path-args = {path_args:}")]
+    BuiltinRequiresFilePathArgs {
+        command: String,
+        path_args: PathArgs,
+    },
+
+    #[error("The {command:} command has an invalid cmd: {error:}")]
+    InvalidBuiltinArgs { command: String, error: String },
+}
+
+impl CommandError {
+    // Most of these are found when the commands are created from the config
+    // file. The rest happen while trying to run a command.
+    pub(crate) fn is_config_error(&self) -> bool {
+        !matches!(
+            self,
+            CommandError::CannotMethodWithCommand { .. }
+                | CommandError::PathHasNoParent { .. }
+                | CommandError::PathDoesNotExist { .. }
+                | CommandError::PathsFilePathHasNewline { .. }
+                | CommandError::ExecutableNotInstalled { .. }
+                | CommandError::ExecutableStillMissingAfterInstall { .. }
+                | CommandError::InvalidPatch { .. }
+                | CommandError::ReadOnlyCommandChangedFiles { .. }
+                | CommandError::VersionCmdFailed { .. }
+        )
+    }
+}
+
+// The value for a `--mount` flag that mounts the path at the same path in the
+// container. Unlike `--volume`, this works with paths that contain a `:`. The
+// value is parsed as CSV, so a field with a comma or quote has to be quoted.
+fn bind_mount(path: &Path) -> String {
+    let field = |key: &str| {
+        let f = format!("{key}={}", path.to_string_lossy());
+        if f.contains([',', '"']) {
+            format!("\"{}\"", f.replace('"', "\"\""))
+        } else {
+            f
+        }
+    };
+    format!("type=bind,{},{}", field("src"), field("dst"))
 }
 
+const RUSTUP_TOOLCHAIN_ENV: &str = "RUSTUP_TOOLCHAIN";
+
+// Pairs of tidiers that format the same code differently, so running both
+// on the same files means that each run undoes the other's changes.
+const KNOWN_CONFLICTS: &[(&str, &str)] = &[
+    ("autopep8", "black"),
+    ("autopep8", "yapf"),
+    ("black", "yapf"),
+    ("dprint", "prettier"),
+];
+
+// Commands that run the tool named after them, like `poetry run black`. The
+// tool is the first argument after the wrapper's own arguments.
+const KNOWN_WRAPPERS: &[(&str, &[&str])] = &[
+    ("bunx", &[]),
+    ("npx", &[]),
+    ("pipenv", &["run"]),
+    ("poetry", &["run"]),
+    ("python", &["-m"]),
+    ("python3", &["-m"]),
+    ("uv", &["run"]),
+];
+
+// These describe how a command was run. They're set for every invocation, and
+// passed through to commands with a container runner as well.
+const RUN_CONTEXT_ENV_VARS: &[&str] = &[
+    "PRECIOUS_COMMAND_NAME",
+    "PRECIOUS_FILE_COUNT",
+    "PRECIOUS_INVOKE",
+    "PRECIOUS_MODE",
+];
+
 #[derive(Debug)]
 #[allow(clippy::module_name_repetitions)]
 pub struct LintOrTidyCommand {
@@ -171,10 +496,16 @@ pub struct LintOrTidyCommand {
     includer: Matcher,
     include: Vec<String>,
     excluder: Matcher,
+    dir_includer: Matcher,
     invoke: Invoke,
     working_dir: WorkingDir,
     path_args: PathArgs,
     cmd: Vec<String>,
+    // This is set when `cmd` is something like `precious://whitespace`.
+    builtin: Option<Builtin>,
+    install_cmd: Option<Vec<String>>,
+    daemon_cmd: Option<Vec<String>>,
+    runner: Option<Runner>,
     env: HashMap<String, String>,
     lint_flags: Option<Vec<String>>,
     tidy_flags: Option<Vec<String>>,
@@ -182,6 +513,49 @@ pub struct LintOrTidyCommand {
     ok_exit_codes: Vec<i32>,
     lint_failure_exit_codes: HashSet<i32>,
     ignore_stderr: Option<Vec<Regex>>,
+    ignore_stdout: Vec<Regex>,
+    redirect_stderr_to_stdout: bool,
+    version_cmd: Vec<String>,
+    also_trigger_on: Matcher,
+    stdin_from: Option<StdinFrom>,
+    report_new_files: bool,
+    hash_algorithm: HashAlgorithm,
+    max_memory: Option<u64>,
+    max_cpu_seconds: Option<u64>,
+    path_style: Option<PathStyle>,
+    max_output_bytes: Option<u64>,
+    save_full_output: bool,
+    lint_failure_output: Option<Regex>,
+    run_when: RunWhen,
+    scratch_files: Matcher,
+    track_changes: bool,
+    read_only: bool,
+    skip_binary: bool,
+    max_file_size: Option<u64>,
+    follow_symlinks: Option<bool>,
+    output_encoding: Option<&'static Encoding>,
+    paths_file: bool,
+    paths_file_flag: Option<Vec<String>>,
+    paths_file_null_separated: bool,
+    check_flags: Option<Vec<String>>,
+    pub(crate) serial: bool,
+    pub(crate) depends_on: Vec<String>,
+    pub(crate) conflicts_with: Vec<String>,
+    pub(crate) mutex: Option<String>,
+    max_bytes_per_invocation: Option<u64>,
+    max_files_per_invocation: Option<usize>,
+    line_range_flag: Option<Vec<String>>,
+    changed_lines: Option<ChangedLines>,
+    only_dirty_dirs: bool,
+    mode: Option<String>,
+    recorder: Option<Arc<Recorder>>,
+    log_dir: Option<PathBuf>,
+    diagnostic_regex: Option<Regex>,
+    hash_cache: Arc<HashCache>,
+    // With `report-new-files`, this is the list of files from after the last
+    // invocation, which is the list from before the next one, since the
+    // invocations of such a command run one at a time.
+    files_snapshot: Mutex<Option<(PathBuf, HashSet<PathBuf>)>>,
 }
 
 #[derive(Debug)]
@@ -191,10 +565,14 @@ pub struct LintOrTidyCommandParams {
     pub typ: LintOrTidyCommandType,
     pub include: Vec<String>,
     pub exclude: Vec<String>,
+    pub dir_include: Vec<String>,
     pub invoke: Invoke,
     pub working_dir: WorkingDir,
     pub path_args: PathArgs,
     pub cmd: Vec<String>,
+    pub install_cmd: Vec<String>,
+    pub daemon_cmd: Vec<String>,
+    pub runner: Option<Runner>,
     pub env: HashMap<String, String>,
     pub lint_flags: Vec<String>,
     pub tidy_flags: Vec<String>,
@@ -203,20 +581,82 @@ pub struct LintOrTidyCommandParams {
     pub lint_failure_exit_codes: Vec<u8>,
     pub expect_stderr: bool,
     pub ignore_stderr: Vec<String>,
+    pub ignore_stdout: Vec<String>,
+    pub redirect_stderr_to_stdout: bool,
+    pub version_cmd: Vec<String>,
+    pub also_trigger_on: Vec<String>,
+    pub stdin_from: Option<StdinFrom>,
+    pub toolchain: Option<String>,
+    pub report_new_files: bool,
+    pub max_memory: Option<u64>,
+    pub max_cpu_seconds: Option<u64>,
+    pub path_style: Option<PathStyle>,
+    pub max_output_bytes: Option<u64>,
+    pub save_full_output: bool,
+    pub lint_failure_output: Option<String>,
+    pub run_when: RunWhen,
+    pub scratch_files: Vec<String>,
+    pub track_changes: bool,
+    pub read_only: bool,
+    pub skip_binary: bool,
+    pub max_file_size: Option<u64>,
+    pub follow_symlinks: Option<bool>,
+    pub output_encoding: Option<String>,
+    pub paths_file: bool,
+    pub paths_file_flag: Vec<String>,
+    pub paths_file_null_separated: bool,
+    pub check_flags: Vec<String>,
+    pub serial: bool,
+    pub depends_on: Vec<String>,
+    pub conflicts_with: Vec<String>,
+    pub mutex: Option<String>,
+    pub max_bytes_per_invocation: Option<u64>,
+    pub max_files_per_invocation: Option<usize>,
+    pub line_range_flag: Vec<String>,
+    pub diagnostic_regex: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum TidyOutcome {
     Unchanged,
     Changed,
     Unknown,
 }
 
+// Whether a command is given a file, and what decided it. This is what
+// `precious explain` prints for each command.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum FileDecision {
+    Included { glob: String },
+    NotIncluded,
+    NegatedInclude { glob: String },
+    Excluded { glob: String },
+    TooLarge { size: u64, max: u64 },
+    Binary,
+    // This is the file or directory that is a symlink.
+    Symlink { path: PathBuf },
+}
+
+// The result of running a tidier on some files.
 #[derive(Debug)]
+pub struct TidyResult {
+    pub outcome: TidyOutcome,
+    // Files that didn't exist before the command ran, relative to the project
+    // root. These are only looked for when the command sets
+    // `report-new-files`.
+    pub new_files: Vec<PathBuf>,
+    pub resources: Option<exec::Resources>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct LintOutcome {
     pub ok: bool,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    // This isn't sent back by the server, since the client only uses it for
+    // reporting on commands it ran itself.
+    #[serde(skip)]
+    pub resources: Option<exec::Resources>,
 }
 
 #[derive(Clone, Debug)]
@@ -227,9 +667,73 @@ struct PathMetadata {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct PathInfo {
+    stamp: FileStamp,
+    hash: FileHash,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum FileHash {
+    Md5(md5::Digest),
+    Xxh3(u128),
+    Blake3(blake3::Hash),
+}
+
+// What we know about a file without reading it. The mtime can be set back to
+// its old value after a write, but on Unix the ctime can't be, and a tool that
+// writes a new file and renames it over the old one gives it a new inode, so
+// these catch changes that the mtime and size alone would miss.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
     mtime: SystemTime,
     size: u64,
-    hash: md5::Digest,
+    ctime: Option<(i64, i64)>,
+    inode: Option<u64>,
+}
+
+impl FileStamp {
+    fn new(meta: &fs::Metadata) -> Result<Self> {
+        #[cfg(unix)]
+        let (ctime, inode) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some((meta.ctime(), meta.ctime_nsec())), Some(meta.ino()))
+        };
+        #[cfg(not(unix))]
+        let (ctime, inode) = (None, None);
+
+        Ok(Self {
+            mtime: meta.modified()?,
+            size: meta.len(),
+            ctime,
+            inode,
+        })
+    }
+}
+
+// The content hash of each file that's been hashed during a run, along with
+// the file's stamp at the time. This is shared by every command in a run, so
+// a file that several tidiers look at is only read again when its stamp has
+// changed since it was last hashed.
+#[derive(Debug, Default)]
+pub(crate) struct HashCache {
+    hashes: Mutex<HashMap<(PathBuf, HashAlgorithm), (FileStamp, FileHash)>>,
+}
+
+impl HashCache {
+    fn hash(&self, file: &Path, stamp: FileStamp, algorithm: HashAlgorithm) -> Result<FileHash> {
+        let key = (file.to_path_buf(), algorithm);
+        if let Some((cached, hash)) = self.hashes.lock().unwrap().get(&key) {
+            if *cached == stamp {
+                return Ok(hash.clone());
+            }
+        }
+
+        let hash = algorithm.hash(&fs::read(file)?);
+        self.hashes
+            .lock()
+            .unwrap()
+            .insert(key, (stamp, hash.clone()));
+        Ok(hash)
+    }
 }
 
 // This should be safe because we never mutate the Command struct in any of its
@@ -243,6 +747,7 @@ impl LintOrTidyCommand {
                 return Err(CommandError::CommandWhichIsBothRequiresLintOrTidyFlags.into());
             }
         }
+        let builtin = Self::builtin_for(&params)?;
 
         let ignore_stderr = if params.expect_stderr {
             // If this regex isn't
@@ -261,20 +766,216 @@ impl LintOrTidyCommand {
             )
         };
 
+        let ignore_stdout = params
+            .ignore_stdout
+            .iter()
+            .map(|i| Regex::new(i).map_err(Into::into))
+            .collect::<Result<Vec<_>>>()?;
+
+        let output_encoding = match params.output_encoding {
+            Some(label) => Some(Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+                CommandError::UnknownOutputEncoding {
+                    command: params.name.clone(),
+                    encoding: label,
+                }
+            })?),
+            None => None,
+        };
+
+        if !params.line_range_flag.is_empty() && params.invoke != Invoke::PerFile {
+            return Err(CommandError::LineRangeFlagRequiresPerFile {
+                command: params.name,
+            }
+            .into());
+        }
+        if params.stdin_from.is_some() && params.invoke != Invoke::PerFile {
+            return Err(CommandError::StdinFromRequiresPerFile {
+                command: params.name,
+            }
+            .into());
+        }
+        // The patch the command prints would have its stderr mixed in.
+        if params.stdin_from.is_some() && params.redirect_stderr_to_stdout {
+            return Err(CommandError::StdinFromCannotRedirectStderr {
+                command: params.name,
+            }
+            .into());
+        }
+        // Removing lines from the patch would change what gets applied.
+        if params.stdin_from.is_some() && !params.ignore_stdout.is_empty() {
+            return Err(CommandError::StdinFromCannotIgnoreStdout {
+                command: params.name,
+            }
+            .into());
+        }
+
+        let zero_limit = if params.max_files_per_invocation == Some(0) {
+            Some("max-files-per-invocation")
+        } else if params.max_bytes_per_invocation == Some(0) {
+            Some("max-bytes-per-invocation")
+        } else if params.max_output_bytes == Some(0) {
+            Some("max-output-bytes")
+        } else {
+            None
+        };
+        if let Some(key) = zero_limit {
+            return Err(CommandError::InvocationLimitIsZero {
+                command: params.name,
+                key,
+            }
+            .into());
+        }
+        // When the command is passed directories (or nothing at all), splitting
+        // up the files would just run the command on the same directories
+        // more than once.
+        if (params.max_files_per_invocation.is_some() || params.max_bytes_per_invocation.is_some())
+            && !matches!(params.path_args, PathArgs::File | PathArgs::AbsoluteFile)
+        {
+            return Err(CommandError::InvocationLimitRequiresFilePathArgs {
+                command: params.name,
+            }
+            .into());
+        }
+
+        // A dir-list command is only ever given the directories that match
+        // its dir-include globs, one at a time.
+        if params.path_args == PathArgs::DirList {
+            if params.invoke != Invoke::PerDir || params.dir_include.is_empty() {
+                return Err(CommandError::DirListRequiresPerDirAndDirInclude {
+                    command: params.name,
+                }
+                .into());
+            }
+        } else if !params.dir_include.is_empty() {
+            return Err(CommandError::DirIncludeRequiresDirList {
+                command: params.name,
+            }
+            .into());
+        }
+
+        if !params.check_flags.is_empty() {
+            if params.typ != LintOrTidyCommandType::Tidy {
+                return Err(CommandError::CheckFlagsRequiresTidy {
+                    command: params.name,
+                }
+                .into());
+            }
+            if params.lint_failure_exit_codes.is_empty() {
+                return Err(CommandError::CheckFlagsRequireLintFailureExitCodes {
+                    command: params.name,
+                }
+                .into());
+            }
+        }
+
+        if params.paths_file || !params.paths_file_flag.is_empty() {
+            if matches!(params.path_args, PathArgs::None | PathArgs::Dot) {
+                return Err(CommandError::PathsFileRequiresPathArgs {
+                    command: params.name,
+                }
+                .into());
+            }
+            // The temp file isn't visible inside the container.
+            if params.runner.is_some() {
+                return Err(CommandError::PathsFileCannotBeUsedWithRunner {
+                    command: params.name,
+                }
+                .into());
+            }
+        }
+
+        let diagnostic_regex = match params.diagnostic_regex {
+            Some(r) => {
+                let re = Regex::new(&r)?;
+                if !re.capture_names().any(|n| n == Some("line")) {
+                    return Err(CommandError::DiagnosticRegexHasNoLineGroup {
+                        command: params.name,
+                    }
+                    .into());
+                }
+                Some(re)
+            }
+            None => None,
+        };
+
+        if params.read_only && params.typ == LintOrTidyCommandType::Tidy {
+            return Err(CommandError::ReadOnlyRequiresLinter {
+                command: params.name,
+            }
+            .into());
+        }
+
+        let lint_failure_output = match params.lint_failure_output {
+            Some(r) => {
+                if params.typ == LintOrTidyCommandType::Tidy {
+                    return Err(CommandError::LintFailureOutputRequiresLinter {
+                        command: params.name,
+                    }
+                    .into());
+                }
+                Some(Regex::new(&r)?)
+            }
+            None => None,
+        };
+
+        // rustup's proxies for `cargo`, `rustfmt`, and friends all respect this
+        // env var, so it works whether the command runs one of those directly
+        // or via some wrapper that does.
+        let mut env = params.env;
+        if let Some(toolchain) = params.toolchain {
+            if env.contains_key(RUSTUP_TOOLCHAIN_ENV) {
+                return Err(CommandError::ToolchainConflictsWithEnv {
+                    command: params.name,
+                }
+                .into());
+            }
+            env.insert(RUSTUP_TOOLCHAIN_ENV.to_string(), toolchain);
+        }
+
         let cmd = replace_root(&params.cmd, &params.project_root);
+        let version_cmd = replace_root(&params.version_cmd, &params.project_root);
         let root = params.project_root.clone();
+        let includer = MatcherBuilder::new(&root)
+            .named(format!("{} include", params.name))
+            .with(&params.include)?
+            .build()?;
+        let excluder = MatcherBuilder::new(&root)
+            .named(format!("{} exclude", params.name))
+            .with(&params.exclude)?
+            .build()?;
+        let dir_includer = MatcherBuilder::new(&root)
+            .named(format!("{} dir-include", params.name))
+            .with(&params.dir_include)?
+            .build()?;
+        let also_trigger_on = MatcherBuilder::new(&root)
+            .named(format!("{} also-trigger-on", params.name))
+            .with(&params.also_trigger_on)?
+            .build()?;
         Ok(LintOrTidyCommand {
             project_root: params.project_root,
             name: params.name,
             typ: params.typ,
-            includer: MatcherBuilder::new(&root).with(&params.include)?.build()?,
+            includer,
             include: params.include,
-            excluder: MatcherBuilder::new(&root).with(&params.exclude)?.build()?,
+            excluder,
+            dir_includer,
             invoke: params.invoke,
             working_dir: params.working_dir,
             path_args: params.path_args,
             cmd,
-            env: params.env,
+            builtin,
+            install_cmd: if params.install_cmd.is_empty() {
+                None
+            } else {
+                Some(params.install_cmd)
+            },
+            daemon_cmd: if params.daemon_cmd.is_empty() {
+                None
+            } else {
+                Some(replace_root(&params.daemon_cmd, &root))
+            },
+            runner: params.runner,
+            env,
             lint_flags: if params.lint_flags.is_empty() {
                 None
             } else {
@@ -300,9 +1001,126 @@ impl LintOrTidyCommand {
                 .map(i32::from)
                 .collect(),
             ignore_stderr,
+            ignore_stdout,
+            redirect_stderr_to_stdout: params.redirect_stderr_to_stdout,
+            version_cmd,
+            also_trigger_on,
+            stdin_from: params.stdin_from,
+            report_new_files: params.report_new_files,
+            hash_algorithm: HashAlgorithm::default(),
+            max_memory: params.max_memory,
+            max_cpu_seconds: params.max_cpu_seconds,
+            path_style: params.path_style,
+            max_output_bytes: params.max_output_bytes,
+            save_full_output: params.save_full_output,
+            lint_failure_output,
+            run_when: params.run_when,
+            scratch_files: MatcherBuilder::new(&root)
+                .with(&params.scratch_files)?
+                .build()?,
+            track_changes: params.track_changes,
+            read_only: params.read_only,
+            skip_binary: params.skip_binary,
+            max_file_size: params.max_file_size,
+            follow_symlinks: params.follow_symlinks,
+            output_encoding,
+            paths_file: params.paths_file || !params.paths_file_flag.is_empty(),
+            paths_file_flag: if params.paths_file_flag.is_empty() {
+                None
+            } else {
+                Some(params.paths_file_flag)
+            },
+            paths_file_null_separated: params.paths_file_null_separated,
+            check_flags: if params.check_flags.is_empty() {
+                None
+            } else {
+                Some(params.check_flags)
+            },
+            serial: params.serial,
+            depends_on: params.depends_on,
+            conflicts_with: params.conflicts_with,
+            mutex: params.mutex,
+            max_bytes_per_invocation: params.max_bytes_per_invocation,
+            max_files_per_invocation: params.max_files_per_invocation,
+            line_range_flag: if params.line_range_flag.is_empty() {
+                None
+            } else {
+                Some(params.line_range_flag)
+            },
+            changed_lines: None,
+            only_dirty_dirs: false,
+            mode: None,
+            recorder: None,
+            log_dir: None,
+            diagnostic_regex,
+            hash_cache: Arc::default(),
+            files_snapshot: Mutex::default(),
         })
     }
 
+    fn builtin_for(params: &LintOrTidyCommandParams) -> Result<Option<Builtin>> {
+        let builtin =
+            Builtin::from_cmd(&params.cmd).map_err(|known| CommandError::UnknownBuiltin {
+                command: params.name.clone(),
+                cmd: params.cmd[0].clone(),
+                known,
+            })?;
+        let Some(builtin) = builtin else {
+            return Ok(None);
+        };
+
+        if params.typ != LintOrTidyCommandType::Lint {
+            return Err(CommandError::BuiltinRequiresLint {
+                command: params.name.clone(),
+            }
+            .into());
+        }
+        // A builtin reads each path it's given as a file.
+        if params.path_args != PathArgs::File {
+            return Err(CommandError::BuiltinRequiresFilePathArgs {
+                command: params.name.clone(),
+                path_args: params.path_args,
+            }
+            .into());
+        }
+        // A builtin only sees its args, so it can't read a paths file, and it
+        // always runs inside precious, not in a container or with resource
+        // limits.
+        let key = if params.runner.is_some() {
+            Some("runner")
+        } else if params.paths_file || !params.paths_file_flag.is_empty() {
+            Some("paths-file")
+        } else if !params.daemon_cmd.is_empty() {
+            Some("daemon-cmd")
+        } else if params.toolchain.is_some() {
+            Some("toolchain")
+        } else if params.stdin_from.is_some() {
+            Some("stdin-from")
+        } else if params.redirect_stderr_to_stdout {
+            Some("redirect-stderr-to-stdout")
+        } else if params.max_memory.is_some() || params.max_cpu_seconds.is_some() {
+            Some("max-memory or max-cpu-seconds")
+        } else {
+            None
+        };
+        if let Some(key) = key {
+            return Err(CommandError::BuiltinCannotBeUsedWith {
+                command: params.name.clone(),
+                key,
+            }
+            .into());
+        }
+
+        builtin
+            .check_args(&params.cmd[1..])
+            .map_err(|e| CommandError::InvalidBuiltinArgs {
+                command: params.name.clone(),
+                error: e.to_string(),
+            })?;
+
+        Ok(Some(builtin))
+    }
+
     fn unique_exit_codes(ok_exit_codes: &[u8], lint_failure_exit_codes: Option<&[u8]>) -> Vec<i32> {
         let unique_codes: HashSet<i32> = ok_exit_codes
             .iter()
@@ -320,8 +1138,14 @@ impl LintOrTidyCommand {
         &self,
         files: &'a [PathBuf],
     ) -> Result<(Vec<Vec<&'a Path>>, ActualInvoke)> {
-        let files = files.iter().filter(|f| self.file_matches_rules(f));
-        Ok(match self.invoke {
+        if self.path_args == PathArgs::DirList {
+            return Ok((self.dirs_to_args_sets(files), ActualInvoke::PerDir));
+        }
+
+        let files = files
+            .iter()
+            .filter(|f| self.file_matches_rules(f) && self.symlink_policy_allows(f));
+        let (sets, actual_invoke) = match self.invoke {
             // Every file becomes its own one one-element Vec.
             Invoke::PerFile => (
                 files.sorted().map(|f| vec![f.as_path()]).collect(),
@@ -392,47 +1216,183 @@ impl LintOrTidyCommand {
                 vec![files.sorted().map(PathBuf::as_path).collect()],
                 ActualInvoke::Once,
             ),
-        })
-    }
+        };
 
-    fn files_to_dirs<'a>(files: impl Iterator<Item = &'a PathBuf>) -> Result<Vec<Vec<&'a Path>>> {
-        let files = files.map(AsRef::as_ref).collect::<Vec<_>>();
-        let by_dir = Self::files_by_dir(&files)?;
-        Ok(by_dir
-            .into_iter()
-            .sorted_by_key(|(k, _)| *k)
-            .map(|(_, v)| v.into_iter().sorted().collect())
-            .collect())
+        Ok((self.split_into_batches(sets)?, actual_invoke))
     }
 
-    fn files_by_dir<'a>(files: &[&'a Path]) -> Result<HashMap<&'a Path, Vec<&'a Path>>> {
-        let mut by_dir: HashMap<&Path, Vec<&Path>> = HashMap::new();
-        for f in files {
-            let d = f.parent().ok_or_else(|| CommandError::PathHasNoParent {
-                path: f.to_string_lossy().to_string(),
-            })?;
-            by_dir.entry(d).or_default().push(f);
+    // Splits each set of files into batches that stay under the
+    // max-files-per-invocation and max-bytes-per-invocation limits. A single
+    // file that is larger than the byte limit gets a batch of its own.
+    fn split_into_batches<'a>(&self, sets: Vec<Vec<&'a Path>>) -> Result<Vec<Vec<&'a Path>>> {
+        if self.max_files_per_invocation.is_none() && self.max_bytes_per_invocation.is_none() {
+            return Ok(sets);
         }
-        Ok(by_dir)
-    }
 
-    pub fn tidy(
-        &self,
-        actual_invoke: ActualInvoke,
-        files: &[&Path],
-    ) -> Result<Option<TidyOutcome>> {
-        self.require_is_not_command_type("tidy", LintOrTidyCommandType::Lint)?;
+        let max_files = self.max_files_per_invocation.unwrap_or(usize::MAX);
+        let max_bytes = self.max_bytes_per_invocation.unwrap_or(u64::MAX);
+
+        let mut batches = vec![];
+        for set in sets {
+            let mut batch: Vec<&Path> = vec![];
+            let mut batch_bytes = 0;
+            for f in set {
+                let bytes = if self.max_bytes_per_invocation.is_some() {
+                    fs::metadata(self.project_root.join(f))?.len()
+                } else {
+                    0
+                };
+                if !batch.is_empty()
+                    && (batch.len() >= max_files || batch_bytes + bytes > max_bytes)
+                {
+                    batches.push(mem::take(&mut batch));
+                    batch_bytes = 0;
+                }
+                batch.push(f);
+                batch_bytes += bytes;
+            }
+            if !batch.is_empty() {
+                batches.push(batch);
+            }
+        }
+
+        if batches.len() > 1 {
+            debug!(
+                "Split the files for {} into {} batches",
+                self.name,
+                batches.len()
+            );
+        }
+
+        Ok(batches)
+    }
+
+    // Returns the command's executable if it cannot be found in the PATH and
+    // the command has an `install-cmd` that could be used to install it. If
+    // there's no `install-cmd` we leave it to `Exec::run` to report the
+    // missing executable when the command is run. Commands with a `runner`
+    // run their executable inside a container or through a plugin, so
+    // there's nothing to install locally.
+    pub fn missing_executable(&self) -> Option<&str> {
+        self.install_cmd.as_ref()?;
+        if self.runner.is_some() {
+            return None;
+        }
+        let exe = self.cmd.first()?;
+        if which(exe).is_ok() {
+            return None;
+        }
+        Some(exe)
+    }
+
+    pub fn install_missing_executable(&self, install_missing: bool) -> Result<()> {
+        let (Some(exe), Some(install_cmd)) = (self.missing_executable(), &self.install_cmd) else {
+            return Ok(());
+        };
+
+        if !install_missing {
+            return Err(CommandError::ExecutableNotInstalled {
+                exe: exe.to_string(),
+                command: self.name.clone(),
+                install_cmd: install_cmd.join(" "),
+            }
+            .into());
+        }
+
+        info!(
+            "Installing {exe} for the {} command using [{}]",
+            self.name,
+            install_cmd.join(" "),
+        );
+        Exec::builder(&install_cmd[0])
+            .args(&install_cmd[1..])
+            .envs(&self.env)
+            // Installers like `cargo install` print all of their progress to
+            // stderr, so we accept any stderr output from them.
+            .ignore_all_stderr()
+            .in_dir(&self.project_root)
+            .build()
+            .run()?;
+
+        if which(exe).is_err() {
+            return Err(CommandError::ExecutableStillMissingAfterInstall {
+                exe: exe.to_string(),
+                command: self.name.clone(),
+                install_cmd: install_cmd.join(" "),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    // With `path-args = "dir-list"`, every directory containing one of the
+    // files (at any depth) that matches the command's dir-include globs gets
+    // a one-element set of its own. The command's include globs aren't used,
+    // so the directory doesn't need to contain any files they match.
+    fn dirs_to_args_sets<'a>(&self, files: &'a [PathBuf]) -> Vec<Vec<&'a Path>> {
+        files
+            .iter()
+            .filter(|f| !self.excluder.path_matches(f, false))
+            .flat_map(|f| f.ancestors().skip(1))
+            .filter(|d| !d.as_os_str().is_empty())
+            .unique()
+            .filter(|d| self.dir_matches_rules(d))
+            .sorted()
+            .map(|d| vec![d])
+            .collect()
+    }
+
+    fn dir_matches_rules(&self, dir: &Path) -> bool {
+        !self.excluder.path_matches(dir, true) && self.dir_includer.path_matches(dir, true)
+    }
+
+    fn files_to_dirs<'a>(files: impl Iterator<Item = &'a PathBuf>) -> Result<Vec<Vec<&'a Path>>> {
+        let files = files.map(AsRef::as_ref).collect::<Vec<_>>();
+        let by_dir = Self::files_by_dir(&files)?;
+        Ok(by_dir
+            .into_iter()
+            .sorted_by_key(|(k, _)| *k)
+            .map(|(_, v)| v.into_iter().sorted().collect())
+            .collect())
+    }
+
+    fn files_by_dir<'a>(files: &[&'a Path]) -> Result<HashMap<&'a Path, Vec<&'a Path>>> {
+        let mut by_dir: HashMap<&Path, Vec<&Path>> = HashMap::new();
+        for f in files {
+            let d = f.parent().ok_or_else(|| CommandError::PathHasNoParent {
+                path: f.to_string_lossy().to_string(),
+            })?;
+            by_dir.entry(d).or_default().push(f);
+        }
+        Ok(by_dir)
+    }
+
+    pub fn tidy(&self, actual_invoke: ActualInvoke, files: &[&Path]) -> Result<Option<TidyResult>> {
+        self.require_is_not_command_type("tidy", LintOrTidyCommandType::Lint)?;
 
         if !self.should_act_on_files(actual_invoke, files)? {
             return Ok(None);
         }
+        let Some(flags) = self.flags_with_line_ranges(self.tidy_flags.as_deref(), files) else {
+            return Ok(None);
+        };
 
         let path_metadata = self.maybe_path_metadata_for(actual_invoke, files)?;
 
         let in_dir = self.in_dir(files[0])?;
-        let operating_on = self.operating_on(files, &in_dir)?;
+        let operating_on = self.operating_on(actual_invoke, files, &in_dir)?;
+        let dir = self.invocation_dir(actual_invoke, files)?;
+        let (cmd, idx, paths_file) = self.command_for_paths_or_file(&flags, &operating_on)?;
+        let cmd = self.expand_placeholders(cmd, idx, &dir);
+        let scratch_dir = Self::scratch_dir()?;
         let (mut cmd, before_paths_idx) =
-            self.command_for_paths(self.tidy_flags.as_deref(), &operating_on);
+            self.command_for_runner((cmd, idx), &in_dir, Some(scratch_dir.path()))?;
+        let existing_files = if self.report_new_files {
+            Some(self.files_before_invocation(&in_dir)?)
+        } else {
+            None
+        };
 
         info!(
             "Tidying [{}] with {} in [{}] using command [{}]",
@@ -443,22 +1403,284 @@ impl LintOrTidyCommand {
         );
 
         let bin = cmd.remove(0);
-        exec::run(
-            &bin,
-            &cmd.iter().map(String::as_str).collect::<Vec<_>>(),
-            &self.env,
-            &self.ok_exit_codes,
-            self.ignore_stderr.as_deref(),
-            Some(&in_dir),
+        let env = self.env_for(
+            paths_file.as_ref(),
+            scratch_dir.path(),
+            &dir,
+            actual_invoke,
+            files.len(),
+        );
+        let stdin = self.stdin_for(files)?;
+        let result = self.execute("tidy", (&bin, &cmd), &env, &in_dir, stdin.as_deref());
+        let result = self.strip_ignored_stdout(result);
+        self.record(
+            files,
+            &in_dir,
+            (&bin, &cmd),
+            env,
+            paths_file.as_ref(),
+            &result,
+            |_| false,
         )?;
+        // The patch has to be applied before the output is truncated.
+        let patched = match (&stdin, &result) {
+            (Some(original), Ok(output)) => {
+                Some(self.apply_patch(files[0], original, output.stdout.as_deref())?)
+            }
+            _ => None,
+        };
+        let result = self.limit_output(result)?;
+        self.log_resources(result.resources, &in_dir);
 
-        if let Some(pm) = path_metadata {
-            if self.paths_were_changed(pm)? {
-                return Ok(Some(TidyOutcome::Changed));
+        let new_files = match existing_files {
+            Some(existing) => self.new_files_under(&in_dir, &existing)?,
+            None => vec![],
+        };
+        let outcome = match (path_metadata, patched) {
+            _ if !new_files.is_empty() => TidyOutcome::Changed,
+            (_, Some(true)) => TidyOutcome::Changed,
+            (_, Some(false)) => TidyOutcome::Unchanged,
+            (Some(pm), None) => {
+                if self.paths_were_changed(pm)? {
+                    TidyOutcome::Changed
+                } else {
+                    TidyOutcome::Unchanged
+                }
+            }
+            (None, None) => TidyOutcome::Unknown,
+        };
+        Ok(Some(TidyResult {
+            outcome,
+            new_files,
+            resources: result.resources,
+        }))
+    }
+
+    // Runs the command, or hands it to its plugin when the command has a
+    // plugin runner.
+    fn execute(
+        &self,
+        action: &str,
+        (bin, args): (&str, &[String]),
+        env: &HashMap<String, String>,
+        in_dir: &Path,
+        stdin: Option<&str>,
+    ) -> Result<exec::Output> {
+        if let Some(Runner::Plugin { name }) = &self.runner {
+            let request = plugin::Request {
+                protocol: plugin::PROTOCOL_VERSION,
+                command: &self.name,
+                action,
+                cmd: iter::once(bin)
+                    .chain(args.iter().map(String::as_str))
+                    .collect(),
+                dir: in_dir,
+                env,
+                stdin,
+            };
+            return plugin::run(
+                &plugin::exe(name),
+                &request,
+                &self.ok_exit_codes,
+                self.ignore_stderr.as_deref(),
+                self.limits(),
+            );
+        }
+
+        let mut exec = Exec::builder(bin)
+            .args(args)
+            .envs(env)
+            .ok_exit_codes(&self.ok_exit_codes)
+            .ignore_stderr(self.ignore_stderr.iter().flatten().cloned())
+            .in_dir(in_dir)
+            .limits(self.limits())
+            .merge_stderr(self.redirect_stderr_to_stdout);
+        if let Some(encoding) = self.output_encoding {
+            exec = exec.encoding(encoding);
+        }
+        if let Some(stdin) = stdin {
+            exec = exec.stdin(stdin);
+        }
+        let Some(max) = self.max_output_bytes.filter(|_| self.streams_stdout()) else {
+            return exec.build().run();
+        };
+
+        // Stdout is cut down as it's read, so a command with a huge amount of
+        // output never has all of it in memory at once.
+        let limiter = Arc::new(Mutex::new(self.output_limiter("stdout", max)?));
+        let ignore_stdout = self.ignore_stdout.clone();
+        let result = exec
+            .capture_stdout(false)
+            .on_output_line({
+                let limiter = Arc::clone(&limiter);
+                move |stream, line| {
+                    if stream == exec::OutputStream::Stdout
+                        && !ignore_stdout.iter().any(|re| re.is_match(line))
+                    {
+                        limiter.lock().unwrap().push_line(line);
+                    }
+                }
+            })
+            .build()
+            .run();
+        let stdout = Arc::into_inner(limiter)
+            .expect("the exec that shares the limiter has been dropped")
+            .into_inner()
+            .unwrap()
+            .finish()?;
+
+        match result {
+            Ok(mut o) => {
+                o.stdout = (!stdout.is_empty()).then_some(stdout);
+                Ok(o)
+            }
+            Err(e) => match e.downcast::<exec::Error>() {
+                Ok(exec::Error::UnexpectedExitCode {
+                    cmd, code, stderr, ..
+                }) => Err(exec::Error::UnexpectedExitCode {
+                    cmd,
+                    code,
+                    stdout,
+                    stderr,
+                }
+                .into()),
+                Ok(e) => Err(e.into()),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    // With `max-output-bytes`, stdout is cut down while the command runs,
+    // unless something needs to see all of it. That's the case when we look
+    // for a patch, a failure, or diagnostics on changed lines in it, or when
+    // it's recorded. A builtin or a plugin returns its output all at once, so
+    // there's nothing to stream.
+    fn streams_stdout(&self) -> bool {
+        self.max_output_bytes.is_some()
+            && self.builtin.is_none()
+            && !matches!(self.runner, Some(Runner::Plugin { .. }))
+            && self.stdin_from.is_none()
+            && self.lint_failure_output.is_none()
+            && (self.diagnostic_regex.is_none() || self.changed_lines.is_none())
+            && self.recorder.is_none()
+            && self.log_dir.is_none()
+    }
+
+    // Runs the command's `version-cmd` in the project root, for `--manifest`.
+    // Some tools print their version to stderr, so that's used when there's
+    // nothing on stdout.
+    pub(crate) fn version(&self) -> Result<Option<String>> {
+        if self.version_cmd.is_empty() {
+            return Ok(None);
+        }
+
+        let (mut cmd, _) = self.command_for_runner(
+            (self.version_cmd.clone(), self.version_cmd.len()),
+            &self.project_root,
+            None,
+        )?;
+        let bin = cmd.remove(0);
+        let output = Exec::builder(&bin)
+            .args(&cmd)
+            .envs(&self.env)
+            .ignore_all_stderr()
+            .in_dir(&self.project_root)
+            .build()
+            .run()
+            .map_err(|e| CommandError::VersionCmdFailed {
+                command: self.name.clone(),
+                error: e.to_string(),
+            })?;
+        let version = output.stdout.or(output.stderr).unwrap_or_default();
+        Ok(Some(version.trim().to_string()))
+    }
+
+    // With `stdin-from = "file"`, this is the content of the one file the
+    // command is invoked for.
+    fn stdin_for(&self, files: &[&Path]) -> Result<Option<String>> {
+        match self.stdin_from {
+            Some(StdinFrom::File) => {
+                Ok(Some(fs::read_to_string(self.project_root.join(files[0]))?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Applies the patch the command printed for a file it was given on stdin,
+    // and returns whether that changed the file. No output at all means that
+    // the file doesn't need any changes.
+    fn apply_patch(&self, file: &Path, original: &str, patch: Option<&str>) -> Result<bool> {
+        let Some(patch) = patch.filter(|p| !p.trim().is_empty()) else {
+            return Ok(false);
+        };
+        let patched = patch::apply(original, patch).map_err(|e| CommandError::InvalidPatch {
+            command: self.name.clone(),
+            path: file.to_path_buf(),
+            error: e.to_string(),
+        })?;
+        if patched == original {
+            return Ok(false);
+        }
+        debug!(
+            "Applying the patch from {} to {}",
+            self.name,
+            file.display()
+        );
+        fs::write(self.project_root.join(file), patched)?;
+        Ok(true)
+    }
+
+    // Returns every file in the directory the command runs in, skipping
+    // anything that git ignores, so that we can tell which files a tidier
+    // created.
+    fn files_under(&self, dir: &Path) -> Result<HashSet<PathBuf>> {
+        let mut files = HashSet::new();
+        for entry in ignore::WalkBuilder::new(dir)
+            .hidden(false)
+            .filter_entry(|e| e.file_name() != ".git")
+            .build()
+        {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|t| t.is_file())
+                && !self.scratch_files.path_matches(entry.path(), false)
+            {
+                files.insert(entry.into_path());
             }
-            return Ok(Some(TidyOutcome::Unchanged));
         }
-        Ok(Some(TidyOutcome::Unknown))
+        Ok(files)
+    }
+
+    // Nothing else runs while a command that reports new files is running, so
+    // the files after one invocation in a dir are the files before the next
+    // one, and we don't need to look at every file in the dir again.
+    fn files_before_invocation(&self, dir: &Path) -> Result<HashSet<PathBuf>> {
+        match self.files_snapshot.lock().unwrap().take() {
+            Some((snapshot_dir, files)) if snapshot_dir == dir => Ok(files),
+            _ => self.files_under(dir),
+        }
+    }
+
+    fn new_files_under(&self, dir: &Path, existing: &HashSet<PathBuf>) -> Result<Vec<PathBuf>> {
+        let files = self.files_under(dir)?;
+        let mut new_files = files
+            .iter()
+            .filter(|f| !existing.contains(*f))
+            .cloned()
+            .map(|f| match f.strip_prefix(&self.project_root) {
+                Ok(rel) => rel.to_path_buf(),
+                Err(_) => f,
+            })
+            .collect::<Vec<_>>();
+        new_files.sort();
+        if !new_files.is_empty() {
+            debug!(
+                "{} created new files: {}",
+                self.name,
+                new_files.iter().map(|f| f.display()).join(" "),
+            );
+        }
+        *self.files_snapshot.lock().unwrap() = Some((dir.to_path_buf(), files));
+        Ok(new_files)
     }
 
     pub fn lint(
@@ -467,15 +1689,58 @@ impl LintOrTidyCommand {
         files: &[&Path],
     ) -> Result<Option<LintOutcome>> {
         self.require_is_not_command_type("lint", LintOrTidyCommandType::Tidy)?;
+        self.lint_with_flags(self.lint_flags.as_deref(), actual_invoke, files)
+    }
+
+    // Runs a tidier without changing anything. A command that is both a
+    // linter and tidier is run with its lint-flags, and a tidy-only command
+    // is run with its check-flags. If the outcome is not ok, tidying would
+    // have changed the files.
+    pub fn check(
+        &self,
+        actual_invoke: ActualInvoke,
+        files: &[&Path],
+    ) -> Result<Option<LintOutcome>> {
+        self.require_is_not_command_type("check", LintOrTidyCommandType::Lint)?;
+        self.require_can_check()?;
+        let flags = match self.typ {
+            LintOrTidyCommandType::Tidy => self.check_flags.as_deref(),
+            _ => self.lint_flags.as_deref(),
+        };
+        self.lint_with_flags(flags, actual_invoke, files)
+    }
 
+    pub fn require_can_check(&self) -> Result<()> {
+        if self.typ == LintOrTidyCommandType::Tidy && self.check_flags.is_none() {
+            return Err(CommandError::CannotCheckWithoutCheckFlags {
+                command: self.name.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn lint_with_flags(
+        &self,
+        flags: Option<&[String]>,
+        actual_invoke: ActualInvoke,
+        files: &[&Path],
+    ) -> Result<Option<LintOutcome>> {
         if !self.should_act_on_files(actual_invoke, files)? {
             return Ok(None);
         }
+        let Some(flags) = self.flags_with_line_ranges(flags, files) else {
+            return Ok(None);
+        };
 
         let in_dir = self.in_dir(files[0])?;
-        let operating_on = self.operating_on(files, &in_dir)?;
+        let operating_on = self.operating_on(actual_invoke, files, &in_dir)?;
+        let dir = self.invocation_dir(actual_invoke, files)?;
+        let (cmd, idx, paths_file) = self.command_for_paths_or_file(&flags, &operating_on)?;
+        let cmd = self.expand_placeholders(cmd, idx, &dir);
+        let scratch_dir = Self::scratch_dir()?;
         let (mut cmd, before_paths_idx) =
-            self.command_for_paths(self.lint_flags.as_deref(), &operating_on);
+            self.command_for_runner((cmd, idx), &in_dir, Some(scratch_dir.path()))?;
 
         info!(
             "Linting [{}] with {} in [{}] using command [{}]",
@@ -486,22 +1751,313 @@ impl LintOrTidyCommand {
         );
 
         let bin = cmd.remove(0);
-        let result = exec::run(
-            &bin,
-            &cmd.iter().map(String::as_str).collect::<Vec<_>>(),
-            &self.env,
-            &self.ok_exit_codes,
-            self.ignore_stderr.as_deref(),
-            Some(&in_dir),
+        let env = self.env_for(
+            paths_file.as_ref(),
+            scratch_dir.path(),
+            &dir,
+            actual_invoke,
+            files.len(),
+        );
+        let stdin = self.stdin_for(files)?;
+        let read_only_metadata = self.read_only_metadata_for(actual_invoke, files)?;
+        let result = match self.builtin {
+            Some(builtin) => builtin.run(&cmd, &in_dir, &self.ok_exit_codes),
+            None => self.execute("lint", (&bin, &cmd), &env, &in_dir, stdin.as_deref()),
+        };
+        let result = self.strip_ignored_stdout(result);
+        self.record(
+            files,
+            &in_dir,
+            (&bin, &cmd),
+            env,
+            paths_file.as_ref(),
+            &result,
+            |o| !self.lint_output_is_ok(o),
         )?;
+        if let Some(pm) = read_only_metadata {
+            if self.paths_were_changed(pm)? {
+                return Err(CommandError::ReadOnlyCommandChangedFiles {
+                    command: self.name.clone(),
+                    paths: file_summary_for_log(files),
+                }
+                .into());
+            }
+        }
+        // This has to look at the output before it's truncated.
+        let ok = result.as_ref().is_ok_and(|o| self.lint_output_is_ok(o));
+        // So does this, or else a diagnostic on a changed line could be in
+        // the part that's cut out.
+        let (ok, result) = match (result, &self.changed_lines) {
+            (Ok(mut o), Some(changed)) => {
+                let outcome = self.filter_to_changed_lines(
+                    LintOutcome {
+                        ok,
+                        stdout: o.stdout.take(),
+                        stderr: o.stderr.take(),
+                        resources: o.resources,
+                    },
+                    files,
+                    changed,
+                );
+                o.stdout = outcome.stdout;
+                o.stderr = outcome.stderr;
+                (outcome.ok, Ok(o))
+            }
+            (result, _) => (ok, result),
+        };
+        let result = self.limit_output(result)?;
+        self.log_resources(result.resources, &in_dir);
 
         Ok(Some(LintOutcome {
-            ok: !self.lint_failure_exit_codes.contains(&result.exit_code),
+            ok,
             stdout: result.stdout,
             stderr: result.stderr,
+            resources: result.resources,
         }))
     }
 
+    fn limits(&self) -> exec::Limits {
+        exec::Limits::new()
+            .max_memory(self.max_memory)
+            .max_cpu_seconds(self.max_cpu_seconds)
+    }
+
+    // Lines of stdout that match one of the `ignore-stdout` regexes are
+    // removed before anything else looks at the output, whether it's in the
+    // outcome or in the error from a command that exited unexpectedly.
+    fn strip_ignored_stdout(&self, result: Result<exec::Output>) -> Result<exec::Output> {
+        if self.ignore_stdout.is_empty() {
+            return result;
+        }
+
+        match result {
+            Ok(mut o) => {
+                o.stdout = o.stdout.and_then(|s| self.without_ignored_lines(&s));
+                Ok(o)
+            }
+            Err(e) => match e.downcast::<exec::Error>() {
+                Ok(exec::Error::UnexpectedExitCode {
+                    cmd,
+                    code,
+                    stdout,
+                    stderr,
+                }) => Err(exec::Error::UnexpectedExitCode {
+                    cmd,
+                    code,
+                    stdout: self.without_ignored_lines(&stdout).unwrap_or_default(),
+                    stderr,
+                }
+                .into()),
+                Ok(e) => Err(e.into()),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn without_ignored_lines(&self, stdout: &str) -> Option<String> {
+        let kept = stdout
+            .split_inclusive('\n')
+            .filter(|line| {
+                let line = line.trim_end_matches(['\r', '\n']);
+                !self.ignore_stdout.iter().any(|re| re.is_match(line))
+            })
+            .collect::<String>();
+        (!kept.is_empty()).then_some(kept)
+    }
+
+    // Output over `max-output-bytes` is cut down to its beginning and end,
+    // whether it's in the outcome or in the error from a command that exited
+    // unexpectedly. This applies to stdout and stderr separately.
+    fn limit_output(&self, result: Result<exec::Output>) -> Result<exec::Output> {
+        let Some(max) = self.max_output_bytes else {
+            return result;
+        };
+        let limit = |output: String, stream| self.truncate_output(output, stream, max);
+        // Streamed stdout has already been cut down.
+        let limit_stdout = |output: String| {
+            if self.streams_stdout() {
+                Ok(output)
+            } else {
+                limit(output, "stdout")
+            }
+        };
+
+        match result {
+            Ok(mut o) => {
+                o.stdout = o.stdout.map(limit_stdout).transpose()?;
+                o.stderr = o.stderr.map(|s| limit(s, "stderr")).transpose()?;
+                Ok(o)
+            }
+            Err(e) => match e.downcast::<exec::Error>() {
+                Ok(exec::Error::UnexpectedExitCode {
+                    cmd,
+                    code,
+                    stdout,
+                    stderr,
+                }) => Err(exec::Error::UnexpectedExitCode {
+                    cmd,
+                    code,
+                    stdout: limit_stdout(stdout)?,
+                    stderr: limit(stderr, "stderr")?,
+                }
+                .into()),
+                Ok(exec::Error::UnexpectedStderr { cmd, code, stderr }) => {
+                    Err(exec::Error::UnexpectedStderr {
+                        cmd,
+                        code,
+                        stderr: limit(stderr, "stderr")?,
+                    }
+                    .into())
+                }
+                Ok(e) => Err(e.into()),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn truncate_output(&self, output: String, stream: &'static str, max: u64) -> Result<String> {
+        if output.len() as u64 <= max {
+            return Ok(output);
+        }
+        let mut limiter = self.output_limiter(stream, max)?;
+        limiter.push(&output);
+        limiter.finish()
+    }
+
+    fn output_limiter(&self, stream: &'static str, max: u64) -> Result<OutputLimiter> {
+        let full_output = if self.save_full_output {
+            Some(
+                tempfile::Builder::new()
+                    .prefix(&format!(
+                        "precious-{}-{stream}-",
+                        self.name.replace(' ', "-")
+                    ))
+                    .suffix(".txt")
+                    .tempfile()?,
+            )
+        } else {
+            None
+        };
+        Ok(OutputLimiter {
+            stream,
+            max: usize::try_from(max).unwrap_or(usize::MAX),
+            head: String::new(),
+            tail: String::new(),
+            elided: 0,
+            full_output,
+            write_error: None,
+        })
+    }
+
+    fn lint_output_is_ok(&self, output: &exec::Output) -> bool {
+        // Some tools always exit 0, so the only way to tell that they found
+        // problems is by looking at what they print.
+        let output_shows_failure = match (&self.lint_failure_output, &output.stdout) {
+            (Some(re), Some(stdout)) => re.is_match(stdout),
+            _ => false,
+        };
+        // A command that is given the file on stdin prints a patch when the
+        // file needs to be changed.
+        let printed_patch = self.stdin_from.is_some()
+            && output
+                .stdout
+                .as_deref()
+                .is_some_and(|s| !s.trim().is_empty());
+        !(self.lint_failure_exit_codes.contains(&output.exit_code)
+            || output_shows_failure
+            || printed_patch)
+    }
+
+    // With `--record`, this saves everything needed to print or re-run this
+    // invocation later, and with `--log-dir` it writes the same details to
+    // log files. An exec error is recorded as a failure, with whatever exit
+    // code and output the error carries.
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &self,
+        files: &[&Path],
+        in_dir: &Path,
+        (bin, args): (&str, &[String]),
+        env: HashMap<String, String>,
+        paths_file: Option<&NamedTempFile>,
+        result: &Result<exec::Output>,
+        failed: impl Fn(&exec::Output) -> bool,
+    ) -> Result<()> {
+        if self.recorder.is_none() && self.log_dir.is_none() {
+            return Ok(());
+        }
+
+        let (exit_code, stdout, stderr, error, failed) = match result {
+            Ok(o) => (
+                Some(o.exit_code),
+                o.stdout.clone().unwrap_or_default(),
+                o.stderr.clone().unwrap_or_default(),
+                None,
+                failed(o),
+            ),
+            Err(e) => {
+                let (code, stdout, stderr) = match e.downcast_ref::<exec::Error>() {
+                    Some(exec::Error::UnexpectedExitCode {
+                        code,
+                        stdout,
+                        stderr,
+                        ..
+                    }) => (Some(*code), stdout.clone(), stderr.clone()),
+                    Some(exec::Error::UnexpectedStderr { code, stderr, .. }) => {
+                        (Some(*code), String::new(), stderr.clone())
+                    }
+                    _ => (None, String::new(), String::new()),
+                };
+                (code, stdout, stderr, Some(e.to_string()), true)
+            }
+        };
+        let paths_file = match paths_file {
+            Some(f) => Some(String::from_utf8_lossy(&fs::read(f.path())?).into_owned()),
+            None => None,
+        };
+
+        let invocation = RecordedInvocation {
+            id: 0,
+            command: self.name.clone(),
+            paths: files.iter().map(|f| f.to_path_buf()).collect(),
+            cwd: match in_dir.strip_prefix(&self.project_root) {
+                Ok(rel) if rel.as_os_str().is_empty() => PathBuf::from("."),
+                Ok(rel) => rel.to_path_buf(),
+                Err(_) => in_dir.to_path_buf(),
+            },
+            argv: [bin.to_string()]
+                .into_iter()
+                .chain(args.iter().cloned())
+                .collect(),
+            env: env.into_iter().collect(),
+            paths_file,
+            exit_code,
+            passing_exit_codes: self
+                .ok_exit_codes
+                .iter()
+                .filter(|c| !self.lint_failure_exit_codes.contains(c))
+                .copied()
+                .collect(),
+            stdout,
+            stderr,
+            error,
+            failed,
+        };
+        if let Some(dir) = &self.log_dir {
+            record::write_log(dir, &invocation)?;
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(invocation);
+        }
+        Ok(())
+    }
+
+    fn log_resources(&self, resources: Option<exec::Resources>, in_dir: &Path) {
+        if let Some(r) = resources {
+            info!("Ran {} in [{}] using {r}", self.name, in_dir.display());
+        }
+    }
+
     fn require_is_not_command_type(
         &self,
         method: &'static str,
@@ -519,6 +2075,24 @@ impl LintOrTidyCommand {
     }
 
     fn should_act_on_files(&self, actual_invoke: ActualInvoke, files: &[&Path]) -> Result<bool> {
+        if self.path_args == PathArgs::DirList {
+            let dir = files[0];
+            if self.dir_matches_rules(dir) {
+                debug!(
+                    "Directory {} is included for the {} command",
+                    dir.display(),
+                    self.name,
+                );
+                return Ok(true);
+            }
+            debug!(
+                "Directory {} is not included in the {} command",
+                dir.display(),
+                self.name,
+            );
+            return Ok(false);
+        }
+
         match actual_invoke {
             ActualInvoke::PerFile => {
                 let f = &files[0];
@@ -610,8 +2184,13 @@ impl LintOrTidyCommand {
     // them as is (but sorted), or we may turn them paths relative to the
     // given directory. The given directory is the directory in which the
     // command will be run, and may not be the project root.
-    fn operating_on(&self, files: &[&Path], in_dir: &Path) -> Result<Vec<PathBuf>> {
-        match self.path_args {
+    fn operating_on(
+        &self,
+        actual_invoke: ActualInvoke,
+        files: &[&Path],
+        in_dir: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        match self.path_args_for(actual_invoke) {
             PathArgs::File => Ok(files
                 .iter()
                 .sorted()
@@ -622,6 +2201,11 @@ impl LintOrTidyCommand {
                 .sorted()
                 .map(|r| self.path_relative_to(r, in_dir))
                 .collect::<Vec<_>>()),
+            PathArgs::DirList => Ok(files
+                .iter()
+                .sorted()
+                .map(|d| self.path_relative_to(d, in_dir))
+                .collect::<Vec<_>>()),
             PathArgs::None => Ok(vec![]),
             PathArgs::Dot => Ok(vec![PathBuf::from(".")]),
             PathArgs::AbsoluteFile => Ok(files
@@ -647,6 +2231,32 @@ impl LintOrTidyCommand {
         }
     }
 
+    // These are the dirs, relative to the project root, where an invocation
+    // can change files that it wasn't given. A command that's passed dirs can
+    // change anything in them, and one that's passed `.` or no paths at all
+    // can change anything under the dir it runs in.
+    pub(crate) fn dirs_touched_by(
+        &self,
+        actual_invoke: ActualInvoke,
+        files: &[&Path],
+    ) -> Result<Vec<PathBuf>> {
+        match self.path_args_for(actual_invoke) {
+            PathArgs::File | PathArgs::AbsoluteFile => Ok(vec![]),
+            PathArgs::Dir | PathArgs::AbsoluteDir => Ok(Self::files_by_dir(files)?
+                .into_keys()
+                .map(Path::to_path_buf)
+                .collect()),
+            PathArgs::DirList => Ok(files.iter().map(|d| d.to_path_buf()).collect()),
+            PathArgs::None | PathArgs::Dot => {
+                let in_dir = self.in_dir(files[0])?;
+                Ok(vec![in_dir
+                    .strip_prefix(&self.project_root)
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf()])
+            }
+        }
+    }
+
     fn path_relative_to(&self, path: &Path, in_dir: &Path) -> PathBuf {
         let mut abs = self.project_root.clone();
         abs.push(path);
@@ -676,23 +2286,44 @@ impl LintOrTidyCommand {
             // If it's invoked per file we know that we only have one file in
             // `files`.
             ActualInvoke::PerFile => Ok(Some(self.path_metadata_for(files[0])?)),
-            // If it's invoked per dir we can look at the first file's
-            // parent. All the files should have the same dir.
-            ActualInvoke::PerDir => {
-                let dir = files[0]
-                    .parent()
-                    .ok_or_else(|| CommandError::PathHasNoParent {
-                        path: files[0].to_string_lossy().to_string(),
-                    })?;
-                Ok(Some(self.path_metadata_for(dir)?))
-            }
-            // If it's invoked once we would have to look at the entire
-            // tree. That might be too expensive so we won't report a tidy
-            // outcome in this case.
+            // If it's invoked per dir we can look at the directory the set
+            // is for. All the files should have the same dir.
+            ActualInvoke::PerDir => Ok(Some(self.path_metadata_for(self.dir_for_set(files)?)?)),
+            // If it's invoked once we only look at the files it was given,
+            // and only if the command asked us to, since hashing every file
+            // in a big project can be slow.
+            ActualInvoke::Once if self.track_changes => Ok(Some(PathMetadata {
+                dir: None,
+                path_map: self.metadata_for_files(
+                    files.iter().map(|f| self.project_root.join(f)).collect(),
+                )?,
+            })),
             ActualInvoke::Once => Ok(None),
         }
     }
 
+    // With `read-only`, this is the metadata for the paths a linter is run on,
+    // so we can tell whether it changed anything. Unlike a tidier, a command
+    // that's invoked once is always checked.
+    fn read_only_metadata_for(
+        &self,
+        actual_invoke: ActualInvoke,
+        files: &[&Path],
+    ) -> Result<Option<PathMetadata>> {
+        if !self.read_only {
+            return Ok(None);
+        }
+        match actual_invoke {
+            ActualInvoke::Once => Ok(Some(PathMetadata {
+                dir: None,
+                path_map: self.metadata_for_files(
+                    files.iter().map(|f| self.project_root.join(f)).collect(),
+                )?,
+            })),
+            _ => self.maybe_path_metadata_for(actual_invoke, files),
+        }
+    }
+
     // Given a directory, this gets the metadata for all files in the
     // directory that match the command's include/exclude rules.
     fn path_metadata_for(&self, path: &Path) -> Result<PathMetadata> {
@@ -702,26 +2333,25 @@ impl LintOrTidyCommand {
         full_path.push(path);
 
         if full_path.is_file() {
-            let meta = Self::metadata_for_file(&full_path)?;
+            let meta = self.metadata_for_file(&full_path)?;
             path_map.insert(full_path, meta);
         } else if full_path.is_dir() {
             dir = Some(path.to_path_buf());
+            let mut files = vec![];
             for entry in fs::read_dir(full_path)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_file() && self.file_matches_rules(&path) {
-                    let meta = entry.metadata()?;
-                    let hash = md5::compute(fs::read(&path)?);
-                    path_map.insert(
-                        path,
-                        PathInfo {
-                            mtime: meta.modified()?,
-                            size: meta.len(),
-                            hash,
-                        },
-                    );
+                let path = entry?.path();
+                // A dir-list command doesn't use its include globs, so any
+                // file in the directory that it changes counts.
+                let matches = if self.path_args == PathArgs::DirList {
+                    !self.excluder.path_matches(&path, false)
+                } else {
+                    self.file_matches_rules(&path)
+                };
+                if path.is_file() && matches && !self.scratch_files.path_matches(&path, false) {
+                    files.push(path);
                 }
             }
+            path_map = self.metadata_for_files(files)?;
         } else if !path.exists() {
             return Err(CommandError::PathDoesNotExist {
                 path: path.to_string_lossy().to_string(),
@@ -736,25 +2366,336 @@ impl LintOrTidyCommand {
         Ok(PathMetadata { dir, path_map })
     }
 
+    pub(crate) fn runs_when(&self, in_ci: bool) -> bool {
+        match self.run_when {
+            RunWhen::Always => true,
+            RunWhen::Ci => in_ci,
+            RunWhen::Local => !in_ci,
+        }
+    }
+
+    // This only looks at the include and exclude rules, since a command's
+    // rules matching no files at all usually means there's a typo in them.
+    pub(crate) fn rules_match_any_file(&self, files: &[PathBuf]) -> bool {
+        if self.path_args == PathArgs::DirList {
+            return !self.dirs_to_args_sets(files).is_empty();
+        }
+        files
+            .iter()
+            .any(|f| !self.excluder.path_matches(f, false) && self.includer.path_matches(f, false))
+    }
+
+    // Returns the names of the tools this command and the other command run
+    // if they're a pair of tidiers that are known to fight over formatting.
+    pub(crate) fn known_conflict_with(
+        &self,
+        other: &LintOrTidyCommand,
+    ) -> Option<(&'static str, &'static str)> {
+        let (ours, theirs) = (self.known_tool()?, other.known_tool()?);
+        KNOWN_CONFLICTS
+            .iter()
+            .any(|&(a, b)| (a, b) == (ours, theirs) || (b, a) == (ours, theirs))
+            .then_some((ours, theirs))
+    }
+
+    // This only looks at the executable and, for a known wrapper like
+    // `poetry run black`, the tool that the wrapper runs. Any other argument
+    // could be a file or flag that just happens to share a tool's name.
+    fn known_tool(&self) -> Option<&'static str> {
+        fn file_name(c: &str) -> Option<&str> {
+            Path::new(c).file_name()?.to_str()
+        }
+        let known = |name: &str| {
+            KNOWN_CONFLICTS
+                .iter()
+                .flat_map(|&(a, b)| [a, b])
+                .find(|&t| t == name)
+        };
+
+        let exe = file_name(self.cmd.first()?)?;
+        if let Some(tool) = known(exe) {
+            return Some(tool);
+        }
+        let (_, wrapper_args) = KNOWN_WRAPPERS.iter().find(|(w, _)| *w == exe)?;
+        let rest = &self.cmd[1..];
+        if rest.len() <= wrapper_args.len()
+            || !rest.iter().zip(wrapper_args.iter()).all(|(a, w)| a == w)
+        {
+            return None;
+        }
+        known(file_name(&rest[wrapper_args.len()])?)
+    }
+
+    // Returns the first of the files that matches `also-trigger-on`. In git
+    // modes, a change to one of these means that the command is run on every
+    // file its rules match, even if none of those files changed.
+    pub(crate) fn trigger_file<'a>(&self, files: &'a [PathBuf]) -> Option<&'a PathBuf> {
+        files
+            .iter()
+            .find(|f| self.also_trigger_on.path_matches(f, false))
+    }
+
     fn file_matches_rules(&self, file: &Path) -> bool {
         if self.excluder.path_matches(file, false) {
             return false;
         }
         if self.includer.path_matches(file, false) {
-            return true;
+            return self.file_passes_content_filters(file);
+        }
+        false
+    }
+
+    // This follows the same steps as `files_to_args_sets`, but says which
+    // step decided whether the command is given the file.
+    pub(crate) fn decision_for_file(&self, file: &Path) -> FileDecision {
+        if let GlobMatch::Matched(glob) = self.excluder.glob_match(file, false) {
+            return FileDecision::Excluded {
+                glob: glob.to_string(),
+            };
+        }
+        let glob = match self.includer.glob_match(file, false) {
+            GlobMatch::Matched(glob) => glob.to_string(),
+            GlobMatch::Negated(glob) => {
+                return FileDecision::NegatedInclude {
+                    glob: glob.to_string(),
+                }
+            }
+            GlobMatch::Unmatched => return FileDecision::NotIncluded,
+        };
+        if let Some((size, max)) = self.size_over_max(file) {
+            return FileDecision::TooLarge { size, max };
+        }
+        if self.is_skipped_binary(file) {
+            return FileDecision::Binary;
         }
+        if let Some(path) = self.symlink_in_path(file) {
+            return FileDecision::Symlink { path };
+        }
+        FileDecision::Included { glob }
+    }
+
+    // If we can't look at the file we let it through, since the command
+    // will give a more useful error about it than we can.
+    fn file_passes_content_filters(&self, file: &Path) -> bool {
+        if let Some((size, max)) = self.size_over_max(file) {
+            debug!(
+                "File {} is not passed to the {} command because it is {size} bytes, which is larger than the max-file-size of {max} bytes",
+                file.display(),
+                self.name,
+            );
+            return false;
+        }
+        if self.is_skipped_binary(file) {
+            debug!(
+                "File {} is not passed to the {} command because it is binary",
+                file.display(),
+                self.name,
+            );
+            return false;
+        }
+        true
+    }
+
+    // Returns the file's size and the max when it's over `max-file-size`.
+    fn size_over_max(&self, file: &Path) -> Option<(u64, u64)> {
+        let max = self.max_file_size?;
+        let size = fs::metadata(self.project_root.join(file)).ok()?.len();
+        (size > max).then_some((size, max))
+    }
+
+    fn is_skipped_binary(&self, file: &Path) -> bool {
+        self.skip_binary && content::is_binary(&self.project_root.join(file)).unwrap_or(false)
+    }
+
+    // When `follow-symlinks` is false, symlinked files are not passed to the
+    // command, and neither is anything under a symlinked directory. When it's
+    // not set, symlinked files are passed, but files under a symlinked
+    // directory are not.
+    fn symlink_policy_allows(&self, file: &Path) -> bool {
+        let Some(s) = self.symlink_in_path(file) else {
+            return true;
+        };
+        debug!(
+            "File {} is not passed to the {} command because {} is a symlink",
+            file.display(),
+            self.name,
+            s.display(),
+        );
         false
     }
 
-    fn metadata_for_file(file: &Path) -> Result<PathInfo> {
-        let meta = fs::metadata(file)?;
+    // Returns the file itself or the directory above it that is a symlink.
+    // When `follow-symlinks` is not set, only a symlinked directory counts.
+    // We only find files under one of those when another command sets
+    // `follow-symlinks = true`.
+    fn symlink_in_path(&self, file: &Path) -> Option<PathBuf> {
+        let path = self.project_root.join(file);
+        let mut paths = path.ancestors().take_while(|a| *a != self.project_root);
+        match self.follow_symlinks {
+            Some(true) => return None,
+            Some(false) => (),
+            None => {
+                paths.next();
+            }
+        }
+        paths.find(|a| a.is_symlink()).map(Path::to_path_buf)
+    }
+
+    fn file_was_changed(&self, prev_file: &Path, prev_meta: &PathInfo) -> Result<bool> {
+        debug!("Checking {} for changes", prev_file.display());
+        let current_meta = match fs::metadata(prev_file) {
+            Ok(m) => m,
+            // If the file no longer exists the command must've deleted it.
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
+        // If the stamp is unchanged we don't need to compare anything else.
+        // Unfortunately there's no guarantee a command won't modify the mtime
+        // even if it doesn't change the file's contents, so we cannot assume
+        // anything was changed just because the stamp changed. For example,
+        // Perl::Tidy does this :(
+        let stamp = FileStamp::new(&current_meta)?;
+        if prev_meta.stamp == stamp {
+            return Ok(false);
+        }
+
+        // If the size changed we know the contents changed.
+        if prev_meta.stamp.size != stamp.size {
+            return Ok(true);
+        }
+
+        // Otherwise we need to compare the content hash. This goes into the
+        // cache, so the next command to look at this file won't read it again.
+        let hash = self
+            .hash_cache
+            .hash(prev_file, stamp, self.hash_algorithm)?;
+        Ok(prev_meta.hash != hash)
+    }
+
+    // Reading and hashing each file is the slow part of checking a big
+    // directory, so the files are done in parallel.
+    fn metadata_for_files(&self, files: Vec<PathBuf>) -> Result<HashMap<PathBuf, PathInfo>> {
+        files
+            .into_par_iter()
+            .map(|f| {
+                let meta = self.metadata_for_file(&f)?;
+                Ok((f, meta))
+            })
+            .collect()
+    }
+
+    // The old contents are gone once the command runs, so we can't wait
+    // until a comparison needs the old hash to get it. But if an earlier
+    // command in this run already hashed the file and its stamp hasn't changed
+    // since, this doesn't read it again.
+    fn metadata_for_file(&self, file: &Path) -> Result<PathInfo> {
+        let stamp = FileStamp::new(&fs::metadata(file)?)?;
         Ok(PathInfo {
-            mtime: meta.modified()?,
-            size: meta.len(),
-            hash: md5::compute(fs::read(file)?),
+            stamp,
+            hash: self.hash_cache.hash(file, stamp, self.hash_algorithm)?,
         })
     }
 
+    // When the command has a `line-range-flag` and we know which lines were
+    // changed, this adds one flag for each changed range in the file. If the
+    // file has no changed lines, this returns `None` and the command is not
+    // run at all.
+    fn flags_with_line_ranges(
+        &self,
+        flags: Option<&[String]>,
+        files: &[&Path],
+    ) -> Option<Vec<String>> {
+        let mut flags = flags.map(<[String]>::to_vec).unwrap_or_default();
+        let (Some(template), Some(changed)) = (&self.line_range_flag, &self.changed_lines) else {
+            return Some(flags);
+        };
+
+        let ranges = changed.ranges(files[0]).unwrap_or_default();
+        if ranges.is_empty() {
+            debug!(
+                "Not running {} on {} because it has no changed lines",
+                self.name,
+                files[0].display(),
+            );
+            return None;
+        }
+        for r in ranges {
+            for t in template {
+                flags.push(
+                    t.replace("{start}", &r.start().to_string())
+                        .replace("{end}", &r.end().to_string()),
+                );
+            }
+        }
+
+        Some(flags)
+    }
+
+    // The runner uses this to make sure that nothing else runs at the same
+    // time as one of this command's invocations, which would make it look
+    // like the invocation created files that something else created.
+    pub fn reports_new_files(&self) -> bool {
+        self.report_new_files
+    }
+
+    pub fn has_line_range_flag(&self) -> bool {
+        self.line_range_flag.is_some()
+    }
+
+    pub fn has_diagnostic_regex(&self) -> bool {
+        self.diagnostic_regex.is_some()
+    }
+
+    pub fn set_changed_lines(&mut self, changed: ChangedLines) {
+        self.changed_lines = Some(changed);
+    }
+
+    pub fn changed_lines(&self) -> Option<&ChangedLines> {
+        self.changed_lines.as_ref()
+    }
+
+    pub fn set_only_dirty_dirs(&mut self) {
+        self.only_dirty_dirs = true;
+    }
+
+    // The mode is passed to the command in the `PRECIOUS_MODE` env var.
+    pub fn set_mode(&mut self, mode: &str) {
+        self.mode = Some(mode.to_string());
+    }
+
+    pub fn mode(&self) -> Option<&str> {
+        self.mode.as_deref()
+    }
+
+    pub(crate) fn set_recorder(&mut self, recorder: Arc<Recorder>) {
+        self.recorder = Some(recorder);
+    }
+
+    pub(crate) fn set_log_dir(&mut self, dir: PathBuf) {
+        self.log_dir = Some(dir);
+    }
+
+    pub(crate) fn set_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        self.hash_algorithm = algorithm;
+    }
+
+    pub(crate) fn set_hash_cache(&mut self, cache: Arc<HashCache>) {
+        self.hash_cache = cache;
+    }
+
+    // With --only-dirty-dirs, a per-dir invocation is passed the files it
+    // was given instead of their directory.
+    fn path_args_for(&self, actual_invoke: ActualInvoke) -> PathArgs {
+        if !self.only_dirty_dirs || actual_invoke != ActualInvoke::PerDir {
+            return self.path_args;
+        }
+        match self.path_args {
+            PathArgs::Dir => PathArgs::File,
+            PathArgs::AbsoluteDir => PathArgs::AbsoluteFile,
+            path_args => path_args,
+        }
+    }
+
     fn command_for_paths(
         &self,
         flags: Option<&[String]>,
@@ -773,12 +2714,247 @@ impl LintOrTidyCommand {
             if let Some(pf) = &self.path_flag {
                 cmd.push(pf.clone());
             }
-            cmd.push(p.to_string_lossy().to_string());
+            cmd.push(self.path_arg(p));
         }
 
         (cmd, idx)
     }
 
+    fn path_arg(&self, path: &Path) -> String {
+        match self.path_style {
+            Some(style) => style.apply(path),
+            None => path.to_string_lossy().to_string(),
+        }
+    }
+
+    // With paths-file, the paths are written to a temp file instead of being
+    // passed as arguments. The returned file is deleted when it's dropped, so
+    // it must be kept around until the command finishes.
+    fn command_for_paths_or_file(
+        &self,
+        flags: &[String],
+        paths: &[PathBuf],
+    ) -> Result<(Vec<String>, usize, Option<NamedTempFile>)> {
+        if !self.paths_file {
+            let (cmd, idx) = self.command_for_paths(Some(flags), paths);
+            return Ok((cmd, idx, None));
+        }
+
+        let mut file = tempfile::Builder::new()
+            .prefix("precious-paths-")
+            .tempfile()?;
+        for p in paths {
+            file.write_all(&self.paths_file_entry(p)?)?;
+        }
+        file.flush()?;
+
+        let (mut cmd, _) = self.command_for_paths(Some(flags), &[]);
+        let idx = cmd.len();
+        if let Some(template) = &self.paths_file_flag {
+            let path = file.path().to_string_lossy();
+            cmd.extend(template.iter().map(|t| t.replace("{file}", &path)));
+        }
+        Ok((cmd, idx, Some(file)))
+    }
+
+    // The paths are written as they are, so paths that aren't valid UTF-8
+    // survive the trip through the file. Paths are separated by newlines by
+    // default, so a path containing one can only be written when the paths
+    // are NUL-separated.
+    fn paths_file_entry(&self, path: &Path) -> Result<Vec<u8>> {
+        let mut entry = match self.path_style {
+            Some(style) => style.apply(path).into_bytes(),
+            None => path.as_os_str().as_encoded_bytes().to_vec(),
+        };
+        if self.paths_file_null_separated {
+            entry.push(b'\0');
+        } else {
+            if entry.contains(&b'\n') {
+                return Err(CommandError::PathsFilePathHasNewline {
+                    command: self.name.clone(),
+                    path: path.display().to_string(),
+                }
+                .into());
+            }
+            entry.push(b'\n');
+        }
+        Ok(entry)
+    }
+
+    // This is the directory that `$PRECIOUS_DIR` is replaced with. For
+    // per-file and per-dir invocations, it's the directory containing the
+    // files. An invocation that runs once for all the files is operating on
+    // the whole project, so it's the project root.
+    fn invocation_dir(&self, actual_invoke: ActualInvoke, files: &[&Path]) -> Result<PathBuf> {
+        if actual_invoke == ActualInvoke::Once {
+            return Ok(self.project_root.clone());
+        }
+        let parent = self.dir_for_set(files)?;
+        if parent.as_os_str().is_empty() {
+            return Ok(self.project_root.clone());
+        }
+        Ok(self.project_root.join(parent))
+    }
+
+    // This is the directory that a per-file or per-dir set of files is in.
+    // For a dir-list command, the set is just the directory.
+    fn dir_for_set<'a>(&self, files: &[&'a Path]) -> Result<&'a Path> {
+        if self.path_args == PathArgs::DirList {
+            return Ok(files[0]);
+        }
+        Ok(files[0]
+            .parent()
+            .ok_or_else(|| CommandError::PathHasNoParent {
+                path: files[0].to_string_lossy().to_string(),
+            })?)
+    }
+
+    // The `$PRECIOUS_ROOT` in `cmd` is replaced when the command is created,
+    // but the flags can have it too, and they can also have `$PRECIOUS_DIR`,
+    // which is different for each invocation. The paths after `idx` are left
+    // alone.
+    fn expand_placeholders(&self, mut cmd: Vec<String>, idx: usize, dir: &Path) -> Vec<String> {
+        for c in &mut cmd[..idx] {
+            *c = self.expand_placeholder(c, dir);
+        }
+        cmd
+    }
+
+    fn expand_placeholder(&self, s: &str, dir: &Path) -> String {
+        s.replace("$PRECIOUS_ROOT", &self.project_root.to_string_lossy())
+            .replace("$PRECIOUS_DIR", &dir.to_string_lossy())
+    }
+
+    // Besides the command's own env vars, this tells the command how it was
+    // run, so a wrapper script can do something different for a full run
+    // than it does for a commit hook.
+    fn env_for(
+        &self,
+        paths_file: Option<&NamedTempFile>,
+        scratch_dir: &Path,
+        dir: &Path,
+        actual_invoke: ActualInvoke,
+        file_count: usize,
+    ) -> HashMap<String, String> {
+        let mut env = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), self.expand_placeholder(v, dir)))
+            .collect::<HashMap<_, _>>();
+        env.insert(
+            String::from("PRECIOUS_TMPDIR"),
+            scratch_dir.to_string_lossy().into_owned(),
+        );
+        env.insert(String::from("PRECIOUS_COMMAND_NAME"), self.name.clone());
+        env.insert(
+            String::from("PRECIOUS_INVOKE"),
+            actual_invoke.as_str().to_string(),
+        );
+        env.insert(String::from("PRECIOUS_FILE_COUNT"), file_count.to_string());
+        if let Some(mode) = &self.mode {
+            env.insert(String::from("PRECIOUS_MODE"), mode.clone());
+        }
+        if let Some(file) = paths_file {
+            env.insert(
+                String::from("PRECIOUS_PATHS_FILE"),
+                file.path().to_string_lossy().into_owned(),
+            );
+        }
+        env
+    }
+
+    // Each invocation gets its own scratch dir, so commands running in
+    // parallel can't trip over each other's temp files. The dir and
+    // everything in it is deleted when the returned value is dropped.
+    fn scratch_dir() -> Result<TempDir> {
+        Ok(tempfile::Builder::new()
+            .prefix("precious-scratch-")
+            .tempdir()?)
+    }
+
+    // If the command has a container runner, this wraps the command in a
+    // `docker run` or `podman run` invocation. The project root is mounted
+    // at the same path inside the container, which means that all the paths
+    // we pass to the command, as well as the working directory, are the same
+    // inside and outside the container. The invocation's scratch dir is
+    // mounted the same way, so `$PRECIOUS_TMPDIR` works in the container too.
+    fn command_for_runner(
+        &self,
+        (cmd, before_paths_idx): (Vec<String>, usize),
+        in_dir: &Path,
+        scratch_dir: Option<&Path>,
+    ) -> Result<(Vec<String>, usize)> {
+        let Some(runner) = &self.runner else {
+            return Ok((cmd, before_paths_idx));
+        };
+        let Some((exe, image)) = runner.container() else {
+            return Ok((cmd, before_paths_idx));
+        };
+
+        let mut wrapped = vec![
+            exe.to_string(),
+            String::from("run"),
+            String::from("--rm"),
+            String::from("--mount"),
+            bind_mount(&self.project_root),
+            String::from("--workdir"),
+            in_dir.to_string_lossy().to_string(),
+        ];
+        if let Some(scratch_dir) = scratch_dir {
+            wrapped.push(String::from("--mount"));
+            wrapped.push(bind_mount(scratch_dir));
+        }
+        if self.stdin_from.is_some() {
+            wrapped.push(String::from("--interactive"));
+        }
+        wrapped.append(&mut self.container_user_args(runner)?);
+        // The env vars are set for the docker/podman process by `Exec::run`, so
+        // we just need to tell it to pass them through to the container.
+        for k in self
+            .env
+            .keys()
+            .map(String::as_str)
+            .chain(RUN_CONTEXT_ENV_VARS.iter().copied())
+            .chain(scratch_dir.map(|_| "PRECIOUS_TMPDIR"))
+            .sorted()
+        {
+            wrapped.push(String::from("--env"));
+            wrapped.push(k.to_string());
+        }
+        wrapped.push(image.to_string());
+
+        let idx = before_paths_idx + wrapped.len();
+        wrapped.extend(cmd);
+        Ok((wrapped, idx))
+    }
+
+    // Without this, any files a tidier writes from inside a docker container
+    // will be owned by root. We run the container as the user who owns the
+    // project root instead. Rootless podman already maps the container's user
+    // to the current user, so it just needs to keep that id.
+    #[cfg(target_family = "unix")]
+    fn container_user_args(&self, runner: &Runner) -> Result<Vec<String>> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(match runner {
+            Runner::Docker { .. } => {
+                let meta = fs::metadata(&self.project_root)?;
+                vec![
+                    String::from("--user"),
+                    format!("{}:{}", meta.uid(), meta.gid()),
+                ]
+            }
+            Runner::Podman { .. } => vec![String::from("--userns=keep-id")],
+            Runner::Plugin { .. } => vec![],
+        })
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    #[allow(clippy::unnecessary_wraps)]
+    fn container_user_args(&self, _: &Runner) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
     pub(crate) fn paths_summary(&self, actual_invoke: ActualInvoke, paths: &[&Path]) -> String {
         let all = paths
             .iter()
@@ -809,33 +2985,15 @@ impl LintOrTidyCommand {
     }
 
     fn paths_were_changed(&self, prev: PathMetadata) -> Result<bool> {
-        for (prev_file, prev_meta) in &prev.path_map {
-            debug!("Checking {} for changes", prev_file.display());
-            let current_meta = match fs::metadata(prev_file) {
-                Ok(m) => m,
-                // If the file no longer exists the command must've deleted
-                // it.
-                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
-                Err(e) => return Err(e.into()),
-            };
-            // If the mtime is unchanged we don't need to compare anything
-            // else. Unfortunately there's no guarantee a command won't modify
-            // the mtime even if it doesn't change the file's contents, so we
-            // cannot assume anything was changed just because the mtime
-            // changed. For example, Perl::Tidy does this :(
-            if prev_meta.mtime == current_meta.modified()? {
-                continue;
-            }
-
-            // If the size changed we know the contents changed.
-            if prev_meta.size != current_meta.len() {
-                return Ok(true);
-            }
-
-            // Otherwise we need to compare the content hash.
-            if prev_meta.hash != md5::compute(fs::read(prev_file)?) {
-                return Ok(true);
-            }
+        // This stops as soon as any file is found to be changed, or if
+        // checking a file fails.
+        if let Some(changed) = prev
+            .path_map
+            .par_iter()
+            .map(|(prev_file, prev_meta)| self.file_was_changed(prev_file, prev_meta))
+            .find_any(|changed| !matches!(changed, Ok(false)))
+        {
+            return changed;
         }
 
         if let Some(dir) = prev.dir {
@@ -849,6 +3007,7 @@ impl LintOrTidyCommand {
                 let path = entry.path();
                 if path.is_file()
                     && self.file_matches_rules(&path)
+                    && !self.scratch_files.path_matches(&path, false)
                     && !prev.path_map.contains_key(&path)
                 {
                     return Ok(true);
@@ -859,6 +3018,101 @@ impl LintOrTidyCommand {
         Ok(false)
     }
 
+    // If the command has a `diagnostic-regex`, this looks at each line of
+    // output from a failed lint run to find the diagnostics that are on lines
+    // which were changed. If every diagnostic is on an unchanged line, the
+    // outcome becomes a pass. Otherwise the output is trimmed to just the
+    // diagnostics on changed lines.
+    //
+    // If the regex matches nothing we cannot tell where the problems are, so
+    // the outcome is returned as-is.
+    fn filter_to_changed_lines(
+        &self,
+        outcome: LintOutcome,
+        files: &[&Path],
+        changed: &ChangedLines,
+    ) -> LintOutcome {
+        let Some(re) = &self.diagnostic_regex else {
+            return outcome;
+        };
+        if outcome.ok {
+            return outcome;
+        }
+
+        let mut found = 0;
+        let mut kept: Vec<&str> = vec![];
+        for line in outcome
+            .stdout
+            .iter()
+            .chain(outcome.stderr.iter())
+            .flat_map(|o| o.lines())
+        {
+            let Some(caps) = re.captures(line) else {
+                continue;
+            };
+            let Some(line_num) = caps
+                .name("line")
+                .and_then(|l| l.as_str().parse::<usize>().ok())
+            else {
+                continue;
+            };
+            found += 1;
+
+            let path = match caps.name("path") {
+                Some(p) => Some(self.diagnostic_path(p.as_str(), files)),
+                None if files.len() == 1 => Some(files[0].to_path_buf()),
+                None => None,
+            };
+            // If we don't know which file a diagnostic is for we keep it.
+            if path.is_some_and(|p| !changed.contains(&p, line_num)) {
+                continue;
+            }
+            kept.push(line);
+        }
+
+        if found == 0 {
+            return outcome;
+        }
+
+        debug!(
+            "Kept {} of {} diagnostics from {} on changed lines",
+            kept.len(),
+            found,
+            self.name,
+        );
+        if kept.is_empty() {
+            return LintOutcome {
+                ok: true,
+                stdout: None,
+                stderr: None,
+                resources: outcome.resources,
+            };
+        }
+        LintOutcome {
+            ok: false,
+            stdout: Some(kept.join("\n")),
+            stderr: None,
+            resources: outcome.resources,
+        }
+    }
+
+    // Tools report paths in many ways. They may be relative to the project
+    // root, relative to the directory the command ran in, or absolute. We try
+    // to map these back to one of the files we passed to the command.
+    fn diagnostic_path(&self, path: &str, files: &[&Path]) -> PathBuf {
+        let path = Path::new(path);
+        let path = path.strip_prefix(&self.project_root).unwrap_or(path);
+        let path = path.strip_prefix("./").unwrap_or(path);
+        files
+            .iter()
+            .find(|f| f.ends_with(path))
+            .map_or_else(|| path.to_path_buf(), |f| f.to_path_buf())
+    }
+
+    pub fn daemon_cmd(&self) -> Option<&[String]> {
+        self.daemon_cmd.as_deref()
+    }
+
     pub fn config_key(&self) -> String {
         format!("commands.{}", Self::maybe_toml_quote(&self.name),)
     }
@@ -873,6 +3127,9 @@ impl LintOrTidyCommand {
     fn in_dir(&self, file: &Path) -> Result<PathBuf> {
         match &self.working_dir {
             WorkingDir::Root => Ok(self.project_root.clone()),
+            WorkingDir::Dir if self.path_args == PathArgs::DirList => {
+                Ok(self.project_root.join(file))
+            }
             WorkingDir::Dir => {
                 let mut abs = self.project_root.clone();
                 abs.push(file);
@@ -890,40 +3147,127 @@ impl LintOrTidyCommand {
     }
 
     pub fn config_debug(&self) -> String {
-        format!(
+        let mut debug = format!(
             "{} | working-dir = {} | path-args = {}",
             self.invoke, self.working_dir, self.path_args
-        )
+        );
+        if let Some(runner) = &self.runner {
+            debug.push_str(&format!(" | {runner}"));
+        }
+        debug
     }
 }
 
-fn file_summary_for_log(files: &[&Path]) -> String {
-    if files.len() <= 3 {
-        return files.iter().map(|p| p.to_string_lossy()).join(" ");
-    }
-    let first3 = files.iter().take(3).map(|p| p.to_string_lossy()).join(" ");
-    format!("{} ... and {} more", first3, files.len() - 3)
+// Keeps the first and last half of `max` bytes of output as it's read, and
+// counts the bytes in between. If any were left out, a line saying how many
+// goes between the two halves. With `save-full-output`, all of the output is
+// written to a file as it's read, and the file is kept if anything was left
+// out. The line saying how much was left out then says where it is.
+struct OutputLimiter {
+    stream: &'static str,
+    max: usize,
+    head: String,
+    tail: String,
+    elided: usize,
+    full_output: Option<NamedTempFile>,
+    write_error: Option<std::io::Error>,
 }
 
-fn command_for_log(cmd: &[String], before_paths_idx: usize) -> String {
-    // If this is true, then there's only one path in the command.
-    if before_paths_idx == cmd.len() - 1 {
-        cmd.join(" ")
-    } else {
-        format!("{} <paths>", cmd[..before_paths_idx].join(" "))
+impl OutputLimiter {
+    fn push_line(&mut self, line: &str) {
+        self.push(line);
+        self.push("\n");
     }
-}
 
-fn replace_root(cmd: &[String], root: &Path) -> Vec<String> {
-    cmd.iter()
-        .map(|c| {
-            c.replace(
-                "$PRECIOUS_ROOT",
-                root.to_string_lossy().into_owned().as_str(),
-            )
-        })
-        .collect()
-}
+    fn push(&mut self, mut output: &str) {
+        if let Some(file) = &mut self.full_output {
+            if let Err(e) = file.write_all(output.as_bytes()) {
+                self.write_error.get_or_insert(e);
+            }
+        }
+
+        // Once anything has gone into the tail, the head is done, even if a
+        // multi-byte char at its end left it a little short.
+        let head_max = self.max / 2;
+        if self.tail.is_empty() && self.head.len() < head_max {
+            let mut end = (head_max - self.head.len()).min(output.len());
+            while !output.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.head.push_str(&output[..end]);
+            output = &output[end..];
+        }
+        self.tail.push_str(output);
+        // Trimming the tail every time it goes over would mean moving it for
+        // every line, so this lets it get to twice its size first.
+        if self.tail.len() > (self.max - head_max) * 2 {
+            self.trim_tail();
+        }
+    }
+
+    fn trim_tail(&mut self) {
+        let tail_max = self.max - self.max / 2;
+        if self.tail.len() <= tail_max {
+            return;
+        }
+        let mut start = self.tail.len() - tail_max;
+        while !self.tail.is_char_boundary(start) {
+            start += 1;
+        }
+        self.tail.drain(..start);
+        self.elided += start;
+    }
+
+    fn finish(mut self) -> Result<String> {
+        if let Some(e) = self.write_error {
+            return Err(e.into());
+        }
+        self.trim_tail();
+        if self.elided == 0 {
+            return Ok(self.head + &self.tail);
+        }
+
+        let full_output = match self.full_output {
+            Some(file) => {
+                let (_, path) = file.keep()?;
+                format!(", the full {} is in {}", self.stream, path.display())
+            }
+            None => String::new(),
+        };
+        Ok(format!(
+            "{}\n[... {} bytes elided{full_output} ...]\n{}",
+            self.head, self.elided, self.tail,
+        ))
+    }
+}
+
+pub(crate) fn file_summary_for_log(files: &[&Path]) -> String {
+    if files.len() <= 3 {
+        return files.iter().map(|p| p.to_string_lossy()).join(" ");
+    }
+    let first3 = files.iter().take(3).map(|p| p.to_string_lossy()).join(" ");
+    format!("{} ... and {} more", first3, files.len() - 3)
+}
+
+fn command_for_log(cmd: &[String], before_paths_idx: usize) -> String {
+    // If this is true, then there's at most one path in the command.
+    if before_paths_idx + 1 >= cmd.len() {
+        cmd.join(" ")
+    } else {
+        format!("{} <paths>", cmd[..before_paths_idx].join(" "))
+    }
+}
+
+fn replace_root(cmd: &[String], root: &Path) -> Vec<String> {
+    cmd.iter()
+        .map(|c| {
+            c.replace(
+                "$PRECIOUS_ROOT",
+                root.to_string_lossy().into_owned().as_str(),
+            )
+        })
+        .collect()
+}
 
 #[cfg(test)]
 mod tests {
@@ -949,10 +3293,15 @@ mod tests {
             includer: matcher(&[])?,
             include: vec![],
             excluder: matcher(&[])?,
+            dir_includer: matcher(&[])?,
             invoke: Invoke::PerFile,
             working_dir: WorkingDir::Root,
             path_args: PathArgs::File,
             cmd: vec![],
+            builtin: None,
+            install_cmd: None,
+            daemon_cmd: None,
+            runner: None,
             env: HashMap::new(),
             lint_flags: None,
             tidy_flags: None,
@@ -960,6 +3309,46 @@ mod tests {
             ok_exit_codes: vec![],
             lint_failure_exit_codes: HashSet::new(),
             ignore_stderr: None,
+            ignore_stdout: vec![],
+            redirect_stderr_to_stdout: false,
+            version_cmd: vec![],
+            also_trigger_on: matcher(&[])?,
+            stdin_from: None,
+            report_new_files: false,
+            hash_algorithm: HashAlgorithm::default(),
+            max_memory: None,
+            max_cpu_seconds: None,
+            path_style: None,
+            max_output_bytes: None,
+            save_full_output: false,
+            lint_failure_output: None,
+            run_when: RunWhen::Always,
+            scratch_files: matcher(&[])?,
+            track_changes: false,
+            read_only: false,
+            skip_binary: false,
+            max_file_size: None,
+            follow_symlinks: None,
+            output_encoding: None,
+            paths_file: false,
+            paths_file_flag: None,
+            paths_file_null_separated: false,
+            check_flags: None,
+            serial: false,
+            depends_on: vec![],
+            conflicts_with: vec![],
+            mutex: None,
+            max_bytes_per_invocation: None,
+            max_files_per_invocation: None,
+            line_range_flag: None,
+            changed_lines: None,
+            only_dirty_dirs: false,
+            mode: None,
+            recorder: None,
+            log_dir: None,
+            diagnostic_regex: None,
+            hash_cache: Arc::default(),
+            files_snapshot: Mutex::default(),
         })
     }
 
@@ -995,6 +3384,121 @@ mod tests {
         Ok(())
     }
 
+    #[test_case(None, false, &["big.go", "binary.go", "small.go"] ; "no filters")]
+    #[test_case(Some(100), false, &["binary.go", "small.go"] ; "max-file-size")]
+    #[test_case(None, true, &["big.go", "small.go"] ; "skip-binary")]
+    #[test_case(Some(100), true, &["small.go"] ; "both filters")]
+    #[parallel]
+    fn files_to_args_sets_with_content_filters(
+        max_file_size: Option<u64>,
+        skip_binary: bool,
+        expect: &[&str],
+    ) -> Result<()> {
+        let helper = TestHelper::new()?;
+        helper.write_file("big.go", &"// comment\n".repeat(20))?;
+        helper.write_file("binary.go", "package \0binary\n")?;
+        helper.write_file("small.go", "package small\n")?;
+
+        let command = LintOrTidyCommand {
+            project_root: helper.precious_root(),
+            invoke: Invoke::PerFile,
+            includer: matcher(&["**/*.go"])?,
+            max_file_size,
+            skip_binary,
+            ..default_command()?
+        };
+        let files = ["big.go", "binary.go", "small.go"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let (sets, _) = command.files_to_args_sets(&files)?;
+        assert_eq!(
+            sets.into_iter().flatten().collect::<Vec<_>>(),
+            expect.iter().map(Path::new).collect::<Vec<_>>(),
+        );
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test_case(None, &["foo.go", "link.go", "real/bar.go"] ; "unset")]
+    #[test_case(Some(true), &["foo.go", "link.go", "linked/bar.go", "real/bar.go"] ; "set to true")]
+    #[test_case(Some(false), &["foo.go", "real/bar.go"] ; "set to false")]
+    #[parallel]
+    fn files_to_args_sets_with_follow_symlinks(
+        follow_symlinks: Option<bool>,
+        expect: &[&str],
+    ) -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let helper = TestHelper::new()?;
+        helper.write_file("foo.go", "package foo\n")?;
+        helper.write_file("real/bar.go", "package bar\n")?;
+        let root = helper.precious_root();
+        symlink(root.join("foo.go"), root.join("link.go"))?;
+        symlink(root.join("real"), root.join("linked"))?;
+
+        let command = LintOrTidyCommand {
+            project_root: root,
+            invoke: Invoke::PerFile,
+            includer: matcher(&["**/*.go"])?,
+            follow_symlinks,
+            ..default_command()?
+        };
+        let files = ["foo.go", "link.go", "linked/bar.go", "real/bar.go"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let (sets, _) = command.files_to_args_sets(&files)?;
+        assert_eq!(
+            sets.into_iter().flatten().collect::<Vec<_>>(),
+            expect.iter().map(Path::new).collect::<Vec<_>>(),
+        );
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test_case("small.go", FileDecision::Included { glob: String::from("**/*.go") } ; "included")]
+    #[test_case("small.rs", FileDecision::NotIncluded ; "not included")]
+    #[test_case("gen/small.go", FileDecision::NegatedInclude { glob: String::from("!gen/") } ; "negated include")]
+    #[test_case("vendor/small.go", FileDecision::Excluded { glob: String::from("vendor/") } ; "excluded")]
+    #[test_case("big.go", FileDecision::TooLarge { size: 220, max: 100 } ; "too large")]
+    #[test_case("binary.go", FileDecision::Binary ; "binary")]
+    #[test_case("link.go", FileDecision::Symlink { path: PathBuf::from("link.go") } ; "symlink")]
+    #[parallel]
+    fn decision_for_file(file: &str, expect: FileDecision) -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let helper = TestHelper::new()?;
+        helper.write_file("big.go", &"// comment\n".repeat(20))?;
+        helper.write_file("binary.go", "package \0binary\n")?;
+        for f in ["small.go", "small.rs", "gen/small.go", "vendor/small.go"] {
+            helper.write_file(f, "package small\n")?;
+        }
+        let root = helper.precious_root();
+        symlink(root.join("small.go"), root.join("link.go"))?;
+
+        let command = LintOrTidyCommand {
+            project_root: root.clone(),
+            includer: matcher(&["**/*.go", "!gen/"])?,
+            excluder: matcher(&["vendor/"])?,
+            max_file_size: Some(100),
+            skip_binary: true,
+            follow_symlinks: Some(false),
+            ..default_command()?
+        };
+        let expect = match expect {
+            FileDecision::Symlink { path } => FileDecision::Symlink {
+                path: root.join(path),
+            },
+            e => e,
+        };
+        assert_eq!(command.decision_for_file(Path::new(file)), expect);
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn files_to_args_sets_per_file_or_dir() -> Result<()> {
@@ -1107,6 +3611,41 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[parallel]
+    fn files_to_args_sets_dir_list() -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerDir,
+            path_args: PathArgs::DirList,
+            dir_includer: matcher(&["modules/*"])?,
+            excluder: matcher(&["modules/vendored/"])?,
+            ..default_command()?
+        };
+        let files = &[
+            "main.tf",
+            "modules/network/main.tf",
+            "modules/network/variables.tf",
+            "modules/network/templates/subnet.tpl",
+            "modules/storage/README.md",
+            "modules/vendored/main.tf",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            command.files_to_args_sets(files)?,
+            (
+                vec![
+                    vec![Path::new("modules/network")],
+                    vec![Path::new("modules/storage")],
+                ],
+                ActualInvoke::PerDir,
+            ),
+        );
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn files_to_args_sets_once() -> Result<()> {
@@ -1139,6 +3678,61 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[parallel]
+    fn files_to_args_sets_with_max_files_per_invocation() -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerDir,
+            includer: matcher(&["**/*.go"])?,
+            max_files_per_invocation: Some(2),
+            ..default_command()?
+        };
+        let files = &["a.go", "b.go", "c.go", "d.go", "e.go", "sub/f.go"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let sets = command
+            .files_to_args_sets(files)?
+            .0
+            .into_iter()
+            .map(|set| set.iter().map(|p| p.to_string_lossy()).join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(sets, vec!["a.go b.go", "c.go d.go", "e.go", "sub/f.go"]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn files_to_args_sets_with_max_bytes_per_invocation() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        for (name, size) in [("a.go", 10), ("b.go", 10), ("c.go", 50), ("d.go", 5)] {
+            fs::write(td.path().join(name), "x".repeat(size))?;
+        }
+        let command = LintOrTidyCommand {
+            project_root: td.path().to_path_buf(),
+            invoke: Invoke::Once,
+            includer: matcher(&["**/*.go"])?,
+            max_bytes_per_invocation: Some(20),
+            ..default_command()?
+        };
+        let files = &["a.go", "b.go", "c.go", "d.go"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let (sets, actual_invoke) = command.files_to_args_sets(files)?;
+        assert_eq!(actual_invoke, ActualInvoke::Once);
+        assert_eq!(
+            sets.into_iter()
+                .map(|set| set.iter().map(|p| p.to_string_lossy()).join(" "))
+                .collect::<Vec<_>>(),
+            vec!["a.go b.go", "c.go", "d.go"],
+            "a file larger than the limit gets a batch of its own",
+        );
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn require_is_not_command_type_with_lint_command() -> Result<()> {
@@ -1191,6 +3785,43 @@ mod tests {
         Ok(())
     }
 
+    #[test_case(LintOrTidyCommandType::Tidy, None, false ; "tidier without check-flags")]
+    #[test_case(
+        LintOrTidyCommandType::Tidy,
+        Some(vec![String::from("--check")]),
+        true ;
+        "tidier with check-flags"
+    )]
+    #[test_case(LintOrTidyCommandType::Both, None, true ; "linter and tidier")]
+    #[parallel]
+    fn require_can_check(
+        typ: LintOrTidyCommandType,
+        check_flags: Option<Vec<String>>,
+        can_check: bool,
+    ) -> Result<()> {
+        let command = LintOrTidyCommand {
+            typ,
+            check_flags,
+            ..default_command()?
+        };
+        if can_check {
+            assert!(command.require_can_check().is_ok());
+        } else {
+            assert_eq!(
+                command
+                    .require_can_check()
+                    .unwrap_err()
+                    .downcast::<CommandError>()
+                    .unwrap(),
+                CommandError::CannotCheckWithoutCheckFlags {
+                    command: command.name,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn require_is_not_command_type_with_both_command() -> Result<()> {
@@ -1376,13 +4007,13 @@ mod tests {
         };
         let file1 = Path::new("file1");
         assert_eq!(
-            command.operating_on(&[file1], &command.project_root)?,
+            command.operating_on(ActualInvoke::PerFile, &[file1], &command.project_root)?,
             vec![file1],
         );
 
         let file2 = Path::new("subdir/file2");
         assert_eq!(
-            command.operating_on(&[file2], &command.project_root)?,
+            command.operating_on(ActualInvoke::PerFile, &[file2], &command.project_root)?,
             vec![file2],
         );
 
@@ -1400,7 +4031,7 @@ mod tests {
         in_dir.push("subdir");
         let file = Path::new("subdir/file");
         assert_eq!(
-            command.operating_on(&[file], &in_dir)?,
+            command.operating_on(ActualInvoke::PerFile, &[file], &in_dir)?,
             vec![PathBuf::from("file")],
         );
 
@@ -1416,7 +4047,7 @@ mod tests {
         };
         let files = [Path::new("file1"), Path::new("subdir/file2")];
         assert_eq!(
-            command.operating_on(&files, &command.project_root,)?,
+            command.operating_on(ActualInvoke::PerDir, &files, &command.project_root)?,
             vec![PathBuf::from("."), PathBuf::from("subdir")],
         );
 
@@ -1434,13 +4065,49 @@ mod tests {
         let mut in_dir = command.project_root.clone();
         in_dir.push("subdir");
         assert_eq!(
-            command.operating_on(&files, &in_dir)?,
+            command.operating_on(ActualInvoke::PerDir, &files, &in_dir)?,
             vec![PathBuf::from("."), PathBuf::from("more")],
         );
 
         Ok(())
     }
 
+    #[test]
+    #[parallel]
+    fn operating_on_with_only_dirty_dirs() -> Result<()> {
+        let mut command = LintOrTidyCommand {
+            path_args: PathArgs::Dir,
+            ..default_command()?
+        };
+        command.set_only_dirty_dirs();
+        let files = [Path::new("subdir/file1"), Path::new("subdir/file2")];
+        let mut in_dir = command.project_root.clone();
+        in_dir.push("subdir");
+        assert_eq!(
+            command.operating_on(ActualInvoke::PerDir, &files, &in_dir)?,
+            vec![PathBuf::from("file1"), PathBuf::from("file2")],
+            "per-dir invocation is passed the files instead of the dir",
+        );
+        assert_eq!(
+            command.operating_on(ActualInvoke::Once, &files, &command.project_root)?,
+            vec![PathBuf::from("subdir")],
+            "invoking once still passes the dir",
+        );
+
+        let mut command = LintOrTidyCommand {
+            path_args: PathArgs::Dot,
+            ..default_command()?
+        };
+        command.set_only_dirty_dirs();
+        assert_eq!(
+            command.operating_on(ActualInvoke::PerDir, &files, &in_dir)?,
+            vec![PathBuf::from(".")],
+            "path-args = dot is not affected",
+        );
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn operating_on_with_path_args_absolute_file() -> Result<()> {
@@ -1454,14 +4121,22 @@ mod tests {
         let mut file1 = cwd.clone();
         file1.push("file1");
         assert_eq!(
-            command.operating_on(&[Path::new("file1")], &command.project_root)?,
+            command.operating_on(
+                ActualInvoke::PerFile,
+                &[Path::new("file1")],
+                &command.project_root
+            )?,
             vec![file1],
         );
 
         let mut file1 = cwd;
         file1.push("subdir/file2");
         assert_eq!(
-            command.operating_on(&[Path::new("subdir/file2")], &command.project_root)?,
+            command.operating_on(
+                ActualInvoke::PerFile,
+                &[Path::new("subdir/file2")],
+                &command.project_root
+            )?,
             vec![file1],
         );
 
@@ -1484,14 +4159,14 @@ mod tests {
         let mut file1 = cwd.clone();
         file1.push("file1");
         assert_eq!(
-            command.operating_on(&[Path::new("file1")], &in_dir)?,
+            command.operating_on(ActualInvoke::PerFile, &[Path::new("file1")], &in_dir)?,
             vec![file1],
         );
 
         let mut file1 = cwd;
         file1.push("subdir/file2");
         assert_eq!(
-            command.operating_on(&[Path::new("subdir/file2")], &in_dir)?,
+            command.operating_on(ActualInvoke::PerFile, &[Path::new("subdir/file2")], &in_dir)?,
             vec![file1],
         );
 
@@ -1508,14 +4183,22 @@ mod tests {
             ..default_command()?
         };
         assert_eq!(
-            command.operating_on(&[Path::new("file1")], &command.project_root)?,
+            command.operating_on(
+                ActualInvoke::PerDir,
+                &[Path::new("file1")],
+                &command.project_root
+            )?,
             vec![cwd.clone()],
         );
 
         let mut subdir = cwd;
         subdir.push("subdir");
         assert_eq!(
-            command.operating_on(&[Path::new("subdir/file2")], &command.project_root)?,
+            command.operating_on(
+                ActualInvoke::PerDir,
+                &[Path::new("subdir/file2")],
+                &command.project_root
+            )?,
             vec![subdir],
         );
 
@@ -1535,14 +4218,14 @@ mod tests {
         in_dir.push("subdir");
 
         assert_eq!(
-            command.operating_on(&[Path::new("file1")], &in_dir)?,
+            command.operating_on(ActualInvoke::PerDir, &[Path::new("file1")], &in_dir)?,
             vec![cwd.clone()],
         );
 
         let mut subdir = cwd;
         subdir.push("subdir");
         assert_eq!(
-            command.operating_on(&[Path::new("subdir/file2")], &in_dir)?,
+            command.operating_on(ActualInvoke::PerDir, &[Path::new("subdir/file2")], &in_dir)?,
             vec![subdir],
         );
 
@@ -1558,7 +4241,7 @@ mod tests {
         };
         let files = [Path::new("file1"), Path::new("subdir/file2")];
         assert_eq!(
-            command.operating_on(&files, &command.project_root)?,
+            command.operating_on(ActualInvoke::PerDir, &files, &command.project_root)?,
             vec![PathBuf::from(".")],
         );
 
@@ -1577,7 +4260,56 @@ mod tests {
 
         let files = [Path::new("file1"), Path::new("subdir/file2")];
         assert_eq!(
-            command.operating_on(&files, &in_dir)?,
+            command.operating_on(ActualInvoke::PerDir, &files, &in_dir)?,
+            vec![PathBuf::from(".")],
+        );
+
+        Ok(())
+    }
+
+    #[test_case(&["black"], &["autopep8"], Some(("black", "autopep8")) ; "both known")]
+    #[test_case(&["poetry", "run", "yapf"], &["/usr/bin/black", "--quiet"], Some(("yapf", "black")) ; "run through a wrapper")]
+    #[test_case(&["python3", "-m", "black"], &["npx", "autopep8"], Some(("black", "autopep8")) ; "run through wrappers with and without args")]
+    #[test_case(&["sh", "-c", "black"], &["autopep8"], None ; "unknown wrapper")]
+    #[test_case(&["poetry", "install", "black"], &["autopep8"], None ; "wrapper with other args")]
+    #[test_case(&["ruff", "format", "black"], &["autopep8"], None ; "tool name is a later argument")]
+    #[test_case(&["black"], &["black"], None ; "same tool")]
+    #[test_case(&["black"], &["isort"], None ; "unknown tool")]
+    #[parallel]
+    fn known_conflict_with(
+        cmd: &[&str],
+        other_cmd: &[&str],
+        expect: Option<(&str, &str)>,
+    ) -> Result<()> {
+        let command = LintOrTidyCommand {
+            cmd: cmd.iter().map(ToString::to_string).collect(),
+            ..default_command()?
+        };
+        let other = LintOrTidyCommand {
+            cmd: other_cmd.iter().map(ToString::to_string).collect(),
+            ..default_command()?
+        };
+        assert_eq!(command.known_conflict_with(&other), expect);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn operating_on_with_path_args_dir_list() -> Result<()> {
+        let command = LintOrTidyCommand {
+            path_args: PathArgs::DirList,
+            ..default_command()?
+        };
+        let dir = [Path::new("modules/network")];
+        assert_eq!(
+            command.operating_on(ActualInvoke::PerDir, &dir, &command.project_root)?,
+            vec![PathBuf::from("modules/network")],
+        );
+
+        let in_dir = command.project_root.join("modules/network");
+        assert_eq!(
+            command.operating_on(ActualInvoke::PerDir, &dir, &in_dir)?,
             vec![PathBuf::from(".")],
         );
 
@@ -1593,7 +4325,10 @@ mod tests {
         };
         let files = [Path::new("file1"), Path::new("subdir/file2")];
         let expect: Vec<PathBuf> = vec![];
-        assert_eq!(command.operating_on(&files, &command.project_root)?, expect);
+        assert_eq!(
+            command.operating_on(ActualInvoke::PerDir, &files, &command.project_root)?,
+            expect
+        );
 
         Ok(())
     }
@@ -1610,7 +4345,10 @@ mod tests {
 
         let files = [Path::new("file1"), Path::new("subdir/file2")];
         let expect: Vec<PathBuf> = vec![];
-        assert_eq!(command.operating_on(&files, &in_dir)?, expect);
+        assert_eq!(
+            command.operating_on(ActualInvoke::PerDir, &files, &in_dir)?,
+            expect
+        );
 
         Ok(())
     }
@@ -1734,99 +4472,789 @@ mod tests {
                 ]
                 .iter()
                 .map(|s| s.to_string())
-                .collect::<Vec<_>>(),
-                2
-            ),
-            "with path flags",
+                .collect::<Vec<_>>(),
+                2
+            ),
+            "with path flags",
+        );
+
+        Ok(())
+    }
+
+    #[test_case(None, "src/a\\b/c.rs" ; "unset")]
+    #[test_case(Some(PathStyle::Windows), "src\\a\\b\\c.rs" ; "windows")]
+    #[cfg_attr(
+        not(windows),
+        test_case(Some(PathStyle::Unix), "src/a\\b/c.rs" ; "unix")
+    )]
+    #[cfg_attr(
+        not(windows),
+        test_case(Some(PathStyle::Native), "src/a\\b/c.rs" ; "native")
+    )]
+    #[cfg_attr(windows, test_case(Some(PathStyle::Unix), "src/a/b/c.rs" ; "unix"))]
+    #[cfg_attr(
+        windows,
+        test_case(Some(PathStyle::Native), "src\\a\\b\\c.rs" ; "native")
+    )]
+    #[parallel]
+    fn command_for_paths_with_path_style(
+        path_style: Option<PathStyle>,
+        expect: &str,
+    ) -> Result<()> {
+        let command = LintOrTidyCommand {
+            cmd: vec![String::from("test")],
+            path_style,
+            ..default_command()?
+        };
+        assert_eq!(
+            command.command_for_paths(None, &[PathBuf::from("src/a\\b/c.rs")]),
+            (vec![String::from("test"), String::from(expect)], 1),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn command_for_paths_or_file() -> Result<()> {
+        let command = LintOrTidyCommand {
+            cmd: vec![String::from("test")],
+            paths_file: true,
+            paths_file_flag: Some(vec![String::from("--paths-from"), String::from("{file}")]),
+            ..default_command()?
+        };
+        let paths = vec![PathBuf::from("app.go"), PathBuf::from("main.go")];
+        let flags = vec![String::from("--flag")];
+
+        let (cmd, idx, file) = command.command_for_paths_or_file(&flags, &paths)?;
+        let file = file.expect("paths-file creates a temp file");
+        let file_path = file.path().to_string_lossy().to_string();
+        assert_eq!(
+            cmd,
+            vec![
+                String::from("test"),
+                String::from("--flag"),
+                String::from("--paths-from"),
+                file_path.clone(),
+            ],
+        );
+        assert_eq!(idx, 2);
+        assert_eq!(fs::read_to_string(file.path())?, "app.go\nmain.go\n");
+        assert_eq!(
+            command
+                .env_for(
+                    Some(&file),
+                    Path::new("/tmp"),
+                    Path::new("/tmp"),
+                    ActualInvoke::Once,
+                    2,
+                )
+                .get("PRECIOUS_PATHS_FILE")
+                .map(String::as_str),
+            Some(file_path.as_str()),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn command_for_paths_or_file_with_unusual_paths() -> Result<()> {
+        let command = LintOrTidyCommand {
+            cmd: vec![String::from("test")],
+            paths_file: true,
+            ..default_command()?
+        };
+        let newline = vec![PathBuf::from("app.go"), PathBuf::from("new\nline.go")];
+        let err = command
+            .command_for_paths_or_file(&[], &newline)
+            .unwrap_err()
+            .downcast::<CommandError>()?;
+        assert!(
+            matches!(err, CommandError::PathsFilePathHasNewline { .. }),
+            "{err}"
+        );
+
+        let command = LintOrTidyCommand {
+            paths_file_null_separated: true,
+            ..command
+        };
+        let (_, _, file) = command.command_for_paths_or_file(&[], &newline)?;
+        assert_eq!(
+            fs::read(file.unwrap().path())?,
+            b"app.go\0new\nline.go\0".to_vec(),
+        );
+
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            let paths = vec![PathBuf::from(OsStr::from_bytes(b"not-utf8-\xff.go"))];
+            let (_, _, file) = command.command_for_paths_or_file(&[], &paths)?;
+            assert_eq!(
+                fs::read(file.unwrap().path())?,
+                b"not-utf8-\xff.go\0".to_vec(),
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn paths_were_not_changed_when_only_mtime_changes() -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerFile,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            excluder: MatcherBuilder::new("/")
+                .with(&["**/can_ignore.rs"])?
+                .build()?,
+            ..default_command()?
+        };
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let mut file = helper.git_root();
+        file.push("src/main.rs");
+        let files = vec![file.as_ref()];
+
+        let prev = command.maybe_path_metadata_for(ActualInvoke::PerFile, &files)?;
+        assert!(prev.is_some());
+        assert!(!command.paths_were_changed(prev.clone().unwrap())?);
+
+        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(0, 0))?;
+        assert!(!command.paths_were_changed(prev.unwrap())?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn hash_cache_only_rereads_files_with_a_new_stamp() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let file = helper.git_root().join("src/main.rs");
+        let stamp = FileStamp::new(&fs::metadata(&file)?)?;
+
+        let cache = HashCache::default();
+        let hash = cache.hash(&file, stamp, HashAlgorithm::Md5)?;
+
+        // The content is different but the stamp we pass is the same, so the
+        // cached hash is used.
+        let new_content = fs::read_to_string(&file)?.chars().rev().collect::<String>();
+        helper.write_file(&file, &new_content)?;
+        assert_eq!(cache.hash(&file, stamp, HashAlgorithm::Md5)?, hash);
+
+        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(0, 0))?;
+        let new_stamp = FileStamp::new(&fs::metadata(&file)?)?;
+        let new_hash = cache.hash(&file, new_stamp, HashAlgorithm::Md5)?;
+        assert_ne!(new_hash, hash);
+        assert_eq!(new_hash, HashAlgorithm::Md5.hash(new_content.as_bytes()));
+
+        Ok(())
+    }
+
+    // A command can rewrite a file without changing its size and then set the
+    // mtime back to what it was. The ctime still changes, so the file is
+    // hashed again instead of using the hash from before the rewrite.
+    #[test]
+    #[parallel]
+    #[cfg(unix)]
+    fn paths_were_changed_when_mtime_is_restored() -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerFile,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            ..default_command()?
+        };
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let file = helper.git_root().join("src/main.rs");
+        let files = vec![file.as_ref()];
+
+        let prev = command
+            .maybe_path_metadata_for(ActualInvoke::PerFile, &files)?
+            .unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(&file)?);
+
+        // The ctime only has to move past the old one, which on some
+        // filesystems is only updated every few milliseconds.
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let new_content = fs::read_to_string(&file)?.chars().rev().collect::<String>();
+        helper.write_file(&file, &new_content)?;
+        filetime::set_file_mtime(&file, mtime)?;
+
+        assert!(command.paths_were_changed(prev)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn paths_were_changed_when_size_changes() -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerFile,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            excluder: MatcherBuilder::new("/")
+                .with(&["**/can_ignore.rs"])?
+                .build()?,
+            ..default_command()?
+        };
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let mut file = helper.git_root();
+        file.push("src/main.rs");
+        let files = vec![file.as_ref()];
+
+        let prev = command.maybe_path_metadata_for(ActualInvoke::PerFile, &files)?;
+        assert!(prev.is_some());
+        assert!(!command.paths_were_changed(prev.clone().unwrap())?);
+
+        helper.write_file(&file, "new content that is longer than the old content")?;
+        assert!(command.paths_were_changed(prev.unwrap())?);
+
+        Ok(())
+    }
+
+    #[test_case(HashAlgorithm::Md5 ; "md5")]
+    #[test_case(HashAlgorithm::Xxh3 ; "xxh3")]
+    #[test_case(HashAlgorithm::Blake3 ; "blake3")]
+    #[parallel]
+    fn paths_were_changed_when_content_changes(hash_algorithm: HashAlgorithm) -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerFile,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            excluder: MatcherBuilder::new("/")
+                .with(&["**/can_ignore.rs"])?
+                .build()?,
+            hash_algorithm,
+            ..default_command()?
+        };
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let mut file = helper.git_root();
+        file.push("src/main.rs");
+        let files = vec![file.as_ref()];
+
+        let prev = command.maybe_path_metadata_for(ActualInvoke::PerFile, &files)?;
+        assert!(prev.is_some());
+        assert!(!command.paths_were_changed(prev.clone().unwrap())?);
+
+        // This needs to be the same size as the old content.
+        let new_content = fs::read_to_string(&file)?.chars().rev().collect::<String>();
+        helper.write_file(&file, &new_content)?;
+
+        assert!(command.paths_were_changed(prev.unwrap())?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn paths_were_changed_when_dir_has_new_file() -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerDir,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            excluder: MatcherBuilder::new("/")
+                .with(&["**/can_ignore.rs"])?
+                .build()?,
+            ..default_command()?
+        };
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let mut files = vec![];
+        for path in helper.all_files() {
+            if path.starts_with("src/")
+                && path.to_str().unwrap().ends_with(".rs")
+                && path.ancestors().count() == 3
+            {
+                let mut file = helper.git_root();
+                file.push(path);
+                files.push(file);
+            }
+        }
+
+        let prev = command.maybe_path_metadata_for(
+            ActualInvoke::PerDir,
+            &files.iter().map(|f| f.as_ref()).collect::<Vec<_>>(),
+        )?;
+        assert!(prev.is_some());
+        let prev = prev.unwrap();
+        assert_eq!(
+            prev.path_map.len(),
+            3,
+            "excluded files are not in the path map",
+        );
+        assert!(!command.paths_were_changed(prev.clone())?);
+
+        let mut file = helper.git_root();
+        file.push("src/new.rs");
+        fs::write(&file, "a new file")?;
+        assert!(command.paths_were_changed(prev)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn paths_were_changed_ignores_scratch_files() -> Result<()> {
+        let command = LintOrTidyCommand {
+            invoke: Invoke::PerDir,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            scratch_files: MatcherBuilder::new("/").with(&["**/*.tmp.rs"])?.build()?,
+            ..default_command()?
+        };
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let file = helper.git_root().join("src/main.rs");
+
+        let prev = command.maybe_path_metadata_for(ActualInvoke::PerDir, &[file.as_ref()])?;
+        assert!(prev.is_some());
+        let prev = prev.unwrap();
+        assert!(!command.paths_were_changed(prev.clone())?);
+
+        fs::write(helper.git_root().join("src/main.tmp.rs"), "scratch")?;
+        assert!(
+            !command.paths_were_changed(prev.clone())?,
+            "a new scratch file is not a change",
+        );
+
+        fs::write(helper.git_root().join("src/new.rs"), "a new file")?;
+        assert!(command.paths_were_changed(prev)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn scratch_dir_is_removed_after_running() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let command = LintOrTidyCommand {
+            project_root: helper.precious_root(),
+            typ: LintOrTidyCommandType::Tidy,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            cmd: [
+                "sh",
+                "-c",
+                r#"touch "$PRECIOUS_TMPDIR/x" && echo "$PRECIOUS_TMPDIR" > "$0""#,
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            ok_exit_codes: vec![0],
+            ..default_command()?
+        };
+
+        command.tidy(ActualInvoke::PerFile, &[Path::new("src/main.rs")])?;
+        let scratch_dir = helper.read_file(Path::new("src/main.rs"))?;
+        let scratch_dir = Path::new(scratch_dir.trim());
+        assert!(
+            scratch_dir
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("precious-scratch-"),
+            "PRECIOUS_TMPDIR was set to a scratch dir",
+        );
+        assert!(!scratch_dir.exists(), "the scratch dir was removed");
+
+        Ok(())
+    }
+
+    #[test_case(true, vec![PathBuf::from("src/new.rs")], TidyOutcome::Changed ; "reported")]
+    #[test_case(false, vec![], TidyOutcome::Unchanged ; "not reported")]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn tidy_reports_new_files(
+        report_new_files: bool,
+        expect_new_files: Vec<PathBuf>,
+        expect_outcome: TidyOutcome,
+    ) -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        helper.add_gitignore_files()?;
+        let command = LintOrTidyCommand {
+            project_root: helper.precious_root(),
+            typ: LintOrTidyCommandType::Tidy,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            cmd: ["sh", "-c", "touch src/new.rs src/can_ignore.rs"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            ok_exit_codes: vec![0],
+            report_new_files,
+            ..default_command()?
+        };
+
+        let result = command
+            .tidy(ActualInvoke::PerFile, &[Path::new("src/main.rs")])?
+            .unwrap_or_else(|| unreachable!("The command always runs"));
+        assert_eq!(result.new_files, expect_new_files);
+        assert_eq!(result.outcome, expect_outcome);
+
+        Ok(())
+    }
+
+    #[test_case("tr a-z A-Z", TidyOutcome::Changed, false ; "prints a patch")]
+    #[test_case("cat", TidyOutcome::Unchanged, true ; "prints nothing")]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn stdin_from_file(
+        filter: &str,
+        expect_outcome: TidyOutcome,
+        expect_lint_ok: bool,
+    ) -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let file = Path::new("src/main.rs");
+        let original = helper.read_file(file)?;
+        // This reads the file from stdin and prints a diff between it and
+        // the filtered version, just like a tool that prints a patch would.
+        let script = format!(
+            r#"cat > "$PRECIOUS_TMPDIR/in"; {filter} < "$PRECIOUS_TMPDIR/in" > "$PRECIOUS_TMPDIR/out"; diff -u "$PRECIOUS_TMPDIR/in" "$PRECIOUS_TMPDIR/out""#,
+        );
+        let command = LintOrTidyCommand {
+            project_root: helper.precious_root(),
+            typ: LintOrTidyCommandType::Both,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            cmd: vec![String::from("sh"), String::from("-c"), script],
+            path_args: PathArgs::None,
+            stdin_from: Some(StdinFrom::File),
+            ok_exit_codes: vec![0, 1],
+            ..default_command()?
+        };
+
+        let lint = command
+            .lint(ActualInvoke::PerFile, &[file])?
+            .unwrap_or_else(|| unreachable!("The command always runs"));
+        assert_eq!(lint.ok, expect_lint_ok, "printing a patch fails the lint");
+        assert_eq!(
+            helper.read_file(file)?,
+            original,
+            "linting doesn't change the file"
+        );
+
+        let tidy = command
+            .tidy(ActualInvoke::PerFile, &[file])?
+            .unwrap_or_else(|| unreachable!("The command always runs"));
+        assert_eq!(tidy.outcome, expect_outcome);
+        let expect = if expect_outcome == TidyOutcome::Changed {
+            original.to_uppercase()
+        } else {
+            original
+        };
+        assert_eq!(helper.read_file(file)?, expect, "the patch was applied");
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn stdin_from_file_with_bad_patch() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let command = LintOrTidyCommand {
+            project_root: helper.precious_root(),
+            typ: LintOrTidyCommandType::Tidy,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            cmd: ["sh", "-c", r"printf '@@ -1 +1 @@\n-nope\n+yes\n'"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            path_args: PathArgs::None,
+            stdin_from: Some(StdinFrom::File),
+            ok_exit_codes: vec![0],
+            ..default_command()?
+        };
+
+        let err = command
+            .tidy(ActualInvoke::PerFile, &[Path::new("src/main.rs")])
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<CommandError>(),
+                Some(CommandError::InvalidPatch { .. }),
+            ),
+            "got an InvalidPatch error: {err}",
+        );
+
+        Ok(())
+    }
+
+    #[test_case(Invoke::PerFile, "src" ; "per file")]
+    #[test_case(Invoke::Once, "" ; "once")]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn precious_dir_placeholder(invoke: Invoke, dir: &str) -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let root = helper.precious_root();
+        let command = LintOrTidyCommand {
+            project_root: root.clone(),
+            invoke,
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            cmd: ["sh", "-c", r#"echo "$@" "$FOO""#, "sh", "$PRECIOUS_DIR"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            lint_flags: Some(vec![String::from("--config=$PRECIOUS_DIR/.tool.json")]),
+            env: HashMap::from([(String::from("FOO"), String::from("$PRECIOUS_ROOT/foo"))]),
+            ok_exit_codes: vec![0],
+            ..default_command()?
+        };
+
+        let actual_invoke = match invoke {
+            Invoke::Once => ActualInvoke::Once,
+            _ => ActualInvoke::PerFile,
+        };
+        let outcome = command
+            .lint(actual_invoke, &[Path::new("src/main.rs")])?
+            .unwrap();
+        let dir = if dir.is_empty() {
+            root.clone()
+        } else {
+            root.join(dir)
+        };
+        assert_eq!(
+            outcome.stdout,
+            Some(format!(
+                "{} --config={}/.tool.json src/main.rs {}/foo\n",
+                dir.display(),
+                dir.display(),
+                root.display(),
+            )),
         );
 
         Ok(())
     }
 
+    #[test_case("0123456789", 10, "0123456789" ; "fits")]
+    #[test_case("0123456789", 4, "01\n[... 6 bytes elided ...]\n89" ; "even")]
+    #[test_case("0123456789", 5, "01\n[... 5 bytes elided ...]\n789" ; "odd")]
+    #[test_case("aé…b", 4, "a\n[... 5 bytes elided ...]\nb" ; "multi-byte chars")]
+    #[parallel]
+    fn truncate_output(output: &str, max: u64, expect: &str) -> Result<()> {
+        let command = default_command()?;
+        assert_eq!(
+            command.truncate_output(output.to_string(), "stdout", max)?,
+            expect,
+        );
+        Ok(())
+    }
+
     #[test]
     #[parallel]
-    fn paths_were_not_changed_when_only_mtime_changes() -> Result<()> {
+    #[cfg(not(target_os = "windows"))]
+    fn max_output_bytes() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
         let command = LintOrTidyCommand {
-            invoke: Invoke::PerFile,
+            project_root: helper.precious_root(),
+            name: String::from("long output"),
             includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
-            excluder: MatcherBuilder::new("/")
-                .with(&["**/can_ignore.rs"])?
-                .build()?,
+            cmd: ["sh", "-c", "echo start; echo FAILURE; echo end", "sh"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            ok_exit_codes: vec![0],
+            lint_failure_output: Some(Regex::new("FAILURE")?),
+            max_output_bytes: Some(8),
+            save_full_output: true,
             ..default_command()?
         };
-        let helper = TestHelper::new()?.with_git_repo()?;
-        let mut file = helper.git_root();
-        file.push("src/main.rs");
-        let files = vec![file.as_ref()];
 
-        let prev = command.maybe_path_metadata_for(ActualInvoke::PerFile, &files)?;
-        assert!(prev.is_some());
-        assert!(!command.paths_were_changed(prev.clone().unwrap())?);
+        let outcome = command
+            .lint(ActualInvoke::PerFile, &[Path::new("src/main.rs")])?
+            .unwrap();
+        assert!(
+            !outcome.ok,
+            "the failure in the middle of the output was seen"
+        );
 
-        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(0, 0))?;
-        assert!(!command.paths_were_changed(prev.unwrap())?);
+        let stdout = outcome.stdout.unwrap();
+        let re = Regex::new(
+            r"\Astar\n\[\.\.\. 10 bytes elided, the full stdout is in (.+) \.\.\.\]\nend\n\z",
+        )?;
+        let caps = re.captures(&stdout).expect(&stdout);
+        let file = Path::new(&caps[1]);
+        assert!(
+            file.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("precious-long-output-stdout-"),
+            "{}",
+            file.display(),
+        );
+        assert_eq!(fs::read_to_string(file)?, "start\nFAILURE\nend\n");
+        fs::remove_file(file)?;
 
         Ok(())
     }
 
     #[test]
     #[parallel]
-    fn paths_were_changed_when_size_changes() -> Result<()> {
+    #[cfg(not(target_os = "windows"))]
+    fn max_output_bytes_while_streaming() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
         let command = LintOrTidyCommand {
-            invoke: Invoke::PerFile,
+            project_root: helper.precious_root(),
+            name: String::from("streaming output"),
             includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
-            excluder: MatcherBuilder::new("/")
-                .with(&["**/can_ignore.rs"])?
-                .build()?,
+            cmd: ["sh", "-c", "seq 1 1000; exit 1", "sh"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            ok_exit_codes: vec![0, 1],
+            lint_failure_exit_codes: HashSet::from([1]),
+            ignore_stdout: vec![Regex::new("^999$")?],
+            max_output_bytes: Some(16),
+            save_full_output: true,
             ..default_command()?
         };
-        let helper = TestHelper::new()?.with_git_repo()?;
-        let mut file = helper.git_root();
-        file.push("src/main.rs");
-        let files = vec![file.as_ref()];
+        assert!(command.streams_stdout());
+
+        let outcome = command
+            .lint(ActualInvoke::PerFile, &[Path::new("src/main.rs")])?
+            .unwrap();
+        assert!(!outcome.ok);
+
+        let full = (1..=1000)
+            .filter(|n| *n != 999)
+            .map(|n| format!("{n}\n"))
+            .collect::<String>();
+        let stdout = outcome.stdout.unwrap();
+        let re = Regex::new(&format!(
+            r"\A{}\n\[\.\.\. {} bytes elided, the full stdout is in (.+) \.\.\.\]\n{}\z",
+            regex::escape(&full[..8]),
+            full.len() - 16,
+            regex::escape(&full[full.len() - 8..]),
+        ))?;
+        let caps = re.captures(&stdout).expect(&stdout);
+        let file = Path::new(&caps[1]);
+        assert_eq!(fs::read_to_string(file)?, full);
+        fs::remove_file(file)?;
 
-        let prev = command.maybe_path_metadata_for(ActualInvoke::PerFile, &files)?;
-        assert!(prev.is_some());
-        assert!(!command.paths_were_changed(prev.clone().unwrap())?);
+        Ok(())
+    }
 
-        helper.write_file(&file, "new content that is longer than the old content")?;
-        assert!(command.paths_were_changed(prev.unwrap())?);
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn max_output_bytes_with_changed_lines() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let mut command = LintOrTidyCommand {
+            project_root: helper.precious_root(),
+            name: String::from("many diagnostics"),
+            includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+            cmd: [
+                "sh",
+                "-c",
+                r#"for n in $(seq 1 50); do echo "$1:$n: problem"; done; exit 1"#,
+                "sh",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            ok_exit_codes: vec![0, 1],
+            lint_failure_exit_codes: HashSet::from([1]),
+            diagnostic_regex: Some(Regex::new(r"^(?P<path>[^:]+):(?P<line>\d+):")?),
+            max_output_bytes: Some(64),
+            ..default_command()?
+        };
+        // The only diagnostic on a changed line is in the middle of the
+        // output, which is the part that's cut out.
+        command.set_changed_lines(ChangedLines::from_unified_diff(
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -25 +25 @@\n-x\n+y\n",
+        ));
+        assert!(!command.streams_stdout());
+
+        let outcome = command
+            .lint(ActualInvoke::PerFile, &[Path::new("src/main.rs")])?
+            .unwrap();
+        assert!(!outcome.ok, "the diagnostic on a changed line was seen");
+        assert_eq!(outcome.stdout.as_deref(), Some("src/main.rs:25: problem"));
 
         Ok(())
     }
 
     #[test]
     #[parallel]
-    fn paths_were_changed_when_content_changes() -> Result<()> {
+    #[cfg(not(target_os = "windows"))]
+    fn ignore_stdout() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
         let command = LintOrTidyCommand {
-            invoke: Invoke::PerFile,
+            project_root: helper.precious_root(),
+            name: String::from("chatty"),
             includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
-            excluder: MatcherBuilder::new("/")
-                .with(&["**/can_ignore.rs"])?
-                .build()?,
+            cmd: [
+                "sh",
+                "-c",
+                r#"echo "linter v1.2.3"; echo "FAILURE in $1"; echo "Progress: 100%""#,
+                "sh",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            ok_exit_codes: vec![0],
+            ignore_stdout: vec![Regex::new("^linter v")?, Regex::new("^Progress:")?],
+            lint_failure_output: Some(Regex::new("FAILURE|linter|Progress")?),
             ..default_command()?
         };
-        let helper = TestHelper::new()?.with_git_repo()?;
-        let mut file = helper.git_root();
-        file.push("src/main.rs");
-        let files = vec![file.as_ref()];
 
-        let prev = command.maybe_path_metadata_for(ActualInvoke::PerFile, &files)?;
-        assert!(prev.is_some());
-        assert!(!command.paths_were_changed(prev.clone().unwrap())?);
+        let outcome = command
+            .lint(ActualInvoke::PerFile, &[Path::new("src/main.rs")])?
+            .unwrap();
+        assert!(!outcome.ok);
+        assert_eq!(outcome.stdout.as_deref(), Some("FAILURE in src/main.rs\n"));
 
-        // This needs to be the same size as the old content.
-        let new_content = fs::read_to_string(&file)?.chars().rev().collect::<String>();
-        helper.write_file(&file, &new_content)?;
+        let command = LintOrTidyCommand {
+            lint_failure_output: Some(Regex::new("linter|Progress")?),
+            ignore_stdout: vec![Regex::new(".")?],
+            ..command
+        };
+        let outcome = command
+            .lint(ActualInvoke::PerFile, &[Path::new("src/main.rs")])?
+            .unwrap();
+        assert!(
+            outcome.ok,
+            "ignored lines are not seen by lint-failure-output"
+        );
+        assert_eq!(outcome.stdout, None);
 
-        assert!(command.paths_were_changed(prev.unwrap())?);
+        Ok(())
+    }
+
+    #[test_case(ActualInvoke::PerFile ; "per file")]
+    #[test_case(ActualInvoke::Once ; "once")]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn read_only(actual_invoke: ActualInvoke) -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        let command = |script: &str| -> Result<LintOrTidyCommand> {
+            Ok(LintOrTidyCommand {
+                project_root: helper.precious_root(),
+                name: String::from("eslint"),
+                includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
+                cmd: ["sh", "-c", script, "sh"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+                ok_exit_codes: vec![0],
+                read_only: true,
+                ..default_command()?
+            })
+        };
+        let files = [Path::new("src/main.rs")];
+
+        let outcome = command("cat \"$1\" > /dev/null")?
+            .lint(actual_invoke, &files)?
+            .unwrap();
+        assert!(outcome.ok, "a linter that doesn't change files is fine");
+
+        let err = command("echo fixed >> \"$1\"")?
+            .lint(actual_invoke, &files)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<CommandError>()?,
+            CommandError::ReadOnlyCommandChangedFiles {
+                command: String::from("eslint"),
+                paths: String::from("src/main.rs"),
+            },
+        );
 
         Ok(())
     }
 
     #[test]
     #[parallel]
-    fn paths_were_changed_when_dir_has_new_file() -> Result<()> {
+    fn paths_were_changed_when_dir_has_file_deleted() -> Result<()> {
         let command = LintOrTidyCommand {
             invoke: Invoke::PerDir,
             includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
@@ -1861,9 +5289,7 @@ mod tests {
         );
         assert!(!command.paths_were_changed(prev.clone())?);
 
-        let mut file = helper.git_root();
-        file.push("src/new.rs");
-        fs::write(&file, "a new file")?;
+        fs::remove_file(files.pop().unwrap())?;
         assert!(command.paths_were_changed(prev)?);
 
         Ok(())
@@ -1871,42 +5297,33 @@ mod tests {
 
     #[test]
     #[parallel]
-    fn paths_were_changed_when_dir_has_file_deleted() -> Result<()> {
+    fn paths_were_changed_when_invoked_once() -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
         let command = LintOrTidyCommand {
-            invoke: Invoke::PerDir,
+            project_root: helper.precious_root(),
+            invoke: Invoke::Once,
             includer: MatcherBuilder::new("/").with(&["**/*.rs"])?.build()?,
-            excluder: MatcherBuilder::new("/")
-                .with(&["**/can_ignore.rs"])?
-                .build()?,
             ..default_command()?
         };
-        let helper = TestHelper::new()?.with_git_repo()?;
-        let mut files = vec![];
-        for path in helper.all_files() {
-            if path.starts_with("src/")
-                && path.to_str().unwrap().ends_with(".rs")
-                && path.ancestors().count() == 3
-            {
-                let mut file = helper.git_root();
-                file.push(path);
-                files.push(file);
-            }
-        }
+        let files = [Path::new("src/main.rs"), Path::new("src/bar.rs")];
+        assert!(
+            command
+                .maybe_path_metadata_for(ActualInvoke::Once, &files)?
+                .is_none(),
+            "no metadata is collected without track-changes",
+        );
 
-        let prev = command.maybe_path_metadata_for(
-            ActualInvoke::PerDir,
-            &files.iter().map(|f| f.as_ref()).collect::<Vec<_>>(),
-        )?;
+        let command = LintOrTidyCommand {
+            track_changes: true,
+            ..command
+        };
+        let prev = command.maybe_path_metadata_for(ActualInvoke::Once, &files)?;
         assert!(prev.is_some());
         let prev = prev.unwrap();
-        assert_eq!(
-            prev.path_map.len(),
-            3,
-            "excluded files are not in the path map",
-        );
+        assert_eq!(prev.path_map.len(), 2, "only the given files are checked");
         assert!(!command.paths_were_changed(prev.clone())?);
 
-        fs::remove_file(files.pop().unwrap())?;
+        helper.write_file("src/bar.rs", "fn bar() {}\n")?;
         assert!(command.paths_were_changed(prev)?);
 
         Ok(())
@@ -2013,4 +5430,259 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    #[parallel]
+    fn missing_executable() -> Result<()> {
+        let command = LintOrTidyCommand {
+            cmd: vec![String::from(
+                "I hope this binary does not exist on any system!",
+            )],
+            ..default_command()?
+        };
+        assert_eq!(
+            command.missing_executable(),
+            None,
+            "no install-cmd means nothing is reported as missing",
+        );
+
+        let command = LintOrTidyCommand {
+            install_cmd: Some(vec![String::from("true")]),
+            ..command
+        };
+        assert_eq!(
+            command.missing_executable(),
+            Some("I hope this binary does not exist on any system!"),
+        );
+
+        let command = LintOrTidyCommand {
+            cmd: vec![String::from("git")],
+            ..command
+        };
+        assert_eq!(command.missing_executable(), None, "git is in the path");
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn install_missing_executable_without_install_missing() -> Result<()> {
+        let command = LintOrTidyCommand {
+            name: String::from("Test"),
+            cmd: vec![String::from(
+                "I hope this binary does not exist on any system!",
+            )],
+            install_cmd: Some(vec![String::from("true")]),
+            ..default_command()?
+        };
+        assert_eq!(
+            command
+                .install_missing_executable(false)
+                .unwrap_err()
+                .downcast::<CommandError>()
+                .unwrap(),
+            CommandError::ExecutableNotInstalled {
+                exe: String::from("I hope this binary does not exist on any system!"),
+                command: String::from("Test"),
+                install_cmd: String::from("true"),
+            },
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn install_missing_executable_when_install_does_not_help() -> Result<()> {
+        let command = LintOrTidyCommand {
+            project_root: env::current_dir()?,
+            name: String::from("Test"),
+            cmd: vec![String::from(
+                "I hope this binary does not exist on any system!",
+            )],
+            install_cmd: Some(vec![String::from("true")]),
+            ..default_command()?
+        };
+        assert_eq!(
+            command
+                .install_missing_executable(true)
+                .unwrap_err()
+                .downcast::<CommandError>()
+                .unwrap(),
+            CommandError::ExecutableStillMissingAfterInstall {
+                exe: String::from("I hope this binary does not exist on any system!"),
+                command: String::from("Test"),
+                install_cmd: String::from("true"),
+            },
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn command_for_runner() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let cwd = env::current_dir()?;
+        let meta = fs::metadata(&cwd)?;
+        let mut env = HashMap::new();
+        env.insert(String::from("FOO"), String::from("bar"));
+        let command = LintOrTidyCommand {
+            project_root: cwd.clone(),
+            runner: Some(Runner::Docker {
+                image: String::from("some/image:v1"),
+            }),
+            env,
+            ..default_command()?
+        };
+
+        let mut in_dir = cwd.clone();
+        in_dir.push("subdir");
+        let scratch_dir = Path::new("/tmp/precious-scratch-1234");
+        let (cmd, idx) = command.command_for_runner(
+            (
+                vec![
+                    String::from("test"),
+                    String::from("--flag"),
+                    String::from("app.go"),
+                ],
+                2,
+            ),
+            &in_dir,
+            Some(scratch_dir),
+        )?;
+        let root = cwd.to_string_lossy();
+        assert_eq!(
+            cmd,
+            vec![
+                String::from("docker"),
+                String::from("run"),
+                String::from("--rm"),
+                String::from("--mount"),
+                format!("type=bind,src={root},dst={root}"),
+                String::from("--workdir"),
+                in_dir.to_string_lossy().to_string(),
+                String::from("--mount"),
+                String::from(
+                    "type=bind,src=/tmp/precious-scratch-1234,dst=/tmp/precious-scratch-1234"
+                ),
+                String::from("--user"),
+                format!("{}:{}", meta.uid(), meta.gid()),
+                String::from("--env"),
+                String::from("FOO"),
+                String::from("--env"),
+                String::from("PRECIOUS_COMMAND_NAME"),
+                String::from("--env"),
+                String::from("PRECIOUS_FILE_COUNT"),
+                String::from("--env"),
+                String::from("PRECIOUS_INVOKE"),
+                String::from("--env"),
+                String::from("PRECIOUS_MODE"),
+                String::from("--env"),
+                String::from("PRECIOUS_TMPDIR"),
+                String::from("some/image:v1"),
+                String::from("test"),
+                String::from("--flag"),
+                String::from("app.go"),
+            ],
+        );
+        assert_eq!(idx, 26, "index before paths accounts for runner args");
+
+        Ok(())
+    }
+
+    #[test_case("/src/project", "type=bind,src=/src/project,dst=/src/project" ; "plain path")]
+    #[test_case("/src/a:b", "type=bind,src=/src/a:b,dst=/src/a:b" ; "path with a colon")]
+    #[test_case("/src/a,b", r#"type=bind,"src=/src/a,b","dst=/src/a,b""# ; "path with a comma")]
+    #[test_case("/src/a\"b", r#"type=bind,"src=/src/a""b","dst=/src/a""b""# ; "path with a quote")]
+    #[parallel]
+    fn bind_mount(path: &str, expect: &str) {
+        assert_eq!(super::bind_mount(Path::new(path)), expect);
+    }
+
+    #[test]
+    #[parallel]
+    fn filter_to_changed_lines() -> Result<()> {
+        let command = LintOrTidyCommand {
+            diagnostic_regex: Some(Regex::new(r"^(?P<path>[^:]+):(?P<line>\d+):")?),
+            ..default_command()?
+        };
+        let changed = ChangedLines::from_unified_diff(concat!(
+            "--- a/src/foo.rs\n+++ b/src/foo.rs\n@@ -1,0 +2,2 @@\n+one\n+two\n",
+            "--- a/src/bar.rs\n+++ b/src/bar.rs\n@@ -9 +9 @@\n-y\n+x\n",
+        ));
+        let foo = Path::new("src/foo.rs");
+        let bar = Path::new("src/bar.rs");
+        let failed = |stdout: &str| LintOutcome {
+            ok: false,
+            stdout: Some(stdout.to_string()),
+            stderr: None,
+            resources: None,
+        };
+
+        let outcome = command.filter_to_changed_lines(
+            failed("src/foo.rs:1: old problem\nsrc/bar.rs:4: old problem\n"),
+            &[foo, bar],
+            &changed,
+        );
+        assert!(outcome.ok, "diagnostics on unchanged lines are ignored");
+
+        let outcome = command.filter_to_changed_lines(
+            failed("src/foo.rs:1: old problem\nfoo.rs:3: new problem\n  more context\n"),
+            &[foo, bar],
+            &changed,
+        );
+        assert!(!outcome.ok, "diagnostics on changed lines fail");
+        assert_eq!(outcome.stdout.as_deref(), Some("foo.rs:3: new problem"));
+
+        let outcome = command.filter_to_changed_lines(
+            failed("something went wrong\n"),
+            &[foo, bar],
+            &changed,
+        );
+        assert!(!outcome.ok, "output without diagnostics is left alone");
+        assert_eq!(outcome.stdout.as_deref(), Some("something went wrong\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn flags_with_line_ranges() -> Result<()> {
+        let mut command = LintOrTidyCommand {
+            line_range_flag: Some(vec![String::from("--lines={start}:{end}")]),
+            ..default_command()?
+        };
+        let flags = vec![String::from("-i")];
+        let foo = Path::new("src/foo.c");
+        let bar = Path::new("src/bar.c");
+
+        assert_eq!(
+            command.flags_with_line_ranges(Some(&flags), &[foo]),
+            Some(flags.clone()),
+            "no ranges are added when changed lines are unknown",
+        );
+
+        command.set_changed_lines(ChangedLines::from_unified_diff(
+            "--- a/src/foo.c\n+++ b/src/foo.c\n@@ -1 +1 @@\n-w\n+x\n@@ -9,0 +10,3 @@\n+y\n+y\n+y\n",
+        ));
+        assert_eq!(
+            command.flags_with_line_ranges(Some(&flags), &[foo]),
+            Some(vec![
+                String::from("-i"),
+                String::from("--lines=1:1"),
+                String::from("--lines=10:12"),
+            ]),
+        );
+        assert_eq!(
+            command.flags_with_line_ranges(Some(&flags), &[bar]),
+            None,
+            "a file without changed lines is skipped",
+        );
+
+        Ok(())
+    }
 }
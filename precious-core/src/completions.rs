@@ -0,0 +1,99 @@
+// Shell completion scripts for precious. Most of the script comes straight
+// from the clap definitions, but the values for `--command` come from the
+// config file of whatever project the shell is in. clap only knows how to
+// complete a fixed list of values, so we give it a placeholder value and then
+// replace that with a call to `precious config list --names` in each shell's
+// syntax.
+use clap::builder::PossibleValuesParser;
+use clap_complete::Shell;
+use std::io::Write;
+
+// These are the subcommands that take a `--command` flag.
+const SUBCOMMANDS_WITH_COMMAND_FLAG: &[&str] = &["lint", "tidy", "diff"];
+
+const PLACEHOLDER: &str = "__PRECIOUS_COMMAND_NAMES__";
+
+const LIST_NAMES: &str = "precious config list --names 2>/dev/null";
+
+pub(crate) fn write(mut app: clap::Command, shell: Shell, output: &mut dyn Write) {
+    // clap's elvish and PowerShell completions don't include the possible
+    // values for a flag, so there's nothing to replace for those.
+    let replacements = match shell {
+        Shell::Bash => Some((
+            format!(r#"compgen -W "{PLACEHOLDER}""#),
+            format!(r#"compgen -W "$({LIST_NAMES})""#),
+        )),
+        Shell::Zsh => Some((
+            format!(":NAME:({PLACEHOLDER})"),
+            format!(":NAME:{{compadd -- $({LIST_NAMES})}}"),
+        )),
+        Shell::Fish => Some((
+            format!(r#"-a "{PLACEHOLDER}\t''""#),
+            format!(r#"-a "({LIST_NAMES})""#),
+        )),
+        _ => None,
+    };
+
+    let Some((placeholder, call)) = replacements else {
+        let name = app.get_name().to_string();
+        clap_complete::generate(shell, &mut app, name, output);
+        return;
+    };
+
+    for sub in SUBCOMMANDS_WITH_COMMAND_FLAG {
+        app = app.mut_subcommand(sub, |s| {
+            s.mut_arg("command", |a| {
+                a.value_parser(PossibleValuesParser::new([PLACEHOLDER]))
+            })
+        });
+    }
+    let name = app.get_name().to_string();
+    let mut script = vec![];
+    clap_complete::generate(shell, &mut app, name, &mut script);
+
+    let script = String::from_utf8_lossy(&script).replace(&placeholder, &call);
+    // If this fails, the output is going somewhere that we can't report the
+    // error to either, which matches what `clap_complete::generate` does.
+    output
+        .write_all(script.as_bytes())
+        .expect("failed to write completion file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::precious::App;
+    use clap::CommandFactory;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case(Shell::Bash ; "bash")]
+    #[test_case(Shell::Zsh ; "zsh")]
+    #[test_case(Shell::Fish ; "fish")]
+    #[parallel]
+    fn write_completes_command_names_at_completion_time(shell: Shell) {
+        let mut output = vec![];
+        write(App::command(), shell, &mut output);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("precious"), "{output}");
+        assert!(
+            output.contains("precious config list --names"),
+            "completions call precious to get the command names"
+        );
+        assert!(!output.contains(PLACEHOLDER), "{output}");
+    }
+
+    // clap's elvish and PowerShell completions don't include the possible
+    // values for a flag, so these don't complete command names at all.
+    #[test_case(Shell::Elvish ; "elvish")]
+    #[test_case(Shell::PowerShell ; "powershell")]
+    #[parallel]
+    fn write_without_command_names(shell: Shell) {
+        let mut output = vec![];
+        write(App::command(), shell, &mut output);
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.is_empty());
+        assert!(!output.contains(PLACEHOLDER), "{output}");
+    }
+}
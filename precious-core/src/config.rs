@@ -1,37 +1,82 @@
-use crate::command::{self, Invoke, LintOrTidyCommandType, PathArgs, WorkingDir};
+use crate::{
+    chars::CharsConfig,
+    command::{
+        self, HashAlgorithm, Invoke, LintOrTidyCommandType, PathArgs, PathStyle, RunWhen, Runner,
+        StdinFrom, WorkingDir,
+    },
+    config_extends::{self, ExtendsConfig},
+    config_schema,
+    jobs::Jobs,
+    reporter::SkipReason,
+};
 use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use indexmap::IndexMap;
+use itertools::Itertools;
 use log::warn;
+use schemars::JsonSchema;
 use serde::{de, de::Deserializer, Deserialize};
 use std::{
     collections::HashMap,
     fmt, fs,
     marker::PhantomData,
+    mem,
     path::{Path, PathBuf},
 };
 use thiserror::Error;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[allow(clippy::module_name_repetitions)]
+#[schemars(rename_all = "kebab-case")]
 pub struct CommandConfig {
     #[serde(rename = "type")]
     pub(crate) typ: LintOrTidyCommandType,
     #[serde(deserialize_with = "string_or_seq_string")]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) include: Vec<String>,
     #[serde(default, deserialize_with = "string_or_seq_string")]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) exclude: Vec<String>,
+    #[serde(
+        default,
+        alias = "dir-include",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) dir_include: Vec<String>,
     #[serde(default)]
     pub(crate) invoke: Option<Invoke>,
     #[serde(default, alias = "working-dir", deserialize_with = "working_dir")]
+    #[schemars(schema_with = "config_schema::working_dir")]
     pub(crate) working_dir: Option<WorkingDir>,
     #[serde(default, alias = "path-args")]
     pub(crate) path_args: Option<PathArgs>,
     #[serde(default, alias = "run-mode")]
+    #[schemars(skip)]
     pub(crate) run_mode: Option<OldRunMode>,
     #[serde(default)]
+    #[schemars(skip)]
     pub(crate) chdir: Option<bool>,
     #[serde(deserialize_with = "string_or_seq_string")]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) cmd: Vec<String>,
+    #[serde(
+        default,
+        alias = "install-cmd",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) install_cmd: Vec<String>,
+    #[serde(
+        default,
+        alias = "daemon-cmd",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) daemon_cmd: Vec<String>,
+    #[serde(default, deserialize_with = "runner")]
+    #[schemars(schema_with = "config_schema::runner")]
+    pub(crate) runner: Option<Runner>,
     #[serde(default)]
     pub(crate) env: HashMap<String, String>,
     #[serde(
@@ -39,33 +84,156 @@ pub struct CommandConfig {
         alias = "lint-flags",
         deserialize_with = "string_or_seq_string"
     )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) lint_flags: Vec<String>,
     #[serde(
         default,
         alias = "tidy-flags",
         deserialize_with = "string_or_seq_string"
     )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) tidy_flags: Vec<String>,
     #[serde(default = "empty_string", alias = "path-flag")]
     pub(crate) path_flag: String,
     #[serde(alias = "ok-exit-codes", deserialize_with = "u8_or_seq_u8")]
+    #[schemars(schema_with = "config_schema::exit_codes")]
     pub(crate) ok_exit_codes: Vec<u8>,
     #[serde(
         default,
         alias = "lint-failure-exit-codes",
         deserialize_with = "u8_or_seq_u8"
     )]
+    #[schemars(schema_with = "config_schema::exit_codes")]
     pub(crate) lint_failure_exit_codes: Vec<u8>,
     #[serde(default, alias = "expect-stderr")]
+    #[schemars(extend("deprecated" = true))]
     pub(crate) expect_stderr: bool,
     #[serde(
         default,
         alias = "ignore-stderr",
         deserialize_with = "string_or_seq_string"
     )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) ignore_stderr: Vec<String>,
+    #[serde(
+        default,
+        alias = "ignore-stdout",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) ignore_stdout: Vec<String>,
+    #[serde(default, alias = "redirect-stderr-to-stdout")]
+    pub(crate) redirect_stderr_to_stdout: bool,
+    #[serde(
+        default,
+        alias = "version-cmd",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) version_cmd: Vec<String>,
+    #[serde(
+        default,
+        alias = "also-trigger-on",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) also_trigger_on: Vec<String>,
+    #[serde(default, alias = "stdin-from")]
+    pub(crate) stdin_from: Option<StdinFrom>,
+    #[serde(default)]
+    pub(crate) toolchain: Option<String>,
+    #[serde(default, alias = "report-new-files")]
+    pub(crate) report_new_files: bool,
+    #[serde(default, alias = "max-memory", deserialize_with = "file_size")]
+    #[schemars(schema_with = "config_schema::file_size")]
+    pub(crate) max_memory: Option<u64>,
+    #[serde(default, alias = "max-cpu-seconds")]
+    pub(crate) max_cpu_seconds: Option<u64>,
+    #[serde(default, alias = "path-style")]
+    pub(crate) path_style: Option<PathStyle>,
+    #[serde(default, alias = "max-output-bytes", deserialize_with = "file_size")]
+    #[schemars(schema_with = "config_schema::file_size")]
+    pub(crate) max_output_bytes: Option<u64>,
+    #[serde(default, alias = "save-full-output")]
+    pub(crate) save_full_output: bool,
+    #[serde(default, alias = "lint-failure-output")]
+    pub(crate) lint_failure_output: Option<String>,
+    #[serde(default, alias = "run-when")]
+    pub(crate) run_when: RunWhen,
+    #[serde(
+        default,
+        alias = "scratch-files",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) scratch_files: Vec<String>,
+    #[serde(default, alias = "track-changes")]
+    pub(crate) track_changes: bool,
+    #[serde(default, alias = "read-only")]
+    pub(crate) read_only: bool,
+    #[serde(default, alias = "skip-binary")]
+    pub(crate) skip_binary: bool,
+    #[serde(default, alias = "max-file-size", deserialize_with = "file_size")]
+    #[schemars(schema_with = "config_schema::file_size")]
+    pub(crate) max_file_size: Option<u64>,
+    #[serde(default, alias = "follow-symlinks")]
+    pub(crate) follow_symlinks: Option<bool>,
+    #[serde(default, alias = "output-encoding")]
+    pub(crate) output_encoding: Option<String>,
+    #[serde(default, alias = "paths-file")]
+    pub(crate) paths_file: bool,
+    #[serde(
+        default,
+        alias = "paths-file-flag",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) paths_file_flag: Vec<String>,
+    #[serde(default, alias = "paths-file-null-separated")]
+    pub(crate) paths_file_null_separated: bool,
+    #[serde(
+        default,
+        alias = "check-flags",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) check_flags: Vec<String>,
+    #[serde(default)]
+    pub(crate) serial: bool,
+    #[serde(
+        default,
+        alias = "depends-on",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) depends_on: Vec<String>,
+    #[serde(
+        default,
+        alias = "conflicts-with",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) conflicts_with: Vec<String>,
+    #[serde(default)]
+    pub(crate) mutex: Option<String>,
+    #[serde(default, alias = "max-bytes-per-invocation")]
+    pub(crate) max_bytes_per_invocation: Option<u64>,
+    #[serde(default, alias = "max-files-per-invocation")]
+    pub(crate) max_files_per_invocation: Option<usize>,
+    #[serde(
+        default,
+        alias = "line-range-flag",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) line_range_flag: Vec<String>,
+    #[serde(default, alias = "diagnostic-regex")]
+    pub(crate) diagnostic_regex: Option<String>,
     #[serde(default, deserialize_with = "string_or_seq_string")]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) labels: Vec<String>,
+    #[serde(default = "enabled")]
+    pub(crate) enabled: bool,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
@@ -82,11 +250,192 @@ fn empty_string() -> String {
     String::new()
 }
 
-#[derive(Clone, Debug, Deserialize)]
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(rename_all = "kebab-case")]
 pub struct Config {
+    /// A base config file that this one is merged on top of
+    #[serde(default)]
+    pub(crate) extends: Option<ExtendsConfig>,
     #[serde(default, deserialize_with = "string_or_seq_string")]
+    #[schemars(schema_with = "config_schema::string_or_list")]
     pub(crate) exclude: Vec<String>,
+    #[serde(
+        default,
+        alias = "exclude-attributes",
+        deserialize_with = "string_or_seq_string"
+    )]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) exclude_attributes: Vec<String>,
+    #[serde(default, alias = "canonicalize-paths")]
+    pub(crate) canonicalize_paths: Option<bool>,
+    #[serde(default, alias = "follow-symlinks")]
+    pub(crate) follow_symlinks: Option<bool>,
+    #[serde(default, alias = "ignore-annotations")]
+    pub(crate) ignore_annotations: bool,
+    #[serde(default, deserialize_with = "jobs")]
+    #[schemars(schema_with = "config_schema::jobs")]
+    pub(crate) jobs: Option<Jobs>,
+    #[serde(default, alias = "log-dir")]
+    pub(crate) log_dir: Option<PathBuf>,
+    #[serde(default, alias = "root-marker")]
+    root_marker: bool,
+    /// Settings for how precious prints its output
+    #[serde(default)]
+    pub(crate) ui: UiConfig,
+    /// Settings for `precious tidy`
+    #[serde(default)]
+    pub(crate) tidy: TidyConfig,
+    /// Named sets of options for `precious run`
+    #[serde(default)]
+    profiles: IndexMap<String, ProfileConfig>,
+    /// Other project roots to run in, each with its own config file
+    #[serde(default)]
+    pub(crate) workspace: WorkspaceConfig,
+    /// The commands that precious runs, keyed by name
+    #[serde(default)]
     commands: IndexMap<String, CommandConfig>,
+    /// Templates that each expand into one command for every combination of
+    /// the values in their matrix
+    #[serde(default, alias = "command-templates")]
+    #[schemars(schema_with = "config_schema::command_templates")]
+    command_templates: IndexMap<String, CommandTemplate>,
+}
+
+// A template has all the same keys as a command, plus a `matrix` and an
+// optional `name`. The command keys are kept as TOML until the template is
+// expanded, since `{key}` placeholders can appear in any string value.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct CommandTemplate {
+    matrix: IndexMap<String, Vec<toml::Value>>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(flatten)]
+    command: toml::Table,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct UiConfig {
+    #[serde(default)]
+    pub(crate) ascii: bool,
+    #[serde(default)]
+    pub(crate) color: Option<bool>,
+    #[serde(default)]
+    pub(crate) chars: CharsConfig,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[schemars(rename_all = "kebab-case")]
+pub(crate) struct TidyConfig {
+    #[serde(default, alias = "partially-staged")]
+    pub(crate) partially_staged: PartiallyStaged,
+    #[serde(default, alias = "auto-restage")]
+    pub(crate) auto_restage: bool,
+    #[serde(default, alias = "hash-algorithm")]
+    pub(crate) hash_algorithm: HashAlgorithm,
+    #[serde(default, alias = "unmerged-files")]
+    pub(crate) unmerged_files: UnmergedFiles,
+}
+
+// A meta-repo can list the project roots under it, so that one run of
+// `lint` or `tidy` covers all of them.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub(crate) struct WorkspaceConfig {
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) members: Vec<String>,
+}
+
+// What `tidy --staged` does with staged files that also have unstaged changes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+pub(crate) enum PartiallyStaged {
+    // Tidy them anyway, with a warning.
+    #[default]
+    #[serde(rename = "tidy")]
+    Tidy,
+    // Leave them alone, with a warning.
+    #[serde(rename = "skip")]
+    Skip,
+    // Leave them alone, with a warning, and restage the other files after
+    // tidying them, as if `--restage` was passed.
+    #[serde(rename = "restage")]
+    Restage,
+    #[serde(rename = "fail")]
+    Fail,
+}
+
+// What `tidy` does with files that have unresolved merge conflicts.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+pub(crate) enum UnmergedFiles {
+    // Leave them alone, with a warning.
+    #[default]
+    #[serde(rename = "skip")]
+    Skip,
+    #[serde(rename = "fail")]
+    Fail,
+}
+
+// A named set of options for `precious run`, so that everyone on a project
+// runs precious the same way without having to remember the right flags.
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[schemars(rename_all = "kebab-case")]
+pub(crate) struct ProfileConfig {
+    #[serde(deserialize_with = "profile_actions")]
+    #[schemars(schema_with = "config_schema::profile_actions")]
+    pub(crate) actions: Vec<ProfileAction>,
+    #[serde(default)]
+    pub(crate) mode: Option<ProfileMode>,
+    #[serde(default, alias = "git-diff-from")]
+    pub(crate) git_diff_from: Option<String>,
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    #[schemars(schema_with = "config_schema::string_or_list")]
+    pub(crate) command: Vec<String>,
+    #[serde(default)]
+    pub(crate) label: Option<String>,
+    #[serde(default)]
+    pub(crate) check: bool,
+    #[serde(default)]
+    pub(crate) restage: bool,
+    #[serde(default, alias = "install-missing")]
+    pub(crate) install_missing: bool,
+    #[serde(default, alias = "only-changed-lines")]
+    pub(crate) only_changed_lines: bool,
+    #[serde(default, alias = "only-dirty-dirs")]
+    pub(crate) only_dirty_dirs: bool,
+    #[serde(default, alias = "summary-only")]
+    pub(crate) summary_only: bool,
+    #[serde(default, alias = "parallel-commands")]
+    pub(crate) parallel_commands: bool,
+    #[serde(default)]
+    pub(crate) progress: bool,
+    #[serde(default, alias = "live-output")]
+    pub(crate) live_output: bool,
+    #[serde(default, alias = "strict-config")]
+    pub(crate) strict_config: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ProfileAction {
+    Lint,
+    Tidy,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+pub(crate) enum ProfileMode {
+    #[serde(rename = "all")]
+    All,
+    #[serde(rename = "git")]
+    Git,
+    #[serde(rename = "git-all-uncommitted")]
+    GitAllUncommitted,
+    #[serde(rename = "staged")]
+    Staged,
+    #[serde(rename = "staged-with-stash")]
+    StagedWithStash,
 }
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -103,10 +452,161 @@ pub(crate) enum ConfigError {
     CannotInvokePerDirInRootWithPathArgs { path_args: PathArgs },
     #[error(r#"Cannot set invoke = "once" and working-dir = "dir""#)]
     CannotInvokeOnceWithWorkingDirEqDir,
+    #[error("The {name:} command depends on {dep:}, but there is no command with that name")]
+    UnknownDependency { name: String, dep: String },
+    #[error(
+        "The {name:} command depends on {dep:}, so {dep:} must come before it in the config file"
+    )]
+    DependencyMustComeFirst { name: String, dep: String },
+    #[error("The {name:} command conflicts with {other:}, but there is no command with that name")]
+    UnknownConflict { name: String, other: String },
+    #[error("There is no command named {name:} in the config file")]
+    UnknownCommand { name: String },
+    #[error("The command name {name:} is not a valid glob: {error:}")]
+    InvalidCommandNameGlob { name: String, error: String },
+    #[error(
+        "The {template:} command template has an empty matrix, or a matrix key with no values"
+    )]
+    EmptyMatrix { template: String },
+    #[error("The {template:} command template has a matrix value for {key:} that is not a string or number: {value:}")]
+    InvalidMatrixValue {
+        template: String,
+        key: String,
+        value: String,
+    },
+    #[error("The {template:} command template expands to a command named {name:}, but there is already a command with that name")]
+    DuplicateCommandName { template: String, name: String },
+    #[error("The {name:} command from the {template:} command template is not valid: {error:}")]
+    InvalidCommandFromTemplate {
+        template: String,
+        name: String,
+        error: String,
+    },
+    #[error("There is no profile named {name:} in the config file")]
+    UnknownProfile { name: String },
+    #[error("The {name:} profile sets both mode and git-diff-from, but only one can be set")]
+    ProfileHasModeAndGitDiffFrom { name: String },
+    #[error(
+        "The config file at {} mixes snake_case keys ({}) with kebab-case keys ({}). Run `precious config migrate` to convert it to kebab-case.",
+        file.display(),
+        snake.join(", "),
+        kebab.join(", "),
+    )]
+    MixedKeyStyles {
+        file: PathBuf,
+        snake: Vec<String>,
+        kebab: Vec<String>,
+    },
     #[error(transparent)]
     Toml(#[from] toml::de::Error),
 }
 
+impl CommandTemplate {
+    // Each command's name comes from the template's `name`, with placeholders
+    // replaced. Without a `name`, it's the template's name followed by each
+    // of the matrix values, separated by dashes.
+    fn expand(&self, template_name: &str) -> Result<Vec<(String, CommandConfig)>, ConfigError> {
+        if self.matrix.is_empty() || self.matrix.values().any(Vec::is_empty) {
+            return Err(ConfigError::EmptyMatrix {
+                template: template_name.to_string(),
+            });
+        }
+
+        let mut matrix = vec![];
+        for (key, values) in &self.matrix {
+            let values = values
+                .iter()
+                .map(|v| match v {
+                    toml::Value::String(s) => Ok(s.clone()),
+                    toml::Value::Integer(_) | toml::Value::Float(_) => Ok(v.to_string()),
+                    _ => Err(ConfigError::InvalidMatrixValue {
+                        template: template_name.to_string(),
+                        key: key.clone(),
+                        value: v.to_string(),
+                    }),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            matrix.push(
+                values
+                    .into_iter()
+                    .map(|v| (key.as_str(), v))
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        matrix
+            .into_iter()
+            .multi_cartesian_product()
+            .map(|combo| {
+                let name = match &self.name {
+                    Some(n) => fill_placeholders(n, &combo),
+                    None => format!(
+                        "{template_name}-{}",
+                        combo.iter().map(|(_, v)| v.as_str()).join("-"),
+                    ),
+                };
+                let command =
+                    fill_placeholders_in_value(toml::Value::Table(self.command.clone()), &combo);
+                let command = CommandConfig::deserialize(command).map_err(|e| {
+                    ConfigError::InvalidCommandFromTemplate {
+                        template: template_name.to_string(),
+                        name: name.clone(),
+                        error: e.to_string().trim().to_string(),
+                    }
+                })?;
+                Ok((name, command))
+            })
+            .collect()
+    }
+}
+
+fn fill_placeholders(s: &str, combo: &[(&str, String)]) -> String {
+    combo.iter().fold(s.to_string(), |s, (key, value)| {
+        s.replace(&format!("{{{key}}}"), value)
+    })
+}
+
+fn fill_placeholders_in_value(value: toml::Value, combo: &[(&str, String)]) -> toml::Value {
+    match value {
+        toml::Value::String(s) => toml::Value::String(fill_placeholders(&s, combo)),
+        toml::Value::Array(a) => toml::Value::Array(
+            a.into_iter()
+                .map(|v| fill_placeholders_in_value(v, combo))
+                .collect(),
+        ),
+        toml::Value::Table(t) => toml::Value::Table(
+            t.into_iter()
+                .map(|(k, v)| (k, fill_placeholders_in_value(v, combo)))
+                .collect(),
+        ),
+        v => v,
+    }
+}
+
+// Each name can be a glob like `go-*`, which selects every command with a
+// matching name. A name without any glob characters only matches itself.
+fn command_name_matcher(names: &[String]) -> Result<Option<GlobSet>, ConfigError> {
+    if names.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for name in names {
+        let glob = Glob::new(name).map_err(|e| ConfigError::InvalidCommandNameGlob {
+            name: name.clone(),
+            error: e.kind().to_string(),
+        })?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| ConfigError::InvalidCommandNameGlob {
+            name: names.join(", "),
+            error: e.to_string(),
+        })
+}
+
 // Copied from https://stackoverflow.com/a/43627388 - CC-BY-SA 3.0
 fn string_or_seq_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
@@ -139,6 +639,20 @@ where
     deserializer.deserialize_any(StringOrVec(PhantomData))
 }
 
+fn profile_actions<'de, D>(deserializer: D) -> Result<Vec<ProfileAction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    string_or_seq_string(deserializer)?
+        .into_iter()
+        .map(|a| match a.as_str() {
+            "lint" => Ok(ProfileAction::Lint),
+            "tidy" => Ok(ProfileAction::Tidy),
+            _ => Err(de::Error::unknown_variant(&a, &["lint", "tidy"])),
+        })
+        .collect()
+}
+
 #[allow(clippy::too_many_lines)]
 fn u8_or_seq_u8<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
 where
@@ -270,6 +784,114 @@ where
     deserializer.deserialize_any(U8OrVec(PhantomData))
 }
 
+fn file_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct FileSize(PhantomData<Option<u64>>);
+
+    impl<'de> de::Visitor<'de> for FileSize {
+        type Value = Option<u64>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str(r#"a number of bytes or a size like "500KB" or "1MB""#)
+        }
+
+        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            u64::try_from(value).map(Some).map_err(|_| {
+                de::Error::invalid_value(de::Unexpected::Signed(value), &"a non-negative integer")
+            })
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(Some(value))
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            parse_file_size(value)
+                .map(Some)
+                .ok_or_else(|| de::Error::invalid_value(de::Unexpected::Str(value), &self))
+        }
+    }
+
+    deserializer.deserialize_any(FileSize(PhantomData))
+}
+
+// Parses sizes like "1024", "500KB", or "1.5 MiB". Units are
+// case-insensitive and always multiples of 1024.
+pub(crate) fn parse_file_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let unit_start = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (num, unit) = size.split_at(unit_start);
+    let num: f64 = num.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    Some((num * multiplier as f64) as u64)
+}
+
+// A number of jobs can be an integer, where a negative number is subtracted
+// from the number of CPUs, or a string like "50%".
+fn jobs<'de, D>(deserializer: D) -> Result<Option<Jobs>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct JobsVisitor(PhantomData<Option<Jobs>>);
+
+    impl<'de> de::Visitor<'de> for JobsVisitor {
+        type Value = Option<Jobs>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter
+                .write_str(r#"a number of jobs, a negative number, or a percentage like "50%""#)
+        }
+
+        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.visit_str(&value.to_string())
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.visit_str(&value.to_string())
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            value.parse().map(Some).map_err(de::Error::custom)
+        }
+    }
+
+    deserializer.deserialize_any(JobsVisitor(PhantomData))
+}
+
 fn working_dir<'de, D>(deserializer: D) -> Result<Option<WorkingDir>, D::Error>
 where
     D: Deserializer<'de>,
@@ -353,10 +975,122 @@ where
     deserializer.deserialize_any(WorkingDirOrChdirTo(PhantomData))
 }
 
+// A plugin runner can be given as a "plugin:<name>" string. Every other
+// runner is a table with a "type" key.
+fn runner<'de, D>(deserializer: D) -> Result<Option<Runner>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct PluginOrTable(PhantomData<Option<Runner>>);
+
+    impl<'de> de::Visitor<'de> for PluginOrTable {
+        type Value = Option<Runner>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str(r#"a "plugin:<name>" string or a runner table"#)
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_any(PluginOrTable(PhantomData))
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            match value.strip_prefix("plugin:") {
+                Some(name) if !name.is_empty() => Ok(Some(Runner::Plugin {
+                    name: name.to_string(),
+                })),
+                _ => Err(E::invalid_value(
+                    de::Unexpected::Str(value),
+                    &r#"a string like "plugin:<name>""#,
+                )),
+            }
+        }
+
+        fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+        where
+            A: de::MapAccess<'de>,
+        {
+            Runner::deserialize(de::value::MapAccessDeserializer::new(map)).map(Some)
+        }
+    }
+
+    deserializer.deserialize_any(PluginOrTable(PhantomData))
+}
+
 const DEFAULT_LABEL: &str = "default";
 
 impl Config {
+    // Whether we descend into symlinked directories when looking for files.
+    // A command that sets `follow-symlinks = true` needs the files under them
+    // even when the top-level key is false. Each command filters out the
+    // symlinks that it doesn't want.
+    pub(crate) fn walks_symlinks(&self) -> bool {
+        self.follow_symlinks == Some(true)
+            || self
+                .commands
+                .values()
+                .any(|c| c.follow_symlinks == Some(true))
+    }
+
+    // Commands always run in the order they appear in the config file when
+    // they're run one at a time, so requiring dependencies to come first
+    // means that the order is the same with --parallel-commands, and it also
+    // rules out cycles.
+    fn check_dependencies(&self) -> Result<(), ConfigError> {
+        for (i, (name, c)) in self.commands.iter().enumerate() {
+            for dep in &c.depends_on {
+                match self.commands.get_index_of(dep) {
+                    None => {
+                        return Err(ConfigError::UnknownDependency {
+                            name: name.clone(),
+                            dep: dep.clone(),
+                        })
+                    }
+                    Some(j) if j >= i => {
+                        return Err(ConfigError::DependencyMustComeFirst {
+                            name: name.clone(),
+                            dep: dep.clone(),
+                        })
+                    }
+                    Some(_) => (),
+                }
+            }
+            if let Some(other) = c
+                .conflicts_with
+                .iter()
+                .find(|o| !self.commands.contains_key(*o))
+            {
+                return Err(ConfigError::UnknownConflict {
+                    name: name.clone(),
+                    other: other.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
     pub(crate) fn new(file: &Path) -> Result<Config> {
+        Self::from_resolved_table(Self::resolved_table(file)?)
+    }
+
+    // Returns the config file's table with every base config from `extends`
+    // merged into it. Anything that needs to know whether a config changed
+    // should look at this rather than the file, since a base config can
+    // change without the file changing.
+    pub(crate) fn resolved_table(file: &Path) -> Result<toml::Table> {
         match fs::read(file) {
             Err(e) => Err(ConfigError::FileCannotBeRead {
                 file: file.to_path_buf(),
@@ -365,61 +1099,189 @@ impl Config {
             .into()),
             Ok(bytes) => {
                 let s = String::from_utf8(bytes)?;
-                Ok(toml::from_str(&s)?)
+                let table: toml::Table = toml::from_str(&s)?;
+                check_key_styles(file, &table)?;
+                config_extends::resolve(file, table)
+            }
+        }
+    }
+
+    pub(crate) fn from_resolved_table(table: toml::Table) -> Result<Config> {
+        let mut config: Config = table.try_into()?;
+        config.expand_command_templates()?;
+        Ok(config)
+    }
+
+    // This is checked for config files in the dirs above the cwd while we're
+    // still looking for the project root, so a file that can't be read or
+    // parsed just doesn't count as a marker. If it turns out to be the config
+    // file we use, loading it will report the problem.
+    pub(crate) fn has_root_marker(file: &Path) -> bool {
+        fs::read_to_string(file)
+            .ok()
+            .and_then(|s| toml::from_str::<Config>(&s).ok())
+            .is_some_and(|c| c.root_marker)
+    }
+
+    // The commands from templates come after the ones in the `[commands]`
+    // table, in the order the templates are defined.
+    fn expand_command_templates(&mut self) -> Result<(), ConfigError> {
+        for (template_name, template) in mem::take(&mut self.command_templates) {
+            for (name, c) in template.expand(&template_name)? {
+                if self.commands.contains_key(&name) {
+                    return Err(ConfigError::DuplicateCommandName {
+                        template: template_name,
+                        name,
+                    });
+                }
+                self.commands.insert(name, c);
             }
         }
+        Ok(())
     }
 
     pub(crate) fn into_tidy_commands(
         self,
         project_root: &Path,
-        command: Option<&str>,
+        commands: &[String],
+        skip: &[String],
         label: Option<&str>,
     ) -> Result<Vec<command::LintOrTidyCommand>> {
-        self.into_commands(project_root, command, label, LintOrTidyCommandType::Tidy)
+        self.into_commands(
+            project_root,
+            commands,
+            skip,
+            label,
+            LintOrTidyCommandType::Tidy,
+        )
     }
 
     pub(crate) fn into_lint_commands(
         self,
         project_root: &Path,
-        command: Option<&str>,
+        commands: &[String],
+        skip: &[String],
         label: Option<&str>,
     ) -> Result<Vec<command::LintOrTidyCommand>> {
-        self.into_commands(project_root, command, label, LintOrTidyCommandType::Lint)
+        self.into_commands(
+            project_root,
+            commands,
+            skip,
+            label,
+            LintOrTidyCommandType::Lint,
+        )
     }
 
     fn into_commands(
         self,
         project_root: &Path,
-        command: Option<&str>,
+        names: &[String],
+        skip: &[String],
         label: Option<&str>,
         typ: LintOrTidyCommandType,
     ) -> Result<Vec<command::LintOrTidyCommand>> {
-        let mut commands: Vec<command::LintOrTidyCommand> = vec![];
-        for (name, c) in self.commands {
-            if let Some(c) = command {
-                if name != c {
-                    continue;
-                }
-            }
+        self.check_dependencies()?;
+        self.check_command_names(names)?;
 
-            if !c.matches_label(label.unwrap_or(DEFAULT_LABEL)) {
+        let matcher = command_name_matcher(names)?;
+        let skip_matcher = command_name_matcher(skip)?;
+        let mut commands: Vec<command::LintOrTidyCommand> = vec![];
+        for (name, mut c) in self.commands {
+            // When no names are given, every command can run.
+            if matcher.as_ref().is_some_and(|m| !m.is_match(&name)) {
                 continue;
             }
 
-            if c.typ != typ && c.typ != LintOrTidyCommandType::Both {
+            if c.skip_reason(&name, skip_matcher.as_ref(), label, typ)
+                .is_some()
+            {
                 continue;
             }
 
-            commands.push(c.into_command(project_root, name)?);
+            c.follow_symlinks = c.follow_symlinks.or(self.follow_symlinks);
+            let mut command = c.into_command(project_root, name)?;
+            command.set_hash_algorithm(self.tidy.hash_algorithm);
+            commands.push(command);
         }
 
         Ok(commands)
     }
 
+    // Each name has to match at least one command, even when the other names
+    // match some, so that a typo in a list of names isn't silently ignored.
+    fn check_command_names(&self, names: &[String]) -> Result<(), ConfigError> {
+        for name in names {
+            let Some(matcher) = command_name_matcher(std::slice::from_ref(name))? else {
+                continue;
+            };
+            if !self.commands.keys().any(|c| matcher.is_match(c)) {
+                return Err(ConfigError::UnknownCommand { name: name.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    // The commands that match the given names, if any, but won't be run
+    // because they're disabled or skipped with `--skip-command`, or because of
+    // their labels or type, along with why each is skipped.
+    pub(crate) fn skipped_commands(
+        &self,
+        names: &[String],
+        skip: &[String],
+        label: Option<&str>,
+        typ: LintOrTidyCommandType,
+    ) -> Result<Vec<(String, SkipReason)>> {
+        let matcher = command_name_matcher(names)?;
+        let skip_matcher = command_name_matcher(skip)?;
+        Ok(self
+            .commands
+            .iter()
+            .filter(|(name, _)| matcher.as_ref().is_none_or(|m| m.is_match(name)))
+            .filter_map(|(name, c)| {
+                Some((
+                    name.clone(),
+                    c.skip_reason(name, skip_matcher.as_ref(), label, typ)?,
+                ))
+            })
+            .collect())
+    }
+
+    pub(crate) fn profile(&self, name: &str) -> Result<&ProfileConfig, ConfigError> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownProfile { name: name.into() })?;
+        if profile.mode.is_some() && profile.git_diff_from.is_some() {
+            return Err(ConfigError::ProfileHasModeAndGitDiffFrom { name: name.into() });
+        }
+        Ok(profile)
+    }
+
     pub(crate) fn command_info(self) -> Vec<(String, CommandConfig)> {
         self.commands.into_iter().collect()
     }
+
+    // Returns the params for the named commands, or for all commands if no
+    // names are given, with defaults filled in and any deprecated options
+    // translated.
+    pub(crate) fn command_params(
+        self,
+        project_root: &Path,
+        names: &[String],
+    ) -> Result<Vec<command::LintOrTidyCommandParams>> {
+        if let Some(name) = names.iter().find(|n| !self.commands.contains_key(*n)) {
+            return Err(ConfigError::UnknownCommand { name: name.clone() }.into());
+        }
+
+        self.commands
+            .into_iter()
+            .filter(|(name, _)| names.is_empty() || names.contains(name))
+            .map(|(name, mut c)| {
+                c.follow_symlinks = c.follow_symlinks.or(self.follow_symlinks);
+                c.into_command_params(project_root, name)
+            })
+            .collect()
+    }
 }
 
 impl CommandConfig {
@@ -440,6 +1302,7 @@ impl CommandConfig {
             self.invoke,
             self.working_dir,
             self.path_args,
+            self.stdin_from,
         )?;
         Ok(command::LintOrTidyCommandParams {
             project_root: project_root.to_owned(),
@@ -447,10 +1310,14 @@ impl CommandConfig {
             typ: self.typ,
             include: self.include,
             exclude: self.exclude,
+            dir_include: self.dir_include,
             invoke,
             working_dir,
             path_args,
             cmd: self.cmd,
+            install_cmd: self.install_cmd,
+            daemon_cmd: self.daemon_cmd,
+            runner: self.runner,
             env: self.env,
             lint_flags: self.lint_flags,
             tidy_flags: self.tidy_flags,
@@ -459,6 +1326,39 @@ impl CommandConfig {
             lint_failure_exit_codes: self.lint_failure_exit_codes,
             expect_stderr: self.expect_stderr,
             ignore_stderr: self.ignore_stderr,
+            ignore_stdout: self.ignore_stdout,
+            redirect_stderr_to_stdout: self.redirect_stderr_to_stdout,
+            version_cmd: self.version_cmd,
+            also_trigger_on: self.also_trigger_on,
+            stdin_from: self.stdin_from,
+            toolchain: self.toolchain,
+            report_new_files: self.report_new_files,
+            max_memory: self.max_memory,
+            max_cpu_seconds: self.max_cpu_seconds,
+            path_style: self.path_style,
+            max_output_bytes: self.max_output_bytes,
+            save_full_output: self.save_full_output,
+            lint_failure_output: self.lint_failure_output,
+            run_when: self.run_when,
+            scratch_files: self.scratch_files,
+            track_changes: self.track_changes,
+            read_only: self.read_only,
+            skip_binary: self.skip_binary,
+            max_file_size: self.max_file_size,
+            follow_symlinks: self.follow_symlinks,
+            output_encoding: self.output_encoding,
+            paths_file: self.paths_file,
+            paths_file_flag: self.paths_file_flag,
+            paths_file_null_separated: self.paths_file_null_separated,
+            check_flags: self.check_flags,
+            serial: self.serial,
+            depends_on: self.depends_on,
+            conflicts_with: self.conflicts_with,
+            mutex: self.mutex,
+            max_bytes_per_invocation: self.max_bytes_per_invocation,
+            max_files_per_invocation: self.max_files_per_invocation,
+            line_range_flag: self.line_range_flag,
+            diagnostic_regex: self.diagnostic_regex,
         })
     }
 
@@ -469,6 +1369,7 @@ impl CommandConfig {
         invoke: Option<Invoke>,
         working_dir: Option<WorkingDir>,
         path_args: Option<PathArgs>,
+        stdin_from: Option<StdinFrom>,
     ) -> Result<(Invoke, WorkingDir, PathArgs)> {
         if (run_mode.is_some() || chdir.is_some())
             && (invoke.is_some() || working_dir.is_some() || path_args.is_some())
@@ -479,36 +1380,15 @@ impl CommandConfig {
             .into());
         }
 
-        // This translates the old config options into their equivalent new
-        // options.
         if run_mode.is_some() || chdir.is_some() {
             let (article, plural, options) = match (run_mode, chdir) {
                 (Some(_), None) => ("a ", "", "run-mode"),
                 (None, Some(_)) => ("a ", "", "chdir"),
                 _ => ("", "s", "run-mode and chdir"),
             };
-            warn!("The {name} command is using {article:}deprecated config option{plural:}: {options}");
+            warn!("The {name} command is using {article:}deprecated config option{plural:}: {options}. Run `precious config migrate` to update your config file.");
 
-            match (run_mode, chdir) {
-                (Some(OldRunMode::Files) | None, Some(false) | None) => {
-                    return Ok((Invoke::PerFile, WorkingDir::Root, PathArgs::File))
-                }
-                (Some(OldRunMode::Files) | None, Some(true)) => {
-                    return Ok((Invoke::PerFile, WorkingDir::Dir, PathArgs::File))
-                }
-                (Some(OldRunMode::Dirs), Some(false) | None) => {
-                    return Ok((Invoke::PerDir, WorkingDir::Root, PathArgs::Dir))
-                }
-                (Some(OldRunMode::Dirs), Some(true)) => {
-                    return Ok((Invoke::PerDir, WorkingDir::Dir, PathArgs::None))
-                }
-                (Some(OldRunMode::Root), Some(false) | None) => {
-                    return Ok((Invoke::Once, WorkingDir::Root, PathArgs::Dot))
-                }
-                (Some(OldRunMode::Root), Some(true)) => {
-                    return Ok((Invoke::Once, WorkingDir::Root, PathArgs::None))
-                }
-            }
+            return Ok(translate_old_params(run_mode, chdir));
         }
 
         let invoke = invoke.unwrap_or(Invoke::PerFile);
@@ -516,17 +1396,17 @@ impl CommandConfig {
         let path_args = path_args.unwrap_or(PathArgs::File);
 
         match (invoke, &working_dir, path_args) {
-            (Invoke::PerFile, _, path_args) => {
-                if path_args != PathArgs::File && path_args != PathArgs::AbsoluteFile {
-                    return Err(ConfigError::CannotInvokePerFileWithPathArgs { path_args }.into());
-                }
+            // A command that reads the file from stdin doesn't need its path.
+            (Invoke::PerFile, _, PathArgs::None) if stdin_from.is_some() => (),
+            (Invoke::PerFile, _, path_args)
+                if path_args != PathArgs::File && path_args != PathArgs::AbsoluteFile =>
+            {
+                return Err(ConfigError::CannotInvokePerFileWithPathArgs { path_args }.into());
             }
-            (Invoke::PerDir, &WorkingDir::Root | &WorkingDir::ChdirTo(_), path_args) => {
-                if path_args == PathArgs::Dot || path_args == PathArgs::None {
-                    return Err(
-                        ConfigError::CannotInvokePerDirInRootWithPathArgs { path_args }.into(),
-                    );
-                }
+            (Invoke::PerDir, &WorkingDir::Root | &WorkingDir::ChdirTo(_), path_args)
+                if path_args == PathArgs::Dot || path_args == PathArgs::None =>
+            {
+                return Err(ConfigError::CannotInvokePerDirInRootWithPathArgs { path_args }.into());
             }
             (Invoke::Once, &WorkingDir::Dir, _) => {
                 return Err(ConfigError::CannotInvokeOnceWithWorkingDirEqDir.into());
@@ -543,6 +1423,107 @@ impl CommandConfig {
         }
         self.labels.iter().any(|l| *l == label)
     }
+
+    fn skip_reason(
+        &self,
+        name: &str,
+        skip: Option<&GlobSet>,
+        label: Option<&str>,
+        typ: LintOrTidyCommandType,
+    ) -> Option<SkipReason> {
+        if skip.is_some_and(|s| s.is_match(name)) {
+            return Some(SkipReason::SkipCommand);
+        }
+        if !self.enabled {
+            return Some(SkipReason::Disabled);
+        }
+        let label = label.unwrap_or(DEFAULT_LABEL);
+        if !self.matches_label(label) {
+            return Some(SkipReason::Label {
+                label: label.to_string(),
+            });
+        }
+        if self.typ != typ && self.typ != LintOrTidyCommandType::Both {
+            return Some(SkipReason::Type { typ: self.typ });
+        }
+        None
+    }
+}
+
+// Either style of key is accepted, but a config file that uses both is almost
+// always the result of copying snippets from different places, so we ask the
+// user to pick one. We only look at the keys that precious itself defines,
+// since the keys in an `env` table or the names of commands can be anything.
+pub(crate) fn check_key_styles(file: &Path, table: &toml::Table) -> Result<(), ConfigError> {
+    let mut keys = table.keys().collect::<Vec<_>>();
+    if let Some(ui) = table.get("ui").and_then(toml::Value::as_table) {
+        keys.extend(ui.keys());
+        if let Some(chars) = ui.get("chars").and_then(toml::Value::as_table) {
+            keys.extend(chars.keys());
+        }
+    }
+    if let Some(tidy) = table.get("tidy").and_then(toml::Value::as_table) {
+        keys.extend(tidy.keys());
+    }
+    if let Some(profiles) = table.get("profiles").and_then(toml::Value::as_table) {
+        for profile in profiles.values().filter_map(toml::Value::as_table) {
+            keys.extend(profile.keys());
+        }
+    }
+    for commands in ["commands", "command-templates", "command_templates"] {
+        if let Some(commands) = table.get(commands).and_then(toml::Value::as_table) {
+            for command in commands.values().filter_map(toml::Value::as_table) {
+                keys.extend(command.keys());
+            }
+        }
+    }
+
+    let snake = keys
+        .iter()
+        .filter(|k| k.contains('_'))
+        .map(|k| (*k).clone())
+        .sorted()
+        .dedup()
+        .collect::<Vec<_>>();
+    let kebab = keys
+        .iter()
+        .filter(|k| k.contains('-'))
+        .map(|k| (*k).clone())
+        .sorted()
+        .dedup()
+        .collect::<Vec<_>>();
+    if snake.is_empty() || kebab.is_empty() {
+        return Ok(());
+    }
+
+    Err(ConfigError::MixedKeyStyles {
+        file: file.to_path_buf(),
+        snake,
+        kebab,
+    })
+}
+
+// This translates the old config options into their equivalent new options.
+pub(crate) fn translate_old_params(
+    run_mode: Option<OldRunMode>,
+    chdir: Option<bool>,
+) -> (Invoke, WorkingDir, PathArgs) {
+    match (run_mode, chdir) {
+        (Some(OldRunMode::Files) | None, Some(false) | None) => {
+            (Invoke::PerFile, WorkingDir::Root, PathArgs::File)
+        }
+        (Some(OldRunMode::Files) | None, Some(true)) => {
+            (Invoke::PerFile, WorkingDir::Dir, PathArgs::File)
+        }
+        (Some(OldRunMode::Dirs), Some(false) | None) => {
+            (Invoke::PerDir, WorkingDir::Root, PathArgs::Dir)
+        }
+        (Some(OldRunMode::Dirs), Some(true)) => (Invoke::PerDir, WorkingDir::Dir, PathArgs::None),
+        (Some(OldRunMode::Root), Some(false) | None) => {
+            (Invoke::Once, WorkingDir::Root, PathArgs::Dot)
+        }
+        (Some(OldRunMode::Root), Some(true)) => (Invoke::Once, WorkingDir::Root, PathArgs::None),
+    }
 }
 
 #[cfg(test)]
@@ -877,9 +1858,13 @@ mod tests {
             path_args: Some(path_args),
             include: vec![String::from("**/*.rs")],
             exclude: vec![],
+            dir_include: vec![],
             run_mode: None,
             chdir: None,
             cmd: vec![String::from("some-linter")],
+            install_cmd: vec![],
+            daemon_cmd: vec![],
+            runner: None,
             env: Default::default(),
             lint_flags: vec![],
             tidy_flags: vec![],
@@ -888,7 +1873,41 @@ mod tests {
             lint_failure_exit_codes: vec![],
             expect_stderr: false,
             ignore_stderr: vec![],
+            ignore_stdout: vec![],
+            redirect_stderr_to_stdout: false,
+            version_cmd: vec![],
+            also_trigger_on: vec![],
+            stdin_from: None,
+            toolchain: None,
+            report_new_files: false,
+            max_memory: None,
+            max_cpu_seconds: None,
+            path_style: None,
+            max_output_bytes: None,
+            save_full_output: false,
+            lint_failure_output: None,
+            run_when: RunWhen::Always,
+            scratch_files: vec![],
+            track_changes: false,
+            read_only: false,
+            skip_binary: false,
+            max_file_size: None,
+            follow_symlinks: None,
+            output_encoding: None,
+            paths_file: false,
+            paths_file_flag: vec![],
+            paths_file_null_separated: false,
+            check_flags: vec![],
+            serial: false,
+            depends_on: vec![],
+            conflicts_with: vec![],
+            mutex: None,
+            max_bytes_per_invocation: None,
+            max_files_per_invocation: None,
+            line_range_flag: vec![],
+            diagnostic_regex: None,
             labels: vec![],
+            enabled: true,
         };
         let res = config.into_command(Path::new("."), String::from("some-linter"));
         let err = res.unwrap_err().downcast::<ConfigError>().unwrap();
@@ -897,6 +1916,22 @@ mod tests {
         Ok(())
     }
 
+    #[test_case(Some(StdinFrom::File), true ; "with stdin-from")]
+    #[test_case(None, false ; "without stdin-from")]
+    #[parallel]
+    fn invoke_per_file_without_path_args(stdin_from: Option<StdinFrom>, expect_ok: bool) {
+        let res = CommandConfig::invoke_args(
+            "some-tidier",
+            None,
+            None,
+            Some(Invoke::PerFile),
+            None,
+            Some(PathArgs::None),
+            stdin_from,
+        );
+        assert_eq!(res.is_ok(), expect_ok);
+    }
+
     #[test_case(vec![], "default", true)]
     #[test_case(vec!["default".to_string()], "default", true)]
     #[test_case(vec!["default".to_string(), "foo".to_string()], "default", true)]
@@ -918,9 +1953,13 @@ mod tests {
             path_args: None,
             include: vec![String::from("**/*.rs")],
             exclude: vec![],
+            dir_include: vec![],
             run_mode: None,
             chdir: None,
             cmd: vec![String::from("some-linter")],
+            install_cmd: vec![],
+            daemon_cmd: vec![],
+            runner: None,
             env: Default::default(),
             lint_flags: vec![],
             tidy_flags: vec![],
@@ -929,7 +1968,41 @@ mod tests {
             lint_failure_exit_codes: vec![],
             expect_stderr: false,
             ignore_stderr: vec![],
+            ignore_stdout: vec![],
+            redirect_stderr_to_stdout: false,
+            version_cmd: vec![],
+            also_trigger_on: vec![],
+            stdin_from: None,
+            toolchain: None,
+            report_new_files: false,
+            max_memory: None,
+            max_cpu_seconds: None,
+            path_style: None,
+            max_output_bytes: None,
+            save_full_output: false,
+            lint_failure_output: None,
+            run_when: RunWhen::Always,
+            scratch_files: vec![],
+            track_changes: false,
+            read_only: false,
+            skip_binary: false,
+            max_file_size: None,
+            follow_symlinks: None,
+            output_encoding: None,
+            paths_file: false,
+            paths_file_flag: vec![],
+            paths_file_null_separated: false,
+            check_flags: vec![],
+            serial: false,
+            depends_on: vec![],
+            conflicts_with: vec![],
+            mutex: None,
+            max_bytes_per_invocation: None,
+            max_files_per_invocation: None,
+            line_range_flag: vec![],
+            diagnostic_regex: None,
             labels: labels_in_config,
+            enabled: true,
         };
         if expect_match {
             assert!(config.matches_label(label_to_match));
@@ -976,4 +2049,535 @@ mod tests {
 
         Ok(())
     }
+
+    #[test_case("1024", Some(1024) ; "integer")]
+    #[test_case(r#""2048""#, Some(2048) ; "string with no unit")]
+    #[test_case(r#""100B""#, Some(100) ; "bytes")]
+    #[test_case(r#""500KB""#, Some(500 * 1024) ; "kilobytes")]
+    #[test_case(r#""1MB""#, Some(1024 * 1024) ; "megabytes")]
+    #[test_case(r#""1.5 MiB""#, Some(1024 * 1024 * 3 / 2) ; "fractional mebibytes with a space")]
+    #[test_case(r#""2gb""#, Some(2 * 1024 * 1024 * 1024) ; "lowercase gigabytes")]
+    #[test_case(r#""1TB""#, None ; "unknown unit")]
+    #[test_case(r#""MB""#, None ; "no number")]
+    #[test_case("-1", None ; "negative integer")]
+    #[parallel]
+    fn max_file_size(size: &str, expect: Option<u64>) -> Result<()> {
+        let toml_text = format!(
+            r#"
+            [commands.rustfmt]
+            type = "both"
+            include = "**/*.rs"
+            cmd = [ "rustfmt" ]
+            ok-exit-codes = 0
+            max-file-size = {size}
+        "#
+        );
+
+        let config = toml::from_str::<Config>(&toml_text);
+        match expect {
+            Some(e) => assert_eq!(config?.commands[0].max_file_size, Some(e)),
+            None => assert!(config.is_err(), "{size} is not a valid size"),
+        }
+
+        Ok(())
+    }
+
+    #[test_case("ascii = true", true ; "known key")]
+    #[test_case("colour = true", false ; "unknown key")]
+    #[parallel]
+    fn ui_keys(key: &str, ok: bool) {
+        let toml_text = format!(
+            r#"
+            [ui]
+            {key}
+        "#
+        );
+        assert_eq!(toml::from_str::<Config>(&toml_text).is_ok(), ok, "{key}");
+    }
+
+    #[test]
+    #[parallel]
+    fn workspace() -> Result<()> {
+        let toml_text = r#"
+            [workspace]
+            members = ["subproj-a", "subproj-b"]
+        "#;
+
+        let config = toml::from_str::<Config>(toml_text)?;
+        assert_eq!(config.workspace.members, vec!["subproj-a", "subproj-b"]);
+        assert!(config.command_info().is_empty());
+
+        Ok(())
+    }
+
+    #[test_case("jobs = 4", Some(Jobs::Count(4)) ; "integer")]
+    #[test_case("jobs = -1", Some(Jobs::AllBut(1)) ; "negative integer")]
+    #[test_case(r#"jobs = "50%""#, Some(Jobs::Percent(50)) ; "percentage")]
+    #[test_case("", None ; "not set")]
+    #[parallel]
+    fn jobs(toml_text: &str, expect: Option<Jobs>) -> Result<()> {
+        let config = toml::from_str::<Config>(toml_text)?;
+        assert_eq!(config.jobs, expect);
+        Ok(())
+    }
+
+    #[test_case("", HashAlgorithm::Md5 ; "default")]
+    #[test_case(r#"hash-algorithm = "xxh3""#, HashAlgorithm::Xxh3 ; "xxh3")]
+    #[test_case(r#"hash-algorithm = "blake3""#, HashAlgorithm::Blake3 ; "blake3")]
+    #[parallel]
+    fn tidy_hash_algorithm(toml_text: &str, expect: HashAlgorithm) -> Result<()> {
+        let config = toml::from_str::<Config>(&format!("[tidy]\n{toml_text}"))?;
+        assert_eq!(config.tidy.hash_algorithm, expect);
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn jobs_invalid() {
+        let err = toml::from_str::<Config>(r#"jobs = "lots""#).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(r#"The jobs value "lots" is not valid"#),
+            "{err}",
+        );
+    }
+
+    #[test_case(&[], None ; "no names")]
+    #[test_case(&["rustfmt"], Some(vec!["rustfmt"]) ; "plain name")]
+    #[test_case(&["go-*"], Some(vec!["go-lint-a", "go-lint-b"]) ; "glob")]
+    #[test_case(&["go-lint-?", "rust*"], Some(vec!["go-lint-a", "go-lint-b", "rustfmt"]) ; "several globs")]
+    #[test_case(&["go-lint-[a]"], Some(vec!["go-lint-a"]) ; "character class")]
+    #[parallel]
+    fn command_name_matcher(names: &[&str], expect: Option<Vec<&str>>) -> Result<()> {
+        let names = names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let matched = super::command_name_matcher(&names)?.map(|m| {
+            ["go-lint-a", "go-lint-b", "rustfmt", "clippy"]
+                .into_iter()
+                .filter(|n| m.is_match(n))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(matched, expect);
+
+        Ok(())
+    }
+
+    #[test_case(&[], &[], None, LintOrTidyCommandType::Lint, &[("rustfmt-check", "type"), ("shellcheck", "disabled"), ("typos", "label")] ; "lint")]
+    #[test_case(&[], &[], None, LintOrTidyCommandType::Tidy, &[("clippy", "type"), ("shellcheck", "disabled"), ("typos", "label")] ; "tidy")]
+    #[test_case(&[], &[], Some("spelling"), LintOrTidyCommandType::Lint, &[("clippy", "label"), ("rustfmt-check", "label"), ("shellcheck", "disabled")] ; "with a label")]
+    #[test_case(&["clippy", "typos"], &[], None, LintOrTidyCommandType::Lint, &[("typos", "label")] ; "with names")]
+    #[test_case(&[], &["clippy", "shell*"], None, LintOrTidyCommandType::Lint, &[("clippy", "skip-command"), ("rustfmt-check", "type"), ("shellcheck", "skip-command"), ("typos", "label")] ; "with skip")]
+    #[parallel]
+    fn skipped_commands(
+        names: &[&str],
+        skip: &[&str],
+        label: Option<&str>,
+        typ: LintOrTidyCommandType,
+        expect: &[(&str, &str)],
+    ) -> Result<()> {
+        let toml_text = r#"
+            [commands.clippy]
+            type = "lint"
+            include = "**/*.rs"
+            cmd = "clippy"
+            ok-exit-codes = 0
+
+            [commands.rustfmt-check]
+            type = "tidy"
+            include = "**/*.rs"
+            cmd = "rustfmt"
+            ok-exit-codes = 0
+
+            [commands.shellcheck]
+            type = "lint"
+            include = "**/*.sh"
+            cmd = "shellcheck"
+            ok-exit-codes = 0
+            enabled = false
+
+            [commands.typos]
+            type = "lint"
+            include = "**/*"
+            cmd = "typos"
+            labels = "spelling"
+            ok-exit-codes = 0
+        "#;
+        let config: Config = toml::from_str(toml_text)?;
+        let names = names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let skip = skip.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let skipped = config.skipped_commands(&names, &skip, label, typ)?;
+        assert_eq!(
+            skipped
+                .iter()
+                .map(|(n, r)| (n.as_str(), r.as_str()))
+                .sorted()
+                .collect::<Vec<_>>(),
+            expect,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn command_name_matcher_with_invalid_glob() {
+        assert_eq!(
+            super::command_name_matcher(&[String::from("go-[a")]).unwrap_err(),
+            ConfigError::InvalidCommandNameGlob {
+                name: String::from("go-[a"),
+                error: String::from("unclosed character class; missing ']'"),
+            },
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn command_templates() -> Result<()> {
+        let toml_text = r#"
+            [commands.rustfmt]
+            type = "both"
+            include = "*.rs"
+            cmd = "rustfmt"
+            ok-exit-codes = 0
+
+            [command-templates.go-lint]
+            matrix = { dir = ["api", "web"], version = [1, 2] }
+            type = "lint"
+            include = "{dir}/**/*.go"
+            cmd = ["golangci-lint", "run", "--version={version}"]
+            env = { GO_DIR = "{dir}" }
+            ok-exit-codes = 0
+
+            [command-templates.vet]
+            matrix = { dir = ["api", "web"] }
+            name = "go-vet-in-{dir}"
+            type = "lint"
+            include = "{dir}/**/*.go"
+            cmd = "go vet"
+            ok-exit-codes = 0
+        "#;
+
+        let mut config: Config = toml::from_str(toml_text)?;
+        config.expand_command_templates()?;
+        let commands = config.command_info();
+        assert_eq!(
+            commands.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(),
+            vec![
+                "rustfmt",
+                "go-lint-api-1",
+                "go-lint-api-2",
+                "go-lint-web-1",
+                "go-lint-web-2",
+                "go-vet-in-api",
+                "go-vet-in-web",
+            ],
+        );
+
+        let (_, go_lint) = &commands[3];
+        assert_eq!(go_lint.include, vec!["web/**/*.go"]);
+        assert_eq!(go_lint.cmd, vec!["golangci-lint", "run", "--version=1"]);
+        assert_eq!(
+            go_lint.env,
+            HashMap::from([(String::from("GO_DIR"), String::from("web"))]),
+        );
+
+        Ok(())
+    }
+
+    #[test_case(
+        r#"
+            [command-templates.go]
+            matrix = {}
+            type = "lint"
+            include = "*.go"
+            cmd = "go vet"
+            ok-exit-codes = 0
+        "#,
+        ConfigError::EmptyMatrix {
+            template: String::from("go"),
+        } ;
+        "empty matrix"
+    )]
+    #[test_case(
+        r#"
+            [command-templates.go]
+            matrix = { dir = [] }
+            type = "lint"
+            include = "*.go"
+            cmd = "go vet"
+            ok-exit-codes = 0
+        "#,
+        ConfigError::EmptyMatrix {
+            template: String::from("go"),
+        } ;
+        "matrix key with no values"
+    )]
+    #[test_case(
+        r#"
+            [command-templates.go]
+            matrix = { dir = [true] }
+            type = "lint"
+            include = "*.go"
+            cmd = "go vet"
+            ok-exit-codes = 0
+        "#,
+        ConfigError::InvalidMatrixValue {
+            template: String::from("go"),
+            key: String::from("dir"),
+            value: String::from("true"),
+        } ;
+        "boolean matrix value"
+    )]
+    #[test_case(
+        r#"
+            [commands.go-api]
+            type = "lint"
+            include = "*.go"
+            cmd = "go vet"
+            ok-exit-codes = 0
+
+            [command-templates.go]
+            matrix = { dir = ["api"] }
+            type = "lint"
+            include = "{dir}/*.go"
+            cmd = "go vet"
+            ok-exit-codes = 0
+        "#,
+        ConfigError::DuplicateCommandName {
+            template: String::from("go"),
+            name: String::from("go-api"),
+        } ;
+        "duplicate command name"
+    )]
+    #[test_case(
+        r#"
+            [command-templates.go]
+            matrix = { dir = ["api"] }
+            type = "lint"
+            include = "{dir}/*.go"
+            ok-exit-codes = 0
+        "#,
+        ConfigError::InvalidCommandFromTemplate {
+            template: String::from("go"),
+            name: String::from("go-api"),
+            error: String::from("missing field `cmd`"),
+        } ;
+        "invalid command"
+    )]
+    #[parallel]
+    fn command_templates_errors(toml_text: &str, expect: ConfigError) -> Result<()> {
+        let mut config: Config = toml::from_str(toml_text)?;
+        assert_eq!(config.expand_command_templates(), Err(expect));
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn profiles() -> Result<()> {
+        let toml_text = r#"
+            [profiles.pre-commit]
+            actions = ["tidy", "lint"]
+            mode = "staged"
+            label = "quick"
+            restage = true
+
+            [profiles.ci]
+            actions = "lint"
+            git-diff-from = "main"
+            command = "rustfmt"
+
+            [profiles.some]
+            actions = "lint"
+            mode = "all"
+            command = ["rustfmt", "clippy"]
+
+            [profiles.broken]
+            actions = "lint"
+            mode = "all"
+            git-diff-from = "main"
+
+            [commands.rustfmt]
+            type = "both"
+            include = "**/*.rs"
+            cmd = [ "rustfmt" ]
+            ok-exit-codes = 0
+        "#;
+
+        let config = toml::from_str::<Config>(toml_text)?;
+        let pre_commit = config.profile("pre-commit")?;
+        assert_eq!(
+            pre_commit.actions,
+            vec![ProfileAction::Tidy, ProfileAction::Lint],
+        );
+        assert_eq!(pre_commit.mode, Some(ProfileMode::Staged));
+        assert_eq!(pre_commit.label.as_deref(), Some("quick"));
+        assert!(pre_commit.restage);
+
+        let ci = config.profile("ci")?;
+        assert_eq!(ci.actions, vec![ProfileAction::Lint]);
+        assert_eq!(ci.git_diff_from.as_deref(), Some("main"));
+        assert_eq!(ci.command, vec!["rustfmt"]);
+
+        assert_eq!(
+            config.profile("some")?.command,
+            vec!["rustfmt", "clippy"],
+            "command can be a list of names",
+        );
+
+        assert_eq!(
+            config.profile("broken").unwrap_err(),
+            ConfigError::ProfileHasModeAndGitDiffFrom {
+                name: "broken".into()
+            },
+        );
+        assert_eq!(
+            config.profile("nope").unwrap_err(),
+            ConfigError::UnknownProfile {
+                name: "nope".into()
+            },
+        );
+
+        let err = toml::from_str::<Config>(
+            r#"
+            profiles.bad.actions = "check"
+            commands = {}
+        "#,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("unknown variant `check`"),
+            "an unknown action is an error: {err}",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn runner() -> Result<()> {
+        let toml_text = r#"
+            [commands.golangci-lint]
+            type = "lint"
+            include = "**/*.go"
+            cmd = [ "golangci-lint", "run" ]
+            runner = { type = "docker", image = "golangci/golangci-lint:v1.59" }
+            ok-exit-codes = 0
+            lint-failure-exit-codes = 1
+        "#;
+
+        let config: Config = toml::from_str(toml_text)?;
+        assert_eq!(
+            config.commands[0].runner,
+            Some(Runner::Docker {
+                image: String::from("golangci/golangci-lint:v1.59")
+            }),
+        );
+
+        Ok(())
+    }
+
+    #[test_case(
+        r#"runner = "plugin:bazel""#,
+        Some(Runner::Plugin { name: String::from("bazel") }) ;
+        "plugin string"
+    )]
+    #[test_case(
+        r#"runner = { type = "plugin", name = "bazel" }"#,
+        Some(Runner::Plugin { name: String::from("bazel") }) ;
+        "plugin table"
+    )]
+    #[test_case(
+        r#"runner = { type = "podman", image = "alpine" }"#,
+        Some(Runner::Podman { image: String::from("alpine") }) ;
+        "container table"
+    )]
+    #[test_case(r#"runner = "plugin:""#, None ; "plugin string without a name")]
+    #[test_case(r#"runner = "docker""#, None ; "string that is not a plugin")]
+    #[parallel]
+    fn runner_forms(runner: &str, expect: Option<Runner>) {
+        let toml_text = format!(
+            r#"
+            [commands.foo]
+            type = "lint"
+            include = "*"
+            cmd = "foo"
+            {runner}
+            ok-exit-codes = 0
+            "#,
+        );
+        let config = toml::from_str::<Config>(&toml_text);
+        match expect {
+            Some(expect) => {
+                assert_eq!(
+                    config.expect("config is valid").commands[0].runner,
+                    Some(expect)
+                );
+            }
+            None => assert!(config.is_err(), "{runner} is not a valid runner"),
+        }
+    }
+
+    #[test]
+    #[parallel]
+    fn dependencies_must_come_first() -> Result<()> {
+        let toml_text = r#"
+            [commands.a]
+            type = "lint"
+            include = "*"
+            cmd = "a"
+            ok-exit-codes = 0
+            depends-on = "b"
+
+            [commands.b]
+            type = "lint"
+            include = "*"
+            cmd = "b"
+            ok-exit-codes = 0
+            depends-on = ["c"]
+        "#;
+
+        let config: Config = toml::from_str(toml_text)?;
+        assert_eq!(
+            config.check_dependencies(),
+            Err(ConfigError::DependencyMustComeFirst {
+                name: "a".to_string(),
+                dep: "b".to_string(),
+            }),
+        );
+
+        let mut config: Config = toml::from_str(toml_text)?;
+        config.commands.shift_remove("a");
+        assert_eq!(
+            config.check_dependencies(),
+            Err(ConfigError::UnknownDependency {
+                name: "b".to_string(),
+                dep: "c".to_string(),
+            }),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn conflicts_with_must_name_a_command() -> Result<()> {
+        let toml_text = r#"
+            [commands.a]
+            type = "tidy"
+            include = "*"
+            cmd = "a"
+            ok-exit-codes = 0
+            conflicts-with = "c"
+        "#;
+
+        let config: Config = toml::from_str(toml_text)?;
+        assert_eq!(
+            config.check_dependencies(),
+            Err(ConfigError::UnknownConflict {
+                name: "a".to_string(),
+                other: "c".to_string(),
+            }),
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,623 @@
+// Loads the base config named by a config file's `extends` key and merges the
+// file on top of it. This lets an organization keep one config with its lint
+// policy and share it across many repos, either as a file in the repo, like a
+// git submodule or a vendored directory, or at a URL.
+//
+// A base config at a URL is pinned by its SHA-256 checksum, so a repo always
+// gets exactly the config it was tested with, even if the file at the URL
+// changes. Since the checksum identifies the content, downloaded configs are
+// cached by checksum and never need to be fetched again.
+use crate::config;
+use anyhow::Result;
+use log::{debug, warn};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    env, fmt, fs, mem,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A base config file that this config file is merged on top of
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum ExtendsConfig {
+    /// The path to the base config file, relative to the directory containing
+    /// this config file
+    Path(PathBuf),
+    Url {
+        /// The URL of the base config file. This can be an `http`, `https`,
+        /// or `file` URL.
+        url: String,
+        /// The SHA-256 checksum of the base config file, as a hex string
+        sha256: String,
+    },
+}
+
+impl fmt::Display for ExtendsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtendsConfig::Path(path) => write!(f, "{}", path.display()),
+            ExtendsConfig::Url { url, .. } => write!(f, "{url}"),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum ConfigExtendsError {
+    #[error("Could not fetch the base config file at {url:}: {error:}")]
+    FetchFailed { url: String, error: String },
+    #[error("The base config file at {url:} has a SHA-256 checksum of {actual:}, but the config file says it should be {expected:}")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("The base config file at {url:} extends {}, but a config file from a URL can only extend another URL", path.display())]
+    PathInRemoteConfig { url: String, path: PathBuf },
+    #[error("The config file at {} extends itself, directly or through another base config", file.display())]
+    Cycle { file: PathBuf },
+}
+
+// Returns the table with all of its base configs merged into it. If the table
+// has no `extends` key it's returned unchanged.
+pub(crate) fn resolve(file: &Path, table: toml::Table) -> Result<toml::Table> {
+    resolve_with_cache(file, table, cache_dir().as_deref())
+}
+
+fn resolve_with_cache(
+    file: &Path,
+    table: toml::Table,
+    cache_dir: Option<&Path>,
+) -> Result<toml::Table> {
+    let mut seen = vec![canonical(file)];
+    resolve_from(
+        Origin::File(file.to_path_buf()),
+        table,
+        cache_dir,
+        &mut seen,
+    )
+}
+
+// Where a config came from, which determines what a relative path in its
+// `extends` key is relative to.
+enum Origin {
+    File(PathBuf),
+    Url(String),
+}
+
+fn resolve_from(
+    origin: Origin,
+    mut table: toml::Table,
+    cache_dir: Option<&Path>,
+    seen: &mut Vec<PathBuf>,
+) -> Result<toml::Table> {
+    let Some(extends) = table.remove("extends") else {
+        return Ok(table);
+    };
+    let extends: ExtendsConfig = extends.try_into()?;
+
+    let (base_origin, s) = match (&extends, origin) {
+        (ExtendsConfig::Path(path), Origin::File(file)) => {
+            let base_file = file
+                .parent()
+                .map_or_else(|| path.clone(), |dir| dir.join(path));
+            let canonical = canonical(&base_file);
+            if seen.contains(&canonical) {
+                return Err(ConfigExtendsError::Cycle { file: base_file }.into());
+            }
+            seen.push(canonical);
+            let s = fs::read_to_string(&base_file).map_err(|e| {
+                config::ConfigError::FileCannotBeRead {
+                    file: base_file.clone(),
+                    error: e.to_string(),
+                }
+            })?;
+            (Origin::File(base_file), s)
+        }
+        (ExtendsConfig::Path(path), Origin::Url(url)) => {
+            return Err(ConfigExtendsError::PathInRemoteConfig {
+                url,
+                path: path.clone(),
+            }
+            .into());
+        }
+        (ExtendsConfig::Url { url, sha256 }, _) => {
+            (Origin::Url(url.clone()), fetch(url, sha256, cache_dir)?)
+        }
+    };
+
+    let base: toml::Table = toml::from_str(&s)?;
+    let base_file = match &base_origin {
+        Origin::File(file) => file.clone(),
+        Origin::Url(url) => PathBuf::from(url),
+    };
+    config::check_key_styles(&base_file, &base)?;
+    let base = resolve_from(base_origin, base, cache_dir, seen)?;
+
+    let mut merged = merge(base, table);
+    // The base's own `extends` is resolved by now, so only the top-level one
+    // is kept, which lets `config show` say where the base came from.
+    merged.insert("extends".to_string(), extends_value(&extends));
+    Ok(merged)
+}
+
+fn extends_value(extends: &ExtendsConfig) -> toml::Value {
+    match extends {
+        ExtendsConfig::Path(path) => toml::Value::String(path.to_string_lossy().into_owned()),
+        ExtendsConfig::Url { url, sha256 } => toml::Value::Table(toml::Table::from_iter([
+            ("url".to_string(), toml::Value::String(url.clone())),
+            ("sha256".to_string(), toml::Value::String(sha256.clone())),
+        ])),
+    }
+}
+
+// A file that doesn't exist can't be part of a cycle, so this falls back to
+// the path as given.
+fn canonical(file: &Path) -> PathBuf {
+    fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf())
+}
+
+fn fetch(url: &str, sha256: &str, cache_dir: Option<&Path>) -> Result<String> {
+    let expected = sha256.to_lowercase();
+    let cache_file = cache_dir.map(|d| d.join(format!("{expected}.toml")));
+    if let Some(cache_file) = &cache_file {
+        if let Ok(s) = fs::read_to_string(cache_file) {
+            // A cached file could be truncated or edited, so it's checked
+            // just like a fresh download would be. If it's bad we fetch it
+            // again.
+            if sha256_hex(&s) == expected {
+                debug!(
+                    "Using the cached copy of {} at {}",
+                    url,
+                    cache_file.display(),
+                );
+                return Ok(s);
+            }
+            warn!(
+                "The cached copy of {} at {} does not match its checksum, so it will be fetched again",
+                url,
+                cache_file.display(),
+            );
+        }
+    }
+
+    debug!("Fetching the base config file at {url}");
+    let s = download(url).map_err(|error| ConfigExtendsError::FetchFailed {
+        url: url.to_string(),
+        error,
+    })?;
+    let actual = sha256_hex(&s);
+    if actual != expected {
+        return Err(ConfigExtendsError::ChecksumMismatch {
+            url: url.to_string(),
+            expected,
+            actual,
+        }
+        .into());
+    }
+
+    if let Some(cache_file) = &cache_file {
+        if let Err(e) = write_cache_file(cache_file, &s) {
+            warn!(
+                "Could not cache the base config file at {}: {e}",
+                cache_file.display(),
+            );
+        }
+    }
+
+    Ok(s)
+}
+
+fn download(url: &str) -> Result<String, String> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return fs::read_to_string(path).map_err(|e| e.to_string());
+    }
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err("only http, https, and file URLs are supported".to_string());
+    }
+    ureq::get(url)
+        .timeout(FETCH_TIMEOUT)
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_string()
+        .map_err(|e| e.to_string())
+}
+
+// Several precious processes may fetch the same config at once, so the file is
+// written to a temp file and then renamed into place.
+fn write_cache_file(cache_file: &Path, content: &str) -> Result<()> {
+    let dir = cache_file
+        .parent()
+        .unwrap_or_else(|| unreachable!("The cache file is always in a directory"));
+    fs::create_dir_all(dir)?;
+    let temp = NamedTempFile::new_in(dir)?;
+    fs::write(temp.path(), content)?;
+    temp.persist(cache_file)?;
+    Ok(())
+}
+
+fn sha256_hex(s: &str) -> String {
+    format!("{:x}", Sha256::digest(s.as_bytes()))
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("PRECIOUS_CACHE_DIR").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    Some(user_cache_dir()?.join("configs"))
+}
+
+// This is `$XDG_CACHE_HOME/precious`, or the usual default for that on each
+// platform.
+pub(crate) fn user_cache_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                env::var_os("LOCALAPPDATA").map(PathBuf::from)
+            } else {
+                env::var_os("HOME").map(|h| Path::new(&h).join(".cache"))
+            }
+        })?;
+    Some(base.join("precious"))
+}
+
+// Every key in the local config replaces the same key in the base, except for
+// the top-level `exclude` key and tables. The local `exclude` patterns are
+// added after the base's, so the local config can use `!` patterns to
+// un-exclude paths that the base excludes. Tables are merged key by key,
+// recursively. In the `commands`, `command-templates`, and `profiles` tables,
+// each entry is merged with the base entry of the same name, so a repo can
+// override just the `cmd` of a base command. A merged command keeps its place
+// in the base's order, and new commands come after the base's commands.
+fn merge(mut base: toml::Table, local: toml::Table) -> toml::Table {
+    for (key, value) in local {
+        let base_value = take_either_style(&mut base, &key);
+        let value = match (key.as_str(), base_value, value) {
+            ("exclude", Some(b), l) => {
+                toml::Value::Array(into_array(b).into_iter().chain(into_array(l)).collect())
+            }
+            (
+                "commands" | "command-templates" | "command_templates" | "profiles",
+                Some(toml::Value::Table(b)),
+                toml::Value::Table(l),
+            ) => toml::Value::Table(merge_entries(b, l)),
+            (_, Some(toml::Value::Table(b)), toml::Value::Table(l)) => {
+                toml::Value::Table(merge_settings(b, l))
+            }
+            (_, _, value) => value,
+        };
+        base.insert(key, value);
+    }
+    base
+}
+
+// The `exclude` key can be a single string or an array of strings.
+fn into_array(value: toml::Value) -> Vec<toml::Value> {
+    match value {
+        toml::Value::Array(a) => a,
+        v => vec![v],
+    }
+}
+
+// Entry names are matched exactly, since a command named `foo-bar` is not the
+// same as one named `foo_bar`. Each merged entry is updated in place to keep
+// its position.
+fn merge_entries(mut base: toml::Table, local: toml::Table) -> toml::Table {
+    for (name, value) in local {
+        match (base.get_mut(&name), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(l)) => {
+                *b = merge_settings(mem::take(b), l);
+            }
+            (_, value) => {
+                base.insert(name, value);
+            }
+        }
+    }
+    base
+}
+
+fn merge_settings(mut base: toml::Table, local: toml::Table) -> toml::Table {
+    for (key, value) in local {
+        let value = match (take_either_style(&mut base, &key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(l)) => {
+                toml::Value::Table(merge_settings(b, l))
+            }
+            (_, value) => value,
+        };
+        base.insert(key, value);
+    }
+    base
+}
+
+// The base and local configs may use different key styles, so
+// `partially-staged` in the local config replaces `partially_staged` in the
+// base.
+fn take_either_style(table: &mut toml::Table, key: &str) -> Option<toml::Value> {
+    table
+        .remove(key)
+        .or_else(|| table.remove(&key.replace('-', "_")))
+        .or_else(|| table.remove(&key.replace('_', "-")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    const BASE: &str = r#"
+exclude = ["target"]
+jobs = 4
+
+[tidy]
+partially_staged = "stash"
+
+[commands.rustfmt]
+type = "both"
+include = "**/*.rs"
+cmd = ["rustfmt"]
+ok_exit_codes = 0
+
+[commands.typos]
+type = "lint"
+include = "**/*"
+cmd = ["typos"]
+ok_exit_codes = 0
+"#;
+
+    fn base_file(td: &Path) -> Result<(String, String)> {
+        let file = td.join("base.toml");
+        fs::write(&file, BASE)?;
+        Ok((format!("file://{}", file.display()), sha256_hex(BASE)))
+    }
+
+    #[test]
+    #[parallel]
+    fn merge_with_base() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let (url, sha256) = base_file(td.path())?;
+        let local = format!(
+            r#"
+extends = {{ url = "{url}", sha256 = "{sha256}" }}
+exclude = ["vendor"]
+
+[tidy]
+auto-restage = true
+
+[commands.rustfmt]
+type = "both"
+include = "**/*.rs"
+cmd = ["rustfmt", "--edition", "2021"]
+ok-exit-codes = 0
+
+[commands.clippy]
+type = "lint"
+include = "**/*.rs"
+cmd = ["cargo", "clippy"]
+ok-exit-codes = 0
+"#
+        );
+
+        let merged = resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(&local)?,
+            None,
+        )?;
+        assert_eq!(
+            merged["exclude"],
+            toml::Value::from(vec!["target", "vendor"]),
+        );
+        assert_eq!(merged["jobs"], toml::Value::from(4));
+        assert_eq!(
+            merged["tidy"],
+            toml::Value::Table(toml::from_str(
+                r#"
+partially_staged = "stash"
+auto-restage = true
+"#
+            )?),
+        );
+        let commands = merged["commands"].as_table().unwrap();
+        assert_eq!(
+            commands.keys().collect::<Vec<_>>(),
+            vec!["rustfmt", "typos", "clippy"],
+        );
+        assert_eq!(
+            commands["rustfmt"]["cmd"],
+            toml::Value::from(vec!["rustfmt", "--edition", "2021"]),
+        );
+        assert_eq!(merged["extends"]["url"], toml::Value::from(url));
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn checksum_mismatch() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let (url, _) = base_file(td.path())?;
+        let wrong = sha256_hex("something else");
+        let local = format!(r#"extends = {{ url = "{url}", sha256 = "{wrong}" }}"#);
+
+        let res = resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(&local)?,
+            None,
+        );
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<ConfigExtendsError>(),
+            Some(&ConfigExtendsError::ChecksumMismatch {
+                url,
+                expected: wrong,
+                actual: sha256_hex(BASE),
+            }),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn uses_cache() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let cache = td.path().join("cache");
+        let (url, sha256) = base_file(td.path())?;
+        let local = format!(r#"extends = {{ url = "{url}", sha256 = "{sha256}" }}"#);
+
+        resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(&local)?,
+            Some(&cache),
+        )?;
+        assert_eq!(
+            fs::read_to_string(cache.join(format!("{sha256}.toml")))?,
+            BASE,
+        );
+
+        // Once it's cached, the original is never read again.
+        fs::remove_file(td.path().join("base.toml"))?;
+        let merged = resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(&local)?,
+            Some(&cache),
+        )?;
+        assert!(merged["commands"].as_table().unwrap().contains_key("typos"));
+
+        // A corrupted cache file is fetched again, which fails here because
+        // the original is gone.
+        fs::write(cache.join(format!("{sha256}.toml")), "jobs = 1")?;
+        let res = resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(&local)?,
+            Some(&cache),
+        );
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<ConfigExtendsError>(),
+            Some(ConfigExtendsError::FetchFailed { .. }),
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn unsupported_url() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let local = format!(
+            r#"extends = {{ url = "ftp://example.com/precious.toml", sha256 = "{}" }}"#,
+            sha256_hex(BASE),
+        );
+        let res = resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(&local)?,
+            None,
+        );
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<ConfigExtendsError>(),
+            Some(&ConfigExtendsError::FetchFailed {
+                url: "ftp://example.com/precious.toml".to_string(),
+                error: "only http, https, and file URLs are supported".to_string(),
+            }),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn merge_with_base_path() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let shared = td.path().join("shared");
+        fs::create_dir(&shared)?;
+        fs::write(shared.join("base.toml"), BASE)?;
+        let local = r#"
+extends = "shared/base.toml"
+exclude = "!target/keep"
+
+[commands.rustfmt]
+cmd = ["rustfmt", "--edition", "2021"]
+"#;
+
+        let merged = resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(local)?,
+            None,
+        )?;
+        assert_eq!(
+            merged["exclude"],
+            toml::Value::from(vec!["target", "!target/keep"]),
+        );
+        let commands = merged["commands"].as_table().unwrap();
+        assert_eq!(
+            commands.keys().collect::<Vec<_>>(),
+            vec!["rustfmt", "typos"],
+        );
+        assert_eq!(
+            commands["rustfmt"],
+            toml::Value::Table(toml::from_str(
+                r#"
+type = "both"
+include = "**/*.rs"
+cmd = ["rustfmt", "--edition", "2021"]
+ok_exit_codes = 0
+"#
+            )?),
+        );
+        assert_eq!(merged["extends"], toml::Value::from("shared/base.toml"),);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn extends_cycle() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        fs::write(td.path().join("a.toml"), r#"extends = "b.toml""#)?;
+        fs::write(td.path().join("b.toml"), r#"extends = "a.toml""#)?;
+
+        let file = td.path().join("a.toml");
+        let res = resolve_with_cache(&file, toml::from_str(&fs::read_to_string(&file)?)?, None);
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<ConfigExtendsError>(),
+            Some(&ConfigExtendsError::Cycle { file }),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn path_in_remote_config() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let remote = r#"extends = "base.toml""#;
+        fs::write(td.path().join("remote.toml"), remote)?;
+        let url = format!("file://{}", td.path().join("remote.toml").display());
+        let local = format!(
+            r#"extends = {{ url = "{url}", sha256 = "{}" }}"#,
+            sha256_hex(remote),
+        );
+
+        let res = resolve_with_cache(
+            &td.path().join("precious.toml"),
+            toml::from_str(&local)?,
+            None,
+        );
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<ConfigExtendsError>(),
+            Some(&ConfigExtendsError::PathInRemoteConfig {
+                url,
+                path: PathBuf::from("base.toml"),
+            }),
+        );
+
+        Ok(())
+    }
+}
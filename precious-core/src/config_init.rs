@@ -361,8 +361,8 @@ const SHELL_COMMANDS: [(&str, &str); 2] = [
 type = "lint"
 include = "**/*.sh"
 cmd = "shellcheck"
-ok_exit_codes = 0
-lint_failure_exit_codes = 1
+ok-exit-codes = 0
+lint-failure-exit-codes = 1
 "#,
     ),
     (
@@ -371,10 +371,10 @@ lint_failure_exit_codes = 1
 type = "both"
 include = "**/*.sh"
 cmd = ["shfmt", "--simplify", "--indent", "4"]
-lint_flags = "--diff"
-tidy_flags = "--write"
-ok_exit_codes = 0
-lint_failure_exit_codes = 1
+lint-flags = "--diff"
+tidy-flags = "--write"
+ok-exit-codes = 0
+lint-failure-exit-codes = 1
 "#,
     ),
 ];
@@ -447,10 +447,10 @@ const TOML_COMMANDS: [(&str, &str); 1] = [(
 type = "both"
 include = "**/*.toml"
 cmd = ["taplo", "format", "--option", "indent_string=    ", "--option", "column_width=100"]
-lint_flags = "--check"
-ok_exit_codes = 0
-lint_failure_exit_codes = 1
-ignore_stderr = "INFO taplo.+"
+lint-flags = "--check"
+ok-exit-codes = 0
+lint-failure-exit-codes = 1
+ignore-stderr = "INFO taplo.+"
 "#,
 )];
 
@@ -0,0 +1,347 @@
+// Rewrites a config file to use the current preferred style. All keys are
+// converted to kebab-case, and the deprecated `run-mode` and `chdir` options
+// are replaced with their `invoke`, `working-dir`, and `path-args` equivalents.
+//
+// This works on the parsed document with `toml_edit` rather than
+// deserializing the config, so that comments, ordering, and formatting are
+// preserved wherever possible.
+use crate::{
+    command::{Invoke, PathArgs, WorkingDir},
+    config::{self, OldRunMode},
+};
+use anyhow::Result;
+use std::{fs, path::Path};
+use thiserror::Error;
+use toml_edit::{value, DocumentMut, Item, Key, Table};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum ConfigMigrateError {
+    #[error("The {name:} command has an invalid run-mode value: {run_mode:}")]
+    InvalidRunMode { name: String, run_mode: String },
+    #[error("The {name:} command has an invalid chdir value: {chdir:}")]
+    InvalidChdir { name: String, chdir: String },
+}
+
+const OLD_PARAMS: &[&str] = &["run-mode", "run_mode", "chdir"];
+const NEW_PARAMS: &[&str] = &[
+    "invoke",
+    "working-dir",
+    "working_dir",
+    "path-args",
+    "path_args",
+];
+
+// Returns true if the file was changed.
+pub(crate) fn migrate_file(file: &Path) -> Result<bool> {
+    let orig = fs::read_to_string(file)?;
+    let migrated = migrate(&orig)?;
+    if migrated == orig {
+        return Ok(false);
+    }
+
+    fs::write(file, migrated)?;
+    Ok(true)
+}
+
+fn migrate(toml: &str) -> Result<String> {
+    let mut doc = toml.parse::<DocumentMut>()?;
+
+    rename_keys(doc.as_table_mut());
+    if let Some(ui) = doc.get_mut("ui").and_then(Item::as_table_mut) {
+        rename_keys(ui);
+        if let Some(chars) = ui.get_mut("chars").and_then(Item::as_table_mut) {
+            rename_keys(chars);
+        }
+    }
+    if let Some(tidy) = doc.get_mut("tidy").and_then(Item::as_table_mut) {
+        rename_keys(tidy);
+    }
+    // The profile names are left alone, just like the command names.
+    if let Some(profiles) = doc.get_mut("profiles").and_then(Item::as_table_mut) {
+        for (_, profile) in profiles.iter_mut() {
+            if let Some(profile) = profile.as_table_mut() {
+                rename_keys(profile);
+            }
+        }
+    }
+    for key in ["commands", "command-templates", "command_templates"] {
+        if let Some(commands) = doc.get_mut(key).and_then(Item::as_table_mut) {
+            for (name, command) in commands.iter_mut() {
+                if let Some(command) = command.as_table_mut() {
+                    replace_old_params(name.get(), command)?;
+                    rename_keys(command);
+                }
+            }
+        }
+    }
+
+    Ok(doc.to_string())
+}
+
+// There's no way to rename a key in place, so we remove every entry and
+// reinsert it, which keeps the keys in their original order.
+fn rename_keys(table: &mut Table) {
+    if !table.iter().any(|(k, _)| k.contains('_')) {
+        return;
+    }
+
+    for k in table.iter().map(|(k, _)| k.to_owned()).collect::<Vec<_>>() {
+        let (key, item) = table.remove_entry(&k).unwrap();
+        let key = if k.contains('_') {
+            Key::new(k.replace('_', "-")).with_leaf_decor(key.leaf_decor().clone())
+        } else {
+            key
+        };
+        table.insert_formatted(&key, item);
+    }
+}
+
+fn replace_old_params(name: &str, command: &mut Table) -> Result<()> {
+    if !OLD_PARAMS.iter().any(|k| command.contains_key(k)) {
+        return Ok(());
+    }
+    // Mixing old and new params is an error when the config is loaded. We
+    // can't know what the user intended, so we leave these alone.
+    if NEW_PARAMS.iter().any(|k| command.contains_key(k)) {
+        return Ok(());
+    }
+
+    let run_mode = match command.get("run-mode").or_else(|| command.get("run_mode")) {
+        None => None,
+        Some(item) => Some(match item.as_str() {
+            Some("files") => OldRunMode::Files,
+            Some("dirs") => OldRunMode::Dirs,
+            Some("root") => OldRunMode::Root,
+            _ => {
+                return Err(ConfigMigrateError::InvalidRunMode {
+                    name: name.to_owned(),
+                    run_mode: item.to_string().trim().to_owned(),
+                }
+                .into())
+            }
+        }),
+    };
+    let chdir = match command.get("chdir") {
+        None => None,
+        Some(item) => Some(
+            item.as_bool()
+                .ok_or_else(|| ConfigMigrateError::InvalidChdir {
+                    name: name.to_owned(),
+                    chdir: item.to_string().trim().to_owned(),
+                })?,
+        ),
+    };
+
+    let (invoke, working_dir, path_args) = config::translate_old_params(run_mode, chdir);
+    let new_params = [
+        ("invoke", invoke_value(invoke)),
+        ("working-dir", working_dir_value(&working_dir)),
+        ("path-args", path_args_value(path_args)),
+    ];
+
+    let mut replaced = false;
+    for k in command
+        .iter()
+        .map(|(k, _)| k.to_owned())
+        .collect::<Vec<_>>()
+    {
+        let (key, item) = command.remove_entry(&k).unwrap();
+        if !OLD_PARAMS.contains(&k.as_str()) {
+            command.insert_formatted(&key, item);
+            continue;
+        }
+        if replaced {
+            continue;
+        }
+
+        // The first new param takes the place of the first old param,
+        // including any comment that preceded it.
+        for (i, (new_key, v)) in new_params.iter().enumerate() {
+            let mut new_key = Key::new(*new_key);
+            if i == 0 {
+                new_key = new_key.with_leaf_decor(key.leaf_decor().clone());
+            }
+            command.insert_formatted(&new_key, value(*v));
+        }
+        replaced = true;
+    }
+
+    Ok(())
+}
+
+fn invoke_value(invoke: Invoke) -> &'static str {
+    match invoke {
+        Invoke::PerFile => "per-file",
+        Invoke::PerDir => "per-dir",
+        Invoke::Once => "once",
+        _ => unreachable!("The old params never translate to {invoke}"),
+    }
+}
+
+fn working_dir_value(working_dir: &WorkingDir) -> &'static str {
+    match working_dir {
+        WorkingDir::Root => "root",
+        WorkingDir::Dir => "dir",
+        WorkingDir::ChdirTo(_) => unreachable!("The old params never translate to chdir-to"),
+    }
+}
+
+fn path_args_value(path_args: PathArgs) -> &'static str {
+    match path_args {
+        PathArgs::File => "file",
+        PathArgs::Dir => "dir",
+        PathArgs::None => "none",
+        PathArgs::Dot => "dot",
+        _ => unreachable!("The old params never translate to path-args = {path_args}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    #[test]
+    #[parallel]
+    fn migrate_renames_keys_and_replaces_old_params() -> Result<()> {
+        let orig = r#"
+exclude = ["target"]
+
+[ui]
+ascii = true
+
+[ui.chars]
+lint_free = "ok"
+
+# Lints Rust code.
+[commands.rustfmt]
+type = "both"
+include = "**/*.rs"
+cmd = ["rustfmt", "--edition", "2021"]
+lint_flags = "--check"   # Just report.
+ok-exit-codes = 0
+lint_failure_exit_codes = 1
+
+[commands.go-vet]
+type = "lint"
+include = "**/*.go"
+cmd = ["go", "vet"]
+# Run once per package.
+run_mode = "dirs"
+chdir = true
+ok_exit_codes = 0
+
+[commands.go-vet.env]
+GO_FLAGS = "-mod=vendor"
+"#;
+        let expect = r#"
+exclude = ["target"]
+
+[ui]
+ascii = true
+
+[ui.chars]
+lint-free = "ok"
+
+# Lints Rust code.
+[commands.rustfmt]
+type = "both"
+include = "**/*.rs"
+cmd = ["rustfmt", "--edition", "2021"]
+lint-flags = "--check"   # Just report.
+ok-exit-codes = 0
+lint-failure-exit-codes = 1
+
+[commands.go-vet]
+type = "lint"
+include = "**/*.go"
+cmd = ["go", "vet"]
+# Run once per package.
+invoke = "per-dir"
+working-dir = "dir"
+path-args = "none"
+ok-exit-codes = 0
+
+[commands.go-vet.env]
+GO_FLAGS = "-mod=vendor"
+"#;
+        let migrated = migrate(orig)?;
+        assert_eq!(migrated, expect);
+        assert_eq!(migrate(&migrated)?, migrated, "migrating twice is a no-op");
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn migrate_renames_keys_in_profiles() -> Result<()> {
+        let orig = r#"
+[profiles.ci_fast]
+actions = "lint"
+parallel_commands = true
+summary-only = true
+
+[commands.rustfmt]
+type = "both"
+include = "**/*.rs"
+cmd = "rustfmt"
+ok-exit-codes = 0
+"#;
+        let expect = r#"
+[profiles.ci_fast]
+actions = "lint"
+parallel-commands = true
+summary-only = true
+
+[commands.rustfmt]
+type = "both"
+include = "**/*.rs"
+cmd = "rustfmt"
+ok-exit-codes = 0
+"#;
+        let migrated = migrate(orig)?;
+        assert_eq!(migrated, expect);
+        config::check_key_styles(Path::new("precious.toml"), &toml::from_str(&migrated)?)?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn migrate_leaves_mixed_old_and_new_params_alone() -> Result<()> {
+        let orig = r#"
+[commands.foo]
+type = "lint"
+include = "*"
+cmd = "foo"
+run-mode = "root"
+invoke = "once"
+ok-exit-codes = 0
+"#;
+        assert_eq!(migrate(orig)?, orig);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn migrate_rejects_invalid_run_mode() {
+        let orig = r#"
+[commands.foo]
+type = "lint"
+include = "*"
+cmd = "foo"
+run-mode = "sometimes"
+ok-exit-codes = 0
+"#;
+        let err = migrate(orig).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ConfigMigrateError>(),
+            Some(&ConfigMigrateError::InvalidRunMode {
+                name: "foo".to_string(),
+                run_mode: r#""sometimes""#.to_string(),
+            }),
+        );
+    }
+}
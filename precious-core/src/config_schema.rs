@@ -0,0 +1,345 @@
+// The JSON Schema printed by `precious config schema`. Its structure is
+// derived from the serde types in the `config` module, while the description
+// of each key comes from the tables in the `docs` module, so an editor shows
+// the same text as the generated reference.
+use crate::{
+    command::Runner,
+    config::Config,
+    docs::{self, ConfigKey},
+};
+use schemars::{
+    generate::SchemaSettings,
+    json_schema,
+    transform::{RecursiveTransform, RemoveRefSiblings, Transform},
+    Schema, SchemaGenerator,
+};
+use serde_json::{json, Value};
+
+pub(crate) fn schema() -> Schema {
+    let mut schema = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Config>();
+    schema.insert("title".to_string(), json!("precious config file"));
+
+    if let Some(properties) = schema.get_mut("properties") {
+        add_descriptions(properties, docs::TOP_LEVEL_KEYS);
+    }
+    if let Some(definitions) = schema.get_mut("definitions") {
+        for (name, keys) in [
+            ("UiConfig", docs::UI_KEYS),
+            ("TidyConfig", docs::TIDY_KEYS),
+            ("WorkspaceConfig", docs::WORKSPACE_KEYS),
+            ("ProfileConfig", docs::PROFILE_KEYS),
+            ("CommandConfig", docs::COMMAND_KEYS),
+        ] {
+            if let Some(properties) = definitions.pointer_mut(&format!("/{name}/properties")) {
+                add_descriptions(properties, keys);
+            }
+        }
+    }
+    RecursiveTransform(remove_null).transform(&mut schema);
+    // In draft 7, anything next to a `$ref` is ignored, so this moves each
+    // reference that now has a description into an `allOf`.
+    RemoveRefSiblings::default().transform(&mut schema);
+
+    schema
+}
+
+fn add_descriptions(properties: &mut Value, keys: &[ConfigKey]) {
+    let Some(properties) = properties.as_object_mut() else {
+        return;
+    };
+    for key in keys {
+        let Some(Value::Object(property)) = properties.get_mut(key.name) else {
+            continue;
+        };
+        property.insert(
+            "description".to_string(),
+            json!(key.description.replace(" <br> ", "\n\n")),
+        );
+    }
+}
+
+// TOML has no null, so an `Option` field can only be left out. This removes
+// the null that schemars allows for those fields.
+fn remove_null(schema: &mut Schema) {
+    let Some(obj) = schema.as_object_mut() else {
+        return;
+    };
+    if obj.get("default") == Some(&Value::Null) {
+        obj.remove("default");
+    }
+    if let Some(Value::Array(types)) = obj.get_mut("type") {
+        types.retain(|t| t != "null");
+        if types.len() == 1 {
+            let typ = types.remove(0);
+            obj.insert("type".to_string(), typ);
+        }
+    }
+    if let Some(Value::Array(any_of)) = obj.get_mut("anyOf") {
+        any_of.retain(|s| s.get("type") != Some(&json!("null")));
+        if any_of.len() == 1 {
+            if let Value::Object(only) = any_of.remove(0) {
+                obj.remove("anyOf");
+                obj.extend(only);
+            }
+        }
+    }
+}
+
+pub(crate) fn string_or_list(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "anyOf": [
+            { "type": "string" },
+            { "type": "array", "items": { "type": "string" } }
+        ]
+    })
+}
+
+pub(crate) fn exit_codes(_: &mut SchemaGenerator) -> Schema {
+    let code = json!({ "type": "integer", "minimum": 0, "maximum": 255 });
+    json_schema!({
+        "anyOf": [
+            code,
+            { "type": "array", "items": code }
+        ]
+    })
+}
+
+pub(crate) fn file_size(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "anyOf": [
+            { "type": "integer", "minimum": 0 },
+            { "type": "string", "pattern": "^\\s*[0-9.]+\\s*([kKmMgG]([iI]?[bB])?|[bB])?\\s*$" }
+        ]
+    })
+}
+
+pub(crate) fn jobs(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "anyOf": [
+            { "type": "integer" },
+            { "type": "string", "pattern": "^\\s*(-?[0-9]+|[0-9]+\\s*%)\\s*$" }
+        ]
+    })
+}
+
+// A template has the keys for a command, but a placeholder can stand in for
+// any string value, so the command keys aren't checked beyond that.
+pub(crate) fn command_templates(_: &mut SchemaGenerator) -> Schema {
+    let mut properties = json!({
+        "matrix": {
+            "type": "object",
+            "additionalProperties": {
+                "type": "array",
+                "items": { "type": ["string", "number"] },
+                "minItems": 1
+            },
+            "minProperties": 1
+        },
+        "name": { "type": "string" }
+    });
+    add_descriptions(&mut properties, docs::TEMPLATE_KEYS);
+    json_schema!({
+        "type": "object",
+        "additionalProperties": {
+            "type": "object",
+            "properties": properties,
+            "required": ["matrix"],
+            "additionalProperties": true,
+            "description": "A template takes all of the keys for a command as well as these."
+        }
+    })
+}
+
+pub(crate) fn working_dir(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "anyOf": [
+            { "enum": ["root", "dir"] },
+            {
+                "type": "object",
+                "properties": {
+                    "chdir-to": { "type": "string", "minLength": 1 }
+                },
+                "required": ["chdir-to"],
+                "additionalProperties": false
+            }
+        ]
+    })
+}
+
+pub(crate) fn runner(generator: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "anyOf": [
+            { "type": "string", "pattern": "^plugin:.+$" },
+            generator.subschema_for::<Runner>()
+        ]
+    })
+}
+
+pub(crate) fn profile_actions(_: &mut SchemaGenerator) -> Schema {
+    let action = json!({ "enum": ["lint", "tidy"] });
+    json_schema!({
+        "anyOf": [
+            action,
+            { "type": "array", "items": action, "minItems": 1 }
+        ]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    fn validate(config: &str) -> Vec<String> {
+        let config: toml::Table = toml::from_str(config).unwrap();
+        let instance = serde_json::to_value(config).unwrap();
+        let schema = serde_json::to_value(schema()).unwrap();
+        jsonschema::draft7::new(&schema)
+            .unwrap()
+            .iter_errors(&instance)
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    #[parallel]
+    fn every_key_is_documented() {
+        let schema = serde_json::to_value(schema()).unwrap();
+        for (pointer, keys) in [
+            ("/properties", docs::TOP_LEVEL_KEYS),
+            ("/definitions/UiConfig/properties", docs::UI_KEYS),
+            ("/definitions/TidyConfig/properties", docs::TIDY_KEYS),
+            (
+                "/definitions/WorkspaceConfig/properties",
+                docs::WORKSPACE_KEYS,
+            ),
+            ("/definitions/ProfileConfig/properties", docs::PROFILE_KEYS),
+            ("/definitions/CommandConfig/properties", docs::COMMAND_KEYS),
+        ] {
+            let properties = schema.pointer(pointer).unwrap().as_object().unwrap();
+            let mut documented = keys.iter().map(|k| k.name).collect::<Vec<_>>();
+            documented.sort_unstable();
+            // The tables under the top level are documented in their own
+            // sections rather than as keys.
+            let mut derived = properties
+                .keys()
+                .map(String::as_str)
+                .filter(|k| {
+                    ![
+                        "ui",
+                        "tidy",
+                        "workspace",
+                        "profiles",
+                        "commands",
+                        "command-templates",
+                    ]
+                    .contains(k)
+                })
+                .collect::<Vec<_>>();
+            derived.sort_unstable();
+            assert_eq!(derived, documented, "keys for {pointer}");
+
+            for (name, property) in properties {
+                assert!(
+                    property.get("description").is_some(),
+                    "{name} in {pointer} has a description",
+                );
+            }
+        }
+    }
+
+    #[test_case(include_str!("../../precious.toml"); "this repo")]
+    #[test_case(include_str!("../../examples/golang/precious.toml"); "golang example")]
+    #[test_case(include_str!("../../examples/perl/precious.toml"); "perl example")]
+    #[test_case(include_str!("../../examples/rust/precious.toml"); "rust example")]
+    #[test_case(
+        r#"
+            exclude-attributes = "linguist-generated"
+
+            [ui]
+            ascii = true
+            chars = { lint-free = "ok" }
+
+            [tidy]
+            partially-staged = "skip"
+
+            [profiles.pre-commit]
+            actions = ["tidy", "lint"]
+            mode    = "staged"
+
+            [commands.foo]
+            type          = "both"
+            include       = "*.foo"
+            cmd           = ["foo"]
+            invoke.per-file-or-dir = 10
+            working-dir.chdir-to   = "sub"
+            max-file-size = "1.5 MiB"
+            runner        = { type = "docker", image = "foo:latest" }
+            ok-exit-codes = 0
+        "#;
+        "every kind of value"
+    )]
+    #[test_case(
+        r#"
+            [commands.foo]
+            type          = "lint"
+            include       = "*.foo"
+            cmd           = ["foo"]
+            runner        = "plugin:bar"
+            ok-exit-codes = 0
+        "#;
+        "plugin runner"
+    )]
+    #[parallel]
+    fn valid_config(config: &str) {
+        assert_eq!(validate(config), Vec::<String>::new());
+    }
+
+    #[test_case(r#"invoke = "per-thing""#; "bad invoke")]
+    #[test_case("invoke.per-file-or-dir = -1"; "negative invoke count")]
+    #[test_case(r#"working-dir = "here""#; "bad working dir")]
+    #[test_case(r#"working-dir.chdir-to = """#; "empty chdir-to")]
+    #[test_case(r#"path-args = "files""#; "bad path args")]
+    #[test_case(r#"max-file-size = "10 bytes""#; "bad file size")]
+    #[test_case(r#"runner = { type = "lxc", image = "foo" }"#; "bad runner")]
+    #[test_case(r#"runner = "docker:foo""#; "bad runner string")]
+    #[test_case("lint-failure-exit-codes = [1, 256]"; "exit code out of range")]
+    #[test_case("env = { FOO = 1 }"; "env value is not a string")]
+    #[parallel]
+    fn invalid_command(extra: &str) {
+        let config = format!(
+            r#"
+                [commands.foo]
+                type          = "lint"
+                include       = "*.foo"
+                cmd           = "foo"
+                ok-exit-codes = 0
+                {extra}
+            "#,
+        );
+        assert!(!validate(&config).is_empty(), "{extra} is invalid");
+    }
+
+    #[test]
+    #[parallel]
+    fn missing_required_keys() {
+        let errors = validate(
+            r#"
+                [commands.foo]
+                type = "lint"
+                cmd  = "foo"
+            "#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                r#""include" is a required property"#.to_string(),
+                r#""ok-exit-codes" is a required property"#.to_string(),
+            ],
+        );
+    }
+}
@@ -0,0 +1,156 @@
+// Checks on the contents of files, used to keep files that a command can't
+// handle from being passed to it.
+use std::{fs, io::Read, path::Path};
+
+// This is how much of a file git looks at when deciding whether a file is
+// binary.
+const SNIFF_LEN: u64 = 8000;
+
+// A `precious:ignore` annotation has to be near the top of the file, so we
+// never read more than this much of each file looking for one.
+const ANNOTATION_SNIFF_LEN: u64 = 1024;
+
+const ANNOTATION: &str = "precious:ignore";
+
+// A file can opt out of some or all commands with a comment like
+// `# precious:ignore rustfmt clippy`. With no names, the file is ignored by
+// every command.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum IgnoreAnnotation {
+    AllCommands,
+    Commands(Vec<String>),
+}
+
+impl IgnoreAnnotation {
+    pub(crate) fn ignores(&self, command: &str) -> bool {
+        match self {
+            IgnoreAnnotation::AllCommands => true,
+            IgnoreAnnotation::Commands(names) => names.iter().any(|n| n == command),
+        }
+    }
+}
+
+// Like git, we treat any file with a null byte near the start as binary.
+pub(crate) fn is_binary(path: &Path) -> std::io::Result<bool> {
+    let mut buf = vec![];
+    fs::File::open(path)?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut buf)?;
+    Ok(buf.contains(&0))
+}
+
+pub(crate) fn ignore_annotation(path: &Path) -> std::io::Result<Option<IgnoreAnnotation>> {
+    let mut buf = vec![];
+    fs::File::open(path)?
+        .take(ANNOTATION_SNIFF_LEN)
+        .read_to_end(&mut buf)?;
+    Ok(parse_ignore_annotation(&String::from_utf8_lossy(&buf)))
+}
+
+// The annotation can be in any kind of comment, so we just look for it
+// anywhere in a line. The command names are the words after it, up to the
+// first one that can't be a name, like the `*/` that ends a C comment. Names
+// from more than one annotation are combined.
+fn parse_ignore_annotation(content: &str) -> Option<IgnoreAnnotation> {
+    let mut commands = vec![];
+    for line in content.lines() {
+        let Some((_, rest)) = line.split_once(ANNOTATION) else {
+            continue;
+        };
+        // This skips things like `precious:ignored`.
+        if rest.starts_with(|c: char| !c.is_whitespace() && c != ',') {
+            continue;
+        }
+
+        let names = rest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|n| !n.is_empty())
+            .take_while(|n| {
+                n.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                    && n.chars()
+                        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+            })
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return Some(IgnoreAnnotation::AllCommands);
+        }
+        commands.extend(names);
+    }
+
+    if commands.is_empty() {
+        return None;
+    }
+    Some(IgnoreAnnotation::Commands(commands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreAnnotation;
+    use anyhow::Result;
+    use precious_testhelper::TestHelper;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case(b"fn main() {}\n", false ; "text")]
+    #[test_case(b"", false ; "empty")]
+    #[test_case(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", true ; "png header")]
+    #[test_case("caf\u{e9}\n".as_bytes(), false ; "utf-8")]
+    #[parallel]
+    fn is_binary(content: &[u8], expect: bool) -> Result<()> {
+        let helper = TestHelper::new()?;
+        let path = helper.precious_root().join("file");
+        std::fs::write(&path, content)?;
+        assert_eq!(super::is_binary(&path)?, expect);
+        Ok(())
+    }
+
+    #[test_case("fn main() {}\n", None ; "none")]
+    #[test_case("# precious:ignore\n", Some(IgnoreAnnotation::AllCommands) ; "all")]
+    #[test_case("/* precious:ignore */\n", Some(IgnoreAnnotation::AllCommands) ; "all in c comment")]
+    #[test_case(
+        "#!/bin/sh\n# precious:ignore shellcheck, shfmt\n",
+        Some(IgnoreAnnotation::Commands(vec!["shellcheck".into(), "shfmt".into()])) ;
+        "names"
+    )]
+    #[test_case(
+        "<!-- precious:ignore markdownlint -->\n",
+        Some(IgnoreAnnotation::Commands(vec!["markdownlint".into()])) ;
+        "name in html comment"
+    )]
+    #[test_case(
+        "// precious:ignore rustfmt\n// precious:ignore clippy\n",
+        Some(IgnoreAnnotation::Commands(vec!["rustfmt".into(), "clippy".into()])) ;
+        "more than one annotation"
+    )]
+    #[test_case(
+        "// precious:ignore rustfmt\n// precious:ignore\n",
+        Some(IgnoreAnnotation::AllCommands) ;
+        "all wins"
+    )]
+    #[test_case("# precious:ignored\n", None ; "not the annotation")]
+    #[test_case("Use `precious:ignore` to skip a file.\n", None ; "in backticks")]
+    #[parallel]
+    fn parse_ignore_annotation(content: &str, expect: Option<IgnoreAnnotation>) {
+        assert_eq!(super::parse_ignore_annotation(content), expect);
+    }
+
+    #[test]
+    #[parallel]
+    fn ignore_annotation_only_looks_near_the_top() -> Result<()> {
+        let helper = TestHelper::new()?;
+        let path = helper.precious_root().join("file");
+        let mut content = "x\n".repeat(super::ANNOTATION_SNIFF_LEN as usize);
+        content.push_str("# precious:ignore\n");
+        std::fs::write(&path, content)?;
+        assert_eq!(super::ignore_annotation(&path)?, None);
+
+        std::fs::write(&path, "# precious:ignore\n")?;
+        assert_eq!(
+            super::ignore_annotation(&path)?,
+            Some(IgnoreAnnotation::AllCommands),
+        );
+        Ok(())
+    }
+}
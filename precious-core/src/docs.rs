@@ -0,0 +1,909 @@
+// The reference docs made by `precious generate-docs`. The CLI docs come
+// straight from the clap definitions, and the config file docs come from the
+// tables of keys below. The tests check that these tables cover every key
+// that `precious config show` knows about, so adding a config key without
+// documenting it fails the build.
+use anyhow::Result;
+use clap::{Arg, Command};
+use itertools::Itertools;
+use std::{fmt::Write, fs, path::Path};
+
+const REFERENCE_FILE: &str = "cli-reference.md";
+
+pub(crate) struct ConfigKey {
+    pub(crate) name: &'static str,
+    typ: &'static str,
+    required: bool,
+    // This is only set for command keys.
+    applies_to: Option<&'static str>,
+    default: Option<&'static str>,
+    pub(crate) description: &'static str,
+}
+
+pub(crate) const TOP_LEVEL_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "canonicalize-paths",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`true`"#),
+        description: r#"If this is false, `precious` does not resolve symlinks in the project root or in the paths it finds, so every path must be under the project root as it was given. By default, the project root and any path that is not under it are canonicalized, which can change their names when some part of the path is a symlink, like `/tmp` on macOS. Turn this off if `precious` complains that a path does not contain the project root as a prefix."#,
+    },
+    ConfigKey {
+        name: "exclude",
+        typ: "array of strings",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Each array member is a pattern that will be matched against potential files when `precious` is run. These patterns are matched in the same way as patterns in a [gitignore file](https://git-scm.com/docs/gitignore#_pattern_format). <br> You can use lines starting with a `!` to negate the meaning of previous rules in the list, so that anything that matches is _not_ excluded even if it matches previous rules."#,
+    },
+    ConfigKey {
+        name: "exclude-attributes",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Each entry is the name of a git attribute, like `linguist-generated`, or a name and value, like `lang=en`. Any file with a matching attribute in a `.gitattributes` file is excluded from every command. A name on its own matches when the attribute is set or set to `true`. This runs `git check-attr`, so it only works in a git checkout."#,
+    },
+    ConfigKey {
+        name: "extends",
+        typ: "string or table",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"A base config file to merge this one on top of. This can be a path relative to the directory containing this config file, or a table like `{ url = "...", sha256 = "..." }`. The URL can be `https`, `http`, or `file`. A file from a URL must match the SHA-256 checksum, and it's cached by checksum, so it's only downloaded once. Each key in this file replaces the same key in the base, except that `exclude` patterns are added after the base's patterns, and tables are merged key by key. A command with the same name as one in the base is merged with it, so you can override just some of its keys."#,
+    },
+    ConfigKey {
+        name: "follow-symlinks",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"If this is true, `precious` will descend into symlinked directories when looking for files, and symlinked files are passed to commands. A symlink that points back to one of its own parent directories is skipped. If this is false, commands are not given any symlinked files, or files under a symlinked directory. When this is not set, symlinked directories are not traversed but symlinked files are still passed to commands. Each command can override this with its own `follow-symlinks` key. If any command sets it to true, `precious` descends into symlinked directories, but only the commands that follow symlinks are given the files under them."#,
+    },
+    ConfigKey {
+        name: "ignore-annotations",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"If this is true, `precious` looks for a `precious:ignore` comment near the top of each file, which keeps the file from being passed to some or all commands. See [Ignoring a File With an Annotation](#ignoring-a-file-with-an-annotation) for details."#,
+    },
+    ConfigKey {
+        name: "jobs",
+        typ: "integer or string",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"The number of parallel jobs to run, just like the `--jobs` flag. This can be a number of jobs, a percentage of the available CPUs like `"50%"`, or a negative number like `-1` for all but that many CPUs. The `--jobs` flag takes precedence over this."#,
+    },
+    ConfigKey {
+        name: "log-dir",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"A directory, relative to the project root, where the full command line, env vars, stdout, and stderr of each command invocation are written, just like the `--log-dir` flag. The `--log-dir` flag takes precedence over this."#,
+    },
+    ConfigKey {
+        name: "root-marker",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, the directory containing this config file is a project root, even if it's not the root of a VCS checkout. When `precious` is run in a subdirectory, it uses the closest directory above it that either has a config file with this set or is a checkout root. This lets you run `precious` in a tree that isn't a checkout, like an exported tarball."#,
+    },
+];
+
+pub(crate) const UI_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "ascii",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, `precious` uses boring ASCII characters instead of emoji, just like the `--ascii` flag."#,
+    },
+    ConfigKey {
+        name: "color",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`true`"#),
+        description: r#"Set this to false to disable ANSI color codes in both regular output and log output. The `NO_COLOR` env var also disables color, and `CLICOLOR_FORCE` forces it on. Both env vars take precedence over this config key."#,
+    },
+    ConfigKey {
+        name: "chars",
+        typ: "table",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Override any of the status characters. The keys are `ring`, `tidied`, `unchanged`, `unknown`, `lint-free`, `lint-dirty`, `empty`, `bullet`, and `execution-error`."#,
+    },
+];
+
+pub(crate) const TIDY_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "partially-staged",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`tidy`"#),
+        description: r#"What `tidy --staged` does with staged files that also have unstaged changes. Tidiers change the working tree copy of a file, so tidying these files mixes your staged and unstaged changes. Set this to `tidy` to tidy them anyway, `skip` to leave them alone, `restage` to leave them alone and restage the other files after tidying them, as if `--restage` was passed, or `fail` to exit with an error. With `tidy`, `skip`, and `restage`, `precious` prints a warning listing the files."#,
+    },
+    ConfigKey {
+        name: "auto-restage",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, `tidy --staged` always behaves as if the `--restage` flag was passed. This is ignored in every other mode."#,
+    },
+    ConfigKey {
+        name: "hash-algorithm",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`md5`"#),
+        description: r#"The hash `precious` uses to tell whether a tidier changed a file, when the file's modification time changed but its size didn't. This can be `md5`, `xxh3`, or `blake3`. The `xxh3` and `blake3` hashes are much faster than `md5` for big files, which makes tidying big directories faster. Files are hashed in parallel no matter which hash is used."#,
+    },
+    ConfigKey {
+        name: "unmerged-files",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: Some(r#"`skip`"#),
+        description: r#"What `tidy` does with files that have unresolved merge conflicts. A tidier would most likely mangle the conflict markers in these files. Set this to `skip` to leave them alone with a warning listing the files, or `fail` to exit with an error. This is ignored by `tidy --check` and `diff`, which don't change any files."#,
+    },
+];
+
+pub(crate) const WORKSPACE_KEYS: &[ConfigKey] = &[ConfigKey {
+    name: "members",
+    typ: "string or array of strings",
+    required: false,
+    applies_to: None,
+    default: None,
+    description: r#"The project roots to run in, relative to the directory that contains this config file. Each root must have its own config file. When this is set, `lint`, `tidy`, and `diff` run the commands from each root's config file in that root, instead of the commands in this file."#,
+}];
+
+pub(crate) const COMMAND_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "type",
+        typ: "string",
+        required: true,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"This must be either `lint`, `tidy`, or `both`. This defines what type of command this is. A command which is `both` **must** define `lint-flags` or `tidy-flags` as well."#,
+    },
+    ConfigKey {
+        name: "include",
+        typ: "string or array of strings",
+        required: true,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Each array member is a [gitignore pattern](https://git-scm.com/docs/gitignore#_pattern_format) that tells `precious` what files this command applies to. <br> You can use lines starting with a `!` to negate the meaning of previous rules in the list, so that anything that matches is _not_ included even if it matches previous rules."#,
+    },
+    ConfigKey {
+        name: "exclude",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Each array member is a [gitignore pattern](https://git-scm.com/docs/gitignore#_pattern_format) that tells `precious` what files this command should not be applied to. <br> You can use lines starting with a `!` to negate the meaning of previous rules in the list, so that anything that matches is _not_ excluded even if it matches previous rules."#,
+    },
+    ConfigKey {
+        name: "dir-include",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"One or more [gitignore patterns](https://git-scm.com/docs/gitignore#_pattern_format) for the directories a command with `path-args = "dir-list"` runs in. This is required with `path-args = "dir-list"` and cannot be used with any other `path-args`. See [`path-args`](https://github.com/houseabsolute/precious#path-args) for details."#,
+    },
+    ConfigKey {
+        name: "also-trigger-on",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"One or more [gitignore patterns](https://git-scm.com/docs/gitignore#_pattern_format) for files that also cause this command to run. In the git modes, when a changed file matches one of these, the command is run on every file in the project that its `include` and `exclude` rules match, even if none of those files changed. This is useful for commands that check dependencies, which should run when a lockfile changes. See [A command should run when a lockfile changes](#a-command-should-run-when-a-lockfile-changes) for an example."#,
+    },
+    ConfigKey {
+        name: "invoke",
+        typ: "string or table",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`"per-file"`"#),
+        description: r#"How many times the command is invoked. See [`invoke`](https://github.com/houseabsolute/precious#invoke) for details."#,
+    },
+    ConfigKey {
+        name: "working-dir",
+        typ: "string or table",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`"root"`"#),
+        description: r#"The directory the command is run in. See [`working-dir`](https://github.com/houseabsolute/precious#working-dir) for details."#,
+    },
+    ConfigKey {
+        name: "path-args",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`"file"`"#),
+        description: r#"How paths are passed to the command. See [`path-args`](https://github.com/houseabsolute/precious#path-args) for details."#,
+    },
+    ConfigKey {
+        name: "follow-symlinks",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Overrides the top-level `follow-symlinks` key for this command. If this is false, symlinked files, and any files under a symlinked directory, are not passed to this command. If this is true, they are passed to it."#,
+    },
+    ConfigKey {
+        name: "max-file-size",
+        typ: "integer or string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Files larger than this are not passed to this command. This can be a number of bytes, or a string with a unit, like `"500KB"` or `"1MB"`. Units are case-insensitive and are multiples of 1024, so `"1MB"` is 1,048,576 bytes."#,
+    },
+    ConfigKey {
+        name: "max-memory",
+        typ: "integer or string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"The most virtual memory the command can use, in the same format as `max-file-size`. Past this, the command's allocations fail. This is only enforced on Unix systems, and it is ignored with a warning on Windows. With a `runner`, this limits the runner's process, not the container."#,
+    },
+    ConfigKey {
+        name: "max-cpu-seconds",
+        typ: "integer",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"The most CPU time the command can use, in seconds. Past this, the command is killed by a signal. Like `max-memory`, this is only enforced on Unix systems."#,
+    },
+    ConfigKey {
+        name: "skip-binary",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, binary files are not passed to this command. Like git, `precious` treats a file as binary if it has a null byte in its first 8,000 bytes."#,
+    },
+    ConfigKey {
+        name: "cmd",
+        typ: "string or array of strings",
+        required: true,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"This is the executable to be run followed by any arguments that should always be passed. This can also be one of the [builtin commands](https://github.com/houseabsolute/precious#builtin-commands), like `"precious://whitespace"`."#,
+    },
+    ConfigKey {
+        name: "install-cmd",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"A command to run to install the executable from `cmd` if it cannot be found in your path, for example `["cargo", "install", "typos-cli"]`. This is only run when `precious` is run with the `--install-missing` flag. Without that flag, `precious` will tell you how to install the missing executable."#,
+    },
+    ConfigKey {
+        name: "daemon-cmd",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"A command that starts a long-running process the command can use to avoid startup costs, like `["eslint_d", "start"]`. This is only used when running with `precious daemon`. See below for more details."#,
+    },
+    ConfigKey {
+        name: "version-cmd",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"A command that prints the version of the tool, like `["rustfmt", "--version"]`. This is only run when `--manifest` is given, and its output is included in the manifest. If the command prints nothing on stdout, its stderr is used instead. See [Writing a Run Manifest](#writing-a-run-manifest) for details."#,
+    },
+    ConfigKey {
+        name: "runner",
+        typ: "table or string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Run the command inside a container instead of on the host. This is a table with a `type` key, either `"docker"` or `"podman"`, and an `image` key. It can also be a `"plugin:<name>"` string to run the command through a plugin. See below for more details."#,
+    },
+    ConfigKey {
+        name: "env",
+        typ: "table - values are strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"This key allows you to set one or more environment variables that will be set when the command is run. The values in this table must be strings."#,
+    },
+    ConfigKey {
+        name: "toolchain",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"A Rust toolchain, like `"nightly"` or `"nightly-2024-06-01"`, to run the command with. This sets the `RUSTUP_TOOLCHAIN` env var, which rustup's proxies for `cargo`, `rustfmt`, and other Rust tools use to pick the toolchain. See below for more details."#,
+    },
+    ConfigKey {
+        name: "path-flag",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"By default, `precious` will pass the path being operated on to the command it executes as the final, positional, argument(s). If the command takes paths via a flag you need to specify that flag with this key."#,
+    },
+    ConfigKey {
+        name: "paths-file",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, `precious` writes the paths being operated on to a temp file, one per line, instead of passing them as arguments. The path to this file is passed in the `PRECIOUS_PATHS_FILE` env var. This is useful for tools that can read a list of files, since a long list of arguments can exceed OS limits. This cannot be used with a `runner`, or with `path-args` set to `none` or `dot`."#,
+    },
+    ConfigKey {
+        name: "paths-file-flag",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Flags used to pass the temp file to the command. The string `{file}` is replaced with the file's path, so `"@{file}"` or `["--files-from", "{file}"]` might work, depending on the command. Setting this implies `paths-file = true`."#,
+    },
+    ConfigKey {
+        name: "paths-file-null-separated",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, the paths in the `paths-file` are separated by NUL bytes instead of newlines, like the output of `find -print0`. Paths are always written as they are, even if they are not valid UTF-8, but a path that contains a newline can only be written when this is set."#,
+    },
+    ConfigKey {
+        name: "path-style",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Set this to `"unix"` or `"windows"` to make the paths passed to the command use `/` or `\` as the separator, or to `"native"` to use the separator for the current platform. This applies to paths passed as arguments and to the ones written to a `paths-file`. When this is not set, paths are passed as they are."#,
+    },
+    ConfigKey {
+        name: "stdin-from",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Set this to `"file"` to write the content of the file to the command's stdin. The command should print a unified diff for the file, which `precious` applies when tidying. When linting, the file fails if the command prints anything. This requires `invoke = "per-file"`, but unlike other per-file commands it can be used with `path-args = "none"`. See below for more details."#,
+    },
+    ConfigKey {
+        name: "line-range-flag",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"When running in one of the git modes, pass the lines that were changed in the file to the command. The strings `{start}` and `{end}` are replaced with the first and last line of each changed range, so `"--lines={start}:{end}"` might become `--lines=5:9 --lines=20:20`. This requires `invoke = "per-file"`. See below for more details."#,
+    },
+    ConfigKey {
+        name: "max-files-per-invocation",
+        typ: "integer",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"The maximum number of files to pass to a single invocation of the command. When there are more files than this, they are split into batches, and the batches are run in parallel. This requires `path-args` to be `"file"` or `"absolute-file"`."#,
+    },
+    ConfigKey {
+        name: "max-bytes-per-invocation",
+        typ: "integer",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Like `max-files-per-invocation`, but limits the total size in bytes of the files passed to a single invocation. A file that is larger than this limit is passed to an invocation on its own."#,
+    },
+    ConfigKey {
+        name: "depends-on",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"The names of commands that must finish before this one starts when the `--parallel-commands` flag is used. These commands must come before this one in the config file."#,
+    },
+    ConfigKey {
+        name: "conflicts-with",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"tidier"#),
+        default: None,
+        description: r#"The names of tidy commands that this command conflicts with, because they change the same code in different ways. When `precious tidy` would give this command and one of these commands the same file, it exits with an error instead of tidying. Even without this, `precious` warns when two tidiers that are known to fight, like `black` and `autopep8`, would tidy the same file."#,
+    },
+    ConfigKey {
+        name: "serial",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"false"#),
+        description: r#"If this is true, this command never runs at the same time as any other command, even with the `--parallel-commands` flag."#,
+    },
+    ConfigKey {
+        name: "mutex",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Commands with the same `mutex` name never run at the same time as each other with the `--parallel-commands` flag. This is useful for tools that share some resource, like cargo's `target` dir. Commands with a different `mutex`, or none at all, can still run alongside them."#,
+    },
+    ConfigKey {
+        name: "run-when",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`"always"`"#),
+        description: r#"Set this to `"ci"` to only run the command in CI, or `"local"` to only run it outside of CI. See [Running Commands Only in CI or Locally](https://github.com/houseabsolute/precious#running-commands-only-in-ci-or-locally) for details."#,
+    },
+    ConfigKey {
+        name: "lint-flags",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"combined linter & tidier"#),
+        default: None,
+        description: r#"If a command is both a linter and tidier then it may take extra flags to operate in linting mode. This is how you set that flag."#,
+    },
+    ConfigKey {
+        name: "tidy-flags",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"combined linter & tidier"#),
+        default: None,
+        description: r#"If a command is both a linter and tidier then it may take extra flags to operate in tidying mode. This is how you set that flag."#,
+    },
+    ConfigKey {
+        name: "check-flags",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"tidier"#),
+        default: None,
+        description: r#"Flags that make a tidy-only command report whether it would change files without changing them, like `--check` for many formatters. These are used by `precious tidy --check`. A command with `check-flags` must also set `lint-failure-exit-codes`, which are the exit codes that mean the files would be changed."#,
+    },
+    ConfigKey {
+        name: "track-changes",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"tidier"#),
+        default: Some(r#"`false`"#),
+        description: r#"When a tidier is invoked once for the whole project, `precious` normally cannot tell whether it changed any files. If this is true, `precious` hashes each file it passes to the command before and after running it, so it can report whether the files were changed. This only checks the files passed to the command, so new files created by the command are not noticed."#,
+    },
+    ConfigKey {
+        name: "read-only",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"linters"#),
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, `precious` checks that the linter did not change any of the files it was run on, and reports an error if it did. This catches a linter that is misconfigured to fix files, for example with `eslint --fix` in its `lint-flags`. Like a tidier, the files are compared by their modification time, size, and content hash."#,
+    },
+    ConfigKey {
+        name: "report-new-files",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"tidier"#),
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, `precious` looks for files that the tidier created, like generated code, in the directory the command runs in. Files that git ignores are skipped. Any new files are listed in the output, and the invocation counts as changing files. This means listing every file in that directory before and after each invocation, which can be slow for a command that runs in the project root."#,
+    },
+    ConfigKey {
+        name: "scratch-files",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"tidier"#),
+        default: None,
+        description: r#"Patterns for temp files that the command writes next to the files it tidies. Files matching these patterns are ignored when `precious` checks whether the command changed anything. See [Scratch Files](https://github.com/houseabsolute/precious#scratch-files) for details."#,
+    },
+    ConfigKey {
+        name: "ok-exit-codes",
+        typ: "integer or array of integers",
+        required: true,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"Any exit code that **does not** indicate an abnormal exit should be here. For most commands this is just `0` but some commands may use other exit codes even for a normal exit."#,
+    },
+    ConfigKey {
+        name: "lint-failure-exit-codes",
+        typ: "integer or array of integers",
+        required: false,
+        applies_to: Some(r#"linters"#),
+        default: None,
+        description: r#"If the command is a linter then these are the status codes that indicate a lint failure. These need to be specified so `precious` can distinguish an exit because of a lint failure versus an exit because of some unexpected issue."#,
+    },
+    ConfigKey {
+        name: "lint-failure-output",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"linters"#),
+        default: None,
+        description: r#"A regex that is matched against the command's stdout. If it matches, the lint fails, even if the command exited with one of its `ok-exit-codes`. This is for tools that always exit with `0` and only report problems in their output."#,
+    },
+    ConfigKey {
+        name: "expect-stderr",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`false`"#),
+        description: r#"Deprecated. Use `ignore-stderr = ".*"` instead."#,
+    },
+    ConfigKey {
+        name: "ignore-stderr",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"By default, `precious` assumes that when a command sends output to `stderr` that indicates a failure to lint or tidy. This parameter can specify one or more regexes. These regexes will be matched against the command's stderr output. If _any_ of the regexes match, the stderr output is ignored."#,
+    },
+    ConfigKey {
+        name: "ignore-stdout",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"One or more regexes for lines of the command's stdout that aren't useful, like progress bars or version banners. Every line that matches any of these is removed from the output before anything else looks at it, so these lines don't show up when a command fails, and they aren't matched against `lint-failure-output`. This cannot be used with `stdin-from`, since the output is a patch."#,
+    },
+    ConfigKey {
+        name: "redirect-stderr-to-stdout",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, the command's stdout and stderr are captured through a single pipe, so its output is kept in the order it was written instead of being shown as separate stdout and stderr blocks. This is useful for tools that print some diagnostics to each stream. Since all of the output is treated as stdout, output on stderr no longer counts as a failure. This cannot be used with `stdin-from`."#,
+    },
+    ConfigKey {
+        name: "output-encoding",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"The encoding of the command's output. By default, output is decoded as UTF-8. Set this to a label like `"cp1252"` or `"shift_jis"` for tools that print output in another encoding. The labels from the [WHATWG Encoding Standard](https://encoding.spec.whatwg.org/#names-and-labels) are accepted. Any bytes that are not valid in the encoding are replaced with U+FFFD."#,
+    },
+    ConfigKey {
+        name: "max-output-bytes",
+        typ: "integer or string",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"The most output to keep from each invocation, as a number of bytes or a string with a unit, like `"64KB"`. This applies to stdout and stderr separately. Output over this limit is cut down to its beginning and end, with a line in between that says how many bytes were left out. Stdout is cut down as it's read, so it's never all held in memory at once, unless `precious` needs to look at all of it. That's the case with `stdin-from`, `lint-failure-output`, `diagnostic-regex` with changed lines, a plugin runner, `--record`, and `--log-dir`."#,
+    },
+    ConfigKey {
+        name: "save-full-output",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`false`"#),
+        description: r#"If this is true, output that is cut down because of `max-output-bytes` is saved in full to a file in the temp dir. The line saying how much output was left out includes the path to that file. These files are not deleted by `precious`."#,
+    },
+    ConfigKey {
+        name: "diagnostic-regex",
+        typ: "string",
+        required: false,
+        applies_to: Some(r#"linters"#),
+        default: None,
+        description: r#"A regex that matches one line of the linter's output describing a single problem. It must have a capture group named `line` containing the line number, and may have a group named `path` containing the file's path. This is used by the `--only-changed-lines` flag."#,
+    },
+    ConfigKey {
+        name: "labels",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: None,
+        description: r#"One or more labels used to categorize commands. See below for more details."#,
+    },
+    ConfigKey {
+        name: "enabled",
+        typ: "boolean",
+        required: false,
+        applies_to: Some(r#"all"#),
+        default: Some(r#"`true`"#),
+        description: r#"Set this to false to turn the command off without deleting its config, like when the tool is broken. A disabled command is skipped by `lint` and `tidy`, and is listed as skipped in `--verbose` and `--explain-skips` output. You can also skip commands for a single run with the `--skip-command` flag."#,
+    },
+];
+
+pub(crate) const PROFILE_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "actions",
+        typ: "string or array of strings",
+        required: true,
+        applies_to: None,
+        default: None,
+        description: r#"The actions to run, in order. Each one is either `"lint"` or `"tidy"`."#,
+    },
+    ConfigKey {
+        name: "mode",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"One of `"all"`, `"git"`, `"git-all-uncommitted"`, `"staged"`, or `"staged-with-stash"`, which select files just like the flags of the same names. If this isn't set, you must pass some paths."#,
+    },
+    ConfigKey {
+        name: "git-diff-from",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Run against files that differ from this ref, like the `--git-diff-from` flag. This cannot be set along with `mode`."#,
+    },
+    ConfigKey {
+        name: "command",
+        typ: "string or array of strings",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Only run the commands with these names, like the `--command` flag."#,
+    },
+    ConfigKey {
+        name: "label",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Only run commands with this label, like the `--label` flag."#,
+    },
+    ConfigKey {
+        name: "check",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Tidy with `--check`."#,
+    },
+    ConfigKey {
+        name: "restage",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Tidy with `--restage`."#,
+    },
+    ConfigKey {
+        name: "install-missing",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--install-missing` flag."#,
+    },
+    ConfigKey {
+        name: "only-changed-lines",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--only-changed-lines` flag."#,
+    },
+    ConfigKey {
+        name: "only-dirty-dirs",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--only-dirty-dirs` flag."#,
+    },
+    ConfigKey {
+        name: "summary-only",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--summary-only` flag."#,
+    },
+    ConfigKey {
+        name: "parallel-commands",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--parallel-commands` flag."#,
+    },
+    ConfigKey {
+        name: "progress",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--progress` flag."#,
+    },
+    ConfigKey {
+        name: "live-output",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--live-output` flag."#,
+    },
+    ConfigKey {
+        name: "strict-config",
+        typ: "boolean",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"Like the `--strict-config` flag."#,
+    },
+];
+
+// A template also takes every key in `COMMAND_KEYS`.
+pub(crate) const TEMPLATE_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "matrix",
+        typ: "table of arrays",
+        required: true,
+        applies_to: None,
+        default: None,
+        description: r#"Each key is a placeholder name, and its value is an array of strings or numbers. The template expands into one command for every combination of values. In the template's other keys, `{key}` in any string is replaced by that key's value for the command."#,
+    },
+    ConfigKey {
+        name: "name",
+        typ: "string",
+        required: false,
+        applies_to: None,
+        default: None,
+        description: r#"The name for each command, which can use the matrix placeholders, like `"go-lint-{dir}"`. By default, each name is the template's name followed by each of the command's matrix values, separated by dashes."#,
+    },
+];
+
+const CONFIG_TABLES: &[(&str, &[ConfigKey])] = &[
+    ("The top level table", TOP_LEVEL_KEYS),
+    ("The `[ui]` table", UI_KEYS),
+    ("The `[tidy]` table", TIDY_KEYS),
+    ("The `[workspace]` table", WORKSPACE_KEYS),
+    ("Each `[profiles.<name>]` table", PROFILE_KEYS),
+    ("Each `[commands.<name>]` table", COMMAND_KEYS),
+    (
+        "Each `[command-templates.<name>]` table, along with the keys for a command",
+        TEMPLATE_KEYS,
+    ),
+];
+
+// Writes a man page for precious and each of its subcommands, along with a
+// markdown reference for the CLI and the config file, to the given dir.
+pub(crate) fn generate(app: Command, out_dir: &Path) -> Result<()> {
+    fs::create_dir_all(out_dir)?;
+    clap_mangen::generate_to(app.clone(), out_dir)?;
+    fs::write(out_dir.join(REFERENCE_FILE), reference(app))?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) fn command_keys() -> impl Iterator<Item = &'static str> {
+    COMMAND_KEYS.iter().map(|k| k.name)
+}
+
+fn reference(app: Command) -> String {
+    let mut app = app.disable_help_subcommand(true);
+    app.build();
+
+    let mut out = String::from(
+        "# Precious Reference\n\nThis file is generated by `precious generate-docs`. Do not edit it by hand.\n\n# Command Line\n",
+    );
+    write_command(&mut out, &app);
+    for sub in app.get_subcommands().filter(|s| !s.is_hide_set()) {
+        write_command(&mut out, sub);
+        for sub_sub in sub.get_subcommands().filter(|s| !s.is_hide_set()) {
+            write_command(&mut out, sub_sub);
+        }
+    }
+
+    out.push_str("\n# Config File\n");
+    for (table, keys) in CONFIG_TABLES {
+        write_config_table(&mut out, table, keys);
+    }
+    out
+}
+
+fn write_command(out: &mut String, cmd: &Command) {
+    let name = cmd.get_bin_name().unwrap_or_else(|| cmd.get_name());
+    let _ = write!(out, "\n## `{name}`\n\n");
+    if let Some(about) = cmd.get_long_about().or_else(|| cmd.get_about()) {
+        let _ = write!(out, "{about}\n\n");
+    }
+    let _ = write!(out, "```\n{}\n```\n", cmd.clone().render_usage());
+
+    let args = cmd
+        .get_arguments()
+        .filter(|a| !a.is_hide_set())
+        .collect::<Vec<_>>();
+    if args.is_empty() {
+        return;
+    }
+    out.push('\n');
+    for a in args {
+        let _ = write!(out, "- `{}`", arg_name(a));
+        if let Some(help) = a.get_long_help().or_else(|| a.get_help()) {
+            let _ = write!(out, ": {}", help.to_string().replace('\n', " "));
+        }
+        let values = a
+            .get_possible_values()
+            .into_iter()
+            .filter(|v| !v.is_hide_set())
+            .map(|v| format!("`{}`", v.get_name()))
+            .collect::<Vec<_>>();
+        if !values.is_empty() {
+            let _ = write!(out, " Possible values: {}.", values.join(", "));
+        }
+        if let Some(default) = a.get_default_values().first() {
+            let _ = write!(out, " Defaults to `{}`.", default.to_string_lossy());
+        }
+        out.push('\n');
+    }
+}
+
+fn arg_name(arg: &Arg) -> String {
+    let mut name = [
+        arg.get_short().map(|s| format!("-{s}")),
+        arg.get_long().map(|l| format!("--{l}")),
+    ]
+    .into_iter()
+    .flatten()
+    .join(", ");
+    if arg.get_action().takes_values() {
+        let value = arg.get_value_names().map_or_else(
+            || format!("<{}>", arg.get_id().as_str().to_uppercase()),
+            |v| v.iter().map(|v| format!("<{v}>")).join(" "),
+        );
+        if !name.is_empty() {
+            name.push(' ');
+        }
+        name.push_str(&value);
+    }
+    name
+}
+
+fn write_config_table(out: &mut String, table: &str, keys: &[ConfigKey]) {
+    let _ = write!(
+        out,
+        "\n## {table}\n\n| Key | Type | Required? | Applies To | Default | Description |\n| --- | --- | --- | --- | --- | --- |\n",
+    );
+    for k in keys {
+        let _ = writeln!(
+            out,
+            "| `{}` | {} | {} | {} | {} | {} |",
+            k.name,
+            k.typ,
+            if k.required { "**yes**" } else { "no" },
+            k.applies_to.unwrap_or("all"),
+            k.default.unwrap_or(""),
+            k.description,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::precious::App;
+    use clap::CommandFactory;
+    use serial_test::parallel;
+
+    #[test]
+    #[parallel]
+    fn generate() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        super::generate(App::command(), td.path())?;
+
+        for page in ["precious.1", "precious-lint.1", "precious-config-show.1"] {
+            assert!(td.path().join(page).is_file(), "{page} was generated");
+        }
+        assert!(
+            !td.path().join("precious-generate-docs.1").exists(),
+            "hidden subcommands are not documented",
+        );
+
+        let reference = fs::read_to_string(td.path().join(REFERENCE_FILE))?;
+        for expect in [
+            "## `precious lint`",
+            "## `precious config show`",
+            "- `-c, --config <CONFIG>`: Path to the precious config file",
+            "- `--output <OUTPUT>`",
+            "| `ok-exit-codes` | integer or array of integers | **yes** |",
+            "## The `[tidy]` table",
+        ] {
+            assert!(reference.contains(expect), "reference contains {expect}");
+        }
+        assert!(!reference.contains("## `precious generate-docs`"));
+
+        Ok(())
+    }
+}
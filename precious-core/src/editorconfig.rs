@@ -0,0 +1,537 @@
+// Support for EditorConfig files (https://editorconfig.org/), which the
+// builtin commands use to decide what a file's whitespace should look like.
+// This implements the parts of the spec that matter for checking files:
+// finding the `.editorconfig` files that apply to a path, matching their
+// section globs, and resolving the properties those sections set.
+use anyhow::Result;
+use itertools::Itertools;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const FILE_NAME: &str = ".editorconfig";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum IndentStyle {
+    Space,
+    Tab,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum EndOfLine {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl EndOfLine {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            EndOfLine::Lf => "lf",
+            EndOfLine::Crlf => "crlf",
+            EndOfLine::Cr => "cr",
+        }
+    }
+}
+
+// The spec also allows `latin1`, `utf-16be`, and `utf-16le`, but we only
+// check the charsets that can be checked without decoding the file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Charset {
+    Utf8,
+    Utf8Bom,
+}
+
+impl Charset {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Utf8Bom => "utf-8-bom",
+        }
+    }
+}
+
+// The properties that apply to one file. A property that isn't set, or that
+// is set to a value we don't understand, is `None`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Properties {
+    pub(crate) indent_style: Option<IndentStyle>,
+    pub(crate) indent_size: Option<usize>,
+    pub(crate) tab_width: Option<usize>,
+    pub(crate) end_of_line: Option<EndOfLine>,
+    pub(crate) charset: Option<Charset>,
+    pub(crate) trim_trailing_whitespace: Option<bool>,
+    pub(crate) insert_final_newline: Option<bool>,
+}
+
+impl Properties {
+    // Property names are case-insensitive, and so are the values of all the
+    // properties we look at.
+    fn from_values(values: &HashMap<String, String>) -> Self {
+        let get = |key: &str| values.get(key).map(|v| v.to_lowercase());
+        let number = |key: &str| values.get(key).and_then(|v| v.parse::<usize>().ok());
+        let boolean = |key: &str| match get(key).as_deref() {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+
+        Properties {
+            indent_style: match get("indent_style").as_deref() {
+                Some("space") => Some(IndentStyle::Space),
+                Some("tab") => Some(IndentStyle::Tab),
+                _ => None,
+            },
+            indent_size: number("indent_size"),
+            tab_width: number("tab_width"),
+            end_of_line: match get("end_of_line").as_deref() {
+                Some("lf") => Some(EndOfLine::Lf),
+                Some("crlf") => Some(EndOfLine::Crlf),
+                Some("cr") => Some(EndOfLine::Cr),
+                _ => None,
+            },
+            charset: match get("charset").as_deref() {
+                Some("utf-8") => Some(Charset::Utf8),
+                Some("utf-8-bom") => Some(Charset::Utf8Bom),
+                _ => None,
+            },
+            trim_trailing_whitespace: boolean("trim_trailing_whitespace"),
+            insert_final_newline: boolean("insert_final_newline"),
+        }
+    }
+
+    // When `indent_size` is `tab`, the spec says to use `tab_width`, and
+    // `tab_width` defaults to `indent_size`, so either one can give us the
+    // width of an indent.
+    pub(crate) fn indent_width(&self) -> Option<usize> {
+        self.indent_size.or(self.tab_width)
+    }
+}
+
+// This finds and parses `.editorconfig` files, caching each one so that
+// checking many files in the same directory only reads it once.
+#[derive(Debug, Default)]
+pub(crate) struct EditorConfig {
+    files: HashMap<PathBuf, Option<File>>,
+}
+
+impl EditorConfig {
+    // The `.editorconfig` files are looked for in every directory above the
+    // path, stopping at one that sets `root = true`. Files closer to the path
+    // take precedence, as do later sections within a file.
+    pub(crate) fn properties_for(&mut self, path: &Path) -> Result<Properties> {
+        let mut dirs = vec![];
+        for dir in path.ancestors().skip(1) {
+            let Some(file) = self.file_in(dir)? else {
+                continue;
+            };
+            let root = file.root;
+            dirs.push(dir);
+            if root {
+                break;
+            }
+        }
+
+        let mut values = HashMap::new();
+        for dir in dirs.into_iter().rev() {
+            let Some(file) = &self.files[dir] else {
+                continue;
+            };
+            // Globs always use `/` as the path separator.
+            let rel = path
+                .strip_prefix(dir)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .join("/");
+            for section in file.sections.iter().filter(|s| s.is_match(&rel)) {
+                for (key, value) in &section.properties {
+                    if value.eq_ignore_ascii_case("unset") {
+                        values.remove(key);
+                    } else {
+                        values.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(Properties::from_values(&values))
+    }
+
+    fn file_in(&mut self, dir: &Path) -> Result<Option<&File>> {
+        if !self.files.contains_key(dir) {
+            let file = match fs::read_to_string(dir.join(FILE_NAME)) {
+                Ok(content) => Some(File::parse(&content)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            self.files.insert(dir.to_path_buf(), file);
+        }
+        Ok(self.files[dir].as_ref())
+    }
+}
+
+#[derive(Debug, Default)]
+struct File {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+struct Section {
+    // This is `None` when the glob can't be turned into a regex, in which
+    // case the section never matches anything.
+    glob: Option<Glob>,
+    properties: Vec<(String, String)>,
+}
+
+impl Section {
+    fn is_match(&self, path: &str) -> bool {
+        self.glob.as_ref().is_some_and(|g| g.is_match(path))
+    }
+}
+
+impl File {
+    // Like the reference implementations, this ignores any line it doesn't
+    // understand instead of treating it as an error.
+    fn parse(content: &str) -> Self {
+        let mut file = File::default();
+        for line in content.trim_start_matches('\u{feff}').lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                file.sections.push(Section {
+                    glob: Glob::new(glob),
+                    properties: vec![],
+                });
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_string();
+            match file.sections.last_mut() {
+                Some(section) => section.properties.push((key, value)),
+                None if key == "root" => file.root = value.eq_ignore_ascii_case("true"),
+                None => (),
+            }
+        }
+        file
+    }
+}
+
+// An EditorConfig glob, translated to a regex. Numeric ranges like `{1..3}`
+// can't be expressed as a regex, so each one becomes a capture group, and the
+// number it captures is checked against the range after the regex matches.
+#[derive(Debug)]
+struct Glob {
+    re: Regex,
+    ranges: Vec<(i64, i64)>,
+}
+
+impl Glob {
+    fn new(glob: &str) -> Option<Self> {
+        // A glob with no `/` matches a file name in any directory. Otherwise
+        // it's relative to the directory containing the `.editorconfig` file.
+        let prefix = if glob.contains('/') { "" } else { "(?:.*/)?" };
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        let mut ranges = vec![];
+        let pattern = translate(&glob.chars().collect::<Vec<_>>(), &mut ranges);
+        let re = Regex::new(&format!("^{prefix}{pattern}$")).ok()?;
+        Some(Glob { re, ranges })
+    }
+
+    fn is_match(&self, path: &str) -> bool {
+        let Some(caps) = self.re.captures(path) else {
+            return false;
+        };
+        // A range inside an alternative that didn't match captures nothing,
+        // and doesn't need to be checked.
+        self.ranges.iter().enumerate().all(|(i, (min, max))| {
+            caps.get(i + 1).is_none_or(|m| {
+                m.as_str()
+                    .parse::<i64>()
+                    .is_ok_and(|n| (*min..=*max).contains(&n))
+            })
+        })
+    }
+}
+
+fn translate(glob: &[char], ranges: &mut Vec<(i64, i64)>) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < glob.len() {
+        match glob[i] {
+            '\\' if i + 1 < glob.len() => {
+                i += 1;
+                out.push_str(&regex::escape(&glob[i].to_string()));
+            }
+            '*' if glob.get(i + 1) == Some(&'*') => {
+                i += 1;
+                // `a/**/b` matches `a/b` as well as `a/x/b`.
+                if out.ends_with('/') && glob.get(i + 1) == Some(&'/') {
+                    i += 1;
+                    out.push_str("(?:.*/)?");
+                } else {
+                    out.push_str(".*");
+                }
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => match glob[i + 1..].iter().position(|c| *c == ']') {
+                Some(len) if len > 0 && !glob[i + 1..i + 1 + len].contains(&'/') => {
+                    out.push_str(&translate_class(&glob[i + 1..i + 1 + len]));
+                    i += len + 1;
+                }
+                _ => out.push_str(r"\["),
+            },
+            '{' => match closing_brace(glob, i) {
+                Some(end) => {
+                    out.push_str(&translate_braces(&glob[i + 1..end], ranges));
+                    i = end;
+                }
+                None => out.push_str(r"\{"),
+            },
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn translate_class(class: &[char]) -> String {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) if !rest.is_empty() => (true, rest),
+        _ => (false, class),
+    };
+    let mut out = String::from(if negated { "[^" } else { "[" });
+    for c in class {
+        if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+            out.push('\\');
+        }
+        out.push(*c);
+    }
+    out.push(']');
+    out
+}
+
+// `{a,b}` matches either alternative and `{1..3}` matches a number in the
+// range. Braces with neither, like `{a}`, are matched literally.
+fn translate_braces(inner: &[char], ranges: &mut Vec<(i64, i64)>) -> String {
+    if let Some(range) = numeric_range(&inner.iter().collect::<String>()) {
+        ranges.push(range);
+        return String::from(r"([+-]?\d+)");
+    }
+
+    let alternatives = split_alternatives(inner);
+    if alternatives.len() > 1 {
+        let alternatives = alternatives
+            .into_iter()
+            .map(|a| translate(a, ranges))
+            .join("|");
+        return format!("(?:{alternatives})");
+    }
+    format!(r"\{{{}\}}", translate(inner, ranges))
+}
+
+fn numeric_range(inner: &str) -> Option<(i64, i64)> {
+    let (min, max) = inner.split_once("..")?;
+    Some((min.parse().ok()?, max.parse().ok()?))
+}
+
+fn closing_brace(glob: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < glob.len() {
+        match glob[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+// Splits on the commas that aren't inside nested braces.
+fn split_alternatives(inner: &[char]) -> Vec<&[char]> {
+    let mut alternatives = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < inner.len() {
+        match inner[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    alternatives.push(&inner[start.min(inner.len())..]);
+    alternatives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case("*", "foo.rs", true ; "star")]
+    #[test_case("*", "a/b/foo.rs", true ; "star matches in any directory")]
+    #[test_case("*.rs", "a/foo.rs", true ; "extension")]
+    #[test_case("*.rs", "foo.rs.bak", false ; "extension at end only")]
+    #[test_case("src/*.rs", "src/foo.rs", true ; "relative path")]
+    #[test_case("src/*.rs", "src/a/foo.rs", false ; "star does not match slash")]
+    #[test_case("src/*.rs", "a/src/foo.rs", false ; "relative path is anchored")]
+    #[test_case("/src/*.rs", "src/foo.rs", true ; "leading slash")]
+    #[test_case("src/**.rs", "src/a/b/foo.rs", true ; "star star")]
+    #[test_case("a/**/z.c", "a/z.c", true ; "star star matches no directories")]
+    #[test_case("a/**/z.c", "a/b/c/z.c", true ; "star star matches many directories")]
+    #[test_case("?.c", "a.c", true ; "question mark")]
+    #[test_case("?.c", "ab.c", false ; "question mark matches one character")]
+    #[test_case("[ab].c", "b.c", true ; "class")]
+    #[test_case("[!ab].c", "b.c", false ; "negated class")]
+    #[test_case("[!ab].c", "c.c", true ; "negated class matches others")]
+    #[test_case("*.{js,ts}", "foo.ts", true ; "alternatives")]
+    #[test_case("*.{js,ts}", "foo.rs", false ; "alternatives do not match others")]
+    #[test_case("{Makefile,*.mk}", "sub/rules.mk", true ; "alternatives with a glob")]
+    #[test_case("{a,{b,c}}.txt", "c.txt", true ; "nested alternatives")]
+    #[test_case("file{1..3}.txt", "file2.txt", true ; "range")]
+    #[test_case("file{1..3}.txt", "file4.txt", false ; "outside range")]
+    #[test_case("file{-3..-1}.txt", "file-2.txt", true ; "negative range")]
+    #[test_case("{single}.txt", "{single}.txt", true ; "braces without a comma are literal")]
+    #[test_case(r"\*.txt", "*.txt", true ; "escaped star")]
+    #[test_case(r"\*.txt", "a.txt", false ; "escaped star is literal")]
+    #[test_case("a.txt", "a-txt", false ; "dot is literal")]
+    #[parallel]
+    fn glob(glob: &str, path: &str, expect: bool) {
+        let glob = Glob::new(glob).unwrap();
+        assert_eq!(glob.is_match(path), expect, "{glob:?}");
+    }
+
+    #[test]
+    #[parallel]
+    fn parse() {
+        let file = File::parse(
+            "\u{feff}# comment\nroot = TRUE\n\n[*]\nindent_style = space\n; another comment\nIndent_Size=4\nnot a property\n\n[*.md]\ntrim_trailing_whitespace = false\n",
+        );
+        assert!(file.root);
+        assert_eq!(
+            file.sections
+                .iter()
+                .map(|s| s.properties.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                vec![
+                    (String::from("indent_style"), String::from("space")),
+                    (String::from("indent_size"), String::from("4")),
+                ],
+                vec![(
+                    String::from("trim_trailing_whitespace"),
+                    String::from("false"),
+                )],
+            ],
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn properties_for() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let root = td.path();
+        fs::create_dir_all(root.join("sub/inner"))?;
+        fs::write(
+            root.join(FILE_NAME),
+            "root = true\n\n[*]\nindent_style = space\nindent_size = 4\nend_of_line = lf\ncharset = utf-8\ninsert_final_newline = true\ntrim_trailing_whitespace = true\n\n[*.md]\ntrim_trailing_whitespace = false\n\n[Makefile]\nindent_style = tab\nindent_size = unset\n",
+        )?;
+        // This is in a directory that doesn't have an `.editorconfig` file,
+        // so the one above it applies too.
+        fs::write(
+            root.join("sub").join(FILE_NAME),
+            "[inner/*.py]\nindent_size = 2\nEND_OF_LINE = CRLF\n",
+        )?;
+
+        let mut ec = EditorConfig::default();
+        let all = Properties {
+            indent_style: Some(IndentStyle::Space),
+            indent_size: Some(4),
+            tab_width: None,
+            end_of_line: Some(EndOfLine::Lf),
+            charset: Some(Charset::Utf8),
+            trim_trailing_whitespace: Some(true),
+            insert_final_newline: Some(true),
+        };
+        assert_eq!(ec.properties_for(&root.join("foo.rs"))?, all);
+        assert_eq!(
+            ec.properties_for(&root.join("docs/README.md"))?,
+            Properties {
+                trim_trailing_whitespace: Some(false),
+                ..all.clone()
+            },
+        );
+        assert_eq!(
+            ec.properties_for(&root.join("Makefile"))?,
+            Properties {
+                indent_style: Some(IndentStyle::Tab),
+                indent_size: None,
+                ..all.clone()
+            },
+        );
+        assert_eq!(
+            ec.properties_for(&root.join("sub/inner/foo.py"))?,
+            Properties {
+                indent_size: Some(2),
+                end_of_line: Some(EndOfLine::Crlf),
+                ..all.clone()
+            },
+        );
+        assert_eq!(ec.properties_for(&root.join("sub/foo.py"))?, all);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn properties_for_stops_at_root() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let root = td.path();
+        fs::create_dir_all(root.join("sub"))?;
+        fs::write(
+            root.join(FILE_NAME),
+            "root = true\n[*]\nindent_style = tab\n",
+        )?;
+        fs::write(
+            root.join("sub").join(FILE_NAME),
+            "root = true\n[*]\ninsert_final_newline = true\n",
+        )?;
+
+        let mut ec = EditorConfig::default();
+        assert_eq!(
+            ec.properties_for(&root.join("sub/foo.txt"))?,
+            Properties {
+                insert_final_newline: Some(true),
+                ..Default::default()
+            },
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+// After each run, precious saves the files that each command failed on. In
+// `--failed` mode, it reads these back and only runs those commands on those
+// files. The state is kept in the user's cache dir, keyed by the project root
+// and the action, so that it never shows up in the project's working tree and
+// a `tidy` run doesn't replace the failures from the last `lint` run.
+use crate::config_extends;
+use anyhow::Result;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct Failures {
+    // The paths here are relative to the project root.
+    commands: BTreeMap<String, BTreeSet<PathBuf>>,
+}
+
+impl Failures {
+    pub(crate) fn load(project_root: &Path, action: &str) -> Result<Self> {
+        match state_file(project_root, action) {
+            Some(file) => Self::load_file(&file),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub(crate) fn save(&self, project_root: &Path, action: &str) -> Result<()> {
+        match state_file(project_root, action) {
+            Some(file) => self.save_file(&file),
+            None => {
+                debug!("Not saving failures because there is no cache dir");
+                Ok(())
+            }
+        }
+    }
+
+    fn load_file(file: &Path) -> Result<Self> {
+        match fs::read_to_string(file) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // If nothing failed, the state file is deleted instead of being left
+    // around with nothing in it. Otherwise it's written to a temp file and
+    // renamed into place, so a concurrent run never reads half a file.
+    fn save_file(&self, file: &Path) -> Result<()> {
+        if self.commands.is_empty() {
+            return match fs::remove_file(file) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        let dir = file
+            .parent()
+            .unwrap_or_else(|| unreachable!("The state file is always in a directory"));
+        fs::create_dir_all(dir)?;
+        let temp = NamedTempFile::new_in(dir)?;
+        fs::write(temp.path(), serde_json::to_string(self)?)?;
+        temp.persist(file)?;
+        Ok(())
+    }
+
+    pub(crate) fn add(&mut self, command: &str, paths: &[PathBuf]) {
+        self.commands
+            .entry(command.to_string())
+            .or_default()
+            .extend(paths.iter().cloned());
+    }
+
+    // Drops everything saved for the given commands, which is done before
+    // adding the failures from a run that included those commands.
+    pub(crate) fn clear<'a>(&mut self, commands: impl IntoIterator<Item = &'a str>) {
+        for c in commands {
+            self.commands.remove(c);
+        }
+    }
+
+    pub(crate) fn has_command(&self, command: &str) -> bool {
+        self.commands.contains_key(command)
+    }
+
+    pub(crate) fn files_for(&self, command: &str) -> Option<&BTreeSet<PathBuf>> {
+        self.commands.get(command)
+    }
+
+    pub(crate) fn all_files(&self) -> BTreeSet<&Path> {
+        self.commands
+            .values()
+            .flatten()
+            .map(PathBuf::as_path)
+            .collect()
+    }
+}
+
+fn state_file(project_root: &Path, action: &str) -> Option<PathBuf> {
+    Some(
+        config_extends::user_cache_dir()?
+            .join("failures")
+            .join(format!(
+                "{:x}-{action}.json",
+                md5::compute(project_root.to_string_lossy().as_bytes()),
+            )),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    #[test]
+    #[parallel]
+    fn save_and_load() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let file = td.path().join("failures").join("lint.json");
+        assert_eq!(Failures::load_file(&file)?, Failures::default());
+
+        let mut failures = Failures::default();
+        failures.add("clippy", &[PathBuf::from("src/lib.rs")]);
+        failures.add("clippy", &[PathBuf::from("src/main.rs")]);
+        failures.add("rustfmt", &[PathBuf::from("src/main.rs")]);
+        failures.save_file(&file)?;
+
+        let loaded = Failures::load_file(&file)?;
+        assert_eq!(loaded, failures);
+        assert_eq!(
+            loaded.all_files().into_iter().collect::<Vec<_>>(),
+            vec![Path::new("src/lib.rs"), Path::new("src/main.rs")],
+        );
+
+        failures.clear(["clippy", "rustfmt"]);
+        failures.save_file(&file)?;
+        assert!(!file.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn clear_keeps_other_commands() {
+        let mut failures = Failures::default();
+        failures.add("clippy", &[PathBuf::from("src/lib.rs")]);
+        failures.add("rustfmt", &[PathBuf::from("src/main.rs")]);
+        failures.clear(["clippy", "typos"]);
+
+        assert!(!failures.has_command("clippy"));
+        assert_eq!(
+            failures.files_for("rustfmt"),
+            Some(&BTreeSet::from([PathBuf::from("src/main.rs")])),
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn state_file_is_per_action() {
+        let root = Path::new("/project");
+        assert_ne!(state_file(root, "lint"), state_file(root, "tidy"));
+    }
+}
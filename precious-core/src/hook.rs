@@ -0,0 +1,58 @@
+// Config for other tools' commit hook managers that runs precious. Each one
+// tidies the staged files and then lints them.
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum HookFormat {
+    /// A local hook for the `.pre-commit-config.yaml` file used by
+    /// <https://pre-commit.com>
+    PreCommitFramework,
+    /// A `pre-commit` section for the `lefthook.yml` file used by
+    /// <https://github.com/evilmartians/lefthook>
+    Lefthook,
+    /// A `.husky/pre-commit` script for <https://typicode.github.io/husky/>
+    Husky,
+}
+
+// The pre-commit framework stashes unstaged changes itself, and it fails the
+// hook when a hook changes any files, so we don't restage tidied files for
+// it. The other managers leave restaging up to the hook.
+const PRE_COMMIT_FRAMEWORK: &str = r"# Add this to the `repos` list in your .pre-commit-config.yaml file.
+- repo: local
+  hooks:
+    - id: precious-tidy
+      name: precious tidy
+      entry: precious tidy --staged
+      language: system
+      pass_filenames: false
+      always_run: true
+    - id: precious-lint
+      name: precious lint
+      entry: precious lint --staged
+      language: system
+      pass_filenames: false
+      always_run: true
+";
+
+const LEFTHOOK: &str = r"# Add this to your lefthook.yml file.
+pre-commit:
+  piped: true
+  commands:
+    1_precious-tidy:
+      run: precious tidy --staged --restage
+    2_precious-lint:
+      run: precious lint --staged
+";
+
+const HUSKY: &str = r"# Save this as .husky/pre-commit in your repo.
+precious tidy --staged --restage
+precious lint --staged
+";
+
+pub(crate) fn config_for(format: HookFormat) -> &'static str {
+    match format {
+        HookFormat::PreCommitFramework => PRE_COMMIT_FRAMEWORK,
+        HookFormat::Lefthook => LEFTHOOK,
+        HookFormat::Husky => HUSKY,
+    }
+}
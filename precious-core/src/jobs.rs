@@ -0,0 +1,121 @@
+use std::{num::NonZeroUsize, str::FromStr, thread};
+use thiserror::Error;
+
+// How many jobs to run in parallel, as given by the `--jobs` flag or the
+// `jobs` config key. This is resolved against the number of available CPUs
+// just before the thread pool is built.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Jobs {
+    // An exact number of jobs. Zero means one per CPU.
+    Count(usize),
+    // A percentage of the available CPUs, from 1 to 100.
+    Percent(usize),
+    // The number of available CPUs minus this many.
+    AllBut(usize),
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub(crate) enum JobsError {
+    #[error(
+        r#"The jobs value "{value}" is not valid. It must be a number like "4", a percentage like "50%", or a negative number like "-1""#
+    )]
+    Invalid { value: String },
+
+    #[error(r#"The jobs percentage "{value}" must be between 1% and 100%"#)]
+    PercentOutOfRange { value: String },
+}
+
+impl FromStr for Jobs {
+    type Err = JobsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || JobsError::Invalid {
+            value: s.to_string(),
+        };
+
+        let trimmed = s.trim();
+        if let Some(percent) = trimmed.strip_suffix('%') {
+            let percent = percent.trim_end().parse::<usize>().map_err(|_| invalid())?;
+            if !(1..=100).contains(&percent) {
+                return Err(JobsError::PercentOutOfRange {
+                    value: s.to_string(),
+                });
+            }
+            return Ok(Jobs::Percent(percent));
+        }
+        if let Some(but) = trimmed.strip_prefix('-') {
+            return match but.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Jobs::AllBut(n)),
+                _ => Err(invalid()),
+            };
+        }
+        trimmed
+            .parse::<usize>()
+            .map(Jobs::Count)
+            .map_err(|_| invalid())
+    }
+}
+
+impl Jobs {
+    // Returns the number of threads to use, where zero means one per CPU.
+    // A percentage or negative number always leaves us with at least one
+    // job, even on a machine with a single CPU.
+    pub(crate) fn resolve(self) -> usize {
+        self.resolve_with(
+            thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1),
+        )
+    }
+
+    fn resolve_with(self, cpus: usize) -> usize {
+        match self {
+            Jobs::Count(n) => n,
+            Jobs::Percent(p) => (cpus * p / 100).max(1),
+            Jobs::AllBut(n) => cpus.saturating_sub(n).max(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case("4", Ok(Jobs::Count(4)) ; "count")]
+    #[test_case("0", Ok(Jobs::Count(0)) ; "zero")]
+    #[test_case("50%", Ok(Jobs::Percent(50)) ; "percent")]
+    #[test_case(" 100 % ", Ok(Jobs::Percent(100)) ; "percent with spaces")]
+    #[test_case("-1", Ok(Jobs::AllBut(1)) ; "all but one")]
+    #[test_case(
+        "0%",
+        Err(JobsError::PercentOutOfRange { value: "0%".to_string() }) ;
+        "zero percent"
+    )]
+    #[test_case(
+        "150%",
+        Err(JobsError::PercentOutOfRange { value: "150%".to_string() }) ;
+        "too many percent"
+    )]
+    #[test_case("-0", Err(JobsError::Invalid { value: "-0".to_string() }) ; "negative zero")]
+    #[test_case("many", Err(JobsError::Invalid { value: "many".to_string() }) ; "not a number")]
+    #[test_case("1.5", Err(JobsError::Invalid { value: "1.5".to_string() }) ; "fraction")]
+    #[parallel]
+    fn from_str(value: &str, expect: Result<Jobs, JobsError>) {
+        assert_eq!(value.parse::<Jobs>(), expect);
+    }
+
+    #[test_case(Jobs::Count(3), 8, 3 ; "count")]
+    #[test_case(Jobs::Count(0), 8, 0 ; "zero means one per cpu")]
+    #[test_case(Jobs::Percent(50), 8, 4 ; "half")]
+    #[test_case(Jobs::Percent(50), 5, 2 ; "half rounds down")]
+    #[test_case(Jobs::Percent(10), 4, 1 ; "never less than one from percent")]
+    #[test_case(Jobs::AllBut(1), 8, 7 ; "all but one")]
+    #[test_case(Jobs::AllBut(2), 2, 1 ; "never less than one from all but")]
+    #[parallel]
+    fn resolve_with(jobs: Jobs, cpus: usize, expect: usize) {
+        assert_eq!(jobs.resolve_with(cpus), expect);
+    }
+}
@@ -1,8 +1,35 @@
 pub mod precious;
 
+mod builtin;
 mod chars;
+mod color;
 mod command;
+mod completions;
 mod config;
+mod config_extends;
 mod config_init;
+mod config_migrate;
+mod config_schema;
+mod content;
+mod docs;
+mod editorconfig;
+mod failures;
+mod hook;
+mod jobs;
+mod manifest;
+mod metrics;
+mod patch;
 mod paths;
+mod plugin;
+mod record;
+mod reporter;
+mod runner;
+mod sandbox;
+mod scheduler;
+mod server;
+mod stats;
+mod tap;
 mod vcs;
+
+pub use paths::mode::Mode;
+pub use runner::{Action, Invocation, Outcome, RunReport, Runner, RunnerBuilder};
@@ -0,0 +1,80 @@
+// With `--manifest`, a run writes a JSON file describing exactly what it
+// checked: the precious version, a hash of each config file, the version of
+// each command that has a `version-cmd`, the mode, and the files that were
+// found. Unlike `--record`, this says nothing about what the commands did, so
+// it's small enough to keep as a CI artifact for every run.
+use crate::config;
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    precious_version: &'static str,
+    action: &'a str,
+    mode: &'a str,
+    projects: Vec<Project>,
+}
+
+// With `--root` or a `[workspace]`, there is one of these for each project
+// root. Otherwise there's just one.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct Project {
+    pub(crate) config_file: PathBuf,
+    // This is the BLAKE3 hash of the resolved config, with any base configs
+    // from `extends` merged into it.
+    pub(crate) config_hash: String,
+    // These are relative to the project root.
+    pub(crate) files: Vec<PathBuf>,
+    pub(crate) commands: Vec<Command>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct Command {
+    pub(crate) name: String,
+    // This is the output of the command's `version-cmd`, if it has one.
+    pub(crate) version: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ManifestWriter {
+    action: &'static str,
+    mode: &'static str,
+    projects: Mutex<Vec<Project>>,
+}
+
+impl ManifestWriter {
+    pub(crate) fn new(action: &'static str, mode: &'static str) -> Self {
+        Self {
+            action,
+            mode,
+            projects: Mutex::new(vec![]),
+        }
+    }
+
+    pub(crate) fn add(&self, project: Project) {
+        self.projects.lock().unwrap().push(project);
+    }
+
+    pub(crate) fn write(&self, file: &Path) -> Result<()> {
+        let manifest = Manifest {
+            precious_version: env!("CARGO_PKG_VERSION"),
+            action: self.action,
+            mode: self.mode,
+            projects: self.projects.lock().unwrap().clone(),
+        };
+        fs::write(file, serde_json::to_string_pretty(&manifest)? + "\n")?;
+        Ok(())
+    }
+}
+
+// This hashes the config with all of its base configs merged in, so the hash
+// changes when a base config changes, even if the config file itself doesn't.
+pub(crate) fn config_hash(config_file: &Path) -> Result<String> {
+    let resolved = toml::to_string(&config::Config::resolved_table(config_file)?)?;
+    Ok(blake3::hash(resolved.as_bytes()).to_hex().to_string())
+}
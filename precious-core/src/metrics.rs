@@ -0,0 +1,310 @@
+// With `--metrics-file`, a run writes how long each command took, how many
+// files it processed, and how many of its invocations failed, in the
+// Prometheus text format. The node exporter's textfile collector can pick this
+// file up, so CI nodes can track lint health over time without parsing logs.
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tempfile::NamedTempFile;
+
+#[derive(Debug)]
+pub(crate) struct MetricsWriter {
+    action: &'static str,
+    mode: &'static str,
+    start: Instant,
+    // With `--root` or a `[workspace]`, each command's metrics are labeled
+    // with its project root relative to this dir. A single project run has no
+    // `project` label.
+    workspace_base: Option<PathBuf>,
+    // The key is the project root and the command name. With `--root` or a
+    // `[workspace]`, two projects can each have a command with the same name.
+    commands: Mutex<BTreeMap<(PathBuf, String), CommandMetrics>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct CommandMetrics {
+    pub(crate) duration: Duration,
+    pub(crate) invocations: usize,
+    pub(crate) failures: usize,
+    pub(crate) files: usize,
+}
+
+impl MetricsWriter {
+    pub(crate) fn new(action: &'static str, mode: &'static str) -> Self {
+        Self {
+            action,
+            mode,
+            start: Instant::now(),
+            workspace_base: None,
+            commands: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub(crate) fn with_workspace_base(mut self, base: &Path) -> Self {
+        self.workspace_base = Some(base.to_path_buf());
+        self
+    }
+
+    pub(crate) fn add(&self, project_root: &Path, command: &str, metrics: CommandMetrics) {
+        let mut commands = self.commands.lock().unwrap();
+        let m = commands
+            .entry((project_root.to_path_buf(), command.to_string()))
+            .or_default();
+        m.duration += metrics.duration;
+        m.invocations += metrics.invocations;
+        m.failures += metrics.failures;
+        m.files += metrics.files;
+    }
+
+    // The textfile collector may read the file at any time, so we write it to
+    // a temp file in the same dir and then rename it into place.
+    pub(crate) fn write(&self, file: &Path, status: u8) -> Result<()> {
+        let content = self.render(self.start.elapsed(), status, SystemTime::now())?;
+        let dir = match file.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        let mut temp = NamedTempFile::new_in(dir)?;
+        std::io::Write::write_all(&mut temp, content.as_bytes())?;
+        // The temp file is created with mode 0600, but the collector usually
+        // runs as a different user than precious.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            temp.as_file()
+                .set_permissions(std::fs::Permissions::from_mode(0o644))?;
+        }
+        temp.persist(file)?;
+        Ok(())
+    }
+
+    fn render(&self, elapsed: Duration, status: u8, now: SystemTime) -> Result<String> {
+        let run_labels = format!(
+            r#"action="{}",mode="{}""#,
+            escape(self.action),
+            escape(self.mode),
+        );
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            "precious_run_duration_seconds",
+            "How long the whole run took.",
+            [(run_labels.clone(), elapsed.as_secs_f64().to_string())],
+        )?;
+        write_metric(
+            &mut out,
+            "precious_run_exit_status",
+            "The exit status of the run, which is 0 when it succeeded.",
+            [(run_labels.clone(), status.to_string())],
+        )?;
+        write_metric(
+            &mut out,
+            "precious_run_timestamp_seconds",
+            "When the run finished, in seconds since the Unix epoch.",
+            [(
+                run_labels.clone(),
+                now.duration_since(UNIX_EPOCH)?.as_secs().to_string(),
+            )],
+        )?;
+
+        let commands = self.commands.lock().unwrap();
+        let per_command = |f: fn(&CommandMetrics) -> String| {
+            commands
+                .iter()
+                .map(|((root, command), m)| {
+                    let project = self.project_label(root);
+                    (
+                        format!(r#"{run_labels}{project},command="{}""#, escape(command),),
+                        f(m),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        write_metric(
+            &mut out,
+            "precious_command_duration_seconds",
+            "How long each command took to run on all of its files.",
+            per_command(|m| m.duration.as_secs_f64().to_string()),
+        )?;
+        write_metric(
+            &mut out,
+            "precious_command_invocations",
+            "The number of times each command was invoked.",
+            per_command(|m| m.invocations.to_string()),
+        )?;
+        write_metric(
+            &mut out,
+            "precious_command_failures",
+            "The number of invocations of each command that failed.",
+            per_command(|m| m.failures.to_string()),
+        )?;
+        write_metric(
+            &mut out,
+            "precious_command_files",
+            "The number of files each command processed.",
+            per_command(|m| m.files.to_string()),
+        )?;
+
+        Ok(out)
+    }
+
+    fn project_label(&self, root: &Path) -> String {
+        let Some(base) = &self.workspace_base else {
+            return String::new();
+        };
+        let project = match root.strip_prefix(base) {
+            Ok(p) if p.as_os_str().is_empty() => Path::new("."),
+            Ok(p) => p,
+            Err(_) => root,
+        };
+        format!(r#",project="{}""#, escape(&project.to_string_lossy()))
+    }
+}
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, String)>,
+) -> Result<()> {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} gauge")?;
+    for (labels, value) in samples {
+        writeln!(out, "{name}{{{labels}}} {value}")?;
+    }
+    Ok(())
+}
+
+// Label values must escape backslashes, double quotes, and newlines.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    #[test]
+    #[parallel]
+    fn render() -> Result<()> {
+        let metrics = MetricsWriter::new("lint", "all");
+        let project = Path::new("/project");
+        metrics.add(
+            project,
+            "clippy",
+            CommandMetrics {
+                duration: Duration::from_millis(1500),
+                invocations: 1,
+                failures: 1,
+                files: 10,
+            },
+        );
+        metrics.add(
+            project,
+            "typos",
+            CommandMetrics {
+                duration: Duration::from_millis(250),
+                invocations: 2,
+                failures: 0,
+                files: 2,
+            },
+        );
+        metrics.add(
+            project,
+            "typos",
+            CommandMetrics {
+                duration: Duration::from_millis(250),
+                invocations: 1,
+                failures: 0,
+                files: 1,
+            },
+        );
+
+        let labels = r#"action="lint",mode="all""#;
+        let clippy = format!(r#"{labels},command="clippy""#);
+        let typos = format!(r#"{labels},command="typos""#);
+        let expect = format!(
+            "# HELP precious_run_duration_seconds How long the whole run took.
+# TYPE precious_run_duration_seconds gauge
+precious_run_duration_seconds{{{labels}}} 2.5
+# HELP precious_run_exit_status The exit status of the run, which is 0 when it succeeded.
+# TYPE precious_run_exit_status gauge
+precious_run_exit_status{{{labels}}} 1
+# HELP precious_run_timestamp_seconds When the run finished, in seconds since the Unix epoch.
+# TYPE precious_run_timestamp_seconds gauge
+precious_run_timestamp_seconds{{{labels}}} 1700000000
+# HELP precious_command_duration_seconds How long each command took to run on all of its files.
+# TYPE precious_command_duration_seconds gauge
+precious_command_duration_seconds{{{clippy}}} 1.5
+precious_command_duration_seconds{{{typos}}} 0.5
+# HELP precious_command_invocations The number of times each command was invoked.
+# TYPE precious_command_invocations gauge
+precious_command_invocations{{{clippy}}} 1
+precious_command_invocations{{{typos}}} 3
+# HELP precious_command_failures The number of invocations of each command that failed.
+# TYPE precious_command_failures gauge
+precious_command_failures{{{clippy}}} 1
+precious_command_failures{{{typos}}} 0
+# HELP precious_command_files The number of files each command processed.
+# TYPE precious_command_files gauge
+precious_command_files{{{clippy}}} 10
+precious_command_files{{{typos}}} 3
+"
+        );
+        assert_eq!(
+            metrics.render(
+                Duration::from_millis(2500),
+                1,
+                UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            )?,
+            expect,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn workspace_project_labels() -> Result<()> {
+        let metrics = MetricsWriter::new("tidy", "git").with_workspace_base(Path::new("/ws"));
+        for root in ["/ws", "/ws/a", "/ws/b/c"] {
+            metrics.add(
+                Path::new(root),
+                "rustfmt",
+                CommandMetrics {
+                    invocations: 1,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let rendered = metrics.render(Duration::ZERO, 0, UNIX_EPOCH)?;
+        for project in [".", "a", "b/c"] {
+            let expect = format!(
+                r#"precious_command_invocations{{action="tidy",mode="git",project="{project}",command="rustfmt"}} 1"#
+            );
+            assert!(
+                rendered.lines().any(|l| l == expect),
+                "{expect} is in the metrics:\n{rendered}",
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn escape() {
+        assert_eq!(super::escape("a \"b\" \\c\nd"), r#"a \"b\" \\c\nd"#);
+    }
+}
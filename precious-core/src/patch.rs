@@ -0,0 +1,250 @@
+// Applies a unified diff, like the one printed by `diff -u`, to the content
+// of a single file. This is for tidiers that read a file on stdin and print a
+// patch instead of changing the file themselves. Any file headers are
+// ignored, since we already know which file the patch is for.
+use once_cell::sync::Lazy;
+use regex::Regex;
+use thiserror::Error;
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub(crate) enum PatchError {
+    #[error("The patch has an invalid hunk header: {header:}")]
+    InvalidHunkHeader { header: String },
+
+    #[error("The patch has a hunk starting at line {line:}, which is before the end of the previous hunk or past the end of the file")]
+    HunkOutOfOrder { line: usize },
+
+    #[error(
+        "The patch has a line in a hunk that does not start with a space, \"-\", or \"+\": {line:}"
+    )]
+    InvalidHunkLine { line: String },
+
+    #[error("The patch does not match the file at line {line:}, which should be {expect:?}")]
+    ContextMismatch { line: usize, expect: String },
+}
+
+static HUNK_HEADER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^@@ -(\d+)(?:,(\d+))? \+\d+(?:,(\d+))? @@").unwrap());
+
+struct Hunk {
+    // The index of the first line in the original that the hunk applies to.
+    start: usize,
+    old_lines: usize,
+    new_lines: usize,
+}
+
+// Returns the patched content. A patch with no hunks returns the content
+// unchanged.
+pub(crate) fn apply(original: &str, patch: &str) -> Result<String, PatchError> {
+    let lines = original.split_inclusive('\n').collect::<Vec<_>>();
+    let mut patched = String::with_capacity(original.len());
+    // The index of the next line in the original that hasn't been copied to
+    // the patched content.
+    let mut next = 0;
+    // The number of lines from the original and the patched content that
+    // are left in the current hunk. Lines outside of a hunk are headers.
+    let (mut old_left, mut new_left) = (0, 0);
+    let mut prev_op = None;
+
+    for line in patch.split_inclusive('\n') {
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        if old_left == 0 && new_left == 0 {
+            if text.starts_with('\\') && prev_op.is_some() {
+                no_newline_at_end(&mut patched, prev_op);
+            } else if text.starts_with("@@") {
+                let hunk = parse_hunk_header(text)?;
+                if hunk.start < next || hunk.start > lines.len() {
+                    return Err(PatchError::HunkOutOfOrder {
+                        line: hunk.start + 1,
+                    });
+                }
+                lines[next..hunk.start]
+                    .iter()
+                    .for_each(|l| patched.push_str(l));
+                next = hunk.start;
+                (old_left, new_left) = (hunk.old_lines, hunk.new_lines);
+            }
+            prev_op = None;
+            continue;
+        }
+
+        // Some tools drop the space that starts an empty context line.
+        let (op, rest) = match text.chars().next() {
+            None => (' ', ""),
+            Some(c) => (c, &text[c.len_utf8()..]),
+        };
+        match op {
+            ' ' | '-' => {
+                let Some(orig) = lines.get(next) else {
+                    return Err(PatchError::ContextMismatch {
+                        line: next + 1,
+                        expect: rest.to_string(),
+                    });
+                };
+                if orig.strip_suffix('\n').unwrap_or(orig) != rest {
+                    return Err(PatchError::ContextMismatch {
+                        line: next + 1,
+                        expect: rest.to_string(),
+                    });
+                }
+                if op == ' ' {
+                    patched.push_str(orig);
+                    new_left = new_left.saturating_sub(1);
+                }
+                old_left = old_left.saturating_sub(1);
+                next += 1;
+            }
+            '+' => {
+                patched.push_str(rest);
+                patched.push('\n');
+                new_left = new_left.saturating_sub(1);
+            }
+            '\\' => no_newline_at_end(&mut patched, prev_op),
+            _ => {
+                return Err(PatchError::InvalidHunkLine {
+                    line: text.to_string(),
+                })
+            }
+        }
+        prev_op = Some(op);
+    }
+
+    lines[next..].iter().for_each(|l| patched.push_str(l));
+    Ok(patched)
+}
+
+// This handles "\ No newline at end of file", which applies to the line
+// before it. When that line was removed, there's nothing to change in the
+// patched content.
+fn no_newline_at_end(patched: &mut String, prev_op: Option<char>) {
+    if prev_op != Some('-') && patched.ends_with('\n') {
+        patched.pop();
+    }
+}
+
+// When a hunk has no lines from the original, its start is the line after
+// which the new lines are added.
+fn parse_hunk_header(header: &str) -> Result<Hunk, PatchError> {
+    let invalid = || PatchError::InvalidHunkHeader {
+        header: header.to_string(),
+    };
+    let caps = HUNK_HEADER_RE.captures(header).ok_or_else(invalid)?;
+    let num = |i: usize| {
+        caps.get(i)
+            .map_or(Ok(1), |c| c.as_str().parse::<usize>())
+            .map_err(|_| invalid())
+    };
+    let (start, old_lines, new_lines) = (num(1)?, num(2)?, num(3)?);
+    Ok(Hunk {
+        start: if old_lines == 0 {
+            start
+        } else {
+            start.saturating_sub(1)
+        },
+        old_lines,
+        new_lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case(
+        "a\nb\nc\n",
+        "",
+        "a\nb\nc\n" ;
+        "empty patch"
+    )]
+    #[test_case(
+        "a\nb\nc\n",
+        "--- a/file\n+++ b/file\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n",
+        "a\nB\nc\n" ;
+        "change one line"
+    )]
+    #[test_case(
+        "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n",
+        "@@ -1,2 +1,3 @@\n 1\n+1.5\n 2\n@@ -9,2 +10,1 @@\n 9\n-10\n",
+        "1\n1.5\n2\n3\n4\n5\n6\n7\n8\n9\n" ;
+        "two hunks"
+    )]
+    #[test_case(
+        "a\nb\n",
+        "@@ -0,0 +1 @@\n+first\n",
+        "first\na\nb\n" ;
+        "insert at start"
+    )]
+    #[test_case(
+        "a\n\nb\n",
+        "@@ -1,3 +1,3 @@\n a\n\n-b\n+c\n",
+        "a\n\nc\n" ;
+        "empty context line without a space"
+    )]
+    #[test_case(
+        "a\nb",
+        "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n",
+        "a\nb\n" ;
+        "add newline at end of file"
+    )]
+    #[test_case(
+        "a\nb\n",
+        "@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n",
+        "a\nb" ;
+        "remove newline at end of file"
+    )]
+    #[test_case(
+        "a\r\nb\r\n",
+        "@@ -1,2 +1,2 @@\n a\r\n-b\r\n+c\r\n",
+        "a\r\nc\r\n" ;
+        "crlf line endings"
+    )]
+    #[test_case(
+        "a\n---\nb\n",
+        "--- a/file\n+++ b/file\n@@ -1,3 +1,2 @@\n a\n----\n b\n--- a/other\n+++ b/other\n",
+        "a\nb\n" ;
+        "headers after a hunk"
+    )]
+    #[parallel]
+    fn apply_patch(original: &str, patch: &str, expect: &str) -> Result<(), PatchError> {
+        assert_eq!(apply(original, patch)?, expect);
+        Ok(())
+    }
+
+    #[test_case(
+        "a\nb\n",
+        "@@ -1,2 +1,2 @@\n a\n-x\n+y\n",
+        PatchError::ContextMismatch { line: 2, expect: String::from("x") } ;
+        "context mismatch"
+    )]
+    #[test_case(
+        "a\n",
+        "@@ -1,2 +1,2 @@\n a\n-b\n",
+        PatchError::ContextMismatch { line: 2, expect: String::from("b") } ;
+        "past end of file"
+    )]
+    #[test_case(
+        "a\nb\n",
+        "@@ -2 +2 @@\n-b\n+c\n@@ -1 +1 @@\n-a\n+c\n",
+        PatchError::HunkOutOfOrder { line: 1 } ;
+        "hunks out of order"
+    )]
+    #[test_case(
+        "a\n",
+        "@@ -x +1 @@\n",
+        PatchError::InvalidHunkHeader { header: String::from("@@ -x +1 @@") } ;
+        "invalid hunk header"
+    )]
+    #[test_case(
+        "a\n",
+        "@@ -1 +1 @@\n*a\n",
+        PatchError::InvalidHunkLine { line: String::from("*a") } ;
+        "invalid hunk line"
+    )]
+    #[parallel]
+    fn apply_patch_errors(original: &str, patch: &str, expect: PatchError) {
+        assert_eq!(apply(original, patch), Err(expect));
+    }
+}
@@ -1,20 +1,22 @@
 use crate::{
     paths::{
-        matcher::{Matcher, MatcherBuilder},
+        matcher::{GlobMatch, Matcher, MatcherBuilder},
         mode::Mode,
     },
     vcs,
 };
 use anyhow::Result;
 use clean_path::Clean;
+use itertools::Itertools;
 use log::{debug, error};
 use once_cell::sync::Lazy;
-use precious_helpers::exec;
+use precious_helpers::exec::Exec;
 use regex::Regex;
 use std::{
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 use thiserror::Error;
 
@@ -22,9 +24,21 @@ use thiserror::Error;
 pub struct Finder {
     mode: Mode,
     project_root: PathBuf,
+    // The project root as we were given it. When we canonicalize the root,
+    // paths built from the cwd may still start with this instead.
+    given_root: PathBuf,
+    canonicalize: bool,
     git_root: Option<PathBuf>,
+    jj_root: Option<PathBuf>,
     cwd: PathBuf,
+    // If this isn't empty, only files matching one of these globs are kept.
+    include_globs: Vec<String>,
     exclude_globs: Vec<String>,
+    exclude_attributes: Vec<String>,
+    follow_symlinks: bool,
+    // Passed to every git command we run for the project itself, but not
+    // for its submodules, which have their own git dirs.
+    git_env: HashMap<String, String>,
     stashed: bool,
 }
 
@@ -47,28 +61,67 @@ pub enum FinderError {
     PrefixNotFound { path: PathBuf, prefix: PathBuf },
 }
 
+// The reason a path would never be given to any command.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Exclusion {
+    // The path is inside a VCS directory like `.git`.
+    VcsDir { dir: String },
+    ExcludedByGlob { glob: String },
+    ExcludedByAttribute { attribute: String },
+}
+
 static KEEP_INDEX_RE: Lazy<Regex> = Lazy::new(|| Regex::new(".*").unwrap());
 
+// The number of paths we pass to each `git check-attr` call, which keeps us
+// well under the OS limits on the length of a command line.
+const CHECK_ATTR_BATCH_SIZE: usize = 1000;
+
+// The `git diff` args we use to list changed files. A file that was renamed
+// is listed under its new name, and we turn on rename detection with `-M` so
+// that this doesn't depend on the user's `diff.renames` setting.
+const GIT_DIFF_NAME_STATUS_ARGS: &[&str] =
+    &["diff", "-z", "--name-status", "-M", "--diff-filter=ACMR"];
+
 impl Finder {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         mode: Mode,
         project_root: PathBuf,
         cwd: PathBuf,
+        include_globs: Vec<String>,
         exclude_globs: Vec<String>,
+        exclude_attributes: Vec<String>,
+        follow_symlinks: bool,
+        canonicalize: bool,
     ) -> Result<Finder> {
+        let given_root = project_root.clean();
+        let project_root = if canonicalize {
+            fs::canonicalize(&given_root)?
+        } else {
+            given_root.clone()
+        };
+        let jj_root = vcs::jj_root(&project_root);
+        let git_env = vcs::git_location_env(&cwd);
         Ok(Finder {
             mode,
-            project_root: fs::canonicalize(project_root)?,
+            project_root,
+            given_root,
+            canonicalize,
             git_root: None,
+            jj_root,
             cwd,
+            include_globs,
             exclude_globs,
+            exclude_attributes,
+            follow_symlinks,
+            git_env,
             stashed: false,
         })
     }
 
     pub fn files(&mut self, cli_paths: Vec<PathBuf>) -> Result<Option<Vec<PathBuf>>> {
         match self.mode {
-            Mode::FromCli => (),
+            Mode::FromCli | Mode::Failed => (),
             _ => {
                 if !cli_paths.is_empty() {
                     return Err(FinderError::GotPathsFromCliWithWrongMode {
@@ -79,21 +132,27 @@ impl Finder {
             }
         };
 
-        let mut files = match self.mode.clone() {
+        let files = match self.mode.clone() {
             Mode::All => self.all_files()?,
-            Mode::FromCli => self.files_from_cli(cli_paths)?,
+            Mode::FromCli | Mode::Failed => self.files_from_cli(cli_paths)?,
             Mode::GitModified => self.git_modified_files()?,
+            Mode::GitUncommitted => self.git_uncommitted_files()?,
             Mode::GitStaged | Mode::GitStagedWithStash => self.git_staged_files()?,
             Mode::GitDiffFrom(ref from) => self.git_modified_since(from)?,
+            Mode::Since(since) => self.files_modified_within(since)?,
         };
+        let files = self.include_by_globs(files)?;
+        let mut files = self.exclude_by_attributes(files)?;
         files.sort();
 
         if files.is_empty() {
             return match self.mode {
                 Mode::GitModified
+                | Mode::GitUncommitted
                 | Mode::GitStaged
                 | Mode::GitStagedWithStash
-                | Mode::GitDiffFrom(_) => Ok(None),
+                | Mode::GitDiffFrom(_)
+                | Mode::Since(_) => Ok(None),
                 _ => Err(FinderError::AllPathsWereExcluded {
                     mode: self.mode.clone(),
                 }
@@ -104,22 +163,149 @@ impl Finder {
         Ok(Some(files))
     }
 
+    // Turns a path relative to the cwd into one relative to the project
+    // root, the same way as paths passed on the command line.
+    pub fn path_from_cli(&self, path: &Path) -> Result<PathBuf> {
+        let full = self.cwd.join(path);
+        if !full.exists() {
+            return Err(FinderError::NonExistentPathOnCli {
+                path: path.to_path_buf(),
+            }
+            .into());
+        }
+        self.path_relative_to_project_root(&full)
+    }
+
+    // Returns the reason that a path relative to the project root would not
+    // be found when it's passed on the command line, checking in the same
+    // order that `files` does. Like `files_from_cli`, this doesn't look at
+    // ignore files such as `.gitignore`, since a path that's named explicitly
+    // is always used. This doesn't look at the `--include` globs.
+    pub fn exclusion_for(&self, path: &Path) -> Result<Option<Exclusion>> {
+        let is_dir = self.project_root.join(path).is_dir();
+        let vcs_dirs = MatcherBuilder::new(&self.project_root)
+            .with(
+                &vcs::DIRS
+                    .iter()
+                    .map(|d| format!("{d}/"))
+                    .collect::<Vec<_>>(),
+            )?
+            .build()?;
+        if let GlobMatch::Matched(dir) = vcs_dirs.glob_match(path, is_dir) {
+            return Ok(Some(Exclusion::VcsDir {
+                dir: dir.trim_end_matches('/').to_string(),
+            }));
+        }
+        let excluder = MatcherBuilder::new(&self.project_root)
+            .named("global exclude")
+            .with(&self.exclude_globs)?
+            .build()?;
+        if let GlobMatch::Matched(glob) = excluder.glob_match(path, is_dir) {
+            return Ok(Some(Exclusion::ExcludedByGlob {
+                glob: glob.to_string(),
+            }));
+        }
+        let path = path.to_path_buf();
+        if let Some(attribute) = self
+            .files_with_excluded_attributes(std::slice::from_ref(&path))?
+            .remove(&path)
+        {
+            return Ok(Some(Exclusion::ExcludedByAttribute { attribute }));
+        }
+        Ok(None)
+    }
+
+    // Returns the lines that were added or changed in each file, for the git
+    // modes. For any other mode this returns `None`.
+    pub fn changed_lines(&mut self) -> Result<Option<vcs::ChangedLines>> {
+        if let Some(jj_root) = self.jj_root.clone() {
+            if let Some(revs) = self.jj_diff_revs() {
+                let mut args = vec!["diff", "--git", "--context=0"];
+                args.extend(revs.iter().map(String::as_str));
+                let diff = self.jj_stdout(&args, &jj_root)?;
+                return Ok(Some(self.changed_lines_from_diff(&diff, &jj_root)));
+            }
+        }
+
+        // Forcing `core.quotepath` on means that any path with unusual bytes
+        // is quoted and escaped in the diff, no matter what the user's git
+        // config says, so the diff itself is always plain ASCII.
+        let mut args = vec![
+            String::from("-c"),
+            String::from("core.quotepath=on"),
+            String::from("diff"),
+            String::from("--unified=0"),
+            String::from("--no-color"),
+            String::from("--no-ext-diff"),
+            String::from("-M"),
+            String::from("--diff-filter=ACMR"),
+            String::from("--src-prefix=a/"),
+            String::from("--dst-prefix=b/"),
+            // This includes the changes to files in submodules, with the
+            // submodule's path as a prefix.
+            String::from("--submodule=diff"),
+        ];
+        match &self.mode {
+            Mode::GitModified => args.push(String::from("HEAD")),
+            Mode::GitStaged | Mode::GitStagedWithStash => args.push(String::from("--cached")),
+            Mode::GitDiffFrom(from) => args.push(git_diff_range(from)),
+            // Untracked files aren't in any diff, so we can't tell which of
+            // their lines changed.
+            Mode::FromCli | Mode::All | Mode::GitUncommitted | Mode::Failed | Mode::Since(_) => {
+                return Ok(None)
+            }
+        }
+
+        let git_root = self.git_root()?;
+        let result = Exec::builder("git")
+            .args(&args)
+            .envs(&self.git_env)
+            .in_dir(&self.project_root)
+            .build()
+            .run()?;
+
+        Ok(Some(self.changed_lines_from_diff(
+            result.stdout.as_deref().unwrap_or(""),
+            &git_root,
+        )))
+    }
+
+    fn changed_lines_from_diff(&self, diff: &str, repo_root: &Path) -> vcs::ChangedLines {
+        vcs::ChangedLines::from_unified_diff(diff).map_paths(|p| {
+            let full = repo_root.join(p);
+            if !full.exists() {
+                return None;
+            }
+            self.path_relative_to_project_root(&full).ok()
+        })
+    }
+
     fn git_root(&mut self) -> Result<PathBuf> {
         if let Some(r) = &self.git_root {
             return Ok(r.clone());
         }
 
-        let res = exec::run(
-            "git",
-            &["rev-parse", "--show-toplevel"],
-            &HashMap::new(),
-            &[0],
-            None,
-            Some(&self.project_root),
-        )?;
+        // git always prints the canonical path for `--show-toplevel`. Without
+        // canonicalization we need the root in the same form as the project
+        // root, so we ask for the path up to it instead.
+        let arg = if self.canonicalize {
+            "--show-toplevel"
+        } else {
+            "--show-cdup"
+        };
+        let res = Exec::builder("git")
+            .args(["rev-parse", arg])
+            .envs(&self.git_env)
+            .in_dir(&self.project_root)
+            .build()
+            .run()?;
 
         let stdout = res.stdout.ok_or(FinderError::CouldNotDetermineRepoRoot)?;
-        self.git_root = Some(PathBuf::from(stdout.trim()));
+        self.git_root = Some(if self.canonicalize {
+            PathBuf::from(stdout.trim())
+        } else {
+            self.project_root.join(stdout.trim()).clean()
+        });
 
         Ok(self.git_root.clone().unwrap())
     }
@@ -129,6 +315,25 @@ impl Finder {
         self.walkdir_files(self.project_root.as_path())
     }
 
+    // This uses each file's mtime, so it doesn't need a VCS at all.
+    fn files_modified_within(&self, since: Duration) -> Result<Vec<PathBuf>> {
+        debug!(
+            "Getting files under {} modified in the last {}s",
+            self.project_root.display(),
+            since.as_secs(),
+        );
+        let cutoff = SystemTime::now()
+            .checked_sub(since)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut files = vec![];
+        for f in self.all_files()? {
+            if fs::metadata(self.project_root.join(&f))?.modified()? >= cutoff {
+                files.push(f);
+            }
+        }
+        Ok(files)
+    }
+
     fn files_from_cli(&self, cli_paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
         debug!("Using the list of files passed from the command line");
         let excluder = self.excluder()?;
@@ -157,14 +362,59 @@ impl Finder {
     }
 
     fn git_modified_files(&mut self) -> Result<Vec<PathBuf>> {
+        if self.jj_root.is_some() {
+            return self.jj_changed_files();
+        }
+
         debug!("Getting modified files according to git");
-        self.files_from_git(&["diff", "--name-only", "--diff-filter=ACM", "HEAD"])
+        let files = self.files_from_git_diff(&["HEAD"])?;
+        self.expand_submodules(files)
+    }
+
+    // This is everything that's changed since the last commit, whether or not
+    // it's staged, along with files that git doesn't track yet. We list each
+    // of these separately, rather than using `git diff HEAD`, so that this
+    // works in a repo with no commits.
+    fn git_uncommitted_files(&mut self) -> Result<Vec<PathBuf>> {
+        // Jujutsu tracks new files automatically, so the working copy commit
+        // already has all of these.
+        if self.jj_root.is_some() {
+            return self.jj_changed_files();
+        }
+
+        debug!("Getting staged, unstaged, and untracked files according to git");
+        let mut files = self.files_from_git_diff(&["--cached"])?;
+        files.extend(self.files_from_git_diff(&[])?);
+        let mut files = self.expand_submodules(files)?;
+        files.extend(self.git_untracked_files()?);
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    // The untracked files under the project root that aren't ignored.
+    fn git_untracked_files(&mut self) -> Result<Vec<PathBuf>> {
+        let git_root = self.git_root()?;
+        let stdout = Exec::builder("git")
+            .args([
+                "ls-files",
+                "-z",
+                "--others",
+                "--exclude-standard",
+                "--full-name",
+            ])
+            .envs(&self.git_env)
+            .in_dir(&self.project_root)
+            .build()
+            .run_for_bytes()?;
+        self.files_from_vcs_output(paths_from_nul_separated(&stdout), &git_root)
     }
 
     fn git_staged_files(&mut self) -> Result<Vec<PathBuf>> {
         debug!("Getting staged files according to git");
         self.maybe_git_stash()?;
-        self.files_from_git(&["diff", "--cached", "--name-only", "--diff-filter=ACM"])
+        let files = self.files_from_git_diff(&["--cached"])?;
+        self.expand_submodules(files)
     }
 
     fn maybe_git_stash(&mut self) -> Result<()> {
@@ -172,31 +422,241 @@ impl Finder {
             return Ok(());
         }
 
+        // In a linked worktree, or when GIT_DIR is set, the git dir isn't the
+        // `.git` dir in the repo root, so we ask git where this file would be.
         let git_root = self.git_root()?;
-        let mut mm = git_root.clone();
-        mm.push(".git");
-        mm.push("MERGE_MODE");
+        let mm = git_root.join(
+            self.git_stdout(&["rev-parse", "--git-path", "MERGE_MODE"], &git_root)?
+                .trim(),
+        );
 
         if !mm.exists() {
-            exec::run(
-                "git",
-                &["stash", "--keep-index"],
-                &HashMap::new(),
-                &[0],
+            Exec::builder("git")
+                .args(["stash", "--keep-index"])
+                .envs(&self.git_env)
                 // If there is a post-checkout hook, git will show any output
                 // it prints to stdout on stderr instead.
-                Some(&[KEEP_INDEX_RE.clone()]),
-                Some(&git_root),
-            )?;
+                .ignore_stderr([KEEP_INDEX_RE.clone()])
+                .in_dir(&git_root)
+                .build()
+                .run()?;
             self.stashed = true;
         }
 
         Ok(())
     }
 
+    // Returns the files with changes in the working tree that are not staged.
+    // When a file is also staged, that means only some of its changes are
+    // staged.
+    pub fn unstaged_files(&mut self) -> Result<Vec<PathBuf>> {
+        debug!("Getting files with unstaged changes according to git");
+        self.files_from_git_diff(&[])
+    }
+
+    // Returns the tracked files whose staged or unstaged content is not the
+    // same as in `HEAD`.
+    pub fn files_changed_since_head(&mut self) -> Result<Vec<PathBuf>> {
+        debug!("Getting files that differ from HEAD according to git");
+        let files = self.files_from_git_diff(&["HEAD"])?;
+        self.expand_submodules(files)
+    }
+
+    // Returns the files with unresolved merge conflicts. Outside of a git repo
+    // there can't be any.
+    pub fn unmerged_files(&mut self) -> Result<Vec<PathBuf>> {
+        debug!("Getting files with merge conflicts according to git");
+        let git_root = match self.git_root() {
+            Ok(r) => r,
+            Err(e) => {
+                debug!("Not looking for merge conflicts because this is not a git repo: {e}");
+                return Ok(vec![]);
+            }
+        };
+        let stdout = Exec::builder("git")
+            .args(["diff", "-z", "--name-only", "--diff-filter=U"])
+            .envs(&self.git_env)
+            .in_dir(&self.project_root)
+            .build()
+            .run_for_bytes()?;
+        self.files_from_vcs_output(paths_from_nul_separated(&stdout), &git_root)
+    }
+
+    // The paths must be relative to the project root.
+    pub fn stage_files(&mut self, files: &[&Path]) -> Result<()> {
+        Exec::builder("git")
+            .args(["add", "--"])
+            .args(files)
+            .envs(&self.git_env)
+            .in_dir(&self.project_root)
+            .build()
+            .run()?;
+        Ok(())
+    }
+
     fn git_modified_since(&mut self, since: &str) -> Result<Vec<PathBuf>> {
-        let since_dot = format!("{since:}...");
-        self.files_from_git(&["diff", "--name-only", "--diff-filter=ACM", &since_dot])
+        if self.jj_root.is_some() {
+            return self.jj_changed_files();
+        }
+
+        let range = git_diff_range(since);
+        let files = self.files_from_git_diff(&[&range])?;
+        self.expand_submodules(files)
+    }
+
+    // Jujutsu has no staging area, so only the modified, uncommitted, and
+    // diff-from modes use it. Those are the only modes this is called for.
+    // Jujutsu doesn't support submodules either, so there's nothing to expand
+    // here.
+    fn jj_changed_files(&mut self) -> Result<Vec<PathBuf>> {
+        debug!("Getting {} according to jj", self.mode);
+        let jj_root = self.jj_root.clone().unwrap();
+        let mut args = vec!["diff", "--name-only"];
+        let revs = self.jj_diff_revs().unwrap_or_default();
+        args.extend(revs.iter().map(String::as_str));
+        let stdout = self.jj_stdout(&args, &jj_root)?;
+        self.files_from_vcs_output(stdout.lines().map(PathBuf::from).collect(), &jj_root)
+    }
+
+    // Returns the revisions to pass to `jj diff` for the current mode, or
+    // `None` if the mode doesn't use jj. With no revisions `jj diff` shows the
+    // changes in the working copy commit. For `--git-diff-from` we compare
+    // the working copy against the fork point of the given ref, which is what
+    // `git diff REF...` does, or against the ref itself for `REF..`.
+    fn jj_diff_revs(&self) -> Option<Vec<String>> {
+        self.jj_root.as_ref()?;
+        match &self.mode {
+            Mode::GitModified | Mode::GitUncommitted => Some(vec![]),
+            Mode::GitDiffFrom(from) => {
+                let from = match parse_git_diff_from(from) {
+                    (r, DiffBase::MergeBase) => format!("heads(::@ & ::({r}))"),
+                    (r, DiffBase::Ref) => r.to_string(),
+                };
+                Some(vec![
+                    String::from("--from"),
+                    from,
+                    String::from("--to"),
+                    String::from("@"),
+                ])
+            }
+            _ => None,
+        }
+    }
+
+    fn jj_stdout(&self, args: &[&str], in_dir: &Path) -> Result<String> {
+        let mut full_args = vec!["--no-pager", "--color=never"];
+        full_args.extend(args);
+        let result = Exec::builder("jj")
+            .args(&full_args)
+            .in_dir(in_dir)
+            .build()
+            .run()?;
+        Ok(result.stdout.unwrap_or_default())
+    }
+
+    // When a submodule has changes, git reports the submodule's directory
+    // rather than the files in it that changed. This replaces each of those
+    // directories with the changed files inside the submodule.
+    fn expand_submodules(&mut self, files: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        let mut expanded = vec![];
+        for f in files {
+            let full = self.project_root.join(&f);
+            if !(full.is_dir() && full.join(".git").exists()) {
+                expanded.push(f);
+                continue;
+            }
+
+            debug!("Getting changed files in the submodule at {}", f.display());
+            let excluder = self.excluder()?;
+            for rel in self.submodule_changes(&full)? {
+                let path = f.join(rel);
+                if !self.project_root.join(&path).is_file() || excluder.path_matches(&path, false) {
+                    continue;
+                }
+                expanded.push(path);
+            }
+        }
+        Ok(expanded)
+    }
+
+    // Returns the paths of the files that changed in a submodule, relative to
+    // the submodule's root. What counts as a change depends on the mode, just
+    // like it does for the files in the superproject. The commits we compare
+    // are the ones recorded for the submodule by the superproject.
+    fn submodule_changes(&mut self, submodule: &Path) -> Result<Vec<PathBuf>> {
+        let git_root = self.git_root()?;
+        let in_root = if self.canonicalize {
+            submodule.strip_prefix(fs::canonicalize(&git_root)?)?
+        } else {
+            submodule.strip_prefix(&git_root)?
+        };
+        let in_root = in_root.to_string_lossy();
+
+        let (old, new) = match &self.mode {
+            Mode::GitModified | Mode::GitUncommitted => {
+                (self.rev_parse(&format!("HEAD:{in_root}")), None)
+            }
+            Mode::GitStaged | Mode::GitStagedWithStash => (
+                self.rev_parse(&format!("HEAD:{in_root}")),
+                self.rev_parse(&format!(":{in_root}")),
+            ),
+            Mode::GitDiffFrom(from) => {
+                let base = match parse_git_diff_from(from) {
+                    (r, DiffBase::MergeBase) => self
+                        .git_stdout(&["merge-base", r, "HEAD"], &git_root)?
+                        .trim()
+                        .to_string(),
+                    (r, DiffBase::Ref) => r.to_string(),
+                };
+                (
+                    self.rev_parse(&format!("{base}:{in_root}")),
+                    self.rev_parse(&format!("HEAD:{in_root}")),
+                )
+            }
+            Mode::FromCli | Mode::All | Mode::Failed | Mode::Since(_) => return Ok(vec![]),
+        };
+
+        // If the superproject has no commit for the submodule then the
+        // submodule is new, so all of its files are new too.
+        let Some(old) = old else {
+            return Ok(paths_from_nul_separated(
+                &Exec::builder("git")
+                    .args(["ls-files", "-z"])
+                    .in_dir(submodule)
+                    .build()
+                    .run_for_bytes()?,
+            ));
+        };
+        let mut args = GIT_DIFF_NAME_STATUS_ARGS.to_vec();
+        args.push(&old);
+        if let Some(new) = &new {
+            args.push(new);
+        }
+        Ok(paths_from_name_status(
+            &Exec::builder("git")
+                .args(&args)
+                .in_dir(submodule)
+                .build()
+                .run_for_bytes()?,
+        ))
+    }
+
+    fn rev_parse(&self, rev: &str) -> Option<String> {
+        let root = self.git_root.as_deref().unwrap_or(&self.project_root);
+        self.git_stdout(&["rev-parse", "--verify", "--quiet", rev], root)
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+
+    fn git_stdout(&self, args: &[&str], in_dir: &Path) -> Result<String> {
+        let result = Exec::builder("git")
+            .args(args)
+            .envs(&self.git_env)
+            .in_dir(in_dir)
+            .build()
+            .run()?;
+        Ok(result.stdout.unwrap_or_default())
     }
 
     fn walkdir_files(&self, root: &Path) -> Result<Vec<PathBuf>> {
@@ -208,6 +668,7 @@ impl Finder {
         let mut files: Vec<PathBuf> = vec![];
         for result in ignore::WalkBuilder::new(root)
             .hidden(false)
+            .follow_links(self.follow_symlinks)
             .overrides(exclude_globs.build()?)
             .build()
         {
@@ -218,6 +679,10 @@ impl Finder {
                     }
                     files.push(ent.into_path());
                 }
+                // A symlink that points at one of its own ancestors would
+                // make us walk forever, so the walker reports it as an error
+                // and we just skip it.
+                Err(e) if is_loop_error(&e) => debug!("Skipping a symlink loop: {e}"),
                 Err(e) => return Err(e.into()),
             };
         }
@@ -230,53 +695,135 @@ impl Finder {
             .collect::<Vec<_>>())
     }
 
-    fn files_from_git(&mut self, args: &[&str]) -> Result<Vec<PathBuf>> {
+    // Takes the args that select what to diff, like `HEAD` or `--cached`, and
+    // returns the files that were added, copied, modified, or renamed. A
+    // renamed file is returned under its new name. The paths are separated
+    // with NUL bytes and never quoted, so names with spaces, quotes,
+    // non-ASCII characters, or bytes that aren't valid UTF-8 come back
+    // exactly as they are on disk.
+    fn files_from_git_diff(&mut self, args: &[&str]) -> Result<Vec<PathBuf>> {
         let git_root = self.git_root()?;
-        let result = exec::run(
-            "git",
-            args,
-            &HashMap::new(),
-            &[0],
-            None,
-            Some(&self.project_root),
-        )?;
+        let mut args = args.to_vec();
+        args.splice(0..0, GIT_DIFF_NAME_STATUS_ARGS.iter().copied());
+        let stdout = Exec::builder("git")
+            .args(&args)
+            .envs(&self.git_env)
+            .in_dir(&self.project_root)
+            .build()
+            .run_for_bytes()?;
+        self.files_from_vcs_output(paths_from_name_status(&stdout), &git_root)
+    }
+
+    // Takes the paths listed by a VCS command, relative to the repo root, and
+    // returns those paths relative to the project root.
+    fn files_from_vcs_output(&self, paths: Vec<PathBuf>, repo_root: &Path) -> Result<Vec<PathBuf>> {
         let excluder = self.excluder()?;
+        // In the common case where the repo root and project root are the
+        // same, this isn't necessary, because the paths are already relative
+        // to the project root. But if the precious root _isn't_ the repo
+        // root, we need to get the path relative to the project root, not the
+        // repo root.
+        self.paths_relative_to_project_root(
+            repo_root,
+            paths
+                .into_iter()
+                .filter_map(|rel| {
+                    if excluder.path_matches(&rel, false) {
+                        return None;
+                    }
 
-        match result.stdout {
-            Some(s) => Ok(
-                // In the common case where the git repo root and project root
-                // are the same, this isn't necessary, because git will give
-                // us paths relative to the project root. But if the precious
-                // root _isn't_ the git root, we need to get the path relative
-                // to the project root, not the repo root.
-                self.paths_relative_to_project_root(
-                    &git_root,
-                    s.lines()
-                        .filter_map(|rel| {
-                            let pb = PathBuf::from(rel);
-                            if excluder.path_matches(&pb, false) {
-                                return None;
-                            }
-
-                            let mut f = git_root.clone();
-                            f.push(&pb);
-                            if !f.exists() {
-                                debug!(
-                                    "The staged file at {rel:} was deleted so it will be ignored.",
-                                );
-                                return None;
-                            }
-                            Some(f)
-                        })
-                        .collect(),
-                )?,
-            ),
-            None => Ok(vec![]),
+                    let f = repo_root.join(&rel);
+                    if !f.exists() {
+                        debug!(
+                            "The changed file at {} was deleted so it will be ignored.",
+                            rel.display(),
+                        );
+                        return None;
+                    }
+                    Some(f)
+                })
+                .collect(),
+        )
+    }
+
+    // Removes any files that have one of the `exclude-attributes` set by a
+    // `.gitattributes` file. An attribute without a value matches when it's
+    // set or set to "true", so both `linguist-generated` and
+    // `linguist-generated=true` in `.gitattributes` count.
+    fn exclude_by_attributes(&self, files: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        let excluded = self.files_with_excluded_attributes(&files)?;
+        Ok(files
+            .into_iter()
+            .filter(|f| !excluded.contains_key(f))
+            .collect())
+    }
+
+    // Returns each file that has one of the `exclude-attributes`, along with
+    // the entry from `exclude-attributes` that it matched.
+    fn files_with_excluded_attributes(
+        &self,
+        files: &[PathBuf],
+    ) -> Result<HashMap<PathBuf, String>> {
+        if self.exclude_attributes.is_empty() || files.is_empty() {
+            return Ok(HashMap::new());
         }
+
+        let names = self
+            .exclude_attributes
+            .iter()
+            .map(|a| a.split_once('=').map_or(a.as_str(), |(n, _)| n))
+            .unique()
+            .collect::<Vec<_>>();
+        let mut excluded = HashMap::new();
+        for batch in files.chunks(CHECK_ATTR_BATCH_SIZE) {
+            let stdout = Exec::builder("git")
+                .args(["check-attr", "-z"])
+                .args(&names)
+                .arg("--")
+                .args(batch)
+                .envs(&self.git_env)
+                .in_dir(&self.project_root)
+                .build()
+                .run_for_bytes()?;
+            for (path, attr, value) in stdout.split(|b| *b == 0).tuples() {
+                let attr = String::from_utf8_lossy(attr);
+                let value = String::from_utf8_lossy(value);
+                if let Some(wanted) = self
+                    .exclude_attributes
+                    .iter()
+                    .find(|a| attribute_matches(a, &attr, &value))
+                {
+                    let path = vcs::path_from_bytes(path);
+                    debug!(
+                        "Excluding {} because it has the {attr} attribute",
+                        path.display(),
+                    );
+                    excluded.insert(path, wanted.clone());
+                }
+            }
+        }
+
+        Ok(excluded)
+    }
+
+    fn include_by_globs(&self, files: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        if self.include_globs.is_empty() {
+            return Ok(files);
+        }
+
+        let includer = MatcherBuilder::new(&self.project_root)
+            .named("--include")
+            .with(&self.include_globs)?
+            .build()?;
+        Ok(files
+            .into_iter()
+            .filter(|f| includer.path_matches(f, false))
+            .collect())
     }
 
     fn excluder(&self) -> Result<Matcher> {
         MatcherBuilder::new(&self.project_root)
+            .named("global exclude")
             .with(&self.exclude_globs)?
             .with(vcs::DIRS)?
             .build()
@@ -307,35 +854,60 @@ impl Finder {
     }
 
     fn path_relative_to_project_root(&self, path: &Path) -> Result<PathBuf> {
+        // We try to avoid canonicalizing the path so that symlinks keep their
+        // own names instead of being replaced by the path they point to. But
+        // if the path is not under the project root as given, it may be
+        // because some part of the path to the root is itself a symlink.
+        //
         // If the directory given is just "." then the first clean() removes
         // that and we then strip the prefix, leaving an empty string. The
         // second clean turns that back into ".".
-        Ok(fs::canonicalize(path)?
-            .clean()
-            .strip_prefix(&self.project_root)
-            .map_err(|_| FinderError::PrefixNotFound {
-                path: path.to_path_buf(),
-                prefix: self.project_root.clone(),
-            })?
-            .to_path_buf()
-            .clean())
+        let cleaned = path.clean();
+        for root in [&self.project_root, &self.given_root] {
+            if let Ok(rel) = cleaned.strip_prefix(root) {
+                return Ok(rel.to_path_buf().clean());
+            }
+        }
+
+        if self.canonicalize {
+            // We canonicalize the parent first, which resolves a symlinked
+            // directory in the path, like `/tmp` on macOS, but leaves the
+            // name of a symlinked file alone, even if it points outside of
+            // the project.
+            let candidates = [canonical_parent(&cleaned), fs::canonicalize(path).ok()];
+            for c in candidates.into_iter().flatten() {
+                if let Ok(rel) = c.clean().strip_prefix(&self.project_root) {
+                    return Ok(rel.to_path_buf().clean());
+                }
+            }
+        }
+
+        Err(FinderError::PrefixNotFound {
+            path: path.to_path_buf(),
+            prefix: self.project_root.clone(),
+        }
+        .into())
     }
 }
 
+fn canonical_parent(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    let parent = fs::canonicalize(path.parent()?).ok()?;
+    Some(parent.join(name))
+}
+
 impl Drop for Finder {
     fn drop(&mut self) {
         if !self.stashed {
             return;
         }
 
-        let res = exec::run(
-            "git",
-            &["stash", "pop"],
-            &HashMap::new(),
-            &[0],
-            None,
-            Some(&self.project_root),
-        );
+        let res = Exec::builder("git")
+            .args(["stash", "pop"])
+            .envs(&self.git_env)
+            .in_dir(&self.project_root)
+            .build()
+            .run();
 
         if res.is_ok() {
             return;
@@ -345,6 +917,81 @@ impl Drop for Finder {
     }
 }
 
+// The `value` is what `git check-attr` reports, which is "set", "unset",
+// "unspecified", or the attribute's value.
+fn attribute_matches(wanted: &str, attr: &str, value: &str) -> bool {
+    match wanted.split_once('=') {
+        Some((name, wanted_value)) => name == attr && value == wanted_value,
+        None => wanted == attr && (value == "set" || value == "true"),
+    }
+}
+
+fn is_loop_error(e: &ignore::Error) -> bool {
+    match e {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. } | ignore::Error::WithDepth { err, .. } => {
+            is_loop_error(err)
+        }
+        _ => false,
+    }
+}
+
+// What `--git-diff-from` compares `HEAD` with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum DiffBase {
+    // The merge base of the ref and `HEAD`, like `git diff REF...`.
+    MergeBase,
+    // The ref itself, like `git diff REF..`.
+    Ref,
+}
+
+// Splits a `--git-diff-from` value into a ref and what to compare with. A ref
+// ending in `..` is compared with directly, while a plain ref or one ending in
+// `...` is compared with by its merge base with `HEAD`.
+fn parse_git_diff_from(from: &str) -> (&str, DiffBase) {
+    if let Some(r) = from.strip_suffix("...") {
+        (r, DiffBase::MergeBase)
+    } else if let Some(r) = from.strip_suffix("..") {
+        (r, DiffBase::Ref)
+    } else {
+        (from, DiffBase::MergeBase)
+    }
+}
+
+// Returns the range to pass to `git diff` for a `--git-diff-from` value.
+fn git_diff_range(from: &str) -> String {
+    match parse_git_diff_from(from) {
+        (r, DiffBase::MergeBase) => format!("{r}..."),
+        (r, DiffBase::Ref) => format!("{r}.."),
+    }
+}
+
+// Splits the NUL-separated output of a git command run with `-z` into paths.
+// Each entry in `git diff -z --name-status` output is a status followed by a
+// path, except that renames and copies have the old path and then the new
+// one. We only want the new path.
+fn paths_from_name_status(output: &[u8]) -> Vec<PathBuf> {
+    let mut fields = output.split(|b| *b == 0).filter(|f| !f.is_empty());
+    let mut paths = vec![];
+    while let Some(status) = fields.next() {
+        if matches!(status.first(), Some(b'R' | b'C')) {
+            fields.next();
+        }
+        if let Some(path) = fields.next() {
+            paths.push(vcs::path_from_bytes(path));
+        }
+    }
+    paths
+}
+
+fn paths_from_nul_separated(output: &[u8]) -> Vec<PathBuf> {
+    output
+        .split(|b| *b == 0)
+        .filter(|p| !p.is_empty())
+        .map(vcs::path_from_bytes)
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -354,6 +1001,7 @@ mod tests {
     use pretty_assertions::assert_eq;
     use serial_test::parallel;
     use std::fs;
+    use test_case::test_case;
 
     fn new_finder(mode: Mode, root: PathBuf) -> Result<Finder> {
         new_finder_with_excludes(mode, root.clone(), root, vec![])
@@ -369,7 +1017,7 @@ mod tests {
         cwd: PathBuf,
         exclude: Vec<String>,
     ) -> Result<Finder> {
-        Finder::new(mode, root, cwd, exclude)
+        Finder::new(mode, root, cwd, vec![], exclude, vec![], false, true)
     }
 
     #[cfg(not(target_os = "windows"))]
@@ -403,6 +1051,33 @@ mod tests {
         Ok(())
     }
 
+    #[test_case(&["src/main.rs", "README.md"] ; "some recent files")]
+    #[test_case(&[] ; "no recent files")]
+    #[parallel]
+    fn since_mode(recent: &[&str]) -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let two_hours_ago = filetime::FileTime::from_system_time(
+            SystemTime::now() - Duration::from_secs(2 * 60 * 60),
+        );
+        for f in helper.all_files() {
+            if !recent.contains(&f.to_string_lossy().as_ref()) {
+                filetime::set_file_mtime(helper.precious_root().join(f), two_hours_ago)?;
+            }
+        }
+
+        let mut finder = new_finder(
+            Mode::Since(Duration::from_secs(60 * 60)),
+            helper.precious_root(),
+        )?;
+        let expect = if recent.is_empty() {
+            None
+        } else {
+            Some(recent.iter().map(PathBuf::from).sorted().collect())
+        };
+        assert_eq!(finder.files(vec![])?, expect);
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn all_mode_in_subdir() -> Result<()> {
@@ -415,6 +1090,52 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(not(target_os = "windows"))]
+    #[test_case(true ; "follow symlinks")]
+    #[test_case(false ; "do not follow symlinks")]
+    #[parallel]
+    fn all_mode_with_symlinks(follow_symlinks: bool) -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let root = helper.precious_root();
+        symlink(root.join("src"), root.join("linked-src"))?;
+        // This loops back to the project root, so following it would never
+        // end.
+        symlink(&root, root.join("src/loop"))?;
+        symlink(root.join("README.md"), root.join("README.link"))?;
+
+        let mut expect = helper.all_files();
+        expect.push(PathBuf::from("README.link"));
+        if follow_symlinks {
+            expect.extend(
+                helper
+                    .all_files()
+                    .into_iter()
+                    .filter_map(|f| {
+                        f.strip_prefix("src")
+                            .ok()
+                            .map(|f| PathBuf::from("linked-src").join(f))
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+        expect.sort();
+
+        let mut finder = Finder::new(
+            Mode::All,
+            root.clone(),
+            root,
+            vec![],
+            vec![],
+            vec![],
+            follow_symlinks,
+            true,
+        )?;
+        assert_eq!(finder.files(vec![])?, Some(expect));
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn all_mode_with_gitignore() -> Result<()> {
@@ -444,6 +1165,75 @@ mod tests {
         Ok(())
     }
 
+    #[test_case(&["linguist-generated"], &["src/main.rs", "src/module.rs"] ; "set or true")]
+    #[test_case(&["precious-skip"], &["src/sub/mod.rs"] ; "set by a glob")]
+    #[test_case(&["lang=en"], &["README.md"] ; "with a value")]
+    #[test_case(&["lang=fr"], &[] ; "with a different value")]
+    #[test_case(
+        &["linguist-generated", "precious-skip"],
+        &["src/main.rs", "src/module.rs", "src/sub/mod.rs"] ;
+        "more than one attribute"
+    )]
+    #[parallel]
+    fn all_mode_with_excluded_attributes(attributes: &[&str], excluded: &[&str]) -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        helper.write_file(
+            ".gitattributes",
+            "src/main.rs linguist-generated=true\n\
+             src/module.rs linguist-generated\n\
+             src/sub/*.rs precious-skip\n\
+             README.md lang=en\n",
+        )?;
+        let mut finder = Finder::new(
+            Mode::All,
+            helper.precious_root(),
+            helper.precious_root(),
+            vec![],
+            vec![],
+            attributes.iter().map(|a| a.to_string()).collect(),
+            false,
+            true,
+        )?;
+
+        let mut expect = helper
+            .all_files()
+            .into_iter()
+            .filter(|f| !excluded.contains(&f.to_str().unwrap()))
+            .collect::<Vec<_>>();
+        expect.push(PathBuf::from(".gitattributes"));
+        expect.sort();
+        assert_eq!(finder.files(vec![])?, Some(expect));
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(unix)]
+    fn all_mode_with_excluded_attributes_on_non_utf8_name() -> Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        helper.write_file(".gitattributes", "src/skip-* precious-skip\n")?;
+        let skipped = PathBuf::from(OsStr::from_bytes(b"src/skip-\xff.rs"));
+        fs::write(helper.precious_root().join(&skipped), "fn foo() {}\n")?;
+
+        let mut finder = Finder::new(
+            Mode::All,
+            helper.precious_root(),
+            helper.precious_root(),
+            vec![],
+            vec![],
+            vec!["precious-skip".to_string()],
+            false,
+            true,
+        )?;
+        let files = finder.files(vec![])?.unwrap();
+        assert!(!files.contains(&skipped), "{files:?}");
+        assert!(files.contains(&PathBuf::from("src/main.rs")), "{files:?}");
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn git_modified_mode_empty() -> Result<()> {
@@ -465,6 +1255,238 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[parallel]
+    fn git_uncommitted_mode() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let mut finder = new_finder(Mode::GitUncommitted, helper.precious_root())?;
+        assert_eq!(finder.files(vec![])?, None);
+
+        let mut expect = helper.modify_files()?;
+        helper.stage_some(&[&expect[0]])?;
+        helper.write_file("src/staged.rs", "fn staged() {}\n")?;
+        helper.stage_some(&[Path::new("src/staged.rs")])?;
+        helper.write_file("src/untracked.rs", "fn untracked() {}\n")?;
+        expect.push(PathBuf::from("src/staged.rs"));
+        expect.push(PathBuf::from("src/untracked.rs"));
+        expect.sort();
+
+        let mut finder = new_finder(Mode::GitUncommitted, helper.precious_root())?;
+        assert_eq!(finder.files(vec![])?, Some(expect));
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test_case(true ; "canonicalized")]
+    #[test_case(false ; "not canonicalized")]
+    #[parallel]
+    fn git_modified_mode_with_symlinked_root(canonicalize: bool) -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let modified = helper.modify_files()?;
+        let td = tempfile::tempdir()?;
+        let link = td.path().join("link");
+        std::os::unix::fs::symlink(helper.precious_root(), &link)?;
+
+        let mut finder = Finder::new(
+            Mode::GitModified,
+            link.clone(),
+            link,
+            vec![],
+            vec![],
+            vec![],
+            false,
+            canonicalize,
+        )?;
+        assert_eq!(finder.files(vec![])?, Some(modified));
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn git_modified_mode_with_git_dir_elsewhere() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let td = tempfile::tempdir()?;
+        let git_dir = td.path().join("repo.git");
+        fs::rename(helper.git_root().join(".git"), &git_dir)?;
+        let modified = helper.modify_files()?;
+
+        let mut finder = new_finder(Mode::GitModified, helper.precious_root())?;
+        // Setting this directly means we don't have to change this process's
+        // env, which would affect every other test.
+        finder.git_env = HashMap::from([
+            (
+                String::from("GIT_DIR"),
+                git_dir.to_string_lossy().into_owned(),
+            ),
+            (
+                String::from("GIT_WORK_TREE"),
+                helper.git_root().to_string_lossy().into_owned(),
+            ),
+        ]);
+        assert_eq!(finder.files(vec![])?, Some(modified));
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn git_staged_mode_with_stash_in_worktree_during_merge() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let td = tempfile::tempdir()?;
+        let worktree = td.path().join("worktree");
+        let git = |args: &[&str], in_dir: &Path| -> Result<String> {
+            Ok(Exec::builder("git")
+                .args(args)
+                .ignore_all_stderr()
+                .in_dir(in_dir)
+                .build()
+                .run()?
+                .stdout
+                .unwrap_or_default())
+        };
+        git(
+            &["worktree", "add", "--detach", &worktree.to_string_lossy()],
+            &helper.git_root(),
+        )?;
+
+        // In a linked worktree, `.git` is a file, and the MERGE_MODE file that
+        // marks a merge in progress lives under the main repo's git dir.
+        let mm = worktree.join(git(&["rev-parse", "--git-path", "MERGE_MODE"], &worktree)?.trim());
+        fs::write(&mm, "")?;
+
+        let staged = "src/main.rs";
+        fs::write(worktree.join(staged), "fn main() { println!(\"hi\") }\n")?;
+        git(&["add", staged], &worktree)?;
+        fs::write(worktree.join("src/module.rs"), "fn foo() {}\n")?;
+
+        let mut finder = new_finder(Mode::GitStagedWithStash, worktree)?;
+        assert_eq!(finder.files(vec![])?, Some(vec![PathBuf::from(staged)]));
+        assert!(!finder.stashed);
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn git_modes_with_submodule() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        helper.add_submodule("sub")?;
+        helper.write_file("sub/src/module.rs", "fn bar() {}\n")?;
+        helper.write_file("src/main.rs", "fn bar() {}\n")?;
+
+        let mut finder = new_finder(Mode::GitModified, helper.precious_root())?;
+        assert_eq!(
+            finder.files(vec![])?,
+            Some(vec![
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("sub/src/module.rs"),
+            ]),
+            "modified files in the submodule are included",
+        );
+        let changed = finder.changed_lines()?.unwrap();
+        assert_eq!(
+            changed.ranges(Path::new("sub/src/module.rs")),
+            Some([1..=1].as_slice()),
+            "changed lines in the submodule are included",
+        );
+
+        // The superproject can only stage a new commit for the submodule.
+        let mut sub = helper.precious_root();
+        sub.push("sub");
+        Exec::builder("git")
+            .args(["commit", "-a", "-m", "change"])
+            .in_dir(&sub)
+            .build()
+            .run()?;
+        helper.stage_all()?;
+
+        let mut finder = new_finder(Mode::GitStaged, helper.precious_root())?;
+        assert_eq!(
+            finder.files(vec![])?,
+            Some(vec![
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("sub/src/module.rs"),
+            ]),
+            "files changed by the staged submodule commit are included",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn git_modified_mode_changed_lines() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        helper.write_file("src/module.rs", "fn foo() {}\nfn bar() {}\n")?;
+        helper.commit_all()?;
+        helper.write_file("src/module.rs", "fn foo() {}\nfn baz() {}\nfn quux() {}\n")?;
+
+        let mut finder = new_finder(Mode::GitModified, helper.precious_root())?;
+        let changed = finder.changed_lines()?.unwrap();
+        assert_eq!(
+            changed.ranges(Path::new("src/module.rs")),
+            Some([2..=3].as_slice()),
+        );
+        assert!(!changed.contains(Path::new("src/module.rs"), 1));
+
+        let mut finder = new_finder(Mode::All, helper.precious_root())?;
+        assert_eq!(finder.changed_lines()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn git_modified_mode_with_unusual_names() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let mut names = vec![
+            PathBuf::from("src/with space.rs"),
+            PathBuf::from("src/\u{e9}.rs"),
+            PathBuf::from("src/quote\".rs"),
+        ];
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            names.push(PathBuf::from(OsStr::from_bytes(b"src/not-utf8-\xff.rs")));
+        }
+        for name in &names {
+            fs::write(helper.precious_root().join(name), "fn foo() {}\n")?;
+        }
+        helper.stage_all()?;
+        helper.commit_all()?;
+        for name in &names {
+            fs::write(helper.precious_root().join(name), "fn bar() {}\n")?;
+        }
+
+        let mut finder = new_finder(Mode::GitModified, helper.precious_root())?;
+        let files = finder.files(vec![])?.unwrap();
+        for name in &names {
+            assert!(
+                files.contains(name),
+                "{} is a modified file",
+                name.display()
+            );
+        }
+
+        let changed = finder.changed_lines()?.unwrap();
+        for name in &names {
+            assert_eq!(
+                changed.ranges(name),
+                Some([1..=1].as_slice()),
+                "changed lines for {}",
+                name.display(),
+            );
+        }
+
+        let paths = names.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+        finder.stage_files(&paths)?;
+        let mut finder = new_finder(Mode::GitStaged, helper.precious_root())?;
+        let files = finder.files(vec![])?.unwrap();
+        for name in &names {
+            assert!(files.contains(name), "{} is a staged file", name.display());
+        }
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn git_modified_mode_with_changes_in_subdir() -> Result<()> {
@@ -593,6 +1615,73 @@ mod tests {
         Ok(())
     }
 
+    #[test_case(Mode::GitModified ; "modified")]
+    #[test_case(Mode::GitStaged ; "staged")]
+    #[parallel]
+    fn git_mode_with_renamed_files(mode: Mode) -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let content = "fn foo() {}\nfn bar() {}\nfn baz() {}\nfn quux() {}\n";
+        helper.write_file("src/module.rs", content)?;
+        helper.commit_all()?;
+
+        // One file is renamed and changed, and the other is only renamed.
+        fs::remove_file(helper.precious_root().join("src/module.rs"))?;
+        helper.write_file("src/renamed.rs", &format!("{content}fn more() {{}}\n"))?;
+        fs::rename(
+            helper.precious_root().join("tests/data/foo.txt"),
+            helper.precious_root().join("tests/data/baz.txt"),
+        )?;
+        helper.stage_all()?;
+
+        let mut finder = new_finder(mode, helper.precious_root())?;
+        assert_eq!(
+            finder.files(vec![])?,
+            Some(vec![
+                PathBuf::from("src/renamed.rs"),
+                PathBuf::from("tests/data/baz.txt"),
+            ]),
+        );
+
+        let changed = finder.changed_lines()?.unwrap();
+        assert_eq!(
+            changed.ranges(Path::new("src/renamed.rs")),
+            Some([5..=5].as_slice()),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn paths_from_name_status() {
+        let output = b"M\0src/main.rs\0R087\0old.rs\0new.rs\0A\0added.txt\0C100\0a.txt\0b.txt\0";
+        assert_eq!(
+            super::paths_from_name_status(output),
+            ["src/main.rs", "new.rs", "added.txt", "b.txt"]
+                .into_iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn unstaged_files() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        helper.modify_files()?;
+        helper.stage_all()?;
+
+        let mut finder = new_finder(Mode::GitStaged, helper.precious_root())?;
+        assert_eq!(finder.unstaged_files()?, Vec::<PathBuf>::new());
+
+        helper.write_file("src/module.rs", "fn baz() {}\n")?;
+        assert_eq!(
+            finder.unstaged_files()?,
+            vec![PathBuf::from("src/module.rs")]
+        );
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn git_staged_mode_with_changes_in_subdir() -> Result<()> {
@@ -738,6 +1827,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[parallel]
+    fn unmerged_files() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let mut finder = new_finder(Mode::All, helper.precious_root())?;
+        assert_eq!(finder.unmerged_files()?, Vec::<PathBuf>::new());
+
+        let file = Path::new("merge-conflict-here");
+        helper.write_file(file, "line 1\n")?;
+        helper.stage_all()?;
+        helper.commit_all()?;
+        helper.switch_to_branch("new-branch", false)?;
+        helper.write_file(file, "line 1.5\n")?;
+        helper.commit_all()?;
+        helper.switch_to_branch("master", true)?;
+        helper.write_file(file, "line 1.6\n")?;
+        helper.commit_all()?;
+        helper.switch_to_branch("new-branch", true)?;
+        helper.merge_master(true)?;
+
+        let mut finder = new_finder(Mode::All, helper.precious_root())?;
+        assert_eq!(finder.unmerged_files()?, vec![file.to_path_buf()]);
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn git_staged_mode_with_deleted_file() -> Result<()> {
@@ -776,6 +1890,107 @@ mod tests {
         Ok(())
     }
 
+    #[test_case("master", "" ; "plain ref")]
+    #[test_case("master...", "" ; "merge base")]
+    #[test_case("master..", "tests/data/foo.txt" ; "direct")]
+    #[parallel]
+    fn git_modified_since_with_range(from: &str, also_expect: &str) -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        helper.switch_to_branch("some-branch", false)?;
+        helper.write_file("src/main.rs", "fn main() { branch }\n")?;
+        helper.commit_all()?;
+
+        // This change on master is only seen when comparing with master
+        // itself, rather than with the merge base.
+        helper.switch_to_branch("master", true)?;
+        helper.write_file("tests/data/foo.txt", "changed on master")?;
+        helper.commit_all()?;
+        helper.switch_to_branch("some-branch", true)?;
+
+        let mut expect = vec![PathBuf::from("src/main.rs")];
+        if !also_expect.is_empty() {
+            expect.push(PathBuf::from(also_expect));
+        }
+        let mut finder = new_finder(Mode::GitDiffFrom(from.to_string()), helper.precious_root())?;
+        assert_eq!(finder.files(vec![])?, Some(expect));
+        Ok(())
+    }
+
+    #[test_case("main", ("main", DiffBase::MergeBase), "main..." ; "plain ref")]
+    #[test_case("main...", ("main", DiffBase::MergeBase), "main..." ; "triple dot")]
+    #[test_case("main..", ("main", DiffBase::Ref), "main.." ; "double dot")]
+    #[test_case("HEAD~2..", ("HEAD~2", DiffBase::Ref), "HEAD~2.." ; "relative ref")]
+    #[parallel]
+    fn parse_git_diff_from(from: &str, expect: (&str, DiffBase), range: &str) {
+        assert_eq!(super::parse_git_diff_from(from), expect);
+        assert_eq!(git_diff_range(from), range);
+    }
+
+    #[test_case(Mode::GitModified, Some(vec![]) ; "modified")]
+    #[test_case(
+        Mode::GitDiffFrom("main".to_string()),
+        Some(vec![
+            "--from".to_string(),
+            "heads(::@ & ::(main))".to_string(),
+            "--to".to_string(),
+            "@".to_string(),
+        ]) ;
+        "diff-from"
+    )]
+    #[test_case(
+        Mode::GitDiffFrom("main..".to_string()),
+        Some(vec![
+            "--from".to_string(),
+            "main".to_string(),
+            "--to".to_string(),
+            "@".to_string(),
+        ]) ;
+        "diff-from direct"
+    )]
+    #[test_case(Mode::GitUncommitted, Some(vec![]) ; "uncommitted")]
+    #[test_case(Mode::GitStaged, None ; "staged")]
+    #[test_case(Mode::All, None ; "all")]
+    #[parallel]
+    fn jj_diff_revs(mode: Mode, expect: Option<Vec<String>>) -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let finder = new_finder(mode.clone(), helper.precious_root())?;
+        assert_eq!(finder.jj_diff_revs(), None, "no .jj directory");
+
+        fs::create_dir(helper.git_root().join(".jj"))?;
+        let finder = new_finder(mode, helper.precious_root())?;
+        assert_eq!(finder.jj_diff_revs(), expect);
+        Ok(())
+    }
+
+    // This needs the `jj` binary, which most systems don't have, so it only
+    // runs when asked for with `cargo test -- --ignored`.
+    #[test]
+    #[ignore = "requires jj to be installed"]
+    #[parallel]
+    fn jj_modes() -> Result<()> {
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        Exec::builder("jj")
+            .args(["git", "init", "--colocate"])
+            .ignore_all_stderr()
+            .in_dir(helper.git_root())
+            .build()
+            .run()?;
+
+        let mut finder = new_finder(Mode::GitModified, helper.precious_root())?;
+        assert_eq!(finder.files(vec![])?, None);
+
+        let modified = helper.modify_files()?;
+        let mut finder = new_finder(Mode::GitModified, helper.precious_root())?;
+        assert_eq!(finder.files(vec![])?, Some(modified.clone()));
+
+        let mut finder = new_finder(
+            Mode::GitDiffFrom("master".to_string()),
+            helper.precious_root(),
+        )?;
+        assert_eq!(finder.files(vec![])?, Some(modified));
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn cli_mode() -> Result<()> {
@@ -791,6 +2006,46 @@ mod tests {
         Ok(())
     }
 
+    // The symlinked file points outside the project, so canonicalizing its
+    // full path would never give us something under the project root.
+    #[cfg(not(target_os = "windows"))]
+    #[test_case(true ; "canonicalized")]
+    #[test_case(false ; "not canonicalized")]
+    #[parallel]
+    fn cli_mode_with_symlinked_root(canonicalize: bool) -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let helper = testhelper::TestHelper::new()?.with_git_repo()?;
+        let td = tempfile::tempdir()?;
+        let link = td.path().join("link");
+        symlink(helper.precious_root(), &link)?;
+        let outside = td.path().join("outside.md");
+        fs::write(&outside, "outside")?;
+        symlink(&outside, helper.precious_root().join("outside.link"))?;
+
+        let mut finder = Finder::new(
+            Mode::FromCli,
+            link.clone(),
+            link.join("src"),
+            vec![],
+            vec![],
+            vec![],
+            false,
+            canonicalize,
+        )?;
+        assert_eq!(
+            finder.files(vec![
+                PathBuf::from("../outside.link"),
+                PathBuf::from("main.rs")
+            ])?,
+            Some(vec![
+                PathBuf::from("outside.link"),
+                PathBuf::from("src/main.rs"),
+            ]),
+        );
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn cli_mode_given_dir_in_subdir() -> Result<()> {
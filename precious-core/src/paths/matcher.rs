@@ -1,11 +1,19 @@
 use anyhow::Result;
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
-use std::path::Path;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder, Glob},
+    Match,
+};
+use log::trace;
+use std::{collections::HashMap, path::Path};
 
 #[derive(Debug)]
 #[allow(clippy::module_name_repetitions)]
 pub struct MatcherBuilder {
     builder: GitignoreBuilder,
+    // Maps the globs we add for a directory glob back to the glob they came
+    // from, so we can tell the user about the glob they actually wrote.
+    originals: HashMap<String, String>,
+    name: Option<String>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -13,12 +21,30 @@ impl MatcherBuilder {
     pub fn new<P: AsRef<Path>>(root: P) -> Self {
         Self {
             builder: GitignoreBuilder::new(root),
+            originals: HashMap::new(),
+            name: None,
         }
     }
 
+    // A named matcher logs the glob that decided each path it checks, which
+    // is what the `--trace-matching` flag shows.
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    // In gitignore syntax, a glob ending in a slash like `docs/` only matches
+    // the directory itself. We always match files, so on its own that glob
+    // would never match anything. Instead, we treat it as matching everything
+    // under that directory, as if it were `**/docs/**`.
     pub fn with(mut self, globs: &[impl AsRef<str>]) -> Result<Self> {
         for g in globs {
-            self.builder.add_line(None, g.as_ref())?;
+            let g = g.as_ref();
+            self.builder.add_line(None, g)?;
+            if let Some(contents) = dir_contents_glob(g) {
+                self.builder.add_line(None, &contents)?;
+                self.originals.insert(contents, g.to_string());
+            }
         }
         Ok(self)
     }
@@ -26,18 +52,77 @@ impl MatcherBuilder {
     pub fn build(self) -> Result<Matcher> {
         Ok(Matcher {
             gitignore: self.builder.build()?,
+            originals: self.originals,
+            name: self.name,
         })
     }
 }
 
+// Returns a glob for everything under the directory matched by a glob ending
+// in a slash. A directory glob without any other slash matches a directory of
+// that name at any depth, so the new glob needs a leading `**/` to do the same.
+fn dir_contents_glob(glob: &str) -> Option<String> {
+    if !glob.ends_with('/') {
+        return None;
+    }
+    let (negate, dir) = match glob.strip_prefix('!') {
+        Some(dir) => ("!", dir),
+        None => ("", glob),
+    };
+    let prefix = if dir.trim_end_matches('/').contains('/') {
+        ""
+    } else {
+        "**/"
+    };
+    Some(format!("{negate}{prefix}{dir}**"))
+}
+
 #[derive(Debug)]
 pub struct Matcher {
     gitignore: Gitignore,
+    originals: HashMap<String, String>,
+    name: Option<String>,
+}
+
+// The glob that decided whether a path matches. As in gitignore, the last
+// glob that matches a path wins, so a path matched by a negated glob like
+// `!vendor/` does not match, even if an earlier glob matched it.
+#[derive(Debug, Eq, PartialEq)]
+pub enum GlobMatch<'a> {
+    Matched(&'a str),
+    Negated(&'a str),
+    Unmatched,
 }
 
 impl Matcher {
     pub fn path_matches(&self, path: &Path, is_dir: bool) -> bool {
-        self.gitignore.matched(path, is_dir).is_ignore()
+        matches!(self.glob_match(path, is_dir), GlobMatch::Matched(_))
+    }
+
+    pub fn glob_match(&self, path: &Path, is_dir: bool) -> GlobMatch<'_> {
+        let m = match self.gitignore.matched(path, is_dir) {
+            Match::Ignore(g) => GlobMatch::Matched(self.original(g)),
+            Match::Whitelist(g) => GlobMatch::Negated(self.original(g)),
+            Match::None => GlobMatch::Unmatched,
+        };
+        if let Some(name) = &self.name {
+            trace!("{}", trace_message(name, path, &m));
+        }
+        m
+    }
+
+    fn original<'a>(&'a self, glob: &'a Glob) -> &'a str {
+        let o = glob.original();
+        self.originals.get(o).map_or(o, String::as_str)
+    }
+}
+
+fn trace_message(name: &str, path: &Path, m: &GlobMatch<'_>) -> String {
+    let path = path.display();
+    match m {
+        GlobMatch::Matched(glob) => format!("{name}: {path} matched by `{glob}`"),
+        GlobMatch::Negated(glob) => format!("{name}: {path} rejected by `{glob}`"),
+        GlobMatch::Unmatched => format!("{name}: {path} not matched by any glob"),
     }
 }
 
@@ -45,6 +130,7 @@ impl Matcher {
 mod tests {
     use super::*;
     use serial_test::parallel;
+    use test_case::test_case;
 
     #[test]
     #[parallel]
@@ -88,6 +174,21 @@ mod tests {
                 yes: &["/foo/file.go", "/foo/bar/quux/file.go"],
                 no: &["/bar/file.go", "/foo/bar/baz.txt"],
             },
+            TestSet {
+                globs: &["docs/"],
+                yes: &["docs/index.md", "docs/api/index.md", "src/docs/index.md"],
+                no: &["docs.md", "src/index.md", "mydocs/index.md"],
+            },
+            TestSet {
+                globs: &["/docs/"],
+                yes: &["/docs/index.md", "/docs/api/index.md"],
+                no: &["/src/docs/index.md", "/docs.md"],
+            },
+            TestSet {
+                globs: &["**/*.md", "!vendor/"],
+                yes: &["/README.md", "/docs/index.md"],
+                no: &["/vendor/README.md", "/vendor/pkg/README.md"],
+            },
         ];
 
         for t in tests {
@@ -106,4 +207,59 @@ mod tests {
 
         Ok(())
     }
+
+    #[test_case("*.rs", None ; "not a dir")]
+    #[test_case("docs/", Some("**/docs/**") ; "any depth")]
+    #[test_case("/docs/", Some("/docs/**") ; "anchored")]
+    #[test_case("src/docs/", Some("src/docs/**") ; "nested")]
+    #[test_case("!vendor/", Some("!**/vendor/**") ; "negated")]
+    #[parallel]
+    fn dir_contents_glob(glob: &str, expect: Option<&str>) {
+        assert_eq!(super::dir_contents_glob(glob).as_deref(), expect);
+    }
+
+    #[test_case("README.md", GlobMatch::Matched("**/*.md") ; "matched")]
+    #[test_case("docs/index.md", GlobMatch::Matched("docs/") ; "matched by dir glob")]
+    #[test_case("vendor/README.md", GlobMatch::Negated("!vendor/") ; "negated")]
+    #[test_case("src/main.rs", GlobMatch::Unmatched ; "unmatched")]
+    #[parallel]
+    fn glob_match(path: &str, expect: GlobMatch<'_>) -> Result<()> {
+        let m = MatcherBuilder::new("/")
+            .with(&["**/*.md", "docs/", "!vendor/"])?
+            .build()?;
+        assert_eq!(m.glob_match(Path::new(path), false), expect);
+        Ok(())
+    }
+
+    #[test_case(
+        GlobMatch::Matched("**/*.md"),
+        "rustfmt include: docs/index.md matched by `**/*.md`" ;
+        "matched"
+    )]
+    #[test_case(
+        GlobMatch::Negated("!docs/"),
+        "rustfmt include: docs/index.md rejected by `!docs/`" ;
+        "negated"
+    )]
+    #[test_case(
+        GlobMatch::Unmatched,
+        "rustfmt include: docs/index.md not matched by any glob" ;
+        "unmatched"
+    )]
+    #[parallel]
+    fn trace_message(m: GlobMatch<'_>, expect: &str) {
+        assert_eq!(
+            super::trace_message("rustfmt include", Path::new("docs/index.md"), &m),
+            expect,
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn dir_glob_matches_dir() -> Result<()> {
+        let m = MatcherBuilder::new("/").with(&["docs/"])?.build()?;
+        assert!(m.path_matches(Path::new("/docs"), true));
+        assert!(!m.path_matches(Path::new("/docs"), false));
+        Ok(())
+    }
 }
@@ -1,13 +1,46 @@
-use std::fmt;
+use std::{fmt, time::Duration};
+
+// The units for `--since`, from largest to smallest.
+const SINCE_UNITS: &[(char, u64)] = &[
+    ('w', 7 * 24 * 60 * 60),
+    ('d', 24 * 60 * 60),
+    ('h', 60 * 60),
+    ('m', 60),
+    ('s', 1),
+];
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum Mode {
     FromCli,
     All,
     GitModified,
+    // The union of the staged, unstaged, and untracked files.
+    GitUncommitted,
     GitStaged,
     GitStagedWithStash,
     GitDiffFrom(String),
+    // The files whose mtime is within this long of the start of the run.
+    Since(Duration),
+    // The files that each command failed on in the last run.
+    Failed,
+}
+
+impl Mode {
+    // This is the value of the `PRECIOUS_MODE` env var that commands see.
+    pub fn env_value(&self) -> &'static str {
+        match self {
+            Mode::FromCli => "cli",
+            Mode::All => "all",
+            Mode::GitModified => "git-modified",
+            Mode::GitUncommitted => "git-all-uncommitted",
+            Mode::GitStaged => "staged",
+            Mode::GitStagedWithStash => "staged-with-stash",
+            Mode::GitDiffFrom(_) => "git-diff-from",
+            Mode::Since(_) => "since",
+            Mode::Failed => "failed",
+        }
+    }
 }
 
 impl fmt::Display for Mode {
@@ -16,12 +49,91 @@ impl fmt::Display for Mode {
             Mode::FromCli => write!(f, "paths passed on the command line (recursively)"),
             Mode::All => write!(f, "all files in the project"),
             Mode::GitModified => write!(f, "modified files according to git"),
+            Mode::GitUncommitted => {
+                write!(f, "staged, unstaged, and untracked files according to git")
+            }
             Mode::GitStaged => write!(f, "files staged for a git commit"),
             Mode::GitStagedWithStash => write!(
                 f,
                 "files staged for a git commit, stashing unstaged content"
             ),
             Mode::GitDiffFrom(from) => write!(f, "files modified as compared to {from:}",),
+            Mode::Since(since) => write!(f, "files modified in the last {}", since_string(*since)),
+            Mode::Failed => write!(f, "files that failed in the last run"),
         }
     }
 }
+
+// Parses a duration like "30m", "2h", or "1d12h" for `--since`. Each number
+// needs a unit, which is one of "w", "d", "h", "m", or "s".
+pub fn parse_since(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("expected a duration like 30m, 2h, or 1d12h, but got {value:?}");
+    let mut secs = 0u64;
+    let mut num = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let (_, unit_secs) = SINCE_UNITS
+            .iter()
+            .find(|(u, _)| *u == c.to_ascii_lowercase())
+            .ok_or_else(invalid)?;
+        let n: u64 = num.parse().map_err(|_| invalid())?;
+        secs = n
+            .checked_mul(*unit_secs)
+            .and_then(|s| secs.checked_add(s))
+            .ok_or_else(invalid)?;
+        num.clear();
+    }
+    if !num.is_empty() || secs == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+// This turns a duration back into the format that `parse_since` takes.
+fn since_string(since: Duration) -> String {
+    let mut secs = since.as_secs();
+    let mut string = String::new();
+    for (unit, unit_secs) in SINCE_UNITS {
+        if secs >= *unit_secs {
+            string.push_str(&format!("{}{unit}", secs / unit_secs));
+            secs %= unit_secs;
+        }
+    }
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case("45s", 45 ; "seconds")]
+    #[test_case("30m", 30 * 60 ; "minutes")]
+    #[test_case("2H", 2 * 60 * 60 ; "uppercase unit")]
+    #[test_case("1d12h", 36 * 60 * 60 ; "more than one unit")]
+    #[test_case("1w", 7 * 24 * 60 * 60 ; "weeks")]
+    #[parallel]
+    fn parse_since_ok(value: &str, secs: u64) {
+        let since = parse_since(value).unwrap();
+        assert_eq!(since, Duration::from_secs(secs));
+        assert_eq!(since_string(since), value.to_ascii_lowercase());
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("30" ; "no unit")]
+    #[test_case("h" ; "no number")]
+    #[test_case("2y" ; "unknown unit")]
+    #[test_case("0m" ; "zero")]
+    #[parallel]
+    fn parse_since_err(value: &str) {
+        assert!(
+            parse_since(value).is_err(),
+            "{value:?} is not a valid duration"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+// Runs a command through a plugin, which lets a third party change how
+// commands are invoked or how their output is reported without changing
+// precious. A command uses one by setting `runner = "plugin:<name>"`, and
+// the plugin is an executable named `precious-plugin-<name>` somewhere in
+// the PATH.
+//
+// Instead of running the command itself, we run the plugin with no
+// arguments and write a JSON request describing the command to its stdin.
+// The plugin prints a single JSON response on stdout with the exit code,
+// stdout, and stderr to report for the command, and from there the response
+// is treated just like the output of any other command. Anything the plugin
+// prints to its own stderr is only logged.
+use anyhow::Result;
+use precious_helpers::exec::{self, Exec};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+use thiserror::Error;
+
+pub(crate) const EXE_PREFIX: &str = "precious-plugin-";
+
+// This is incremented whenever the request or response changes in a way that
+// an existing plugin could not handle.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub(crate) enum PluginError {
+    #[error("The {plugin:} plugin did not print a valid response: {error:}")]
+    InvalidResponse { plugin: String, error: String },
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Request<'a> {
+    pub(crate) protocol: u32,
+    // The command's name in the config file.
+    pub(crate) command: &'a str,
+    // Either "lint" or "tidy".
+    pub(crate) action: &'a str,
+    // The command to run, including its flags and paths.
+    pub(crate) cmd: Vec<&'a str>,
+    pub(crate) dir: &'a Path,
+    pub(crate) env: &'a HashMap<String, String>,
+    // This is only set for a command with `stdin-from = "file"`.
+    pub(crate) stdin: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    exit_code: i32,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+}
+
+pub(crate) fn exe(name: &str) -> String {
+    format!("{EXE_PREFIX}{name}")
+}
+
+// The `exe` is either the plugin's executable name, which is looked up in
+// the PATH, or a path to it. The plugin itself has to exit with 0. The exit
+// code and stderr in its response are checked against the command's
+// `ok-exit-codes` and `ignore-stderr`, the same way they would be for the
+// command's own output.
+pub(crate) fn run(
+    exe: &str,
+    request: &Request<'_>,
+    ok_exit_codes: &[i32],
+    ignore_stderr: Option<&[Regex]>,
+    limits: exec::Limits,
+) -> Result<exec::Output> {
+    let output = Exec::builder(exe)
+        .envs(request.env)
+        .ignore_all_stderr()
+        .in_dir(request.dir)
+        .limits(limits)
+        .stdin(serde_json::to_vec(request)?)
+        .build()
+        .run()?;
+    let response: Response = serde_json::from_str(output.stdout.as_deref().unwrap_or_default())
+        .map_err(|e| PluginError::InvalidResponse {
+            plugin: exe.to_string(),
+            error: e.to_string(),
+        })?;
+
+    let cmd = request.cmd.join(" ");
+    if !ok_exit_codes.contains(&response.exit_code) {
+        return Err(exec::Error::UnexpectedExitCode {
+            cmd,
+            code: response.exit_code,
+            stdout: response.stdout,
+            stderr: response.stderr,
+        }
+        .into());
+    }
+    if !response.stderr.is_empty()
+        && !ignore_stderr.is_some_and(|ignore| ignore.iter().any(|i| i.is_match(&response.stderr)))
+    {
+        return Err(exec::Error::UnexpectedStderr {
+            cmd,
+            code: response.exit_code,
+            stderr: response.stderr,
+        }
+        .into());
+    }
+
+    Ok(exec::Output::new(
+        response.exit_code,
+        (!response.stdout.is_empty()).then_some(response.stdout),
+        (!response.stderr.is_empty()).then_some(response.stderr),
+        output.resources,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use precious_testhelper::TestHelper;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use std::fs;
+
+    #[cfg(target_family = "unix")]
+    fn write_plugin(dir: &Path, name: &str, script: &str) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join(exe(name));
+        fs::write(&path, format!("#!/bin/sh\n{script}"))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    fn run_plugin(name: &str, script: &str, ok_exit_codes: &[i32]) -> Result<exec::Output> {
+        let helper = TestHelper::new()?;
+        let root = helper.precious_root();
+        write_plugin(&root, name, script)?;
+        let env = HashMap::new();
+        let request = Request {
+            protocol: PROTOCOL_VERSION,
+            command: "some-linter",
+            action: "lint",
+            cmd: vec!["some-linter", "--check", "foo.rs"],
+            dir: &root,
+            env: &env,
+            stdin: None,
+        };
+        run(
+            &root.join(exe(name)).to_string_lossy(),
+            &request,
+            ok_exit_codes,
+            None,
+            exec::Limits::default(),
+        )
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_reports_response() -> Result<()> {
+        // The plugin checks the request it was given, so this covers both
+        // sides of the protocol.
+        let output = run_plugin(
+            "echo",
+            r#"req=$(cat)
+case "$req" in
+  *'"cmd":["some-linter","--check","foo.rs"]'*) ;;
+  *) echo "bad request: $req" >&2; exit 2 ;;
+esac
+echo "plugin log" >&2
+printf '%s\n' '{"exit_code": 1, "stdout": "foo.rs: bad\n"}'
+"#,
+            &[0, 1],
+        )?;
+        assert_eq!(output.exit_code, 1);
+        assert_eq!(output.stdout.as_deref(), Some("foo.rs: bad\n"));
+        assert_eq!(output.stderr, None);
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_unexpected_exit_code() -> Result<()> {
+        let err = run_plugin("fail", r#"echo '{"exit_code": 3}'"#, &[0])
+            .expect_err("exit code 3 is not ok");
+        assert!(
+            err.to_string()
+                .starts_with("Got unexpected exit code 3 from `some-linter --check foo.rs`"),
+            "{err}",
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_invalid_response() -> Result<()> {
+        let err = run_plugin("bad", "echo 'not json'", &[0]).expect_err("response is not JSON");
+        assert!(
+            err.to_string()
+                .contains("precious-plugin-bad plugin did not print a valid response: "),
+            "{err}",
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,512 @@
+// With `--record`, every command invocation is saved to a JSON file, along
+// with everything needed to run it again. The `precious replay` subcommand
+// reads that file and prints or re-runs the invocations in it. This helps
+// with failures that only happen in CI, since the exact argv, env, and
+// working dir from the CI run can be examined and re-run elsewhere.
+use crate::builtin::Builtin;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum RecordError {
+    #[error("There is no invocation #{id} in the recording at {}", file.display())]
+    NoSuchInvocation { id: usize, file: PathBuf },
+
+    #[error("Invocation #{id} did not run any executable, so it cannot be re-run")]
+    NothingToRun { id: usize },
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Recording {
+    pub(crate) invocations: Vec<RecordedInvocation>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct RecordedInvocation {
+    // This is assigned when the recording is written, starting at 1.
+    #[serde(default)]
+    pub(crate) id: usize,
+    pub(crate) command: String,
+    // The paths the invocation operated on, relative to the project root.
+    pub(crate) paths: Vec<PathBuf>,
+    // The working dir, relative to the project root, so a recording from CI
+    // can be replayed in a checkout at a different path. This is absolute if
+    // the command ran outside the project root.
+    pub(crate) cwd: PathBuf,
+    pub(crate) argv: Vec<String>,
+    // Only the env vars that precious sets for the command are recorded, not
+    // everything inherited from the environment precious ran in.
+    pub(crate) env: BTreeMap<String, String>,
+    // The contents of the file passed in `PRECIOUS_PATHS_FILE`, if any.
+    pub(crate) paths_file: Option<String>,
+    // This is `None` when the command could not be run at all, or when it
+    // was killed by a signal.
+    pub(crate) exit_code: Option<i32>,
+    // The exit codes that precious treats as a pass for this invocation.
+    pub(crate) passing_exit_codes: Vec<i32>,
+    pub(crate) stdout: String,
+    pub(crate) stderr: String,
+    pub(crate) error: Option<String>,
+    pub(crate) failed: bool,
+}
+
+// This is shared by all of the commands in a run. Commands add to it from
+// whichever thread they run on.
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    invocations: Mutex<Vec<RecordedInvocation>>,
+}
+
+impl Recorder {
+    pub(crate) fn record(&self, invocation: RecordedInvocation) {
+        self.invocations.lock().unwrap().push(invocation);
+    }
+
+    // Invocations are sorted by command name and then path, just like the
+    // output of a run, and numbered in that order.
+    pub(crate) fn write(&self, file: &Path) -> Result<()> {
+        let mut invocations = self.invocations.lock().unwrap().clone();
+        invocations.sort_by(|a, b| (&a.command, &a.paths).cmp(&(&b.command, &b.paths)));
+        for (i, inv) in invocations.iter_mut().enumerate() {
+            inv.id = i + 1;
+        }
+        fs::write(
+            file,
+            serde_json::to_string_pretty(&Recording { invocations })? + "\n",
+        )?;
+        Ok(())
+    }
+}
+
+// With `--log-dir`, each invocation gets three files, named after the command
+// and a hash of the paths it was given. The `.cmd` file has the command line,
+// working dir, env, and exit code, while the `.stdout` and `.stderr` files
+// have the full output.
+pub(crate) fn write_log(dir: &Path, inv: &RecordedInvocation) -> Result<()> {
+    let name = inv
+        .command
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    let paths = inv
+        .paths
+        .iter()
+        .map(|p| p.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\0");
+    let hash = format!("{:x}", md5::compute(paths.as_bytes()));
+    let base = dir.join(format!("{name}-{}", &hash[..12]));
+
+    let mut cmd = format!(
+        "command: {}\npaths: {}\ncwd: {}\ncmd: {}\n",
+        inv.command,
+        inv.paths
+            .iter()
+            .map(|p| p.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" "),
+        inv.cwd.display(),
+        inv.argv.join(" "),
+    );
+    match (inv.exit_code, &inv.error) {
+        (Some(code), _) => cmd.push_str(&format!("exit code: {code}\n")),
+        (None, Some(error)) => cmd.push_str(&format!("error: {error}\n")),
+        (None, None) => (),
+    }
+    cmd.push_str("env:\n");
+    for (k, v) in &inv.env {
+        cmd.push_str(&format!("  {k}={v}\n"));
+    }
+
+    fs::write(base.with_extension("cmd"), cmd)?;
+    fs::write(base.with_extension("stdout"), &inv.stdout)?;
+    fs::write(base.with_extension("stderr"), &inv.stderr)?;
+    Ok(())
+}
+
+// Prints the invocations with the given ids, or every failed invocation if
+// no ids are given. With `exec`, each one is run again using the recorded
+// argv, env, and working dir, with the working dir resolved against the given
+// project root. The return value is the exit code for precious, which is 1 if
+// any invocation that was run again did not pass.
+pub(crate) fn replay(
+    file: &Path,
+    ids: &[usize],
+    exec: bool,
+    project_root: &Path,
+    mut output: impl Write,
+) -> Result<u8> {
+    let recording: Recording = serde_json::from_str(&fs::read_to_string(file)?)?;
+
+    let selected = if ids.is_empty() {
+        recording
+            .invocations
+            .iter()
+            .filter(|i| i.failed)
+            .collect::<Vec<_>>()
+    } else {
+        ids.iter()
+            .map(|id| {
+                recording
+                    .invocations
+                    .iter()
+                    .find(|i| i.id == *id)
+                    .ok_or_else(|| RecordError::NoSuchInvocation {
+                        id: *id,
+                        file: file.to_path_buf(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    if selected.is_empty() {
+        writeln!(
+            output,
+            "There are no failed invocations in the recording at {}",
+            file.display(),
+        )?;
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for inv in selected {
+        print_invocation(&mut output, inv)?;
+        if exec && !rerun(&mut output, inv, project_root)? {
+            status = 1;
+        }
+        writeln!(output)?;
+    }
+    Ok(status)
+}
+
+fn print_invocation(output: &mut impl Write, inv: &RecordedInvocation) -> Result<()> {
+    writeln!(
+        output,
+        "#{} {} ({})",
+        inv.id,
+        inv.command,
+        if inv.failed { "failed" } else { "passed" },
+    )?;
+    writeln!(output, "  cwd: {}", inv.cwd.display())?;
+    writeln!(output, "  cmd: {}", inv.argv.join(" "))?;
+    for (k, v) in &inv.env {
+        writeln!(output, "  env: {k}={v}")?;
+    }
+    match (inv.exit_code, &inv.error) {
+        (Some(code), _) => writeln!(output, "  exit code: {code}")?,
+        (None, Some(error)) => writeln!(output, "  error: {error}")?,
+        (None, None) => (),
+    }
+    print_output(output, &inv.stdout, &inv.stderr)
+}
+
+fn print_output(output: &mut impl Write, stdout: &str, stderr: &str) -> Result<()> {
+    for (name, text) in [("stdout", stdout), ("stderr", stderr)] {
+        if text.is_empty() {
+            continue;
+        }
+        writeln!(output, "  {name}:")?;
+        for line in text.lines() {
+            writeln!(output, "    {line}")?;
+        }
+    }
+    Ok(())
+}
+
+// Returns true if the command passed when it was run again. The scratch dir
+// and paths file from the original run are long gone, so we make new ones.
+fn rerun(output: &mut impl Write, inv: &RecordedInvocation, project_root: &Path) -> Result<bool> {
+    let Some((exe, args)) = inv.argv.split_first() else {
+        return Err(RecordError::NothingToRun { id: inv.id }.into());
+    };
+    let cwd = project_root.join(&inv.cwd);
+    if let Ok(Some(builtin)) = Builtin::from_cmd(&inv.argv) {
+        return rerun_builtin(output, inv, builtin, args, &cwd);
+    }
+
+    let scratch_dir = tempfile::Builder::new()
+        .prefix("precious-scratch-")
+        .tempdir()?;
+    let mut env = inv.env.clone();
+    let mut args = args.to_vec();
+    env.insert(
+        String::from("PRECIOUS_TMPDIR"),
+        scratch_dir.path().to_string_lossy().into_owned(),
+    );
+    let paths_file = match &inv.paths_file {
+        Some(contents) => {
+            let file = tempfile::Builder::new()
+                .prefix("precious-paths-")
+                .tempfile()?;
+            fs::write(file.path(), contents)?;
+            let new_path = file.path().to_string_lossy().into_owned();
+            if let Some(old_path) =
+                env.insert(String::from("PRECIOUS_PATHS_FILE"), new_path.clone())
+            {
+                for a in &mut args {
+                    *a = a.replace(&old_path, &new_path);
+                }
+            }
+            Some(file)
+        }
+        None => None,
+    };
+
+    writeln!(output, "  Running #{} again", inv.id)?;
+    let result = process::Command::new(exe)
+        .args(&args)
+        .envs(&env)
+        .current_dir(&cwd)
+        .stdin(process::Stdio::null())
+        .output();
+    drop(paths_file);
+
+    let result = match result {
+        Ok(r) => r,
+        Err(e) => {
+            writeln!(output, "  error: {e}")?;
+            return Ok(false);
+        }
+    };
+    let code = result.status.code();
+    match code {
+        Some(code) => writeln!(output, "  exit code: {code}")?,
+        None => writeln!(output, "  killed by a signal")?,
+    }
+    print_output(
+        output,
+        &String::from_utf8_lossy(&result.stdout),
+        &String::from_utf8_lossy(&result.stderr),
+    )?;
+
+    Ok(code.is_some_and(|c| inv.passing_exit_codes.contains(&c)))
+}
+
+// A builtin command runs inside precious, so it's run the same way again.
+fn rerun_builtin(
+    output: &mut impl Write,
+    inv: &RecordedInvocation,
+    builtin: Builtin,
+    args: &[String],
+    cwd: &Path,
+) -> Result<bool> {
+    writeln!(output, "  Running #{} again", inv.id)?;
+    let result = match builtin.run(args, cwd, &[0, 1]) {
+        Ok(r) => r,
+        Err(e) => {
+            writeln!(output, "  error: {e}")?;
+            return Ok(false);
+        }
+    };
+    writeln!(output, "  exit code: {}", result.exit_code)?;
+    print_output(
+        output,
+        result.stdout.as_deref().unwrap_or_default(),
+        result.stderr.as_deref().unwrap_or_default(),
+    )?;
+
+    Ok(inv.passing_exit_codes.contains(&result.exit_code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    fn invocation(command: &str, path: &str, failed: bool) -> RecordedInvocation {
+        RecordedInvocation {
+            id: 0,
+            command: command.to_string(),
+            paths: vec![PathBuf::from(path)],
+            cwd: std::env::temp_dir(),
+            argv: vec![
+                String::from("sh"),
+                String::from("-c"),
+                format!("echo {path}; exit {}", u8::from(failed)),
+            ],
+            env: BTreeMap::from([(String::from("PRECIOUS_COMMAND_NAME"), command.to_string())]),
+            paths_file: None,
+            exit_code: Some(i32::from(failed)),
+            passing_exit_codes: vec![0],
+            stdout: format!("{path}\n"),
+            stderr: String::new(),
+            error: None,
+            failed,
+        }
+    }
+
+    fn recording(dir: &Path) -> Result<PathBuf> {
+        let recorder = Recorder::default();
+        recorder.record(invocation("rustfmt", "src/main.rs", false));
+        recorder.record(invocation("clippy", "src/main.rs", true));
+        recorder.record(invocation("clippy", "src/lib.rs", false));
+
+        let file = dir.join("run.json");
+        recorder.write(&file)?;
+        Ok(file)
+    }
+
+    #[test]
+    #[parallel]
+    fn write_numbers_invocations_in_order() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let file = recording(td.path())?;
+
+        let recording: Recording = serde_json::from_str(&fs::read_to_string(file)?)?;
+        assert_eq!(
+            recording
+                .invocations
+                .iter()
+                .map(|i| (i.id, i.command.as_str(), i.paths[0].to_string_lossy()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "clippy", "src/lib.rs".into()),
+                (2, "clippy", "src/main.rs".into()),
+                (3, "rustfmt", "src/main.rs".into()),
+            ],
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn write_log() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let mut inv = invocation("cargo clippy", "src/main.rs", true);
+        inv.stderr = String::from("warning: unused\n");
+        super::write_log(td.path(), &inv)?;
+
+        let mut files = fs::read_dir(td.path())?
+            .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        files.sort();
+        let hash = format!("{:x}", md5::compute("src/main.rs"));
+        let base = format!("cargo-clippy-{}", &hash[..12]);
+        assert_eq!(
+            files,
+            vec![
+                format!("{base}.cmd"),
+                format!("{base}.stderr"),
+                format!("{base}.stdout"),
+            ],
+        );
+        assert_eq!(
+            fs::read_to_string(td.path().join(format!("{base}.cmd")))?,
+            format!(
+                "command: cargo clippy\npaths: src/main.rs\ncwd: {}\ncmd: sh -c echo src/main.rs; exit 1\nexit code: 1\nenv:\n  PRECIOUS_COMMAND_NAME=cargo clippy\n",
+                std::env::temp_dir().display(),
+            ),
+        );
+        assert_eq!(
+            fs::read_to_string(td.path().join(format!("{base}.stdout")))?,
+            "src/main.rs\n",
+        );
+        assert_eq!(
+            fs::read_to_string(td.path().join(format!("{base}.stderr")))?,
+            "warning: unused\n",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn replay_prints_failed_invocations() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let file = recording(td.path())?;
+
+        let mut output = vec![];
+        let status = replay(&file, &[], false, td.path(), &mut output)?;
+        assert_eq!(status, 0);
+        assert_eq!(
+            String::from_utf8(output)?,
+            format!(
+                "#2 clippy (failed)\n  cwd: {}\n  cmd: sh -c echo src/main.rs; exit 1\n  env: PRECIOUS_COMMAND_NAME=clippy\n  exit code: 1\n  stdout:\n    src/main.rs\n\n",
+                std::env::temp_dir().display(),
+            ),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn replay_exec() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let file = recording(td.path())?;
+
+        let mut output = vec![];
+        assert_eq!(replay(&file, &[2], true, td.path(), &mut output)?, 1);
+        let output = String::from_utf8(output)?;
+        assert!(
+            output.contains("  Running #2 again\n  exit code: 1\n  stdout:\n    src/main.rs\n"),
+            "{output}",
+        );
+
+        let mut output = vec![];
+        assert_eq!(replay(&file, &[1, 3], true, td.path(), &mut output)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn replay_exec_resolves_cwd_against_root() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let root = fs::canonicalize(td.path())?;
+        fs::create_dir(root.join("sub"))?;
+
+        let mut inv = invocation("pwd", "sub/main.rs", false);
+        inv.cwd = PathBuf::from("sub");
+        inv.argv = vec![String::from("pwd")];
+        let recorder = Recorder::default();
+        recorder.record(inv);
+        let file = root.join("run.json");
+        recorder.write(&file)?;
+
+        let mut output = vec![];
+        assert_eq!(replay(&file, &[1], true, &root, &mut output)?, 0);
+        let output = String::from_utf8(output)?;
+        assert!(output.contains("  cwd: sub\n"), "{output}");
+        assert!(
+            output.contains(&format!("    {}\n", root.join("sub").display())),
+            "{output}",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn replay_unknown_invocation() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let file = recording(td.path())?;
+
+        let err = replay(&file, &[42], false, td.path(), vec![]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RecordError>(),
+            Some(&RecordError::NoSuchInvocation { id: 42, file }),
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1248 @@
+// All of the output from `lint` and `tidy` goes through a `Reporter`. The
+// runner tells the reporter what happened, and the reporter decides what (if
+// anything) to print. Reporters are shared between the threads that run
+// commands, so every method takes `&self`.
+use crate::{
+    chars::Chars,
+    command::LintOrTidyCommandType,
+    paths::mode::Mode,
+    precious::{Exit, ExitCode},
+    runner::{Action, Invocation, Outcome},
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use itertools::Itertools;
+use precious_helpers::exec;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub(crate) trait Reporter: Debug + Send + Sync {
+    // Called once before anything else.
+    fn start(&self) {}
+
+    fn header(&self, action: Action, mode: &Mode);
+
+    fn installing(&self, command: &str, executable: &str);
+
+    // Returns a progress bar that the runner advances after each invocation
+    // of the command.
+    fn command_started(&self, _command: &str, _invocations: usize) -> Option<ProgressBar> {
+        None
+    }
+
+    fn invocation(&self, event: &Event<'_>);
+
+    // Called after tidied files are added to the git index with `--restage`.
+    fn restaged(&self, _paths: &[&Path]) {}
+
+    // Called with the unified diff for each file that `precious diff` found
+    // would be changed by tidying.
+    fn diff(&self, _path: &Path, _diff: &str) {}
+
+    // Called for each command that matched the `--command` names, if any,
+    // but didn't run.
+    fn skipped(&self, _command: &str, _reason: &SkipReason) {}
+
+    fn command_finished(&self, _command: &str, _files_ok: usize, _failures: usize) {}
+
+    // Called after each command finishes, or with `--parallel-commands`, once
+    // all of the commands have finished.
+    fn flush(&self) {}
+
+    fn finish(&self, exit: &Exit);
+
+    // Called instead of `finish` when precious itself fails.
+    fn failed(&self, _error: &anyhow::Error) {}
+
+    // Returns every invocation this reporter was told about. Only the
+    // `QuietReporter` keeps these.
+    fn into_invocations(self: Box<Self>) -> Vec<Invocation> {
+        vec![]
+    }
+}
+
+// A single command invocation that has finished.
+#[derive(Debug)]
+pub(crate) struct Event<'a> {
+    pub(crate) action: Action,
+    pub(crate) command: &'a str,
+    pub(crate) paths: &'a [&'a Path],
+    // A short description of the paths, from
+    // `LintOrTidyCommand::paths_summary`.
+    pub(crate) paths_summary: String,
+    pub(crate) outcome: Outcome,
+    // This is `None` on Windows and for commands run by a server.
+    pub(crate) resources: Option<exec::Resources>,
+    // The files a tidier created, for commands that set `report-new-files`.
+    pub(crate) new_files: Vec<PathBuf>,
+}
+
+impl Event<'_> {
+    pub(crate) fn is_ok(&self) -> bool {
+        !matches!(self.outcome, Outcome::Failed { .. } | Outcome::Error(_))
+    }
+
+    pub(crate) fn description(&self) -> String {
+        let (command, paths) = (self.command, &self.paths_summary);
+        match (&self.outcome, self.action) {
+            (Outcome::Passed, Action::Check) => format!("Already tidy by {command}: {paths}"),
+            (Outcome::Passed, _) => format!("Passed {command}: {paths}"),
+            (Outcome::Failed { .. }, Action::Check) => {
+                format!("Would be changed by {command}: {paths}")
+            }
+            (Outcome::Failed { .. }, _) => format!("Failed {command}: {paths}"),
+            (Outcome::Tidied, _) => format!("Tidied by {command}:    {paths}"),
+            (Outcome::Unchanged, _) => format!("Unchanged by {command}: {paths}"),
+            (Outcome::MaybeTidied, _) => format!("Maybe changed by {command}: {paths}"),
+            (Outcome::Error(_), Action::Lint) => format!("error {command}: {paths}"),
+            (Outcome::Error(_), Action::Tidy | Action::Check) => {
+                format!("Error from {command}: {paths}")
+            }
+        }
+    }
+
+    // The extra details about a failure, as key/value pairs.
+    pub(crate) fn diagnostics(&self) -> Vec<(&str, &str)> {
+        match &self.outcome {
+            Outcome::Failed { stdout, stderr } => {
+                let mut diagnostics = vec![("message", failure_message(self.action))];
+                if let Some(out) = stdout {
+                    diagnostics.push(("stdout", out));
+                }
+                if let Some(err) = stderr {
+                    diagnostics.push(("stderr", err));
+                }
+                diagnostics
+            }
+            Outcome::Error(e) => vec![("message", e)],
+            _ => vec![],
+        }
+    }
+}
+
+// Why a command in the config file didn't run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SkipReason {
+    // The label for this run, which is `default` if none was given.
+    Label { label: String },
+    // The command's own type, like a tidier when linting.
+    Type { typ: LintOrTidyCommandType },
+    // The command's `run-when` key says it doesn't run in this environment.
+    RunWhen { in_ci: bool },
+    // The command's `enabled` key is false.
+    Disabled,
+    // The command was named in `--skip-command`.
+    SkipCommand,
+    NoMatchingFiles,
+}
+
+impl SkipReason {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::Label { .. } => "label",
+            SkipReason::Type { .. } => "type",
+            SkipReason::RunWhen { .. } => "run-when",
+            SkipReason::Disabled => "disabled",
+            SkipReason::SkipCommand => "skip-command",
+            SkipReason::NoMatchingFiles => "no-matching-files",
+        }
+    }
+
+    pub(crate) fn description(&self) -> String {
+        match self {
+            SkipReason::Label { label } => format!("it does not have the {label} label"),
+            SkipReason::Type { typ } => format!("its type is {typ}"),
+            SkipReason::RunWhen { in_ci: true } => String::from("it does not run in CI"),
+            SkipReason::RunWhen { in_ci: false } => String::from("it does not run locally"),
+            SkipReason::Disabled => String::from("it is not enabled"),
+            SkipReason::SkipCommand => String::from("it was given to --skip-command"),
+            SkipReason::NoMatchingFiles => {
+                String::from("none of the files matched its include and exclude globs")
+            }
+        }
+    }
+}
+
+// A command's summary line starts with the symbol for the most notable outcome
+// of its invocations, so a tidier that changed any file is shown as tidied.
+fn outcome_rank(outcome: &Outcome) -> u8 {
+    match outcome {
+        Outcome::Passed => 0,
+        Outcome::Unchanged => 1,
+        Outcome::MaybeTidied => 2,
+        Outcome::Tidied => 3,
+        Outcome::Failed { .. } => 4,
+        Outcome::Error(_) => 5,
+    }
+}
+
+pub(crate) fn action_description(action: Action) -> &'static str {
+    match action {
+        Action::Lint => "Linting",
+        Action::Tidy => "Tidying",
+        Action::Check => "Checking",
+    }
+}
+
+pub(crate) fn failure_message(action: Action) -> &'static str {
+    match action {
+        Action::Check => "tidying would change files",
+        _ => "linting failed",
+    }
+}
+
+// The default output, with one line for each command invocation.
+#[derive(Debug)]
+pub(crate) struct HumanReporter {
+    chars: Chars,
+    quiet: bool,
+    summary_only: bool,
+    explain_skips: bool,
+    progress: Option<MultiProgress>,
+    // The total resources used by each command's invocations so far, which
+    // are included in its summary line.
+    resources: Mutex<HashMap<String, exec::Resources>>,
+    // The rank and symbol of the most notable outcome of each command's
+    // invocations so far, which its summary line starts with.
+    symbols: Mutex<HashMap<String, (u8, String)>>,
+}
+
+impl HumanReporter {
+    pub(crate) fn new(chars: Chars, quiet: bool, summary_only: bool, progress: bool) -> Self {
+        Self {
+            chars,
+            quiet,
+            summary_only,
+            explain_skips: false,
+            progress: progress
+                .then(|| MultiProgress::with_draw_target(ProgressDrawTarget::stdout())),
+            resources: Mutex::new(HashMap::new()),
+            symbols: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // With this, there's a line for each command that didn't run saying why.
+    pub(crate) fn explain_skips(mut self, explain_skips: bool) -> Self {
+        self.explain_skips = explain_skips;
+        self
+    }
+
+    fn skip_line(&self, command: &str, reason: &SkipReason) -> String {
+        format!(
+            "{} Skipped {command} because {}",
+            self.chars.empty,
+            reason.description(),
+        )
+    }
+
+    // When progress bars are shown, they have to be hidden while we print,
+    // or else the bars and the output get mixed together.
+    fn print_line(&self, line: &str) {
+        match &self.progress {
+            Some(progress) => progress.suspend(|| println!("{line}")),
+            None => println!("{line}"),
+        }
+    }
+
+    fn symbol(&self, outcome: &Outcome) -> &str {
+        match outcome {
+            Outcome::Passed => &self.chars.lint_free,
+            Outcome::Failed { .. } => &self.chars.lint_dirty,
+            Outcome::Tidied => &self.chars.tidied,
+            Outcome::Unchanged => &self.chars.unchanged,
+            Outcome::MaybeTidied => &self.chars.unknown,
+            Outcome::Error(_) => &self.chars.execution_error,
+        }
+    }
+
+    fn summary_symbol(&self, command: &str, failures: usize) -> String {
+        match self.symbols.lock().unwrap().remove(command) {
+            Some((_, symbol)) => symbol,
+            None if failures > 0 => self.chars.lint_dirty.to_string(),
+            None => self.chars.lint_free.to_string(),
+        }
+    }
+
+    fn summary_line(
+        &self,
+        command: &str,
+        symbol: &str,
+        files_ok: usize,
+        failures: usize,
+        resources: Option<exec::Resources>,
+    ) -> String {
+        let mut line = if failures > 0 {
+            format!(
+                "{} {}: {} failure{}",
+                symbol,
+                command,
+                failures,
+                if failures > 1 { "s" } else { "" },
+            )
+        } else {
+            format!(
+                "{} {}: {} file{} ok",
+                symbol,
+                command,
+                files_ok,
+                if files_ok > 1 { "s" } else { "" },
+            )
+        };
+        if let Some(r) = resources {
+            line.push_str(&format!(" ({r})"));
+        }
+        line
+    }
+}
+
+impl Reporter for HumanReporter {
+    fn header(&self, action: Action, mode: &Mode) {
+        self.print_line(&format!(
+            "{} {} {}",
+            self.chars.ring,
+            action_description(action),
+            mode
+        ));
+    }
+
+    fn installing(&self, command: &str, executable: &str) {
+        self.print_line(&format!(
+            "{} Installing {executable} for {command}",
+            self.chars.bullet
+        ));
+    }
+
+    fn command_started(&self, command: &str, invocations: usize) -> Option<ProgressBar> {
+        self.progress.as_ref().map(|progress| {
+            progress.add(
+                ProgressBar::new(invocations as u64)
+                    .with_style(
+                        ProgressStyle::with_template("{prefix} [{bar:30}] {pos}/{len} ({elapsed})")
+                            .unwrap()
+                            .progress_chars("=> "),
+                    )
+                    .with_prefix(command.to_string()),
+            )
+        })
+    }
+
+    fn invocation(&self, event: &Event<'_>) {
+        if let Some(r) = event.resources {
+            self.resources
+                .lock()
+                .unwrap()
+                .entry(event.command.to_string())
+                .and_modify(|total| *total = total.combine(r))
+                .or_insert(r);
+        }
+        let rank = outcome_rank(&event.outcome);
+        let symbol = self.symbol(&event.outcome);
+        self.symbols
+            .lock()
+            .unwrap()
+            .entry(event.command.to_string())
+            .and_modify(|s| {
+                if rank > s.0 {
+                    *s = (rank, symbol.to_string());
+                }
+            })
+            .or_insert_with(|| (rank, symbol.to_string()));
+
+        if event.is_ok() && (self.quiet || self.summary_only) {
+            return;
+        }
+
+        self.print_line(&format!(
+            "{} {}",
+            self.symbol(&event.outcome),
+            event.description()
+        ));
+        if !event.new_files.is_empty() {
+            self.print_line(&format!(
+                "{} Created by {}: {}",
+                self.chars.bullet,
+                event.command,
+                event.new_files.iter().map(|p| p.display()).join(" "),
+            ));
+        }
+        // The message for an execution error is shown at the end of the run,
+        // along with all the other errors.
+        if let Outcome::Failed { stdout, stderr } = &event.outcome {
+            if let Some(out) = stdout {
+                self.print_line(out);
+            }
+            if let Some(err) = stderr {
+                self.print_line(err);
+            }
+        }
+    }
+
+    fn restaged(&self, paths: &[&Path]) {
+        if self.quiet {
+            return;
+        }
+        self.print_line(&format!(
+            "{} Restaged {}",
+            self.chars.bullet,
+            paths.iter().map(|p| p.display()).join(" "),
+        ));
+    }
+
+    fn diff(&self, _path: &Path, diff: &str) {
+        self.print_line(diff.trim_end());
+    }
+
+    fn skipped(&self, command: &str, reason: &SkipReason) {
+        if self.explain_skips && !self.quiet {
+            self.print_line(&self.skip_line(command, reason));
+        }
+    }
+
+    fn command_finished(&self, command: &str, files_ok: usize, failures: usize) {
+        let resources = self.resources.lock().unwrap().remove(command);
+        let symbol = self.summary_symbol(command, failures);
+        if self.summary_only && (files_ok > 0 || failures > 0) {
+            self.print_line(&self.summary_line(command, &symbol, files_ok, failures, resources));
+        }
+    }
+
+    fn finish(&self, exit: &Exit) {
+        if let Some(err) = &exit.error {
+            self.print_line(err.trim_end_matches('\n'));
+        }
+        if let Some(msg) = &exit.message {
+            self.print_line(&format!("{} {}", self.chars.empty, msg));
+        }
+    }
+}
+
+// Used when running in GitHub Actions. This is the same as the human output,
+// plus an annotation for each lint failure, which GitHub shows in the UI.
+#[derive(Debug)]
+pub(crate) struct GitHubReporter {
+    human: HumanReporter,
+}
+
+impl GitHubReporter {
+    pub(crate) fn new(human: HumanReporter) -> Self {
+        Self { human }
+    }
+}
+
+impl Reporter for GitHubReporter {
+    fn header(&self, action: Action, mode: &Mode) {
+        self.human.header(action, mode);
+    }
+
+    fn installing(&self, command: &str, executable: &str) {
+        self.human.installing(command, executable);
+    }
+
+    fn command_started(&self, command: &str, invocations: usize) -> Option<ProgressBar> {
+        self.human.command_started(command, invocations)
+    }
+
+    fn invocation(&self, event: &Event<'_>) {
+        self.human.invocation(event);
+        if !matches!(event.outcome, Outcome::Failed { .. }) {
+            return;
+        }
+        let message = match event.action {
+            Action::Check => format!("Tidying with {} would change files", event.command),
+            _ => format!("Linting with {} failed", event.command),
+        };
+        if event.paths.len() == 1 {
+            self.human.print_line(&format!(
+                "::error file={}::{message}",
+                event.paths[0].display(),
+            ));
+        } else {
+            self.human.print_line(&format!("::error::{message}"));
+        }
+    }
+
+    fn restaged(&self, paths: &[&Path]) {
+        self.human.restaged(paths);
+    }
+
+    fn diff(&self, path: &Path, diff: &str) {
+        self.human.diff(path, diff);
+    }
+
+    fn skipped(&self, command: &str, reason: &SkipReason) {
+        self.human.skipped(command, reason);
+    }
+
+    fn command_finished(&self, command: &str, files_ok: usize, failures: usize) {
+        self.human.command_finished(command, files_ok, failures);
+    }
+
+    fn finish(&self, exit: &Exit) {
+        self.human.finish(exit);
+    }
+}
+
+// Used when precious runs in more than one project root. This passes
+// everything through to another reporter, after prefixing each command name
+// with the root it ran in and making each path relative to the directory
+// that contains all of the roots. The header is only shown for the first
+// root, and the summary is only shown after the last one, for all of the
+// roots together.
+#[derive(Debug)]
+pub(crate) struct RootReporter {
+    root: PathBuf,
+    inner: Box<dyn Reporter>,
+    workspace: Arc<WorkspaceSummary>,
+    is_last: bool,
+}
+
+// This is shared by the `RootReporter` for each root in a run.
+#[derive(Debug, Default)]
+pub(crate) struct WorkspaceSummary {
+    started: AtomicBool,
+    header_shown: AtomicBool,
+    exits: Mutex<Vec<(PathBuf, Exit)>>,
+}
+
+impl WorkspaceSummary {
+    pub(crate) fn add(&self, root: PathBuf, exit: Exit) {
+        self.exits.lock().unwrap().push((root, exit));
+    }
+
+    // The status is the first one that isn't a success, and every error is
+    // included, prefixed with the root it came from. There's only a message
+    // if every root had the same one, like "No files found".
+    pub(crate) fn combined(&self) -> Exit {
+        let exits = self.exits.lock().unwrap();
+        let status = exits
+            .iter()
+            .map(|(_, e)| e.status)
+            .find(|s| *s != u8::from(ExitCode::Success))
+            .unwrap_or(ExitCode::Success.into());
+        let errors = exits
+            .iter()
+            .filter_map(|(root, e)| {
+                e.error
+                    .as_ref()
+                    .map(|err| format!("[{}] {}\n", root.display(), err.trim_end()))
+            })
+            .collect::<String>();
+        let mut messages = exits.iter().map(|(_, e)| e.message.as_deref());
+        let message = messages
+            .next()
+            .flatten()
+            .filter(|m| messages.all(|other| other == Some(*m)))
+            .map(String::from);
+        Exit {
+            status,
+            message,
+            error: if errors.is_empty() {
+                None
+            } else {
+                Some(errors)
+            },
+        }
+    }
+}
+
+impl RootReporter {
+    pub(crate) fn new(
+        root: PathBuf,
+        inner: Box<dyn Reporter>,
+        workspace: Arc<WorkspaceSummary>,
+        is_last: bool,
+    ) -> Self {
+        Self {
+            root,
+            inner,
+            workspace,
+            is_last,
+        }
+    }
+
+    fn add_exit(&self, exit: Exit) {
+        self.workspace.add(self.root.clone(), exit);
+        if self.is_last {
+            self.inner.finish(&self.workspace.combined());
+        } else {
+            self.inner.flush();
+        }
+    }
+
+    fn command(&self, command: &str) -> String {
+        format!("[{}] {command}", self.root.display())
+    }
+
+    fn paths(&self, paths: &[&Path]) -> Vec<PathBuf> {
+        paths.iter().map(|p| self.root.join(p)).collect()
+    }
+}
+
+impl Reporter for RootReporter {
+    fn start(&self) {
+        if !self.workspace.started.swap(true, Ordering::Relaxed) {
+            self.inner.start();
+        }
+    }
+
+    fn header(&self, action: Action, mode: &Mode) {
+        if !self.workspace.header_shown.swap(true, Ordering::Relaxed) {
+            self.inner.header(action, mode);
+        }
+    }
+
+    fn installing(&self, command: &str, executable: &str) {
+        self.inner.installing(&self.command(command), executable);
+    }
+
+    fn command_started(&self, command: &str, invocations: usize) -> Option<ProgressBar> {
+        self.inner
+            .command_started(&self.command(command), invocations)
+    }
+
+    fn invocation(&self, event: &Event<'_>) {
+        let command = self.command(event.command);
+        let paths = self.paths(event.paths);
+        self.inner.invocation(&Event {
+            action: event.action,
+            command: &command,
+            paths: &paths.iter().map(PathBuf::as_path).collect::<Vec<_>>(),
+            paths_summary: event.paths_summary.clone(),
+            outcome: event.outcome.clone(),
+            resources: event.resources,
+            new_files: event.new_files.iter().map(|p| self.root.join(p)).collect(),
+        });
+    }
+
+    fn restaged(&self, paths: &[&Path]) {
+        let paths = self.paths(paths);
+        self.inner
+            .restaged(&paths.iter().map(PathBuf::as_path).collect::<Vec<_>>());
+    }
+
+    fn diff(&self, path: &Path, diff: &str) {
+        self.inner.diff(&self.root.join(path), diff);
+    }
+
+    fn skipped(&self, command: &str, reason: &SkipReason) {
+        self.inner.skipped(&self.command(command), reason);
+    }
+
+    fn command_finished(&self, command: &str, files_ok: usize, failures: usize) {
+        self.inner
+            .command_finished(&self.command(command), files_ok, failures);
+    }
+
+    fn finish(&self, exit: &Exit) {
+        self.add_exit(exit.clone());
+    }
+
+    fn failed(&self, error: &anyhow::Error) {
+        self.add_exit(Exit {
+            status: ExitCode::for_error(error).into(),
+            message: None,
+            error: Some(error.to_string()),
+        });
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+
+    fn into_invocations(self: Box<Self>) -> Vec<Invocation> {
+        self.inner.into_invocations()
+    }
+}
+
+// Holds on to what happened with each command until the runner calls
+// `flush`, then passes it all on sorted by command name and then by path.
+// Invocations of a command, and with `--parallel-commands` the commands
+// themselves, run in parallel. Without this, the order of the output would
+// change from one run to the next.
+#[derive(Debug)]
+pub(crate) struct BufferedReporter {
+    inner: Box<dyn Reporter>,
+    buffered: Mutex<Vec<Buffered>>,
+}
+
+#[derive(Debug)]
+enum Buffered {
+    Invocation {
+        action: Action,
+        command: String,
+        paths: Vec<PathBuf>,
+        paths_summary: String,
+        outcome: Outcome,
+        resources: Option<exec::Resources>,
+        new_files: Vec<PathBuf>,
+    },
+    Skipped {
+        command: String,
+        reason: SkipReason,
+    },
+    CommandFinished {
+        command: String,
+        files_ok: usize,
+        failures: usize,
+    },
+}
+
+impl Buffered {
+    // A command's summary comes after all of its invocations.
+    fn sort_key(&self) -> (&str, bool, &[PathBuf]) {
+        match self {
+            Buffered::Invocation { command, paths, .. } => (command, false, paths),
+            Buffered::Skipped { command, .. } | Buffered::CommandFinished { command, .. } => {
+                (command, true, &[])
+            }
+        }
+    }
+}
+
+impl BufferedReporter {
+    pub(crate) fn new(inner: Box<dyn Reporter>) -> Self {
+        Self {
+            inner,
+            buffered: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl Reporter for BufferedReporter {
+    fn start(&self) {
+        self.inner.start();
+    }
+
+    fn header(&self, action: Action, mode: &Mode) {
+        self.inner.header(action, mode);
+    }
+
+    fn installing(&self, command: &str, executable: &str) {
+        self.inner.installing(command, executable);
+    }
+
+    fn command_started(&self, command: &str, invocations: usize) -> Option<ProgressBar> {
+        self.inner.command_started(command, invocations)
+    }
+
+    fn invocation(&self, event: &Event<'_>) {
+        self.buffered.lock().unwrap().push(Buffered::Invocation {
+            action: event.action,
+            command: event.command.to_string(),
+            paths: event.paths.iter().map(|p| p.to_path_buf()).collect(),
+            paths_summary: event.paths_summary.clone(),
+            outcome: event.outcome.clone(),
+            resources: event.resources,
+            new_files: event.new_files.clone(),
+        });
+    }
+
+    fn restaged(&self, paths: &[&Path]) {
+        self.flush();
+        self.inner.restaged(paths);
+    }
+
+    fn diff(&self, path: &Path, diff: &str) {
+        self.flush();
+        self.inner.diff(path, diff);
+    }
+
+    fn skipped(&self, command: &str, reason: &SkipReason) {
+        self.buffered.lock().unwrap().push(Buffered::Skipped {
+            command: command.to_string(),
+            reason: reason.clone(),
+        });
+    }
+
+    fn command_finished(&self, command: &str, files_ok: usize, failures: usize) {
+        self.buffered
+            .lock()
+            .unwrap()
+            .push(Buffered::CommandFinished {
+                command: command.to_string(),
+                files_ok,
+                failures,
+            });
+    }
+
+    fn flush(&self) {
+        let mut buffered = std::mem::take(&mut *self.buffered.lock().unwrap());
+        buffered.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        for b in buffered {
+            match b {
+                Buffered::Invocation {
+                    action,
+                    command,
+                    paths,
+                    paths_summary,
+                    outcome,
+                    resources,
+                    new_files,
+                } => self.inner.invocation(&Event {
+                    action,
+                    command: &command,
+                    paths: &paths.iter().map(PathBuf::as_path).collect::<Vec<_>>(),
+                    paths_summary,
+                    outcome,
+                    resources,
+                    new_files,
+                }),
+                Buffered::Skipped { command, reason } => self.inner.skipped(&command, &reason),
+                Buffered::CommandFinished {
+                    command,
+                    files_ok,
+                    failures,
+                } => self.inner.command_finished(&command, files_ok, failures),
+            }
+        }
+        self.inner.flush();
+    }
+
+    fn finish(&self, exit: &Exit) {
+        self.flush();
+        self.inner.finish(exit);
+    }
+
+    fn failed(&self, error: &anyhow::Error) {
+        self.flush();
+        self.inner.failed(error);
+    }
+
+    fn into_invocations(self: Box<Self>) -> Vec<Invocation> {
+        self.flush();
+        self.inner.into_invocations()
+    }
+}
+
+// Prints one JSON object per line for each event, so that other programs can
+// process the results as they arrive.
+#[derive(Debug, Default)]
+pub(crate) struct JsonReporter;
+
+impl JsonReporter {
+    fn print(value: &serde_json::Value) {
+        println!("{value}");
+    }
+
+    fn skip_value(command: &str, reason: &SkipReason) -> serde_json::Value {
+        let mut value = json!({
+            "event": "skip",
+            "command": command,
+            "reason": reason.as_str(),
+        });
+        match reason {
+            SkipReason::Label { label } => value["label"] = json!(label),
+            SkipReason::Type { typ } => value["type"] = json!(typ.to_string()),
+            SkipReason::RunWhen { in_ci } => value["in_ci"] = json!(in_ci),
+            SkipReason::Disabled | SkipReason::SkipCommand | SkipReason::NoMatchingFiles => (),
+        }
+        value
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn header(&self, _action: Action, _mode: &Mode) {}
+
+    fn installing(&self, command: &str, executable: &str) {
+        Self::print(&json!({
+            "event": "install",
+            "command": command,
+            "executable": executable,
+        }));
+    }
+
+    fn invocation(&self, event: &Event<'_>) {
+        let mut value = json!({
+            "event": "invocation",
+            "action": match event.action {
+                Action::Lint => "lint",
+                Action::Tidy => "tidy",
+                Action::Check => "check",
+            },
+            "command": event.command,
+            "paths": event
+                .paths
+                .iter()
+                .map(|p| p.to_string_lossy())
+                .collect::<Vec<_>>(),
+        });
+        let outcome = match &event.outcome {
+            Outcome::Passed => "passed",
+            Outcome::Failed { stdout, stderr } => {
+                value["stdout"] = json!(stdout);
+                value["stderr"] = json!(stderr);
+                "failed"
+            }
+            Outcome::Tidied => "tidied",
+            Outcome::Unchanged => "unchanged",
+            Outcome::MaybeTidied => "maybe-tidied",
+            Outcome::Error(e) => {
+                value["error"] = json!(e);
+                "error"
+            }
+        };
+        value["outcome"] = json!(outcome);
+        if let Some(r) = event.resources {
+            value["max_rss_bytes"] = json!(r.max_rss);
+            value["cpu_time_secs"] = json!(r.cpu_time.as_secs_f64());
+        }
+        if !event.new_files.is_empty() {
+            value["new_files"] = json!(event
+                .new_files
+                .iter()
+                .map(|p| p.to_string_lossy())
+                .collect::<Vec<_>>());
+        }
+        Self::print(&value);
+    }
+
+    fn skipped(&self, command: &str, reason: &SkipReason) {
+        Self::print(&Self::skip_value(command, reason));
+    }
+
+    fn restaged(&self, paths: &[&Path]) {
+        Self::print(&json!({
+            "event": "restage",
+            "paths": paths.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>(),
+        }));
+    }
+
+    fn diff(&self, path: &Path, diff: &str) {
+        Self::print(&json!({
+            "event": "diff",
+            "path": path.to_string_lossy(),
+            "diff": diff,
+        }));
+    }
+
+    fn finish(&self, exit: &Exit) {
+        Self::print(&json!({
+            "event": "finish",
+            "status": exit.status,
+            "message": exit.message,
+        }));
+    }
+
+    fn failed(&self, error: &anyhow::Error) {
+        Self::print(&json!({
+            "event": "error",
+            "message": format!("{error:#}"),
+        }));
+    }
+}
+
+// Prints nothing. This is used when precious is embedded in another program,
+// which gets the invocations from `into_invocations` instead.
+#[derive(Debug, Default)]
+pub(crate) struct QuietReporter {
+    invocations: Mutex<Vec<Invocation>>,
+}
+
+impl Reporter for QuietReporter {
+    fn header(&self, _action: Action, _mode: &Mode) {}
+
+    fn installing(&self, _command: &str, _executable: &str) {}
+
+    fn invocation(&self, event: &Event<'_>) {
+        self.invocations.lock().unwrap().push(Invocation {
+            command: event.command.to_string(),
+            paths: event.paths.iter().map(|p| p.to_path_buf()).collect(),
+            outcome: event.outcome.clone(),
+            new_files: event.new_files.clone(),
+        });
+    }
+
+    fn finish(&self, _exit: &Exit) {}
+
+    fn into_invocations(self: Box<Self>) -> Vec<Invocation> {
+        self.invocations.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use std::{path::PathBuf, time::Duration};
+    use test_case::test_case;
+
+    #[test_case(Action::Lint, Outcome::Passed, "Passed rustfmt: src/main.rs")]
+    #[test_case(
+        Action::Lint,
+        Outcome::Failed { stdout: None, stderr: None },
+        "Failed rustfmt: src/main.rs"
+    )]
+    #[test_case(
+        Action::Lint,
+        Outcome::Error("boom".to_string()),
+        "error rustfmt: src/main.rs"
+    )]
+    #[test_case(Action::Tidy, Outcome::Tidied, "Tidied by rustfmt:    src/main.rs")]
+    #[test_case(Action::Tidy, Outcome::Unchanged, "Unchanged by rustfmt: src/main.rs")]
+    #[test_case(
+        Action::Tidy,
+        Outcome::MaybeTidied,
+        "Maybe changed by rustfmt: src/main.rs"
+    )]
+    #[test_case(
+        Action::Tidy,
+        Outcome::Error("boom".to_string()),
+        "Error from rustfmt: src/main.rs"
+    )]
+    #[parallel]
+    fn event_description(action: Action, outcome: Outcome, expect: &str) {
+        let paths = [Path::new("src/main.rs")];
+        let event = Event {
+            action,
+            command: "rustfmt",
+            paths: &paths,
+            paths_summary: "src/main.rs".to_string(),
+            outcome,
+            resources: None,
+            new_files: vec![],
+        };
+        assert_eq!(event.description(), expect);
+    }
+
+    #[test]
+    #[parallel]
+    fn root_reporter_prefixes_commands_and_paths() {
+        let reporter = RootReporter::new(
+            PathBuf::from("subproj-a"),
+            Box::new(QuietReporter::default()),
+            Arc::default(),
+            true,
+        );
+        let paths = [Path::new("src/main.rs")];
+        reporter.invocation(&Event {
+            action: Action::Lint,
+            command: "rustfmt",
+            paths: &paths,
+            paths_summary: "src/main.rs".to_string(),
+            outcome: Outcome::Passed,
+            resources: None,
+            new_files: vec![],
+        });
+
+        let invocations = Box::new(reporter).into_invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].command, "[subproj-a] rustfmt");
+        assert_eq!(
+            invocations[0].paths,
+            vec![PathBuf::from("subproj-a/src/main.rs")],
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn workspace_summary_combines_roots() {
+        let exit = |status: ExitCode, message: Option<&str>, error: Option<&str>| Exit {
+            status: status.into(),
+            message: message.map(String::from),
+            error: error.map(String::from),
+        };
+
+        let summary = WorkspaceSummary::default();
+        summary.add(
+            PathBuf::from("a"),
+            exit(ExitCode::Success, Some("No files found"), None),
+        );
+        summary.add(
+            PathBuf::from("b"),
+            exit(ExitCode::Success, Some("No files found"), None),
+        );
+        let combined = summary.combined();
+        assert_eq!(combined.status, u8::from(ExitCode::Success));
+        assert_eq!(combined.message.as_deref(), Some("No files found"));
+        assert_eq!(combined.error, None);
+
+        summary.add(
+            PathBuf::from("c"),
+            exit(ExitCode::ConfigError, None, Some("bad config")),
+        );
+        summary.add(
+            PathBuf::from("d"),
+            exit(ExitCode::Failure, None, Some("lint failed\n")),
+        );
+        let combined = summary.combined();
+        assert_eq!(combined.status, u8::from(ExitCode::ConfigError));
+        assert_eq!(combined.message, None);
+        assert_eq!(
+            combined.error.as_deref(),
+            Some("[c] bad config\n[d] lint failed\n"),
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn buffered_reporter_sorts_by_command_then_path() {
+        let reporter = BufferedReporter::new(Box::new(QuietReporter::default()));
+        for (command, path) in [
+            ("rustfmt", "src/main.rs"),
+            ("clippy", "src/lib.rs"),
+            ("rustfmt", "src/lib.rs"),
+            ("clippy", "build.rs"),
+        ] {
+            let paths = [Path::new(path)];
+            reporter.invocation(&Event {
+                action: Action::Lint,
+                command,
+                paths: &paths,
+                paths_summary: path.to_string(),
+                outcome: Outcome::Passed,
+                resources: None,
+                new_files: vec![],
+            });
+        }
+
+        let invocations = Box::new(reporter).into_invocations();
+        assert_eq!(
+            invocations
+                .into_iter()
+                .map(|i| format!("{} {}", i.command, i.paths[0].display()))
+                .collect::<Vec<_>>(),
+            vec![
+                "clippy build.rs",
+                "clippy src/lib.rs",
+                "rustfmt src/lib.rs",
+                "rustfmt src/main.rs",
+            ],
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn summary_line() {
+        let reporter = HumanReporter::new(crate::chars::BORING_CHARS, false, true, false);
+        assert_eq!(
+            reporter.summary_line("rustfmt", "|", 212, 0, None),
+            "| rustfmt: 212 files ok"
+        );
+        assert_eq!(
+            reporter.summary_line("rustfmt", "|", 1, 0, None),
+            "| rustfmt: 1 file ok"
+        );
+        assert_eq!(
+            reporter.summary_line("clippy", "*", 10, 3, None),
+            "* clippy: 3 failures"
+        );
+        assert_eq!(
+            reporter.summary_line("clippy", "*", 0, 1, None),
+            "* clippy: 1 failure"
+        );
+        assert_eq!(
+            reporter.summary_line(
+                "clippy",
+                "|",
+                4,
+                0,
+                Some(exec::Resources::new(
+                    50 * 1024 * 1024,
+                    Duration::from_millis(1250),
+                )),
+            ),
+            "| clippy: 4 files ok (1.25s CPU, 50.0 MiB peak RSS)",
+        );
+    }
+
+    #[test_case(&[Outcome::Passed, Outcome::Passed], "💯" ; "lint passed")]
+    #[test_case(&[Outcome::Passed, Outcome::Failed { stdout: None, stderr: None }], "💩" ; "lint failed")]
+    #[test_case(&[Outcome::Unchanged, Outcome::Unchanged], "✨" ; "tidy unchanged")]
+    #[test_case(&[Outcome::Unchanged, Outcome::Tidied, Outcome::Unchanged], "💧" ; "tidy tidied")]
+    #[test_case(&[Outcome::MaybeTidied], "🤷🏽" ; "tidy maybe tidied")]
+    #[test_case(&[Outcome::Tidied, Outcome::Error(String::from("oops"))], "💥" ; "tidy error")]
+    #[parallel]
+    fn summary_symbol(outcomes: &[Outcome], expect: &str) {
+        let reporter = HumanReporter::new(crate::chars::FUN_CHARS, false, true, false);
+        let paths = [Path::new("src/main.rs")];
+        for outcome in outcomes {
+            reporter.invocation(&Event {
+                action: Action::Tidy,
+                command: "rustfmt",
+                paths: &paths,
+                paths_summary: String::from("src/main.rs"),
+                outcome: outcome.clone(),
+                resources: None,
+                new_files: vec![],
+            });
+        }
+        assert_eq!(reporter.summary_symbol("rustfmt", 0), expect);
+    }
+
+    #[test_case(
+        SkipReason::Label { label: "default".to_string() },
+        "_ Skipped typos because it does not have the default label",
+        json!({"event": "skip", "command": "typos", "reason": "label", "label": "default"}) ;
+        "label"
+    )]
+    #[test_case(
+        SkipReason::Type { typ: LintOrTidyCommandType::Tidy },
+        "_ Skipped typos because its type is tidy",
+        json!({"event": "skip", "command": "typos", "reason": "type", "type": "tidy"}) ;
+        "wrong type"
+    )]
+    #[test_case(
+        SkipReason::RunWhen { in_ci: true },
+        "_ Skipped typos because it does not run in CI",
+        json!({"event": "skip", "command": "typos", "reason": "run-when", "in_ci": true}) ;
+        "run when"
+    )]
+    #[test_case(
+        SkipReason::Disabled,
+        "_ Skipped typos because it is not enabled",
+        json!({"event": "skip", "command": "typos", "reason": "disabled"}) ;
+        "disabled"
+    )]
+    #[test_case(
+        SkipReason::SkipCommand,
+        "_ Skipped typos because it was given to --skip-command",
+        json!({"event": "skip", "command": "typos", "reason": "skip-command"}) ;
+        "skip command"
+    )]
+    #[test_case(
+        SkipReason::NoMatchingFiles,
+        "_ Skipped typos because none of the files matched its include and exclude globs",
+        json!({"event": "skip", "command": "typos", "reason": "no-matching-files"}) ;
+        "no matching files"
+    )]
+    #[parallel]
+    fn skipped(reason: SkipReason, expect_line: &str, expect_json: serde_json::Value) {
+        let human =
+            HumanReporter::new(crate::chars::BORING_CHARS, false, false, false).explain_skips(true);
+        assert_eq!(human.skip_line("typos", &reason), expect_line);
+        assert_eq!(JsonReporter::skip_value("typos", &reason), expect_json);
+    }
+
+    #[test]
+    #[parallel]
+    fn quiet_reporter_keeps_invocations() {
+        let reporter = Box::new(QuietReporter::default());
+        let paths = [Path::new("src/main.rs")];
+        reporter.invocation(&Event {
+            action: Action::Lint,
+            command: "clippy",
+            paths: &paths,
+            paths_summary: "src/main.rs".to_string(),
+            outcome: Outcome::Passed,
+            resources: None,
+            new_files: vec![],
+        });
+        assert_eq!(
+            reporter.into_invocations(),
+            vec![Invocation {
+                command: "clippy".to_string(),
+                paths: vec![PathBuf::from("src/main.rs")],
+                outcome: Outcome::Passed,
+                new_files: vec![],
+            }],
+        );
+    }
+}
@@ -0,0 +1,404 @@
+// The API for running precious from other Rust programs. This does the same
+// thing as the `lint` and `tidy` subcommands, except that it doesn't print
+// anything. Instead, the result of every command invocation is returned in a
+// `RunReport`.
+use crate::{
+    config,
+    jobs::Jobs,
+    paths::mode::Mode,
+    precious::{self, LintOrTidyRunner, OutputFormat, RunnerParams},
+};
+use anyhow::Result;
+use std::{env, path::PathBuf};
+
+/// Whether to lint or tidy files.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Lint,
+    Tidy,
+    /// Report which files tidying would change without changing them, like
+    /// `precious tidy --check`. Failed invocations are reported with
+    /// [`Outcome::Failed`].
+    Check,
+}
+
+/// Builds a [`Runner`]. Create one with [`Runner::builder`].
+#[derive(Debug)]
+#[must_use]
+pub struct RunnerBuilder {
+    action: Action,
+    mode: Mode,
+    paths: Vec<PathBuf>,
+    cwd: Option<PathBuf>,
+    config_file: Option<PathBuf>,
+    commands: Vec<String>,
+    skip_commands: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    label: Option<String>,
+    jobs: usize,
+    install_missing: bool,
+    only_changed_lines: bool,
+    only_dirty_dirs: bool,
+    parallel_commands: bool,
+    in_order: bool,
+    restage: bool,
+}
+
+/// Runs lint or tidy commands, just like `precious lint` or `precious tidy`.
+#[derive(Debug)]
+pub struct Runner {
+    inner: LintOrTidyRunner,
+}
+
+/// The results of a run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RunReport {
+    /// One entry for each command invocation, in the order the invocations
+    /// finished. Invocations that were skipped are not included.
+    pub invocations: Vec<Invocation>,
+    /// This is true if there were no files to operate on.
+    pub no_files: bool,
+}
+
+/// A single execution of a command on one or more paths.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Invocation {
+    /// The command's name from the config file.
+    pub command: String,
+    /// The paths that were passed to the command, relative to the project
+    /// root.
+    pub paths: Vec<PathBuf>,
+    pub outcome: Outcome,
+    /// The files that a tidier created, relative to the project root. This is
+    /// only filled in for commands that set `report-new-files`.
+    pub new_files: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Outcome {
+    /// The linter found no problems.
+    Passed,
+    /// The linter found problems.
+    Failed {
+        stdout: Option<String>,
+        stderr: Option<String>,
+    },
+    /// The tidier changed at least one file.
+    Tidied,
+    /// The tidier did not change any files.
+    Unchanged,
+    /// The tidier was run, but we can't tell whether it changed anything.
+    /// This happens with commands that are invoked once for the whole
+    /// project.
+    MaybeTidied,
+    /// The command could not be run or exited with an unexpected exit code.
+    Error(String),
+}
+
+impl Runner {
+    /// Returns a builder for linting all files in the project found by
+    /// starting from the current directory.
+    pub fn builder() -> RunnerBuilder {
+        RunnerBuilder {
+            action: Action::Lint,
+            mode: Mode::All,
+            paths: vec![],
+            cwd: None,
+            config_file: None,
+            commands: vec![],
+            skip_commands: vec![],
+            include: vec![],
+            exclude: vec![],
+            label: None,
+            jobs: 0,
+            install_missing: false,
+            only_changed_lines: false,
+            only_dirty_dirs: false,
+            parallel_commands: false,
+            in_order: false,
+            restage: false,
+        }
+    }
+
+    /// Runs all the matching commands.
+    ///
+    /// # Errors
+    ///
+    /// This returns an error if precious itself fails, for example because no
+    /// commands match the given command name or label. Command failures are
+    /// reported in the returned [`RunReport`].
+    pub fn run(self) -> Result<RunReport> {
+        self.inner.run_for_report()
+    }
+}
+
+impl RunnerBuilder {
+    pub fn action(mut self, action: Action) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Sets the mode used to find files. To operate on specific paths, use
+    /// [`Mode::FromCli`] and set the paths with [`RunnerBuilder::paths`].
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the paths to operate on when the mode is [`Mode::FromCli`].
+    /// Relative paths are relative to the working directory.
+    pub fn paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Sets the working directory, which is used to find the project root and
+    /// config file. This defaults to the current directory of the process.
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Sets the config file to use. The project root is the directory
+    /// containing this file.
+    pub fn config(mut self, config_file: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(config_file.into());
+        self
+    }
+
+    /// Only run the command with this name. This can be called more than
+    /// once to run several commands.
+    pub fn command(mut self, command: impl Into<String>) -> Self {
+        self.commands.push(command.into());
+        self
+    }
+
+    /// Don't run the command with this name, even if it would otherwise run.
+    /// This can be called more than once to skip several commands.
+    pub fn skip_command(mut self, command: impl Into<String>) -> Self {
+        self.skip_commands.push(command.into());
+        self
+    }
+
+    /// Only operate on files matching this glob. This can be called more than
+    /// once, in which case a file only has to match one of the globs.
+    pub fn include(mut self, glob: impl Into<String>) -> Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    /// Exclude files matching this glob, in addition to the `exclude` globs in
+    /// the config file. This can be called more than once.
+    pub fn exclude(mut self, glob: impl Into<String>) -> Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    /// Only run commands with this label.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Sets the number of parallel jobs. The default of 0 uses the `jobs` key
+    /// from the config file, or one job per core if that isn't set.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn install_missing(mut self, install_missing: bool) -> Self {
+        self.install_missing = install_missing;
+        self
+    }
+
+    pub fn only_changed_lines(mut self, only_changed_lines: bool) -> Self {
+        self.only_changed_lines = only_changed_lines;
+        self
+    }
+
+    /// Pass per-directory commands the changed files in each directory
+    /// instead of the directory, like the `--only-dirty-dirs` flag. This
+    /// requires one of the git modes.
+    pub fn only_dirty_dirs(mut self, only_dirty_dirs: bool) -> Self {
+        self.only_dirty_dirs = only_dirty_dirs;
+        self
+    }
+
+    /// Run commands concurrently, like the `--parallel-commands` flag.
+    pub fn parallel_commands(mut self, parallel_commands: bool) -> Self {
+        self.parallel_commands = parallel_commands;
+        self
+    }
+
+    /// Start each command's invocations in path order instead of starting
+    /// the ones with the largest files first, like the `--in-order` flag.
+    pub fn in_order(mut self, in_order: bool) -> Self {
+        self.in_order = in_order;
+        self
+    }
+
+    /// After tidying staged files, add the files that were changed to the
+    /// git index, like the `--restage` flag. This requires [`Mode::GitStaged`].
+    pub fn restage(mut self, restage: bool) -> Self {
+        self.restage = restage;
+        self
+    }
+
+    /// Finds the project root and loads the config file.
+    ///
+    /// # Errors
+    ///
+    /// This returns an error if the project root cannot be found, the config
+    /// file is invalid, or the settings are not compatible with each other.
+    pub fn build(self) -> Result<Runner> {
+        let cwd = match self.cwd {
+            Some(cwd) => cwd,
+            None => env::current_dir()?,
+        };
+        let project_root = precious::project_root(self.config_file.as_deref(), &cwd)?;
+        let config_file = self
+            .config_file
+            .unwrap_or_else(|| precious::default_config_file(&project_root));
+        let config = config::Config::new(&config_file)?;
+
+        Ok(Runner {
+            inner: LintOrTidyRunner::from_params(
+                RunnerParams {
+                    mode: self.mode,
+                    should_lint: self.action == Action::Lint,
+                    check: self.action == Action::Check,
+                    diff: false,
+                    restage: self.restage,
+                    expect_changes: None,
+                    commands: self.commands,
+                    skip_commands: self.skip_commands,
+                    include: self.include,
+                    exclude: self.exclude,
+                    label: self.label,
+                    paths: self.paths,
+                    jobs: (self.jobs > 0).then_some(Jobs::Count(self.jobs)),
+                    ascii: false,
+                    quiet: true,
+                    install_missing: self.install_missing,
+                    server: None,
+                    only_changed_lines: self.only_changed_lines,
+                    only_dirty_dirs: self.only_dirty_dirs,
+                    hermetic: false,
+                    output: OutputFormat::Human,
+                    summary_only: false,
+                    explain_skips: false,
+                    parallel_commands: self.parallel_commands,
+                    ci: None,
+                    progress: false,
+                    live_output: false,
+                    in_order: self.in_order,
+                    strict_config: false,
+                    debug_scheduler: None,
+                    recorder: None,
+                    manifest: None,
+                    metrics: None,
+                    log_dir: None,
+                    collect: true,
+                },
+                cwd,
+                project_root,
+                config_file,
+                config,
+            )?,
+        })
+    }
+
+    /// A shortcut for calling [`RunnerBuilder::build`] and then
+    /// [`Runner::run`].
+    ///
+    /// # Errors
+    ///
+    /// See those two methods.
+    pub fn run(self) -> Result<RunReport> {
+        self.build()?.run()
+    }
+}
+
+impl RunReport {
+    /// Returns true if every invocation passed or tidied successfully.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// Returns the invocations that failed or could not be run.
+    pub fn failures(&self) -> impl Iterator<Item = &Invocation> {
+        self.invocations
+            .iter()
+            .filter(|i| matches!(i.outcome, Outcome::Failed { .. } | Outcome::Error(_)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use precious_testhelper::TestHelper;
+    use pretty_assertions::assert_eq;
+    // Anything that does pushd must be run serially or else chaos ensues.
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    #[cfg(not(target_os = "windows"))]
+    fn run_report() -> Result<()> {
+        let config = r#"
+[commands.true]
+type = "lint"
+include = "**/*.rs"
+cmd = ["true"]
+ok-exit-codes = 0
+
+[commands.false]
+type = "lint"
+include = "src/main.rs"
+cmd = ["sh", "-c", "echo bad; exit 1"]
+ok-exit-codes = 0
+lint-failure-exit-codes = 1
+"#;
+        let helper = TestHelper::new()?
+            .with_git_repo()?
+            .with_config_file("precious.toml", config)?;
+
+        let report = Runner::builder()
+            .cwd(helper.precious_root())
+            .mode(Mode::FromCli)
+            .paths(vec![helper.precious_root().join("src/main.rs")])
+            .run()?;
+
+        assert!(!report.no_files);
+        assert!(!report.is_ok());
+        let mut invocations = report.invocations.clone();
+        invocations.sort_by(|a, b| a.command.cmp(&b.command));
+        assert_eq!(
+            invocations,
+            vec![
+                Invocation {
+                    command: "false".to_string(),
+                    paths: vec![PathBuf::from("src/main.rs")],
+                    outcome: Outcome::Failed {
+                        stdout: Some("bad\n".to_string()),
+                        stderr: None,
+                    },
+                    new_files: vec![],
+                },
+                Invocation {
+                    command: "true".to_string(),
+                    paths: vec![PathBuf::from("src/main.rs")],
+                    outcome: Outcome::Passed,
+                    new_files: vec![],
+                },
+            ],
+        );
+        assert_eq!(report.failures().count(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+// A sandbox is a temp dir with copies of some of the project's files, so that
+// tidiers can be run against those copies without touching the working tree.
+// Afterwards we can compare each copy to the original to see what tidying
+// would have changed.
+//
+// A sandbox can also hold every file that git tracks, exported from the index
+// or from a commit. With `--hermetic`, linters run in one of these, so that
+// untracked, ignored, or unstaged files can't affect their results.
+use anyhow::Result;
+use log::debug;
+use precious_helpers::exec::Exec;
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+// The number of unchanged lines shown around each change, which is the same
+// as the default for `diff -u` and `git diff`.
+const CONTEXT: usize = 3;
+
+#[derive(Debug)]
+pub(crate) struct Sandbox {
+    // We only hold onto this so that the temp dir is deleted on drop.
+    _dir: TempDir,
+    // This is the copy of the project root, which is a subdirectory of the
+    // temp dir when the project root isn't the root of the git repo.
+    root: PathBuf,
+}
+
+// What to export from git.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum GitExport {
+    // The staged contents of each file.
+    Index,
+    // The contents of each file in this commit.
+    Commit(String),
+}
+
+impl Sandbox {
+    // Copies the given files, which are relative to the project root, into a
+    // new temp dir. The other files in the project root and in every dir
+    // between the root and one of the given files are copied as well, since
+    // that's where tools look for their config files, like a `.rustfmt.toml`
+    // or `pyproject.toml` in a subdirectory.
+    pub(crate) fn new(project_root: &Path, files: &[PathBuf]) -> Result<Sandbox> {
+        let dir = tempfile::Builder::new()
+            .prefix("precious-sandbox-")
+            .tempdir()?;
+        debug!("Creating a sandbox in {}", dir.path().display());
+
+        let dirs = files
+            .iter()
+            .filter_map(|f| f.parent())
+            .flat_map(Path::ancestors)
+            .collect::<BTreeSet<_>>();
+        for d in dirs {
+            let to_dir = dir.path().join(d);
+            fs::create_dir_all(&to_dir)?;
+            for entry in fs::read_dir(project_root.join(d))? {
+                let path = entry?.path();
+                if path.is_file() {
+                    fs::copy(&path, to_dir.join(path.file_name().unwrap()))?;
+                }
+            }
+        }
+        for f in files {
+            let to = dir.path().join(f);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(project_root.join(f), to)?;
+        }
+
+        let root = dir.path().to_path_buf();
+        Ok(Sandbox { _dir: dir, root })
+    }
+
+    // Exports every file that git tracks into a new temp dir, using git's
+    // plumbing commands so that the working tree and index aren't touched.
+    // Submodules are not exported.
+    pub(crate) fn from_git(project_root: &Path, export: &GitExport) -> Result<Sandbox> {
+        let dir = tempfile::Builder::new()
+            .prefix("precious-hermetic-")
+            .tempdir()?;
+        debug!(
+            "Exporting {} to {}",
+            match export {
+                GitExport::Index => String::from("the git index"),
+                GitExport::Commit(rev) => format!("the {rev} commit"),
+            },
+            dir.path().display(),
+        );
+
+        let git_root = git_stdout(project_root, &["rev-parse", "--show-toplevel"])?;
+        let git_root = PathBuf::from(git_root.trim());
+        // The trailing slash tells git that the prefix is a directory.
+        let prefix = format!("--prefix={}/", dir.path().display());
+        let mut checkout = Exec::builder("git")
+            .args(["checkout-index", "--all", "--force", &prefix])
+            .in_dir(&git_root);
+
+        // To export a commit, we read it into a temp index file instead of
+        // the repo's own index.
+        let index_dir = tempfile::tempdir()?;
+        if let GitExport::Commit(rev) = export {
+            let index_file = index_dir.path().join("index");
+            Exec::builder("git")
+                .args(["read-tree", rev])
+                .env("GIT_INDEX_FILE", index_file.to_string_lossy())
+                .in_dir(&git_root)
+                .build()
+                .run()?;
+            checkout = checkout.env("GIT_INDEX_FILE", index_file.to_string_lossy());
+        }
+        checkout.build().run()?;
+
+        let in_repo = git_stdout(project_root, &["rev-parse", "--show-prefix"])?;
+        let root = dir.path().join(in_repo.trim());
+        fs::create_dir_all(&root)?;
+
+        Ok(Sandbox { _dir: dir, root })
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Returns a unified diff for each of the given files that is different in
+    // the sandbox than it is in the project root.
+    pub(crate) fn diffs(
+        &self,
+        project_root: &Path,
+        files: &[PathBuf],
+    ) -> Result<Vec<(PathBuf, String)>> {
+        let mut diffs = vec![];
+        for f in files {
+            let orig = fs::read(project_root.join(f))?;
+            let sandboxed = fs::read(self.root().join(f))?;
+            if orig == sandboxed {
+                continue;
+            }
+            diffs.push((
+                f.clone(),
+                unified_diff(
+                    f,
+                    &String::from_utf8_lossy(&orig),
+                    &String::from_utf8_lossy(&sandboxed),
+                ),
+            ));
+        }
+        Ok(diffs)
+    }
+}
+
+fn git_stdout(in_dir: &Path, args: &[&str]) -> Result<String> {
+    Ok(Exec::builder("git")
+        .args(args)
+        .in_dir(in_dir)
+        .build()
+        .run()?
+        .stdout
+        .unwrap_or_default())
+}
+
+fn unified_diff(path: &Path, old: &str, new: &str) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    let lines = diff::slice(&old_lines, &new_lines);
+
+    // For each line we record where it came from, along with the number of
+    // old and new lines that came before it.
+    let mut ops = Vec::with_capacity(lines.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for l in lines {
+        let op = match l {
+            diff::Result::Both(s, _) => (' ', *s),
+            diff::Result::Left(s) => ('-', *s),
+            diff::Result::Right(s) => ('+', *s),
+        };
+        ops.push((op, old_line, new_line));
+        if op.0 != '+' {
+            old_line += 1;
+        }
+        if op.0 != '-' {
+            new_line += 1;
+        }
+    }
+
+    // Changes that are close enough together that their context would
+    // overlap go in the same hunk.
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (i, ((kind, _), _, _)) in ops.iter().enumerate() {
+        if *kind == ' ' {
+            continue;
+        }
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path.display(), path.display());
+    if hunks.is_empty() {
+        // The lines are all the same, so the difference must be in the line
+        // endings at the end of the file.
+        out.push_str("(only the line ending at the end of the file is different)\n");
+        return out;
+    }
+    for (start, end) in hunks {
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|((k, _), _, _)| *k != '+').count();
+        let new_count = hunk.iter().filter(|((k, _), _, _)| *k != '-').count();
+        let (_, old_before, new_before) = hunk[0];
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(old_before, old_count),
+            hunk_range(new_before, new_count),
+        );
+        for ((kind, line), _, _) in hunk {
+            let _ = writeln!(out, "{kind}{line}");
+        }
+    }
+    out
+}
+
+// An empty range is given as the line before it, which is how `diff -u` does
+// it.
+fn hunk_range(before: usize, count: usize) -> String {
+    match count {
+        0 => format!("{before},0"),
+        1 => format!("{}", before + 1),
+        _ => format!("{},{count}", before + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use precious_testhelper::TestHelper;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test_case(
+        "a\nb\nc\n",
+        "a\nB\nc\n",
+        "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n" ;
+        "one changed line"
+    )]
+    #[test_case(
+        "",
+        "a\n",
+        "--- a/f\n+++ b/f\n@@ -0,0 +1 @@\n+a\n" ;
+        "added to an empty file"
+    )]
+    #[test_case(
+        "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n",
+        "one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\ntwelve\n",
+        "--- a/f\n+++ b/f\n@@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n 4\n@@ -9,4 +9,4 @@\n 9\n 10\n 11\n-12\n+twelve\n" ;
+        "changes far apart are separate hunks"
+    )]
+    #[test_case(
+        "1\n2\n3\n4\n5\n6\n7\n",
+        "one\n2\n3\n4\n5\n6\nseven\n",
+        "--- a/f\n+++ b/f\n@@ -1,7 +1,7 @@\n-1\n+one\n 2\n 3\n 4\n 5\n 6\n-7\n+seven\n" ;
+        "changes close together are one hunk"
+    )]
+    #[test_case(
+        "a\nb\n",
+        "a\n",
+        "--- a/f\n+++ b/f\n@@ -1,2 +1 @@\n a\n-b\n" ;
+        "deleted line"
+    )]
+    #[test_case(
+        "a\n",
+        "a",
+        "--- a/f\n+++ b/f\n(only the line ending at the end of the file is different)\n" ;
+        "only the trailing newline changed"
+    )]
+    #[parallel]
+    fn unified_diff(old: &str, new: &str, expect: &str) {
+        assert_eq!(super::unified_diff(Path::new("f"), old, new), expect);
+    }
+
+    #[test]
+    #[parallel]
+    fn diffs() -> Result<()> {
+        let helper = TestHelper::new()?
+            .with_git_repo()?
+            .with_config_file("precious.toml", "")?;
+        helper.write_file("src/.rustfmt.toml", "edition = \"2021\"\n")?;
+        let root = helper.precious_root();
+        let files = vec![PathBuf::from("src/sub/mod.rs"), PathBuf::from("src/bar.rs")];
+
+        let sandbox = Sandbox::new(&root, &files)?;
+        assert!(
+            sandbox.root().join("precious.toml").is_file(),
+            "files in the project root are copied",
+        );
+        assert!(
+            sandbox.root().join("src/.rustfmt.toml").is_file(),
+            "files in the dirs between the root and a file are copied",
+        );
+        assert!(
+            !sandbox.root().join("tests/data/foo.txt").exists(),
+            "files in other dirs are not copied",
+        );
+        assert_eq!(sandbox.diffs(&root, &files)?, vec![]);
+
+        fs::write(sandbox.root().join("src/bar.rs"), "fn bar() {}\n")?;
+        let orig = fs::read_to_string(root.join("src/bar.rs"))?;
+        assert_eq!(
+            sandbox.diffs(&root, &files)?,
+            vec![(
+                PathBuf::from("src/bar.rs"),
+                super::unified_diff(Path::new("src/bar.rs"), &orig, "fn bar() {}\n"),
+            )],
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("src/bar.rs"))?,
+            orig,
+            "the original file is not changed",
+        );
+
+        Ok(())
+    }
+
+    #[test_case(GitExport::Index, "fn bar() { staged }\n" ; "index")]
+    #[test_case(GitExport::Commit(String::from("HEAD")), "fn foo() {}\n" ; "head commit")]
+    #[parallel]
+    fn from_git(export: GitExport, expect: &str) -> Result<()> {
+        let helper = TestHelper::new()?.with_git_repo()?;
+        helper.write_file("src/bar.rs", "fn bar() { staged }\n")?;
+        helper.stage_all()?;
+        helper.write_file("src/bar.rs", "fn bar() { unstaged }\n")?;
+        helper.write_file("src/untracked.rs", "fn untracked() {}\n")?;
+
+        let sandbox = Sandbox::from_git(&helper.precious_root(), &export)?;
+        assert_eq!(
+            fs::read_to_string(sandbox.root().join("src/bar.rs"))?,
+            expect
+        );
+        assert!(
+            sandbox.root().join("src/main.rs").is_file(),
+            "other tracked files are exported",
+        );
+        assert!(
+            !sandbox.root().join("src/untracked.rs").exists(),
+            "untracked files are not exported",
+        );
+        assert_eq!(
+            fs::read_to_string(helper.precious_root().join("src/bar.rs"))?,
+            "fn bar() { unstaged }\n",
+            "the working tree is not changed",
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+// With `--debug-scheduler`, this logs each command invocation as it's queued,
+// started, and finished, along with the worker thread that runs it. It also
+// runs a watchdog thread that warns about every invocation that's still
+// running when none have finished for a while, so that when a run hangs you
+// can see which command and which files it's waiting on.
+use crate::command::file_summary_for_log;
+use log::{debug, warn};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+pub(crate) struct Scheduler {
+    shared: Arc<(Mutex<State>, Condvar)>,
+    watchdog: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct State {
+    // Keyed by the command name and the index of the invocation's set of
+    // files.
+    in_flight: BTreeMap<(String, usize), InFlight>,
+    // This is the last time an invocation finished, or the last time one
+    // started when nothing else was running.
+    last_progress: Instant,
+    last_warning: Option<Instant>,
+    done: bool,
+}
+
+#[derive(Debug)]
+struct InFlight {
+    paths: String,
+    worker: Option<usize>,
+    started: Instant,
+}
+
+impl Scheduler {
+    pub(crate) fn new(stall_timeout: Duration) -> Scheduler {
+        let shared = Arc::new((
+            Mutex::new(State {
+                in_flight: BTreeMap::new(),
+                last_progress: Instant::now(),
+                last_warning: None,
+                done: false,
+            }),
+            Condvar::new(),
+        ));
+        let watchdog = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || watch(&shared, stall_timeout))
+        };
+        Scheduler {
+            shared,
+            watchdog: Some(watchdog),
+        }
+    }
+
+    pub(crate) fn queued(&self, command: &str, i: usize, set: &[&Path]) {
+        debug!(
+            "Queued {command} invocation {i} for {}",
+            file_summary_for_log(set)
+        );
+    }
+
+    pub(crate) fn started(&self, command: &str, i: usize, set: &[&Path]) {
+        let worker = rayon::current_thread_index();
+        debug!(
+            "Started {command} invocation {i} on worker {}",
+            worker_name(worker),
+        );
+
+        let mut state = self.shared.0.lock().unwrap();
+        let now = Instant::now();
+        if state.in_flight.is_empty() {
+            state.last_progress = now;
+        }
+        state.in_flight.insert(
+            (command.to_string(), i),
+            InFlight {
+                paths: file_summary_for_log(set),
+                worker,
+                started: now,
+            },
+        );
+    }
+
+    pub(crate) fn finished(&self, command: &str, i: usize) {
+        let mut state = self.shared.0.lock().unwrap();
+        let Some(inv) = state.in_flight.remove(&(command.to_string(), i)) else {
+            return;
+        };
+        state.last_progress = Instant::now();
+        state.last_warning = None;
+        debug!(
+            "Finished {command} invocation {i} on worker {} after {:.2}s",
+            worker_name(inv.worker),
+            inv.started.elapsed().as_secs_f64(),
+        );
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.shared;
+        lock.lock().unwrap().done = true;
+        cvar.notify_all();
+        if let Some(watchdog) = self.watchdog.take() {
+            let _ = watchdog.join();
+        }
+    }
+}
+
+// Wakes up once a second to see whether anything has finished within the
+// stall timeout. The warning is repeated once per timeout for as long as
+// nothing finishes.
+fn watch(shared: &(Mutex<State>, Condvar), stall_timeout: Duration) {
+    let (lock, cvar) = shared;
+    let mut state = lock.lock().unwrap();
+    loop {
+        state = cvar
+            .wait_timeout(state, Duration::from_secs(1).min(stall_timeout))
+            .unwrap()
+            .0;
+        if state.done {
+            return;
+        }
+        if state.in_flight.is_empty() {
+            continue;
+        }
+        let since = state.last_warning.unwrap_or(state.last_progress);
+        if since.elapsed() < stall_timeout {
+            continue;
+        }
+
+        warn!("{}", stall_message(&state));
+        state.last_warning = Some(Instant::now());
+    }
+}
+
+fn stall_message(state: &State) -> String {
+    let mut message = format!(
+        "No command invocation has finished in the last {:.0}s. These are still running:",
+        state.last_progress.elapsed().as_secs_f64(),
+    );
+    for ((command, i), inv) in &state.in_flight {
+        message.push_str(&format!(
+            "\n  {command} invocation {i} on worker {} for {:.0}s: {}",
+            worker_name(inv.worker),
+            inv.started.elapsed().as_secs_f64(),
+            inv.paths,
+        ));
+    }
+    message
+}
+
+fn worker_name(worker: Option<usize>) -> String {
+    worker.map_or_else(|| String::from("(none)"), |w| w.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    #[test]
+    #[parallel]
+    fn stall_message() {
+        let scheduler = Scheduler::new(Duration::from_secs(600));
+        let files = [Path::new("a.rs"), Path::new("b.rs")];
+        scheduler.started("rustfmt", 0, &files[..1]);
+        scheduler.started("rustfmt", 1, &files[1..]);
+        scheduler.started("clippy", 0, &files);
+        scheduler.finished("rustfmt", 0);
+
+        let state = scheduler.shared.0.lock().unwrap();
+        assert_eq!(
+            super::stall_message(&state)
+                .lines()
+                .map(|l| l.split(" for ").next().unwrap_or(l).to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "No command invocation has finished in the last 0s. These are still running:",
+                "  clippy invocation 0 on worker (none)",
+                "  rustfmt invocation 1 on worker (none)",
+            ],
+        );
+        assert!(super::stall_message(&state).ends_with("b.rs"));
+    }
+}
@@ -0,0 +1,1052 @@
+// This implements a very simple protocol for delegating command execution to
+// a long-running `precious serve` process. The client still does all of the
+// file discovery and decides how to split files into invocations. Each
+// invocation is sent to the server as a single line of JSON over a Unix
+// socket, and the server responds with a single line of JSON describing the
+// outcome.
+//
+// A server is started for a single project root, and it only loads config
+// files from inside that root. It keeps the parsed config for each project
+// around between requests unless it's told not to, and it remembers every lint invocation that passed. If the same
+// command is later asked to lint the same files and neither the config nor the
+// file contents have changed, the server returns the cached result without
+// running the command again.
+//
+// Commands with a `daemon-cmd` get a warm worker process. The first time the
+// server sees a request for such a command it runs the `daemon-cmd`, and it
+// keeps that process alive until the server is told to shut down.
+//
+// Anyone who can connect to the socket can make the server run commands, so
+// the daemon's socket lives in a directory that only the current user can
+// access, and the client refuses to talk to a socket owned by another user.
+use crate::{
+    command::{ActualInvoke, LintOrTidyCommand, LintOutcome, TidyOutcome, TidyResult},
+    config,
+    vcs::ChangedLines,
+};
+use anyhow::Result;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, Stdio},
+    slice,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum ServerError {
+    #[error("There is already a server listening on {}", socket.display())]
+    AlreadyServing { socket: PathBuf },
+
+    #[error("Could not connect to a precious server on {}: {error:}", socket.display())]
+    CannotConnect { socket: PathBuf, error: String },
+
+    #[error("The directory for the precious daemon's socket, {}, {reason:}", dir.display())]
+    InsecureSocketDir { dir: PathBuf, reason: String },
+
+    #[error("The config file {} is not under the server's project root, {}", config_file.display(), root.display())]
+    ConfigOutsideRoot { config_file: PathBuf, root: PathBuf },
+
+    #[error("The daemon command for {name:}, `{daemon_cmd:}`, exited unsuccessfully: {status:}")]
+    DaemonCommandFailed {
+        name: String,
+        daemon_cmd: String,
+        status: String,
+    },
+
+    #[error("No {action:} command named {name:} was found in {}", config_file.display())]
+    NoSuchCommand {
+        action: Action,
+        name: String,
+        config_file: PathBuf,
+    },
+
+    #[error("Error from precious server: {error:}")]
+    RemoteError { error: String },
+
+    #[error("The socket at {} is owned by another user (uid {owner:})", socket.display())]
+    SocketNotOwned { socket: PathBuf, owner: u32 },
+
+    #[error("The precious server sent an unexpected response: {response:}")]
+    UnexpectedResponse { response: String },
+
+    #[error("The precious server is only supported on Unix platforms")]
+    #[allow(dead_code)]
+    UnsupportedPlatform,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) enum Action {
+    Lint,
+    Tidy,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Action::Lint => "lint",
+            Action::Tidy => "tidy",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Request {
+    pub(crate) action: Action,
+    pub(crate) project_root: PathBuf,
+    pub(crate) config_file: PathBuf,
+    pub(crate) command: String,
+    pub(crate) label: Option<String>,
+    pub(crate) actual_invoke: ActualInvoke,
+    pub(crate) files: Vec<PathBuf>,
+    pub(crate) changed_lines: Option<ChangedLines>,
+    #[serde(default)]
+    pub(crate) only_dirty_dirs: bool,
+    #[serde(default)]
+    pub(crate) mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+enum Message {
+    Run(Request),
+    Shutdown,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+enum Response {
+    Stopped,
+    Skipped,
+    Linted {
+        outcome: LintOutcome,
+        cached: bool,
+    },
+    Tidied {
+        outcome: TidyOutcome,
+        #[serde(default)]
+        new_files: Vec<PathBuf>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) struct Client {
+    socket: PathBuf,
+}
+
+impl Client {
+    pub(crate) fn new(socket: PathBuf) -> Self {
+        Self { socket }
+    }
+
+    pub(crate) fn lint(&self, req: &Request) -> Result<Option<LintOutcome>> {
+        match self.send(&Message::Run(req.clone()))? {
+            Response::Skipped => Ok(None),
+            Response::Linted { outcome, cached } => {
+                if cached {
+                    debug!(
+                        "Lint result for {} from the server's cache of passing runs",
+                        req.command,
+                    );
+                }
+                Ok(Some(outcome))
+            }
+            r => Self::unexpected(r),
+        }
+    }
+
+    pub(crate) fn tidy(&self, req: &Request) -> Result<Option<TidyResult>> {
+        match self.send(&Message::Run(req.clone()))? {
+            Response::Skipped => Ok(None),
+            Response::Tidied { outcome, new_files } => Ok(Some(TidyResult {
+                outcome,
+                new_files,
+                resources: None,
+            })),
+            r => Self::unexpected(r),
+        }
+    }
+
+    pub(crate) fn shutdown(&self) -> Result<()> {
+        match self.send(&Message::Shutdown)? {
+            Response::Stopped => Ok(()),
+            r => Self::unexpected(r),
+        }
+    }
+
+    fn unexpected<T>(response: Response) -> Result<T> {
+        if let Response::Error { error } = response {
+            return Err(ServerError::RemoteError { error }.into());
+        }
+        Err(ServerError::UnexpectedResponse {
+            response: format!("{response:?}"),
+        }
+        .into())
+    }
+
+    #[cfg(target_family = "unix")]
+    fn send(&self, msg: &Message) -> Result<Response> {
+        check_socket_owner(&self.socket)?;
+        let stream = std::os::unix::net::UnixStream::connect(&self.socket).map_err(|e| {
+            ServerError::CannotConnect {
+                socket: self.socket.clone(),
+                error: e.to_string(),
+            }
+        })?;
+        write_line(&stream, msg)?;
+        read_line(&stream)
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    fn send(&self, _msg: &Message) -> Result<Response> {
+        Err(ServerError::UnsupportedPlatform.into())
+    }
+}
+
+// A client sends its request as soon as it connects, so a connection that
+// hasn't sent a full line by now is not going to.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Workers are keyed by the project root and the command name.
+type WorkerKey = (PathBuf, String);
+
+#[derive(Debug)]
+pub(crate) struct Server {
+    root: PathBuf,
+    cache_configs: bool,
+    configs: Mutex<HashMap<PathBuf, LoadedConfig>>,
+    passed: Mutex<HashSet<md5::Digest>>,
+    workers: Mutex<HashMap<WorkerKey, Worker>>,
+    // Each daemon command has its own lock, held while it's being started, so
+    // that starting one worker doesn't block requests for any other command.
+    starting: Mutex<HashMap<WorkerKey, Arc<Mutex<()>>>>,
+    stopping: std::sync::atomic::AtomicBool,
+}
+
+#[derive(Debug)]
+enum Worker {
+    // The daemon command is still running in the background.
+    Running(Child),
+    // The daemon command exited successfully, which means it started a daemon
+    // of its own (like `eslint_d start`).
+    Started,
+}
+
+#[derive(Clone, Debug)]
+struct LoadedConfig {
+    digest: md5::Digest,
+    config: config::Config,
+}
+
+impl Server {
+    pub(crate) fn new(root: &Path, cache_configs: bool) -> Result<Self> {
+        // A root from a relative `--config` path can be empty.
+        let root = if root.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            root
+        };
+        Ok(Self {
+            root: fs::canonicalize(root)?,
+            cache_configs,
+            configs: Mutex::default(),
+            passed: Mutex::default(),
+            workers: Mutex::default(),
+            starting: Mutex::default(),
+            stopping: std::sync::atomic::AtomicBool::default(),
+        })
+    }
+
+    #[cfg(target_family = "unix")]
+    pub(crate) fn serve(self, socket: &Path) -> Result<()> {
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        if socket.exists() {
+            check_socket_owner(socket)?;
+            if UnixStream::connect(socket).is_ok() {
+                return Err(ServerError::AlreadyServing {
+                    socket: socket.to_path_buf(),
+                }
+                .into());
+            }
+            debug!("Removing stale socket at {}", socket.display());
+            fs::remove_file(socket)?;
+        }
+
+        let listener = UnixListener::bind(socket)?;
+        info!("Listening for requests on {}", socket.display());
+
+        let server = Arc::new(self);
+        let slots = Arc::new(ConnectionSlots::new(max_connections()));
+        for stream in listener.incoming() {
+            if server.stopping.load(std::sync::atomic::Ordering::SeqCst) {
+                break;
+            }
+            let stream = stream?;
+            // Once every slot is taken, we stop accepting connections until
+            // one of them is done.
+            slots.acquire();
+            let server = Arc::clone(&server);
+            let slots = Arc::clone(&slots);
+            let socket = socket.to_path_buf();
+            // The request is read on the connection's own thread, so a client
+            // that connects and then stalls doesn't block anyone else.
+            std::thread::spawn(move || {
+                if let Err(e) = server.handle_connection(&stream, &socket) {
+                    error!("Error responding to request: {e:#}");
+                }
+                slots.release();
+            });
+        }
+
+        fs::remove_file(socket)?;
+
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    fn handle_connection(
+        &self,
+        stream: &std::os::unix::net::UnixStream,
+        socket: &Path,
+    ) -> Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        match read_line::<Message>(stream) {
+            Ok(Message::Run(req)) => write_line(stream, &self.response_for(&req)),
+            Ok(Message::Shutdown) => {
+                info!("Shutting down");
+                self.stopping
+                    .store(true, std::sync::atomic::Ordering::SeqCst);
+                self.stop_workers();
+                write_line(stream, &Response::Stopped)?;
+                // The listener only checks whether it's stopping when it
+                // accepts a connection, so we give it one.
+                std::os::unix::net::UnixStream::connect(socket)?;
+                Ok(())
+            }
+            Err(e) => write_line(
+                stream,
+                &Response::Error {
+                    error: format!("Could not parse request: {e:#}"),
+                },
+            ),
+        }
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    pub(crate) fn serve(self, _socket: &Path) -> Result<()> {
+        Err(ServerError::UnsupportedPlatform.into())
+    }
+
+    fn response_for(&self, req: &Request) -> Response {
+        self.handle_request(req)
+            .unwrap_or_else(|e| Response::Error {
+                error: format!("{e:#}"),
+            })
+    }
+
+    fn handle_request(&self, req: &Request) -> Result<Response> {
+        debug!(
+            "Received request to {} {} file{} with {}",
+            req.action,
+            req.files.len(),
+            if req.files.len() == 1 { "" } else { "s" },
+            req.command,
+        );
+
+        let loaded = self.config_for(&req.config_file)?;
+        let mut command = Self::command_for(&loaded.config, req)?;
+        if let Some(changed) = &req.changed_lines {
+            command.set_changed_lines(changed.clone());
+        }
+        if req.only_dirty_dirs {
+            command.set_only_dirty_dirs();
+        }
+        if let Some(mode) = &req.mode {
+            command.set_mode(mode);
+        }
+        if let Some(daemon_cmd) = command.daemon_cmd() {
+            self.ensure_worker(&req.project_root, &command.name, daemon_cmd)?;
+        }
+        let files = req.files.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+
+        match req.action {
+            Action::Lint => {
+                let key = Self::cache_key(&loaded.digest, req)?;
+                if self.passed.lock().unwrap().contains(&key) {
+                    return Ok(Response::Linted {
+                        outcome: LintOutcome {
+                            ok: true,
+                            stdout: None,
+                            stderr: None,
+                            resources: None,
+                        },
+                        cached: true,
+                    });
+                }
+
+                match command.lint(req.actual_invoke, &files)? {
+                    Some(outcome) => {
+                        if outcome.ok {
+                            self.passed.lock().unwrap().insert(key);
+                        }
+                        Ok(Response::Linted {
+                            outcome,
+                            cached: false,
+                        })
+                    }
+                    None => Ok(Response::Skipped),
+                }
+            }
+            Action::Tidy => match command.tidy(req.actual_invoke, &files)? {
+                Some(TidyResult {
+                    outcome, new_files, ..
+                }) => Ok(Response::Tidied { outcome, new_files }),
+                None => Ok(Response::Skipped),
+            },
+        }
+    }
+
+    fn ensure_worker(&self, project_root: &Path, name: &str, daemon_cmd: &[String]) -> Result<()> {
+        let key = (project_root.to_path_buf(), name.to_string());
+        let start_lock = Arc::clone(
+            self.starting
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_default(),
+        );
+        let _starting = start_lock.lock().unwrap();
+
+        match self.workers.lock().unwrap().get_mut(&key) {
+            Some(Worker::Started) => return Ok(()),
+            Some(Worker::Running(child)) => match child.try_wait()? {
+                None => return Ok(()),
+                Some(status) => {
+                    info!("The daemon command for {name} exited with {status}, restarting it");
+                }
+            },
+            None => (),
+        }
+
+        info!(
+            "Starting daemon command for {name}: {}",
+            daemon_cmd.join(" ")
+        );
+        let mut child = process::Command::new(&daemon_cmd[0])
+            .args(&daemon_cmd[1..])
+            .current_dir(project_root)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        // Give the command a moment to either exit or keep running. Commands
+        // like `eslint_d start` fork a daemon and exit right away.
+        std::thread::sleep(Duration::from_millis(100));
+        let worker = match child.try_wait()? {
+            None => Worker::Running(child),
+            Some(status) if status.success() => Worker::Started,
+            Some(status) => {
+                return Err(ServerError::DaemonCommandFailed {
+                    name: name.to_string(),
+                    daemon_cmd: daemon_cmd.join(" "),
+                    status: status.to_string(),
+                }
+                .into())
+            }
+        };
+        self.workers.lock().unwrap().insert(key, worker);
+
+        Ok(())
+    }
+
+    fn stop_workers(&self) {
+        for ((_, name), worker) in self.workers.lock().unwrap().iter_mut() {
+            if let Worker::Running(child) = worker {
+                debug!("Stopping the daemon command for {name}");
+                if let Err(e) = child.kill().and_then(|()| child.wait().map(|_| ())) {
+                    error!("Could not stop the daemon command for {name}: {e}");
+                }
+            }
+        }
+    }
+
+    fn config_for(&self, config_file: &Path) -> Result<LoadedConfig> {
+        // Anything that can connect to the socket can send a request, so we
+        // don't load a config, which can contain arbitrary commands, from
+        // outside the project.
+        if !fs::canonicalize(config_file)?.starts_with(&self.root) {
+            return Err(ServerError::ConfigOutsideRoot {
+                config_file: config_file.to_path_buf(),
+                root: self.root.clone(),
+            }
+            .into());
+        }
+
+        // The digest covers every base config from `extends`, so changing a
+        // base config is noticed even when the config file is unchanged.
+        let table = config::Config::resolved_table(config_file)?;
+        let digest = md5::compute(toml::to_string(&table)?);
+
+        let mut configs = self.configs.lock().unwrap();
+        if let Some(loaded) = configs.get(config_file) {
+            if loaded.digest == digest {
+                return Ok(loaded.clone());
+            }
+        }
+
+        debug!("Loading config from {}", config_file.display());
+        let loaded = LoadedConfig {
+            digest,
+            config: config::Config::from_resolved_table(table)?,
+        };
+        if self.cache_configs {
+            configs.insert(config_file.to_path_buf(), loaded.clone());
+        }
+
+        Ok(loaded)
+    }
+
+    fn command_for(config: &config::Config, req: &Request) -> Result<LintOrTidyCommand> {
+        let commands = match req.action {
+            Action::Lint => config.clone().into_lint_commands(
+                &req.project_root,
+                slice::from_ref(&req.command),
+                &[],
+                req.label.as_deref(),
+            )?,
+            Action::Tidy => config.clone().into_tidy_commands(
+                &req.project_root,
+                slice::from_ref(&req.command),
+                &[],
+                req.label.as_deref(),
+            )?,
+        };
+
+        commands.into_iter().next().ok_or_else(|| {
+            ServerError::NoSuchCommand {
+                action: req.action,
+                name: req.command.clone(),
+                config_file: req.config_file.clone(),
+            }
+            .into()
+        })
+    }
+
+    // The key covers the config, the command, how it's invoked, and the
+    // path, content, and changed lines of every file it's asked to lint.
+    fn cache_key(config_digest: &md5::Digest, req: &Request) -> Result<md5::Digest> {
+        let mut ctx = md5::Context::new();
+        ctx.consume(config_digest.0);
+        ctx.consume(req.command.as_bytes());
+        ctx.consume(format!("{:?}", req.actual_invoke).as_bytes());
+        // Passing a command just the changed files in a directory may pass
+        // when linting the whole directory would not.
+        if req.only_dirty_dirs {
+            ctx.consume(b"only-dirty-dirs");
+        }
+        // A command can check `PRECIOUS_MODE` and do a less thorough check
+        // in some modes.
+        if let Some(mode) = &req.mode {
+            ctx.consume(mode.as_bytes());
+        }
+        for f in &req.files {
+            ctx.consume(f.to_string_lossy().as_bytes());
+            if let Some(ranges) = req.changed_lines.as_ref().and_then(|c| c.ranges(f)) {
+                ctx.consume(format!("{ranges:?}").as_bytes());
+            }
+            let mut full = req.project_root.clone();
+            full.push(f);
+            if full.is_file() {
+                ctx.consume(md5::compute(fs::read(&full)?).0);
+            }
+        }
+        Ok(ctx.compute())
+    }
+}
+
+// Each connection gets its own thread, so this limits how many threads the
+// server can have at once. Clients run at most one invocation per job at a
+// time, so this leaves room for a couple of clients running at once.
+#[cfg(target_family = "unix")]
+fn max_connections() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get()) * 2
+}
+
+#[cfg(target_family = "unix")]
+#[derive(Debug)]
+struct ConnectionSlots {
+    max: usize,
+    in_use: Mutex<usize>,
+    released: std::sync::Condvar,
+}
+
+#[cfg(target_family = "unix")]
+impl ConnectionSlots {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            in_use: Mutex::new(0),
+            released: std::sync::Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) {
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use >= self.max {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        *in_use += 1;
+    }
+
+    fn release(&self) {
+        *self.in_use.lock().unwrap() -= 1;
+        self.released.notify_one();
+    }
+}
+
+// Returns the socket used by `precious daemon` for the given project root. It
+// goes in `$XDG_RUNTIME_DIR/precious` when that's set, and otherwise in a
+// `precious-<uid>` directory in the temp dir. Either way, the directory must
+// belong to the current user and be inaccessible to everyone else.
+#[cfg(target_family = "unix")]
+pub(crate) fn daemon_socket(project_root: &Path) -> Result<PathBuf> {
+    let mut dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(d) if !d.is_empty() => PathBuf::from(d).join("precious"),
+        _ => std::env::temp_dir().join(format!("precious-{}", current_uid())),
+    };
+    ensure_private_dir(&dir)?;
+    dir.push(format!(
+        "{:x}.sock",
+        md5::compute(project_root.to_string_lossy().as_bytes()),
+    ));
+    Ok(dir)
+}
+
+#[cfg(not(target_family = "unix"))]
+pub(crate) fn daemon_socket(_project_root: &Path) -> Result<PathBuf> {
+    Err(ServerError::UnsupportedPlatform.into())
+}
+
+#[cfg(target_family = "unix")]
+fn current_uid() -> u32 {
+    // SAFETY: getuid cannot fail and has no preconditions.
+    unsafe { libc::getuid() }
+}
+
+#[cfg(target_family = "unix")]
+fn ensure_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    if let Err(e) = fs::DirBuilder::new().mode(0o700).create(dir) {
+        if e.kind() != std::io::ErrorKind::AlreadyExists {
+            return Err(e.into());
+        }
+    }
+
+    // We use `symlink_metadata` so that someone else can't point us at a
+    // directory they control with a symlink.
+    let meta = fs::symlink_metadata(dir)?;
+    let reason = if !meta.is_dir() {
+        Some("is not a directory".to_string())
+    } else if meta.uid() != current_uid() {
+        Some(format!("is owned by another user (uid {})", meta.uid()))
+    } else if meta.mode() & 0o077 != 0 {
+        Some(format!(
+            "can be accessed by other users (mode {:o})",
+            meta.mode() & 0o777,
+        ))
+    } else {
+        None
+    };
+    if let Some(reason) = reason {
+        return Err(ServerError::InsecureSocketDir {
+            dir: dir.to_path_buf(),
+            reason,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+fn check_socket_owner(socket: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = match fs::symlink_metadata(socket) {
+        Ok(m) => m,
+        // Connecting will report a missing socket.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if meta.uid() != current_uid() {
+        return Err(ServerError::SocketNotOwned {
+            socket: socket.to_path_buf(),
+            owner: meta.uid(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn write_line(mut writer: impl Write, value: &impl Serialize) -> Result<()> {
+    serde_json::to_writer(&mut writer, value)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_line<T: serde::de::DeserializeOwned>(reader: impl std::io::Read) -> Result<T> {
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use precious_testhelper::TestHelper;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    fn request(helper: &TestHelper, action: Action, command: &str) -> Request {
+        Request {
+            action,
+            project_root: helper.precious_root(),
+            config_file: helper.config_file("precious.toml"),
+            command: command.to_string(),
+            label: None,
+            actual_invoke: ActualInvoke::Once,
+            files: vec![PathBuf::from("README.md")],
+            changed_lines: None,
+            only_dirty_dirs: false,
+            mode: None,
+        }
+    }
+
+    fn roundtrip(server: &Server, req: &Request) -> Result<Response> {
+        let mut buf = vec![];
+        write_line(&mut buf, &server.response_for(req))?;
+        read_line(buf.as_slice())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn lint_results_are_cached() -> Result<()> {
+        let config = r#"
+[commands.true]
+type    = "lint"
+include = "**/*"
+cmd     = ["true"]
+invoke  = "once"
+ok-exit-codes = 0
+"#;
+        let helper = TestHelper::new()?.with_config_file("precious.toml", config)?;
+        let server = Server::new(&helper.precious_root(), true)?;
+        let req = request(&helper, Action::Lint, "true");
+
+        match roundtrip(&server, &req)? {
+            Response::Linted { outcome, cached } => {
+                assert!(outcome.ok);
+                assert!(!cached, "first run is not cached");
+            }
+            r => panic!("unexpected response: {r:?}"),
+        }
+        match roundtrip(&server, &req)? {
+            Response::Linted { outcome, cached } => {
+                assert!(outcome.ok);
+                assert!(cached, "second run is cached");
+            }
+            r => panic!("unexpected response: {r:?}"),
+        }
+
+        helper.write_file("README.md", "new content")?;
+        match roundtrip(&server, &req)? {
+            Response::Linted { cached, .. } => {
+                assert!(!cached, "cache is not used after a file changes");
+            }
+            r => panic!("unexpected response: {r:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn base_config_change_reloads_config() -> Result<()> {
+        let base = r#"
+[commands.check]
+type    = "lint"
+include = "**/*"
+cmd     = ["true"]
+invoke  = "once"
+ok-exit-codes = 0
+"#;
+        let helper =
+            TestHelper::new()?.with_config_file("precious.toml", r#"extends = "base.toml""#)?;
+        helper.write_file("base.toml", base)?;
+        let server = Server::new(&helper.precious_root(), true)?;
+        let req = request(&helper, Action::Lint, "check");
+
+        match roundtrip(&server, &req)? {
+            Response::Linted { outcome, .. } => assert!(outcome.ok),
+            r => panic!("unexpected response: {r:?}"),
+        }
+
+        helper.write_file("base.toml", &base.replace(r#"["true"]"#, r#"["false"]"#))?;
+        match roundtrip(&server, &req)? {
+            // `false` exits 1, which isn't in `ok-exit-codes`.
+            Response::Error { error } => assert!(
+                error.contains("`false README.md`"),
+                "the changed base config is used: {error}"
+            ),
+            r => panic!("unexpected response: {r:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn unknown_command() -> Result<()> {
+        let config = r#"
+[commands.true]
+type    = "lint"
+include = "**/*"
+cmd     = ["true"]
+ok-exit-codes = 0
+"#;
+        let helper = TestHelper::new()?.with_config_file("precious.toml", config)?;
+        let server = Server::new(&helper.precious_root(), true)?;
+        let req = request(&helper, Action::Tidy, "true");
+
+        match roundtrip(&server, &req)? {
+            Response::Error { error } => assert_eq!(
+                error,
+                format!(
+                    "No tidy command named true was found in {}",
+                    req.config_file.display(),
+                ),
+            ),
+            r => panic!("unexpected response: {r:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn daemon_cmd_is_started_once() -> Result<()> {
+        let config = r#"
+[commands.true]
+type    = "lint"
+include = "**/*"
+cmd     = ["true"]
+daemon-cmd = ["sleep", "60"]
+invoke  = "once"
+ok-exit-codes = 0
+"#;
+        let helper = TestHelper::new()?.with_config_file("precious.toml", config)?;
+        let server = Server::new(&helper.precious_root(), true)?;
+        let mut req = request(&helper, Action::Lint, "true");
+
+        for f in ["README.md", "tests/data/foo.txt"] {
+            req.files = vec![PathBuf::from(f)];
+            match roundtrip(&server, &req)? {
+                Response::Linted { outcome, .. } => assert!(outcome.ok),
+                r => panic!("unexpected response: {r:?}"),
+            }
+        }
+
+        {
+            let mut workers = server.workers.lock().unwrap();
+            assert_eq!(workers.len(), 1);
+            let worker = workers.values_mut().next().unwrap();
+            match worker {
+                Worker::Running(child) => assert!(child.try_wait()?.is_none()),
+                Worker::Started => panic!("sleep should still be running"),
+            }
+        }
+
+        server.stop_workers();
+        let mut workers = server.workers.lock().unwrap();
+        match workers.values_mut().next().unwrap() {
+            Worker::Running(child) => assert!(child.try_wait()?.is_some()),
+            Worker::Started => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn config_outside_root() -> Result<()> {
+        let config = r#"
+[commands.true]
+type    = "lint"
+include = "**/*"
+cmd     = ["true"]
+ok-exit-codes = 0
+"#;
+        let helper = TestHelper::new()?.with_config_file("precious.toml", config)?;
+        let other = TestHelper::new()?.with_config_file("precious.toml", config)?;
+        let server = Server::new(&helper.precious_root(), true)?;
+        let req = request(&other, Action::Lint, "true");
+
+        match roundtrip(&server, &req)? {
+            Response::Error { error } => assert_eq!(
+                error,
+                format!(
+                    "The config file {} is not under the server's project root, {}",
+                    req.config_file.display(),
+                    server.root.display(),
+                ),
+            ),
+            r => panic!("unexpected response: {r:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(not(target_os = "windows"))]
+    fn config_cache_can_be_disabled() -> Result<()> {
+        let config = r#"
+[commands.true]
+type    = "lint"
+include = "**/*"
+cmd     = ["true"]
+invoke  = "once"
+ok-exit-codes = 0
+"#;
+        let helper = TestHelper::new()?.with_config_file("precious.toml", config)?;
+        let req = request(&helper, Action::Lint, "true");
+
+        for cache_configs in [true, false] {
+            let server = Server::new(&helper.precious_root(), cache_configs)?;
+            match roundtrip(&server, &req)? {
+                Response::Linted { outcome, .. } => assert!(outcome.ok),
+                r => panic!("unexpected response: {r:?}"),
+            }
+            assert_eq!(
+                server.configs.lock().unwrap().len(),
+                usize::from(cache_configs)
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn connection_slots() {
+        let slots = Arc::new(ConnectionSlots::new(1));
+        slots.acquire();
+
+        let waiter = {
+            let slots = Arc::clone(&slots);
+            std::thread::spawn(move || slots.acquire())
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(
+            !waiter.is_finished(),
+            "second acquire waits for a free slot"
+        );
+
+        slots.release();
+        waiter.join().unwrap();
+        assert_eq!(*slots.in_use.lock().unwrap(), 1);
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn stalled_client_does_not_block_others() -> Result<()> {
+        let config = r#"
+[commands.true]
+type    = "lint"
+include = "**/*"
+cmd     = ["true"]
+invoke  = "once"
+ok-exit-codes = 0
+"#;
+        let helper = TestHelper::new()?.with_config_file("precious.toml", config)?;
+        let socket_dir = tempfile::tempdir()?;
+        let socket = socket_dir.path().join("precious.sock");
+        let server = {
+            let socket = socket.clone();
+            let server = Server::new(&helper.precious_root(), true)?;
+            std::thread::spawn(move || server.serve(&socket))
+        };
+        while !socket.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // This connection never sends a request.
+        let _stalled = std::os::unix::net::UnixStream::connect(&socket)?;
+
+        let client = Client::new(socket);
+        let outcome = client.lint(&request(&helper, Action::Lint, "true"))?;
+        assert!(outcome.is_some_and(|o| o.ok));
+
+        client.shutdown()?;
+        server.join().unwrap()?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn private_dir() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let td = tempfile::tempdir()?;
+
+        let new = td.path().join("new");
+        ensure_private_dir(&new)?;
+        assert_eq!(fs::metadata(&new)?.permissions().mode() & 0o777, 0o700);
+        // Calling it again with an existing private dir is fine.
+        ensure_private_dir(&new)?;
+
+        let open = td.path().join("open");
+        fs::create_dir(&open)?;
+        fs::set_permissions(&open, fs::Permissions::from_mode(0o755))?;
+        let err = ensure_private_dir(&open).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ServerError>(),
+            Some(&ServerError::InsecureSocketDir {
+                dir: open,
+                reason: "can be accessed by other users (mode 755)".to_string(),
+            }),
+        );
+
+        let link = td.path().join("link");
+        std::os::unix::fs::symlink(&new, &link)?;
+        let err = ensure_private_dir(&link).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ServerError>(),
+            Some(&ServerError::InsecureSocketDir {
+                dir: link,
+                reason: "is not a directory".to_string(),
+            }),
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+// After each run, precious saves how long each command took and whether it
+// failed. `precious stats` summarizes this history to show which commands are
+// slowest and which ones flip between passing and failing. The history is
+// kept in a `.precious` dir in the project root.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tempfile::NamedTempFile;
+
+const STATE_DIR: &str = ".precious";
+
+// The dir is only for precious's own state, so it ignores everything in it,
+// which keeps the state out of git and out of the files precious finds.
+const STATE_DIR_GITIGNORE: &str = "# Created by precious\n*\n";
+
+// Only the most recent runs of each command are kept, so the history doesn't
+// grow forever.
+const MAX_RUNS: usize = 100;
+
+// The number of runs that are averaged for the "recent" column.
+const RECENT_RUNS: usize = 5;
+
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct Stats {
+    // The runs for each command, oldest first.
+    commands: BTreeMap<String, Vec<Run>>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct Run {
+    pub(crate) millis: u64,
+    pub(crate) failed: bool,
+}
+
+impl Run {
+    pub(crate) fn new(elapsed: Duration, failed: bool) -> Self {
+        Self {
+            millis: u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            failed,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Summary {
+    pub(crate) command: String,
+    pub(crate) runs: usize,
+    pub(crate) failures: usize,
+    // The number of times the command went from passing to failing or from
+    // failing to passing between one run and the next.
+    pub(crate) flips: usize,
+    pub(crate) average: Duration,
+    pub(crate) recent_average: Duration,
+}
+
+impl Stats {
+    pub(crate) fn load(project_root: &Path) -> Result<Self> {
+        match fs::read_to_string(state_file(project_root)) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Several runs in the same project can finish at once, so the stats are
+    // loaded, updated, and saved while holding a lock. Otherwise one run could
+    // overwrite the runs that another one just saved. The file is replaced
+    // atomically, so `load` never sees a partly written file.
+    pub(crate) fn update(project_root: &Path, f: impl FnOnce(&mut Self)) -> Result<()> {
+        let _lock = lock(project_root)?;
+        let mut stats = Self::load(project_root)?;
+        f(&mut stats);
+
+        let file = state_file(project_root);
+        let mut temp = NamedTempFile::new_in(file.parent().unwrap())?;
+        serde_json::to_writer(&mut temp, &stats)?;
+        temp.persist(&file)?;
+        Ok(())
+    }
+
+    pub(crate) fn clear(project_root: &Path) -> Result<()> {
+        if !project_root.join(STATE_DIR).exists() {
+            return Ok(());
+        }
+        let _lock = lock(project_root)?;
+        match fs::remove_file(state_file(project_root)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn add(&mut self, command: &str, run: Run) {
+        let runs = self.commands.entry(command.to_string()).or_default();
+        runs.push(run);
+        if runs.len() > MAX_RUNS {
+            runs.drain(..runs.len() - MAX_RUNS);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    // These are sorted by command name.
+    pub(crate) fn summaries(&self) -> Vec<Summary> {
+        self.commands
+            .iter()
+            .filter(|(_, runs)| !runs.is_empty())
+            .map(|(command, runs)| Summary {
+                command: command.clone(),
+                runs: runs.len(),
+                failures: runs.iter().filter(|r| r.failed).count(),
+                flips: runs
+                    .windows(2)
+                    .filter(|w| w[0].failed != w[1].failed)
+                    .count(),
+                average: average(runs),
+                recent_average: average(&runs[runs.len().saturating_sub(RECENT_RUNS)..]),
+            })
+            .collect()
+    }
+}
+
+fn average(runs: &[Run]) -> Duration {
+    let total = runs.iter().map(|r| u128::from(r.millis)).sum::<u128>();
+    let count = u128::try_from(runs.len()).unwrap_or(u128::MAX).max(1);
+    Duration::from_millis(u64::try_from(total / count).unwrap_or(u64::MAX))
+}
+
+fn state_file(project_root: &Path) -> PathBuf {
+    project_root.join(STATE_DIR).join("stats.json")
+}
+
+// The lock is released when the returned file is dropped.
+fn lock(project_root: &Path) -> Result<fs::File> {
+    let dir = project_root.join(STATE_DIR);
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(".gitignore"), STATE_DIR_GITIGNORE)?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join("stats.lock"))?;
+    file.lock()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    fn run(millis: u64, failed: bool) -> Run {
+        Run { millis, failed }
+    }
+
+    #[test]
+    #[parallel]
+    fn save_load_and_clear() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        assert!(Stats::load(td.path())?.is_empty());
+
+        let mut stats = Stats::default();
+        stats.add("clippy", run(1_000, false));
+        stats.add("rustfmt", run(10, true));
+        Stats::update(td.path(), |s| {
+            s.add("clippy", run(1_000, false));
+            s.add("rustfmt", run(10, true));
+        })?;
+        assert_eq!(Stats::load(td.path())?, stats);
+        assert_eq!(
+            fs::read_to_string(td.path().join(STATE_DIR).join(".gitignore"))?,
+            STATE_DIR_GITIGNORE,
+        );
+
+        Stats::clear(td.path())?;
+        assert!(!state_file(td.path()).exists());
+        // Clearing stats that don't exist is fine.
+        Stats::clear(td.path())?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn concurrent_updates_are_not_lost() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        std::thread::scope(|s| {
+            let handles = (0..8)
+                .map(|_| s.spawn(|| Stats::update(td.path(), |s| s.add("clippy", run(1, false)))))
+                .collect::<Vec<_>>();
+            handles.into_iter().try_for_each(|h| h.join().unwrap())
+        })?;
+        assert_eq!(Stats::load(td.path())?.commands["clippy"].len(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    fn add_keeps_the_most_recent_runs() {
+        let mut stats = Stats::default();
+        for i in 0..(MAX_RUNS + 10) {
+            stats.add("clippy", run(i as u64, false));
+        }
+        let runs = &stats.commands["clippy"];
+        assert_eq!(runs.len(), MAX_RUNS);
+        assert_eq!(runs[0].millis, 10);
+    }
+
+    #[test]
+    #[parallel]
+    fn summaries() {
+        let mut stats = Stats::default();
+        for r in [
+            run(100, false),
+            run(100, true),
+            run(100, false),
+            run(100, false),
+            run(600, false),
+            run(600, false),
+            run(600, true),
+            run(600, true),
+            run(600, false),
+        ] {
+            stats.add("clippy", r);
+        }
+        stats.add("rustfmt", run(10, false));
+
+        assert_eq!(
+            stats.summaries(),
+            vec![
+                Summary {
+                    command: String::from("clippy"),
+                    runs: 9,
+                    failures: 3,
+                    flips: 4,
+                    average: Duration::from_millis(377),
+                    recent_average: Duration::from_millis(600),
+                },
+                Summary {
+                    command: String::from("rustfmt"),
+                    runs: 1,
+                    failures: 0,
+                    flips: 0,
+                    average: Duration::from_millis(10),
+                    recent_average: Duration::from_millis(10),
+                },
+            ],
+        );
+    }
+}
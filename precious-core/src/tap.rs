@@ -0,0 +1,205 @@
+// Output in the Test Anything Protocol (TAP) version 14 format. See
+// https://testanything.org/tap-version-14-specification.html for details.
+//
+// Each command invocation is a test point. Since commands run in parallel, the
+// test point number is assigned and the line is printed while holding a lock,
+// so that the numbers in the output are always in order.
+use crate::{
+    paths::mode::Mode,
+    precious::Exit,
+    reporter::{self, Event, Reporter},
+    runner::Action,
+};
+use itertools::Itertools;
+use std::{fmt::Debug, io::Write, path::Path, sync::Mutex};
+
+#[derive(Debug)]
+pub(crate) struct Tap<W: Write + Send = std::io::Stdout> {
+    state: Mutex<(usize, W)>,
+}
+
+impl Tap {
+    pub(crate) fn new() -> Self {
+        Self::with_writer(std::io::stdout())
+    }
+}
+
+impl<W: Write + Send> Tap<W> {
+    pub(crate) fn with_writer(writer: W) -> Self {
+        Self {
+            state: Mutex::new((0, writer)),
+        }
+    }
+
+    pub(crate) fn version(&self) {
+        self.write("TAP version 14\n");
+    }
+
+    pub(crate) fn ok(&self, description: &str) {
+        self.test_point(true, description, &[]);
+    }
+
+    pub(crate) fn not_ok(&self, description: &str, diagnostics: &[(&str, &str)]) {
+        self.test_point(false, description, diagnostics);
+    }
+
+    pub(crate) fn comment(&self, comment: &str) {
+        self.write(&format!("# {comment}\n"));
+    }
+
+    pub(crate) fn bail_out(&self, reason: &str) {
+        self.write(&format!("Bail out! {}\n", one_line(reason)));
+    }
+
+    // The plan comes at the end of the output, since we don't know how many
+    // invocations there will be until they've all run.
+    pub(crate) fn plan(&self) {
+        let mut state = self.state.lock().unwrap();
+        let count = state.0;
+        let _ = writeln!(state.1, "1..{count}");
+    }
+
+    pub(crate) fn skip_all(&self, reason: &str) {
+        self.write(&format!("1..0 # SKIP {}\n", one_line(reason)));
+    }
+
+    fn test_point(&self, ok: bool, description: &str, diagnostics: &[(&str, &str)]) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+
+        let mut out = format!(
+            "{}ok {} - {}\n",
+            if ok { "" } else { "not " },
+            state.0,
+            escape(description),
+        );
+        if !diagnostics.is_empty() {
+            out.push_str("  ---\n");
+            for (k, v) in diagnostics {
+                if v.contains('\n') {
+                    out.push_str(&format!("  {k}: |\n"));
+                    for line in v.trim_end().lines() {
+                        out.push_str(&format!("    {line}\n"));
+                    }
+                } else {
+                    out.push_str(&format!("  {k}: '{}'\n", v.replace('\'', "''")));
+                }
+            }
+            out.push_str("  ...\n");
+        }
+
+        let _ = state.1.write_all(out.as_bytes());
+    }
+
+    fn write(&self, s: &str) {
+        let _ = self.state.lock().unwrap().1.write_all(s.as_bytes());
+    }
+}
+
+impl<W: Write + Send + Debug> Reporter for Tap<W> {
+    fn start(&self) {
+        self.version();
+    }
+
+    fn header(&self, action: Action, mode: &Mode) {
+        self.comment(&format!(
+            "{} {}",
+            reporter::action_description(action),
+            mode
+        ));
+    }
+
+    fn installing(&self, command: &str, executable: &str) {
+        self.comment(&format!("Installing {executable} for {command}"));
+    }
+
+    fn invocation(&self, event: &Event<'_>) {
+        if event.is_ok() {
+            self.ok(&event.description());
+        } else {
+            self.not_ok(&event.description(), &event.diagnostics());
+        }
+        if !event.new_files.is_empty() {
+            self.comment(&format!(
+                "Created by {}: {}",
+                event.command,
+                event.new_files.iter().map(|p| p.display()).join(" "),
+            ));
+        }
+    }
+
+    fn restaged(&self, paths: &[&Path]) {
+        self.comment(&format!(
+            "Restaged {}",
+            paths.iter().map(|p| p.display()).join(" ")
+        ));
+    }
+
+    fn diff(&self, _path: &Path, diff: &str) {
+        for line in diff.lines() {
+            self.comment(line);
+        }
+    }
+
+    // Failures are already reported as test points.
+    fn finish(&self, exit: &Exit) {
+        match &exit.message {
+            Some(msg) => self.skip_all(msg),
+            None => self.plan(),
+        }
+    }
+
+    fn failed(&self, error: &anyhow::Error) {
+        self.bail_out(&format!("Failed to run precious: {error}"));
+    }
+}
+
+// A `#` in a description would start a directive, so it must be escaped.
+fn escape(description: &str) -> String {
+    one_line(&description.replace('\\', "\\\\").replace('#', "\\#"))
+}
+
+fn one_line(s: &str) -> String {
+    s.lines().map(str::trim).join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+
+    #[test]
+    #[parallel]
+    fn output() {
+        let tap = Tap::with_writer(vec![]);
+        tap.version();
+        tap.ok("Passed rustfmt: src/main.rs");
+        tap.comment("Installing typos for typos");
+        tap.not_ok(
+            "Failed clippy: src/lib.rs #1",
+            &[
+                ("message", "linting failed"),
+                ("output", "line 1\nline 2\n"),
+            ],
+        );
+        tap.plan();
+
+        let output = String::from_utf8(tap.state.into_inner().unwrap().1).unwrap();
+        assert_eq!(
+            output,
+            r"TAP version 14
+ok 1 - Passed rustfmt: src/main.rs
+# Installing typos for typos
+not ok 2 - Failed clippy: src/lib.rs \#1
+  ---
+  message: 'linting failed'
+  output: |
+    line 1
+    line 2
+  ...
+1..2
+",
+        );
+    }
+}
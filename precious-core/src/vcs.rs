@@ -1 +1,377 @@
-pub const DIRS: &[&str] = &[".git", ".hg", ".svn"];
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+pub const DIRS: &[&str] = &[".git", ".hg", ".jj", ".svn"];
+
+// These tell git where to find the repo and its working tree when that can't
+// be found by looking for a `.git` dir, for example when the repo's git dir is
+// kept somewhere outside of the working tree.
+const GIT_LOCATION_ENV_VARS: &[&str] = &["GIT_DIR", "GIT_WORK_TREE"];
+
+// Returns whichever of the git location env vars are set, with relative paths
+// made absolute by resolving them against the given dir, which should be the
+// dir precious was run from. Git resolves them against its own working dir,
+// but we often run git in some other dir, like the project root.
+pub fn git_location_env(cwd: &Path) -> HashMap<String, String> {
+    git_location_env_from(cwd, |k| env::var_os(k))
+}
+
+fn git_location_env_from(
+    cwd: &Path,
+    get: impl Fn(&str) -> Option<OsString>,
+) -> HashMap<String, String> {
+    GIT_LOCATION_ENV_VARS
+        .iter()
+        .filter_map(|k| {
+            let v = get(k).filter(|v| !v.is_empty())?;
+            Some((k.to_string(), cwd.join(v).to_string_lossy().into_owned()))
+        })
+        .collect()
+}
+
+// Returns the root of the Jujutsu repo containing the given directory, if
+// there is one. We stop at the first directory with either a `.jj` or `.git`
+// directory, so a plain git repo nested inside a jj workspace is still
+// treated as a git repo.
+pub fn jj_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|d| d.join(".jj").is_dir() || d.join(".git").exists())
+        .filter(|d| d.join(".jj").is_dir())
+        .map(Path::to_path_buf)
+}
+
+// The line ranges in each file that were added or changed, according to the
+// VCS. Paths are relative to the project root.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ChangedLines {
+    files: HashMap<PathBuf, Vec<RangeInclusive<usize>>>,
+}
+
+static HUNK_HEADER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^@@ -\d+(?:,(\d+))? \+(\d+)(?:,(\d+))? @@").unwrap());
+
+impl ChangedLines {
+    // Parses the output of `git diff --unified=0`. Hunks that only delete lines
+    // don't add anything to the new version of the file, so they are ignored.
+    // The paths in the returned value are as they appear in the diff, minus
+    // the "b/" prefix. Paths that git quoted are unquoted first.
+    //
+    // We count the lines left in each hunk, so that an added line whose
+    // content starts with "++ " or "@@ " is never mistaken for a header. A
+    // "+++ " line is only a file header right after a "--- " header.
+    pub fn from_unified_diff(diff: &str) -> ChangedLines {
+        let mut files: HashMap<PathBuf, Vec<RangeInclusive<usize>>> = HashMap::new();
+        let mut current: Option<PathBuf> = None;
+        let mut after_old_header = false;
+        let (mut old_left, mut new_left) = (0_usize, 0_usize);
+
+        for line in diff.lines() {
+            if old_left > 0 || new_left > 0 {
+                match line.as_bytes().first() {
+                    Some(b'+') => new_left = new_left.saturating_sub(1),
+                    Some(b'-') => old_left = old_left.saturating_sub(1),
+                    // This is a "\ No newline at end of file" marker.
+                    Some(b'\\') => (),
+                    _ => {
+                        old_left = old_left.saturating_sub(1);
+                        new_left = new_left.saturating_sub(1);
+                    }
+                }
+                continue;
+            }
+
+            if line.starts_with("--- ") {
+                after_old_header = true;
+                continue;
+            }
+            if after_old_header {
+                after_old_header = false;
+                if let Some(path) = line.strip_prefix("+++ ") {
+                    // Git adds a trailing tab after names that contain a space.
+                    let path = unquote_path(path.strip_suffix('\t').unwrap_or(path));
+                    current = match path.as_slice() {
+                        b"/dev/null" => None,
+                        p => Some(path_from_bytes(p.strip_prefix(b"b/").unwrap_or(p))),
+                    };
+                    continue;
+                }
+            }
+
+            let Some(caps) = HUNK_HEADER_RE.captures(line) else {
+                continue;
+            };
+            let count = |i: usize| {
+                caps.get(i)
+                    .map_or(1, |c| c.as_str().parse::<usize>().unwrap_or(0))
+            };
+            old_left = count(1);
+            new_left = count(3);
+
+            let Some(path) = &current else {
+                continue;
+            };
+            let start = caps[2].parse::<usize>().unwrap_or(0);
+            let count = new_left;
+            if count == 0 {
+                continue;
+            }
+
+            files
+                .entry(path.clone())
+                .or_default()
+                .push(start..=start + count - 1);
+        }
+
+        ChangedLines { files }
+    }
+
+    pub fn map_paths<F>(self, mut f: F) -> ChangedLines
+    where
+        F: FnMut(PathBuf) -> Option<PathBuf>,
+    {
+        ChangedLines {
+            files: self
+                .files
+                .into_iter()
+                .filter_map(|(p, r)| f(p).map(|p| (p, r)))
+                .collect(),
+        }
+    }
+
+    pub fn ranges(&self, path: &Path) -> Option<&[RangeInclusive<usize>]> {
+        self.files.get(path).map(Vec::as_slice)
+    }
+
+    pub fn contains(&self, path: &Path, line: usize) -> bool {
+        self.ranges(path)
+            .is_some_and(|ranges| ranges.iter().any(|r| r.contains(&line)))
+    }
+}
+
+// Git quotes a path in a diff header when it contains a double quote, a
+// backslash, a control character, or (with `core.quotepath` on) any byte
+// outside of ASCII. The quoted form uses C-style escapes, with octal escapes
+// for the raw bytes. Unquoted paths are returned as is.
+fn unquote_path(path: &str) -> Vec<u8> {
+    let Some(quoted) = path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) else {
+        return path.as_bytes().to_vec();
+    };
+
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut iter = quoted.bytes().peekable();
+    while let Some(b) = iter.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(d @ b'0'..=b'7') => {
+                let mut value = u32::from(d - b'0');
+                for _ in 0..2 {
+                    match iter.peek() {
+                        Some(d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            iter.next();
+                        }
+                        _ => break,
+                    }
+                }
+                bytes.push(u8::try_from(value).unwrap_or(u8::MAX));
+            }
+            Some(b'a') => bytes.push(0x07),
+            Some(b'b') => bytes.push(0x08),
+            Some(b'f') => bytes.push(0x0c),
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'v') => bytes.push(0x0b),
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    bytes
+}
+
+// Turns the raw bytes of a path reported by a VCS into a `PathBuf`. On Unix
+// any bytes are a valid path. Elsewhere, git always reports paths as UTF-8.
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serial_test::parallel;
+    use test_case::test_case;
+
+    #[test]
+    #[parallel]
+    fn changed_lines_from_unified_diff() {
+        let diff = r#"diff --git a/src/lib.rs b/src/lib.rs
+index 1234567..89abcde 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -3 +3 @@ fn foo() {
+-    old();
++    new();
+@@ -10,0 +11,3 @@ fn bar() {
++    one();
++    two();
++    three();
+@@ -20,2 +23,0 @@ fn baz() {
+-    gone();
+-    gone();
+diff --git a/new.txt b/new.txt
+new file mode 100644
+index 0000000..1111111
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++a
++b
+"#;
+        let changed = ChangedLines::from_unified_diff(diff);
+        assert_eq!(
+            changed.ranges(Path::new("src/lib.rs")),
+            Some([3..=3, 11..=13].as_slice()),
+        );
+        assert_eq!(
+            changed.ranges(Path::new("new.txt")),
+            Some([1..=2].as_slice())
+        );
+
+        assert!(changed.contains(Path::new("src/lib.rs"), 12));
+        assert!(!changed.contains(Path::new("src/lib.rs"), 4));
+        assert!(!changed.contains(Path::new("src/lib.rs"), 23));
+        assert!(!changed.contains(Path::new("other.rs"), 1));
+    }
+
+    #[test]
+    #[parallel]
+    fn changed_lines_with_header_like_content() {
+        // The added lines here look like a file header and a hunk header.
+        let diff = "diff --git a/notes.md b/notes.md
+--- a/notes.md
++++ b/notes.md
+@@ -1 +1,3 @@
+-- old
++++ not/a/file.md
++@@ -1 +100,5 @@
++plain
+\\ No newline at end of file
+@@ -10,0 +11 @@
++added
+";
+        let changed = ChangedLines::from_unified_diff(diff);
+        assert_eq!(
+            changed.ranges(Path::new("notes.md")),
+            Some([1..=3, 11..=11].as_slice()),
+        );
+        assert_eq!(changed.ranges(Path::new("not/a/file.md")), None);
+    }
+
+    #[test]
+    #[parallel]
+    fn changed_lines_with_quoted_paths() {
+        let diff = "diff --git a/with space.rs b/with space.rs
+--- a/with space.rs\t
++++ b/with space.rs\t
+@@ -1 +1 @@
+-a
++b
+diff --git \"a/\\303\\251.rs\" \"b/\\303\\251.rs\"
+--- \"a/\\303\\251.rs\"
++++ \"b/\\303\\251.rs\"
+@@ -2 +2 @@
+-a
++b
+diff --git \"a/tab\\there.rs\" \"b/tab\\there.rs\"
+--- \"a/tab\\there.rs\"
++++ \"b/tab\\there.rs\"
+@@ -3 +3 @@
+-a
++b
+";
+        let changed = ChangedLines::from_unified_diff(diff);
+        assert_eq!(
+            changed.ranges(Path::new("with space.rs")),
+            Some([1..=1].as_slice()),
+        );
+        assert_eq!(
+            changed.ranges(Path::new("\u{e9}.rs")),
+            Some([2..=2].as_slice()),
+        );
+        assert_eq!(
+            changed.ranges(Path::new("tab\there.rs")),
+            Some([3..=3].as_slice()),
+        );
+    }
+
+    #[test_case("plain.rs", b"plain.rs" ; "unquoted")]
+    #[test_case(r#""a\"b.rs""#, b"a\"b.rs" ; "escaped quote")]
+    #[test_case(r#""a\\b.rs""#, b"a\\b.rs" ; "escaped backslash")]
+    #[test_case(r#""\303\251.rs""#, b"\xc3\xa9.rs" ; "octal utf8")]
+    #[test_case(r#""\377.rs""#, b"\xff.rs" ; "octal not utf8")]
+    #[test_case(r#""a\nb""#, b"a\nb" ; "newline")]
+    #[parallel]
+    fn unquote_path(path: &str, expect: &[u8]) {
+        assert_eq!(super::unquote_path(path), expect);
+    }
+
+    #[test_case(&[], &[] ; "neither is set")]
+    #[test_case(&[("GIT_DIR", "")], &[] ; "empty value")]
+    #[test_case(&[("GIT_DIR", "../repo.git")], &[("GIT_DIR", "../repo.git")] ; "relative git dir")]
+    #[test_case(
+        &[("GIT_DIR", "/repos/project.git"), ("GIT_WORK_TREE", ".")],
+        &[("GIT_DIR", "/repos/project.git"), ("GIT_WORK_TREE", ".")] ;
+        "absolute git dir and relative work tree"
+    )]
+    #[parallel]
+    fn git_location_env(vars: &[(&str, &str)], expect: &[(&str, &str)]) {
+        let cwd = Path::new("/home/user/project");
+        let vars = vars.iter().copied().collect::<HashMap<_, _>>();
+        assert_eq!(
+            git_location_env_from(cwd, |k| vars.get(k).map(OsString::from)),
+            expect
+                .iter()
+                .map(|(k, v)| (k.to_string(), cwd.join(v).to_string_lossy().into_owned()))
+                .collect::<HashMap<_, _>>(),
+        );
+    }
+
+    #[test]
+    #[parallel]
+    fn jj_root() -> anyhow::Result<()> {
+        let td = tempfile::tempdir()?;
+        let root = td.path();
+        std::fs::create_dir_all(root.join("jj/.jj"))?;
+        std::fs::create_dir_all(root.join("jj/.git"))?;
+        std::fs::create_dir_all(root.join("jj/src/nested/.git"))?;
+        std::fs::create_dir_all(root.join("git/.git"))?;
+
+        assert_eq!(super::jj_root(&root.join("jj")), Some(root.join("jj")));
+        assert_eq!(super::jj_root(&root.join("jj/src")), Some(root.join("jj")));
+        assert_eq!(super::jj_root(&root.join("jj/src/nested")), None);
+        assert_eq!(super::jj_root(&root.join("git")), None);
+
+        Ok(())
+    }
+}
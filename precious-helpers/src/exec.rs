@@ -1,18 +1,33 @@
 use anyhow::{Context, Result};
+use encoding_rs::Encoding;
 use itertools::Itertools;
 use log::{
     Level::Debug,
     {debug, error, log_enabled},
 };
 use regex::Regex;
-use std::{collections::HashMap, env, fs, path::Path, process};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    io::{BufRead, BufReader, Read},
+    path::PathBuf,
+    process,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use which::which;
 
+#[cfg(target_family = "windows")]
+use log::warn;
 #[cfg(target_family = "unix")]
 use std::os::unix::prelude::*;
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error(r#"Could not find "{exe:}" in your path ({path:}"#)]
     ExecutableNotInPath { exe: String, path: String },
@@ -37,6 +52,9 @@ pub enum Error {
         code: i32,
         stderr: String,
     },
+
+    #[error("Ran `{cmd:}` and it was killed because it ran for more than {}s", timeout.as_secs_f64())]
+    TimedOut { cmd: String, timeout: Duration },
 }
 
 fn exec_output_summary(stdout: &str, stderr: &str) -> String {
@@ -55,148 +73,798 @@ fn exec_output_summary(stdout: &str, stderr: &str) -> String {
     output
 }
 
+/// The result of running a command.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct Output {
     pub exit_code: i32,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    /// The resources used by the process. This is only available on Unix
+    /// systems.
+    pub resources: Option<Resources>,
 }
 
-#[allow(clippy::implicit_hasher, clippy::missing_errors_doc)]
-pub fn run(
-    exe: &str,
-    args: &[&str],
-    env: &HashMap<String, String>,
-    ok_exit_codes: &[i32],
-    ignore_stderr: Option<&[Regex]>,
-    in_dir: Option<&Path>,
-) -> Result<Output> {
-    if which(exe).is_err() {
-        let path = match env::var("PATH") {
-            Ok(p) => p,
-            Err(e) => format!("<could not get PATH environment variable: {e}>"),
-        };
-        return Err(Error::ExecutableNotInPath {
-            exe: exe.to_string(),
-            path,
+impl Output {
+    /// Creates the output for a command that wasn't run with [`Exec`], like
+    /// one that runs in the current process, so that it can be handled the
+    /// same way as the output from any other command.
+    #[must_use]
+    pub fn new(
+        exit_code: i32,
+        stdout: Option<String>,
+        stderr: Option<String>,
+        resources: Option<Resources>,
+    ) -> Self {
+        Self {
+            exit_code,
+            stdout,
+            stderr,
+            resources,
         }
-        .into());
     }
+}
 
-    let mut c = process::Command::new(exe);
-    for a in args {
-        c.arg(a);
+/// The resources used by a process, as reported by `wait4`. These are only
+/// collected on Unix systems. On Windows they would need a Job Object for each
+/// process, which isn't implemented, so [`Output::resources`] is always `None`
+/// there.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Resources {
+    /// The peak resident set size of the process, in bytes.
+    pub max_rss: u64,
+    /// The total user and system CPU time used by the process.
+    pub cpu_time: Duration,
+}
+
+impl Resources {
+    #[must_use]
+    pub fn new(max_rss: u64, cpu_time: Duration) -> Self {
+        Self { max_rss, cpu_time }
     }
 
-    // We are canonicalizing this primarily for the benefit of our debugging
-    // output, because otherwise we might see the current dir as just `.`,
-    // which is not helpful.
-    let cwd = if let Some(d) = in_dir {
-        fs::canonicalize(d)?
-    } else {
-        fs::canonicalize(env::current_dir()?)?
-    };
-    c.current_dir(cwd.clone());
+    /// Combines the resources used by two processes. The CPU times are added
+    /// together and the peak RSS is the larger of the two.
+    #[must_use]
+    pub fn combine(self, other: Resources) -> Resources {
+        Resources {
+            max_rss: self.max_rss.max(other.max_rss),
+            cpu_time: self.cpu_time + other.cpu_time,
+        }
+    }
+}
 
-    c.envs(env);
+/// Limits on the resources a process can use. These are set with `setrlimit`
+/// in the child process before it runs the executable, so they are only
+/// enforced on Unix systems. On other systems they are ignored with a warning.
+///
+/// Each limit sets the soft limit, capped at the existing hard limit. The hard
+/// limit is left alone.
+///
+/// Create one with [`Limits::new`], which doesn't limit anything, and then
+/// set the limits you want:
+///
+/// ```
+/// use precious_helpers::exec::Limits;
+///
+/// let limits = Limits::new().max_memory(Some(1 << 30)).max_cpu_seconds(Some(60));
+/// assert_eq!(limits.max_memory, Some(1 << 30));
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Limits {
+    /// The maximum size of the process's virtual memory, in bytes. This is
+    /// `RLIMIT_AS`, so allocations past this fail.
+    pub max_memory: Option<u64>,
+    /// The maximum CPU time the process can use, in seconds. This is
+    /// `RLIMIT_CPU`, so the process is killed by a signal when it goes past
+    /// this.
+    pub max_cpu_seconds: Option<u64>,
+}
 
-    if log_enabled!(Debug) {
-        debug!(
-            "Running command [{}] with cwd = {}",
-            exec_string(exe, args),
-            cwd.display()
-        );
-        for k in env.keys().sorted() {
-            debug!(
-                r#"  with env: {k} = "{}""#,
-                env.get(k).unwrap_or(&"<not UTF-8>".to_string()),
-            );
+impl Limits {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum size of the process's virtual memory, in bytes. With
+    /// `None`, memory is not limited.
+    #[must_use]
+    pub fn max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Sets the maximum CPU time the process can use, in seconds. With
+    /// `None`, CPU time is not limited.
+    #[must_use]
+    pub fn max_cpu_seconds(mut self, max_cpu_seconds: Option<u64>) -> Self {
+        self.max_cpu_seconds = max_cpu_seconds;
+        self
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.max_memory.is_none() && self.max_cpu_seconds.is_none()
+    }
+}
+
+impl fmt::Display for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}s CPU, {:.1} MiB peak RSS",
+            self.cpu_time.as_secs_f64(),
+            self.max_rss as f64 / (1024.0 * 1024.0),
+        )
+    }
+}
+
+/// Which of a command's output streams a line came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// An external command to run. Create one with [`Exec::builder`].
+///
+/// By default, the command is run in the current directory with an empty
+/// stdin. It has to exit with `0` and must not print anything to stderr, or
+/// else running it returns an [`Error`]. Its output is captured and decoded
+/// as UTF-8.
+#[derive(Clone, Debug)]
+pub struct Exec {
+    exe: String,
+    args: Vec<OsString>,
+    env: HashMap<String, String>,
+    ok_exit_codes: Vec<i32>,
+    ignore_stderr: Vec<Regex>,
+    in_dir: Option<PathBuf>,
+    encoding: Option<&'static Encoding>,
+    limits: Limits,
+    stdin: Option<Vec<u8>>,
+    merge_stderr: bool,
+    timeout: Option<Duration>,
+    on_output_line: Option<LineCallback>,
+    capture_stdout: bool,
+}
+
+/// Builds an [`Exec`]. Create one with [`Exec::builder`].
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct ExecBuilder {
+    exec: Exec,
+}
+
+type LineFn = dyn Fn(OutputStream, &str) + Send + Sync;
+
+#[derive(Clone)]
+struct LineCallback(Arc<LineFn>);
+
+impl fmt::Debug for LineCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LineCallback")
+    }
+}
+
+// This is how often we check whether a command with a timeout has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl Exec {
+    /// Returns a builder for running the given executable, which is looked up
+    /// in the `PATH` unless it contains a path separator.
+    pub fn builder(exe: impl Into<String>) -> ExecBuilder {
+        ExecBuilder {
+            exec: Exec {
+                exe: exe.into(),
+                args: vec![],
+                env: HashMap::new(),
+                ok_exit_codes: vec![0],
+                ignore_stderr: vec![],
+                in_dir: None,
+                encoding: None,
+                limits: Limits::default(),
+                stdin: None,
+                merge_stderr: false,
+                timeout: None,
+                on_output_line: None,
+                capture_stdout: true,
+            },
         }
     }
 
-    let output = output_from_command(c, ok_exit_codes, exe, args)
-        .with_context(|| format!(r"Failed to execute command `{}`", exec_string(exe, args)))?;
+    /// Runs the command and returns its exit code and output. Bytes in the
+    /// output which are not valid in its encoding are replaced with U+FFFD
+    /// rather than causing an error.
+    ///
+    /// # Errors
+    ///
+    /// This returns an [`Error`] if the executable can't be found, the command
+    /// exits with a code that isn't ok, is killed by a signal, runs past its
+    /// timeout, or prints unexpected output to stderr. Other errors, like
+    /// failing to spawn the process, are returned as they are.
+    pub fn run(&self) -> Result<Output> {
+        let (output, resources) = self.output()?;
+        Ok(Output {
+            exit_code: output.status.code().unwrap_or(-1),
+            stdout: to_option_string(&output.stdout, self.encoding),
+            stderr: to_option_string(&output.stderr, self.encoding),
+            resources,
+        })
+    }
 
-    if log_enabled!(Debug) && !output.stdout.is_empty() {
-        debug!("Stdout was:\n{}", String::from_utf8(output.stdout.clone())?);
+    /// This is the same as [`Exec::run`], but it returns the command's stdout
+    /// as raw bytes instead of decoding it. This is for output that contains
+    /// file names, which are not always valid UTF-8.
+    ///
+    /// # Errors
+    ///
+    /// This returns the same errors as [`Exec::run`].
+    pub fn run_for_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.output()?.0.stdout)
     }
 
-    let code = output.status.code().unwrap_or(-1);
-    if !output.stderr.is_empty() {
-        let stderr = String::from_utf8(output.stderr.clone())?;
+    fn output(&self) -> Result<(process::Output, Option<Resources>)> {
+        if which(&self.exe).is_err() {
+            let path = match env::var("PATH") {
+                Ok(p) => p,
+                Err(e) => format!("<could not get PATH environment variable: {e}>"),
+            };
+            return Err(Error::ExecutableNotInPath {
+                exe: self.exe.clone(),
+                path,
+            }
+            .into());
+        }
+
+        let mut c = process::Command::new(&self.exe);
+        c.args(&self.args);
+
+        // We are canonicalizing this primarily for the benefit of our debugging
+        // output, because otherwise we might see the current dir as just `.`,
+        // which is not helpful.
+        let cwd = if let Some(d) = &self.in_dir {
+            fs::canonicalize(d)?
+        } else {
+            fs::canonicalize(env::current_dir()?)?
+        };
+        c.current_dir(cwd.clone());
+
+        c.envs(&self.env);
+        self.set_limits(&mut c);
+
         if log_enabled!(Debug) {
-            debug!("Stderr was:\n{stderr}");
+            debug!(
+                "Running command [{}] with cwd = {}",
+                self.exec_string(),
+                cwd.display()
+            );
+            for k in self.env.keys().sorted() {
+                debug!(r#"  with env: {k} = "{}""#, self.env[k]);
+            }
         }
 
-        let ok = if let Some(ignore) = ignore_stderr {
-            ignore.iter().any(|i| i.is_match(&stderr))
+        let (output, resources) = self
+            .output_from_command(c)
+            .with_context(|| format!(r"Failed to execute command `{}`", self.exec_string()))?;
+        if let Some(r) = resources {
+            debug!("Resources used by [{}]: {r}", self.exec_string());
+        }
+
+        if log_enabled!(Debug) && !output.stdout.is_empty() {
+            debug!("Stdout was:\n{}", decode(&output.stdout, self.encoding));
+        }
+
+        let code = output.status.code().unwrap_or(-1);
+        if !output.stderr.is_empty() {
+            let stderr = decode(&output.stderr, self.encoding);
+            if log_enabled!(Debug) {
+                debug!("Stderr was:\n{stderr}");
+            }
+
+            if !self.ignore_stderr.iter().any(|i| i.is_match(&stderr)) {
+                return Err(Error::UnexpectedStderr {
+                    cmd: self.exec_string(),
+                    code,
+                    stderr,
+                }
+                .into());
+            }
+        }
+
+        Ok((output, resources))
+    }
+
+    fn output_from_command(
+        &self,
+        c: process::Command,
+    ) -> Result<(process::Output, Option<Resources>)> {
+        let (output, resources) = self.output_and_resources(c)?;
+        if let Some(code) = output.status.code() {
+            let estr = self.exec_string();
+            debug!("Ran [{}] and got exit code of {}", estr, code);
+            if !self.ok_exit_codes.contains(&code) {
+                return Err(Error::UnexpectedExitCode {
+                    cmd: estr,
+                    code,
+                    stdout: decode(&output.stdout, self.encoding),
+                    stderr: decode(&output.stderr, self.encoding),
+                }
+                .into());
+            }
         } else {
-            false
+            let estr = self.exec_string();
+            if output.status.success() {
+                error!("Ran {} successfully but it had no exit code", estr);
+            } else {
+                let signal = signal_from_status(output.status);
+                debug!("Ran {} which exited because of signal {}", estr, signal);
+                return Err(Error::ProcessKilledBySignal { cmd: estr, signal }.into());
+            }
+        }
+
+        Ok((output, resources))
+    }
+
+    // This does the same thing as `Command::output`, except that it can kill
+    // the child when it runs past its timeout, and it can pass each line of
+    // output to a callback as soon as it's read. On Unix, it also reaps the
+    // child with `wait4` so that we can get its resource usage.
+    fn output_and_resources(
+        &self,
+        c: process::Command,
+    ) -> Result<(process::Output, Option<Resources>)> {
+        let (mut child, merged) = self.spawn(c)?;
+        let stdin_writer = write_stdin(&mut child, self.stdin.as_deref());
+
+        let stdout_pipe: Box<dyn Read + Send> = match merged {
+            Some(merged) => Box::new(merged),
+            None => Box::new(child.stdout.take().expect("stdout is piped")),
         };
-        if !ok {
-            return Err(Error::UnexpectedStderr {
-                cmd: exec_string(exe, args),
-                code,
-                stderr,
+        let stderr_pipe = child.stderr.take();
+
+        // We need to read stdout and stderr while we wait for the child, or
+        // else it can block forever when it fills up a pipe we're not reading
+        // from.
+        let (stdout, stderr, waited) = thread::scope(|s| -> Result<_> {
+            let stderr_reader =
+                stderr_pipe.map(|p| s.spawn(move || self.read_output(p, OutputStream::Stderr)));
+            let stdout_reader =
+                s.spawn(move || self.read_output(stdout_pipe, OutputStream::Stdout));
+            let waited = wait(&mut child, self.timeout);
+            if waited.is_err() {
+                // The readers won't finish until the child's pipes are closed.
+                let _ = child.kill();
+            }
+            let stdout = stdout_reader
+                .join()
+                .expect("the thread reading stdout does not panic")?;
+            let stderr = match stderr_reader {
+                Some(r) => r
+                    .join()
+                    .expect("the thread reading stderr does not panic")?,
+                None => vec![],
+            };
+            Ok((stdout, stderr, waited?))
+        })?;
+        if let Some(w) = stdin_writer {
+            w.join().expect("the thread writing stdin does not panic")?;
+        }
+
+        let (status, resources, timed_out) = waited;
+        if timed_out {
+            return Err(Error::TimedOut {
+                cmd: self.exec_string(),
+                timeout: self.timeout.unwrap_or_default(),
             }
             .into());
         }
+
+        Ok((
+            process::Output {
+                status,
+                stdout,
+                stderr,
+            },
+            resources,
+        ))
     }
 
-    Ok(Output {
-        exit_code: code,
-        stdout: to_option_string(&output.stdout),
-        stderr: to_option_string(&output.stderr),
-    })
-}
+    // With `merge_stderr`, the child's stdout and stderr are both the write
+    // end of a single pipe, and this returns the read end. The command is
+    // dropped once the child is spawned, since it holds copies of the write
+    // end, and the reader only sees EOF once every copy is closed.
+    fn spawn(
+        &self,
+        mut c: process::Command,
+    ) -> Result<(process::Child, Option<std::io::PipeReader>)> {
+        c.stdin(stdin_for(self.stdin.as_deref()));
+        let merged = if self.merge_stderr {
+            let (reader, writer) = std::io::pipe()?;
+            c.stdout(writer.try_clone()?).stderr(writer);
+            Some(reader)
+        } else {
+            c.stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped());
+            None
+        };
+        // When the child may be killed for running too long, it's made the
+        // leader of a new process group, so anything it starts can be killed
+        // along with it.
+        #[cfg(target_family = "unix")]
+        if self.timeout.is_some() {
+            c.process_group(0);
+        }
+        let child = c.spawn()?;
+        drop(c);
+        Ok((child, merged))
+    }
 
-fn output_from_command(
-    mut c: process::Command,
-    ok_exit_codes: &[i32],
-    exe: &str,
-    args: &[&str],
-) -> Result<process::Output> {
-    let output = c.output()?;
-    if let Some(code) = output.status.code() {
-        let estr = exec_string(exe, args);
-        debug!("Ran [{}] and got exit code of {}", estr, code);
-        if !ok_exit_codes.contains(&code) {
-            return Err(Error::UnexpectedExitCode {
-                cmd: estr,
-                code,
-                stdout: String::from_utf8(output.stdout)?,
-                stderr: String::from_utf8(output.stderr)?,
+    fn read_output(&self, mut pipe: impl Read, stream: OutputStream) -> std::io::Result<Vec<u8>> {
+        let capture = self.capture_stdout || stream == OutputStream::Stderr;
+        let mut output = vec![];
+        let Some(on_output_line) = &self.on_output_line else {
+            if capture {
+                pipe.read_to_end(&mut output)?;
+            } else {
+                std::io::copy(&mut pipe, &mut std::io::sink())?;
             }
-            .into());
+            return Ok(output);
+        };
+
+        let mut reader = BufReader::new(pipe);
+        loop {
+            if !capture {
+                output.clear();
+            }
+            let start = output.len();
+            if reader.read_until(b'\n', &mut output)? == 0 {
+                return Ok(output);
+            }
+            let line = decode(&output[start..], self.encoding);
+            (on_output_line.0)(stream, line.trim_end_matches(['\r', '\n']));
         }
-    } else {
-        let estr = exec_string(exe, args);
-        if output.status.success() {
-            error!("Ran {} successfully but it had no exit code", estr);
+    }
+
+    #[cfg(target_family = "unix")]
+    fn set_limits(&self, c: &mut process::Command) {
+        let limits = self.limits;
+        if limits.is_empty() {
+            return;
+        }
+        debug!(
+            "Running [{}] with resource limits: {limits:?}",
+            self.exec_string(),
+        );
+
+        // SAFETY: This runs in the child between `fork` and `exec`, so it can
+        // only call async-signal-safe functions. `getrlimit` and `setrlimit`
+        // are both plain syscalls that don't allocate or take any locks.
+        unsafe {
+            c.pre_exec(move || {
+                // Only the soft limit is changed, and it can't go past the
+                // hard limit, since an unprivileged process can't raise that.
+                let set_soft_limit = |resource, max: u64| {
+                    let mut limit = libc::rlimit {
+                        rlim_cur: 0,
+                        rlim_max: 0,
+                    };
+                    if libc::getrlimit(resource, &mut limit) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    limit.rlim_cur = libc::rlim_t::try_from(max)
+                        .unwrap_or(libc::RLIM_INFINITY)
+                        .min(limit.rlim_max);
+                    if libc::setrlimit(resource, &limit) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                };
+                if let Some(max) = limits.max_memory {
+                    set_soft_limit(libc::RLIMIT_AS, max)?;
+                }
+                if let Some(max) = limits.max_cpu_seconds {
+                    set_soft_limit(libc::RLIMIT_CPU, max)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(target_family = "windows")]
+    fn set_limits(&self, _: &mut process::Command) {
+        if !self.limits.is_empty() {
+            warn!(
+                "Resource limits are not supported on Windows, so they are ignored when running [{}]",
+                self.exec_string(),
+            );
+        }
+    }
+
+    fn exec_string(&self) -> String {
+        exec_string(&self.exe, &self.args)
+    }
+}
+
+impl ExecBuilder {
+    /// Adds one argument for the command. Arguments don't have to be valid
+    /// UTF-8, so paths can be passed as they are.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.exec.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Adds arguments for the command.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.exec
+            .args
+            .extend(args.into_iter().map(|a| a.as_ref().to_os_string()));
+        self
+    }
+
+    /// Sets an environment variable for the command. The command also
+    /// inherits the environment of the current process.
+    pub fn env(mut self, key: impl Into<String>, val: impl Into<String>) -> Self {
+        self.exec.env.insert(key.into(), val.into());
+        self
+    }
+
+    /// Sets several environment variables for the command.
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.exec.env.extend(
+            vars.into_iter()
+                .map(|(k, v)| (k.as_ref().to_string(), v.as_ref().to_string())),
+        );
+        self
+    }
+
+    /// Sets the exit codes that count as success. This defaults to just `0`.
+    pub fn ok_exit_codes(mut self, codes: &[i32]) -> Self {
+        self.exec.ok_exit_codes = codes.to_vec();
+        self
+    }
+
+    /// By default, any output on stderr is an error. If any of these regexes
+    /// match the command's stderr, it's allowed.
+    pub fn ignore_stderr(mut self, regexes: impl IntoIterator<Item = Regex>) -> Self {
+        self.exec.ignore_stderr.extend(regexes);
+        self
+    }
+
+    /// Allows the command to print anything to stderr.
+    pub fn ignore_all_stderr(self) -> Self {
+        self.ignore_stderr([Regex::new("").expect("the empty regex is valid")])
+    }
+
+    /// Sets the directory to run the command in. This defaults to the current
+    /// directory.
+    pub fn in_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.exec.in_dir = Some(dir.into());
+        self
+    }
+
+    /// Decodes the command's output using the given encoding instead of as
+    /// UTF-8.
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.exec.encoding = Some(encoding);
+        self
+    }
+
+    /// Sets limits on the resources the command can use.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.exec.limits = limits;
+        self
+    }
+
+    /// Writes these bytes to the command's stdin, which is otherwise empty.
+    pub fn stdin(mut self, stdin: impl Into<Vec<u8>>) -> Self {
+        self.exec.stdin = Some(stdin.into());
+        self
+    }
+
+    /// If this is true, the command's stdout and stderr are the same pipe, so
+    /// all of its output is returned as stdout, in the order it was written.
+    pub fn merge_stderr(mut self, merge_stderr: bool) -> Self {
+        self.exec.merge_stderr = merge_stderr;
+        self
+    }
+
+    /// Kills the command if it runs for longer than this, in which case
+    /// running it returns [`Error::TimedOut`]. On Unix, the command is run in
+    /// its own process group, and the whole group is killed. On Windows, only
+    /// the command's own process is killed, so any processes it started keep
+    /// running.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.exec.timeout = Some(timeout);
+        self
+    }
+
+    /// Calls the given function with each line of the command's output as
+    /// soon as it's read, without the line ending. The output is still
+    /// captured and returned as usual. Stdout and stderr are read at the same
+    /// time, so lines from the two streams can be passed in any order. With
+    /// [`ExecBuilder::merge_stderr`], every line comes from
+    /// [`OutputStream::Stdout`].
+    pub fn on_output_line(
+        mut self,
+        on_output_line: impl Fn(OutputStream, &str) + Send + Sync + 'static,
+    ) -> Self {
+        self.exec.on_output_line = Some(LineCallback(Arc::new(on_output_line)));
+        self
+    }
+
+    /// If this is false, the command's stdout is only passed to the
+    /// [`ExecBuilder::on_output_line`] callback rather than also being
+    /// captured, so output that's too big to hold in memory can be handled as
+    /// it's read. The stdout in the [`Output`] and in any [`Error`] is then
+    /// empty. Stderr is always captured, since it's checked against
+    /// [`ExecBuilder::ignore_stderr`].
+    pub fn capture_stdout(mut self, capture_stdout: bool) -> Self {
+        self.exec.capture_stdout = capture_stdout;
+        self
+    }
+
+    /// Returns the [`Exec`], which can be run any number of times.
+    pub fn build(self) -> Exec {
+        self.exec
+    }
+}
+
+// The child is reaped with `wait4` so that we can get its resource usage.
+// With a timeout, this polls until the child exits or the timeout passes, in
+// which case its process group is killed.
+#[cfg(target_family = "unix")]
+fn wait(
+    child: &mut process::Child,
+    timeout: Option<Duration>,
+) -> Result<(process::ExitStatus, Option<Resources>, bool)> {
+    let pid = libc::pid_t::try_from(child.id())?;
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut timed_out = false;
+    let mut status = 0;
+    // SAFETY: All-zero bytes is a valid value for this C struct.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        let options = if deadline.is_some() && !timed_out {
+            libc::WNOHANG
         } else {
-            let signal = signal_from_status(output.status);
-            debug!("Ran {} which exited because of signal {}", estr, signal);
-            return Err(Error::ProcessKilledBySignal { cmd: estr, signal }.into());
+            0
+        };
+        // SAFETY: We pass pointers to values that live until the call
+        // returns, and the pid is our child, which hasn't been reaped yet.
+        match unsafe { libc::wait4(pid, &mut status, options, &mut rusage) } {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+            0 => {
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    // SAFETY: The child is the leader of its own process
+                    // group, and it hasn't been reaped, so the group still
+                    // exists.
+                    unsafe { libc::kill(-pid, libc::SIGKILL) };
+                    timed_out = true;
+                } else {
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+            _ => break,
+        }
+    }
+
+    Ok((
+        process::ExitStatus::from_raw(status),
+        Some(Resources::new(
+            max_rss_bytes(rusage.ru_maxrss),
+            timeval_duration(rusage.ru_utime) + timeval_duration(rusage.ru_stime),
+        )),
+        timed_out,
+    ))
+}
+
+// Unlike on Unix, there's no process group to kill here, so a command that
+// times out is killed but anything it started is not. Doing that would need a
+// Job Object, as would getting the resources the process used, so this never
+// returns any resources.
+#[cfg(target_family = "windows")]
+fn wait(
+    child: &mut process::Child,
+    timeout: Option<Duration>,
+) -> Result<(process::ExitStatus, Option<Resources>, bool)> {
+    let Some(timeout) = timeout else {
+        return Ok((child.wait()?, None, false));
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, None, false));
         }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            return Ok((child.wait()?, None, true));
+        }
+        thread::sleep(POLL_INTERVAL);
     }
+}
 
-    Ok(output)
+fn stdin_for(stdin: Option<&[u8]>) -> process::Stdio {
+    if stdin.is_some() {
+        process::Stdio::piped()
+    } else {
+        process::Stdio::null()
+    }
 }
 
-fn exec_string(exe: &str, args: &[&str]) -> String {
+// This writes from its own thread, since the child may not read all of its
+// stdin before it starts writing to stdout. A child that exits without
+// reading everything closes the pipe, which is not an error.
+fn write_stdin(
+    child: &mut process::Child,
+    stdin: Option<&[u8]>,
+) -> Option<thread::JoinHandle<std::io::Result<()>>> {
+    use std::io::Write;
+
+    let bytes = stdin?.to_vec();
+    let mut pipe = child.stdin.take().expect("stdin is piped");
+    Some(thread::spawn(move || match pipe.write_all(&bytes) {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        r => r,
+    }))
+}
+
+// macOS reports this in bytes, but Linux and the BSDs use kilobytes.
+#[cfg(target_os = "macos")]
+fn max_rss_bytes(max_rss: libc::c_long) -> u64 {
+    u64::try_from(max_rss).unwrap_or(0)
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn max_rss_bytes(max_rss: libc::c_long) -> u64 {
+    u64::try_from(max_rss).unwrap_or(0) * 1024
+}
+
+#[cfg(target_family = "unix")]
+fn timeval_duration(tv: libc::timeval) -> Duration {
+    Duration::from_secs(u64::try_from(tv.tv_sec).unwrap_or(0))
+        + Duration::from_micros(u64::try_from(tv.tv_usec).unwrap_or(0))
+}
+
+fn exec_string<S: AsRef<OsStr>>(exe: &str, args: &[S]) -> String {
     let mut estr = exe.to_string();
     if !args.is_empty() {
         estr.push(' ');
-        estr.push_str(args.join(" ").as_str());
+        estr.push_str(&args.iter().map(|a| a.as_ref().to_string_lossy()).join(" "));
     }
     estr
 }
 
-fn to_option_string(v: &[u8]) -> Option<String> {
+fn to_option_string(v: &[u8], encoding: Option<&'static Encoding>) -> Option<String> {
     if v.is_empty() {
         None
     } else {
-        Some(String::from_utf8_lossy(v).into_owned())
+        Some(decode(v, encoding))
+    }
+}
+
+fn decode(v: &[u8], encoding: Option<&'static Encoding>) -> String {
+    match encoding {
+        Some(e) => e.decode_without_bom_handling(v).0.into_owned(),
+        None => String::from_utf8_lossy(v).into_owned(),
     }
 }
 
@@ -212,16 +880,17 @@ fn signal_from_status(_: process::ExitStatus) -> i32 {
 
 #[cfg(test)]
 mod tests {
-    use super::Error;
+    use super::{Error, Exec, Limits, OutputStream};
     use anyhow::{format_err, Result};
     use pretty_assertions::assert_eq;
     use regex::Regex;
     // Anything that does pushd must be run serially or else chaos ensues.
     use serial_test::{parallel, serial};
     use std::{
-        collections::HashMap,
         env, fs,
         path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
     };
     use tempfile::tempdir;
 
@@ -229,7 +898,7 @@ mod tests {
     #[parallel]
     fn exec_string() {
         assert_eq!(
-            super::exec_string("foo", &[]),
+            super::exec_string::<&str>("foo", &[]),
             String::from("foo"),
             "command without args",
         );
@@ -245,20 +914,303 @@ mod tests {
         );
     }
 
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn non_utf8_args() -> Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let arg = OsStr::from_bytes(b"caf\xe9");
+        let stdout = Exec::builder("echo").arg(arg).build().run_for_bytes()?;
+        assert_eq!(stdout, b"caf\xe9\n");
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn run_exit_0() -> Result<()> {
-        let res = super::run("echo", &["foo"], &HashMap::new(), &[0], None, None)?;
+        let res = Exec::builder("echo").arg("foo").build().run()?;
         assert_eq!(res.exit_code, 0, "process exits 0");
 
         Ok(())
     }
 
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_non_utf8_output() -> Result<()> {
+        // This is "café" encoded as Windows-1252.
+        let args = ["-c", r"printf 'caf\351\n'"];
+
+        let res = Exec::builder("sh").args(args).build().run()?;
+        assert_eq!(
+            res.stdout,
+            Some(String::from("caf\u{FFFD}\n")),
+            "invalid UTF-8 is replaced",
+        );
+
+        let res = Exec::builder("sh")
+            .args(args)
+            .encoding(encoding_rs::WINDOWS_1252)
+            .build()
+            .run()?;
+        assert_eq!(
+            res.stdout,
+            Some(String::from("café\n")),
+            "output is decoded using the given encoding",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_reports_resources() -> Result<()> {
+        let res = Exec::builder("echo").arg("foo").build().run()?;
+        assert_eq!(
+            res.stdout,
+            Some(String::from("foo\n")),
+            "output is captured"
+        );
+        let resources = res.resources.expect("resources are reported on Unix");
+        assert!(resources.max_rss > 0, "peak RSS is reported");
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_stdin() -> Result<()> {
+        let run = |cmd: &str, stdin: &[u8]| {
+            Exec::builder("sh")
+                .args(["-c", cmd])
+                .stdin(stdin)
+                .build()
+                .run()
+        };
+
+        let res = run("tr a-z A-Z", b"foo\nbar\n")?;
+        assert_eq!(
+            res.stdout,
+            Some(String::from("FOO\nBAR\n")),
+            "stdin is given to the command",
+        );
+
+        let res = run("exit 0", &[b'x'; 1024 * 1024])?;
+        assert_eq!(
+            res.exit_code, 0,
+            "a command that doesn't read its stdin still works",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_merged_stderr() -> Result<()> {
+        let run = |merge_stderr: bool| {
+            Exec::builder("sh")
+                .args(["-c", "echo one; echo two >&2; echo three; exit 1"])
+                .ok_exit_codes(&[0, 1])
+                .ignore_all_stderr()
+                .merge_stderr(merge_stderr)
+                .build()
+                .run()
+        };
+
+        let res = run(true)?;
+        assert_eq!(
+            (res.exit_code, res.stdout, res.stderr),
+            (1, Some(String::from("one\ntwo\nthree\n")), None),
+            "stderr is interleaved with stdout",
+        );
+
+        let res = run(false)?;
+        assert_eq!(
+            (res.stdout, res.stderr),
+            (
+                Some(String::from("one\nthree\n")),
+                Some(String::from("two\n"))
+            ),
+            "stderr is captured separately by default",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_timeout() -> Result<()> {
+        let res = Exec::builder("sh")
+            .args(["-c", "echo fast"])
+            .timeout(Duration::from_secs(60))
+            .build()
+            .run()?;
+        assert_eq!(
+            res.stdout,
+            Some(String::from("fast\n")),
+            "a command that finishes in time is not killed",
+        );
+
+        // The sleep is a child of the shell, so this also checks that
+        // everything the command started is killed.
+        let start = Instant::now();
+        let res = Exec::builder("sh")
+            .args(["-c", "sleep 30; echo slow"])
+            .timeout(Duration::from_millis(200))
+            .build()
+            .run();
+        let err = error_from_run(res)?;
+        assert!(
+            matches!(err, Error::TimedOut { .. }),
+            "process is killed when it runs too long: {err:?}",
+        );
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "run returns soon after the timeout",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_on_output_line() -> Result<()> {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let res = Exec::builder("sh")
+            .args(["-c", "echo one; echo two >&2; printf three"])
+            .ignore_all_stderr()
+            .on_output_line({
+                let lines = Arc::clone(&lines);
+                move |stream, line| lines.lock().unwrap().push((stream, line.to_string()))
+            })
+            .build()
+            .run()?;
+        assert_eq!(
+            res.stdout,
+            Some(String::from("one\nthree")),
+            "output is still captured",
+        );
+
+        let mut lines = lines.lock().unwrap().clone();
+        lines.sort_by_key(|(stream, _)| *stream == OutputStream::Stderr);
+        assert_eq!(
+            lines,
+            vec![
+                (OutputStream::Stdout, String::from("one")),
+                (OutputStream::Stdout, String::from("three")),
+                (OutputStream::Stderr, String::from("two")),
+            ],
+            "each line is passed to the callback",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_without_capturing_stdout() -> Result<()> {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let res = Exec::builder("sh")
+            .args(["-c", "echo one; echo two >&2; echo three"])
+            .ignore_all_stderr()
+            .capture_stdout(false)
+            .on_output_line({
+                let lines = Arc::clone(&lines);
+                move |stream, line| {
+                    if stream == OutputStream::Stdout {
+                        lines.lock().unwrap().push(line.to_string());
+                    }
+                }
+            })
+            .build()
+            .run()?;
+        assert_eq!(res.stdout, None, "stdout is not captured");
+        assert_eq!(
+            res.stderr,
+            Some(String::from("two\n")),
+            "stderr is captured"
+        );
+        assert_eq!(*lines.lock().unwrap(), vec!["one", "three"]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_with_limits() -> Result<()> {
+        let limits = Limits {
+            max_memory: Some(4 * 1024 * 1024 * 1024),
+            max_cpu_seconds: Some(300),
+        };
+        let res = Exec::builder("sh")
+            .args(["-c", "ulimit -t; ulimit -v"])
+            .limits(limits)
+            .build()
+            .run()?;
+        assert_eq!(
+            res.stdout,
+            Some(String::from("300\n4194304\n")),
+            "limits are set in the child process",
+        );
+
+        let hard_limits = |limits| {
+            Exec::builder("sh")
+                .args(["-c", "ulimit -H -t; ulimit -H -v"])
+                .limits(limits)
+                .build()
+                .run()
+                .map(|res| res.stdout)
+        };
+        assert_eq!(
+            hard_limits(limits)?,
+            hard_limits(Limits::new())?,
+            "hard limits are not changed",
+        );
+
+        let res = Exec::builder("sh")
+            .args(["-c", "while :; do :; done"])
+            .limits(Limits {
+                max_memory: None,
+                max_cpu_seconds: Some(1),
+            })
+            .build()
+            .run();
+        let err = error_from_run(res)?;
+        assert!(
+            matches!(err, Error::ProcessKilledBySignal { .. }),
+            "process is killed when it uses too much CPU: {err:?}",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[parallel]
+    #[cfg(target_family = "unix")]
+    fn run_for_bytes() -> Result<()> {
+        let stdout = Exec::builder("printf")
+            .arg(r"a\0\377\0")
+            .build()
+            .run_for_bytes()?;
+        assert_eq!(stdout, b"a\0\xff\0", "output is not decoded");
+
+        Ok(())
+    }
+
     #[test]
     #[parallel]
     fn run_exit_0_with_unexpected_stderr() -> Result<()> {
         let args = ["-c", "echo 'some stderr output' 1>&2"];
-        let res = super::run("sh", &args, &HashMap::new(), &[0], None, None);
+        let res = Exec::builder("sh").args(args).build().run();
         assert!(res.is_err(), "run returned Err");
         match error_from_run(res)? {
             Error::UnexpectedStderr {
@@ -278,14 +1230,11 @@ mod tests {
     #[parallel]
     fn run_exit_0_with_matching_ignore_stderr() -> Result<()> {
         let args = ["-c", "echo 'some stderr output' 1>&2"];
-        let res = super::run(
-            "sh",
-            &args,
-            &HashMap::new(),
-            &[0],
-            Some(&[Regex::new("some.+output").unwrap()]),
-            None,
-        )?;
+        let res = Exec::builder("sh")
+            .args(args)
+            .ignore_stderr([Regex::new("some.+output").unwrap()])
+            .build()
+            .run()?;
         assert_eq!(res.exit_code, 0, "process exits 0");
         assert!(res.stdout.is_none(), "process has no stdout output");
         assert_eq!(
@@ -300,14 +1249,11 @@ mod tests {
     #[parallel]
     fn run_exit_0_with_non_matching_ignore_stderr() -> Result<()> {
         let args = ["-c", "echo 'some stderr output' 1>&2"];
-        let res = super::run(
-            "sh",
-            &args,
-            &HashMap::new(),
-            &[0],
-            Some(&[Regex::new("some.+output is ok").unwrap()]),
-            None,
-        );
+        let res = Exec::builder("sh")
+            .args(args)
+            .ignore_stderr([Regex::new("some.+output is ok").unwrap()])
+            .build()
+            .run();
         assert!(res.is_err(), "run returned Err");
         match error_from_run(res)? {
             Error::UnexpectedStderr {
@@ -327,17 +1273,14 @@ mod tests {
     #[parallel]
     fn run_exit_0_with_multiple_ignore_stderr() -> Result<()> {
         let args = ["-c", "echo 'some stderr output' 1>&2"];
-        let res = super::run(
-            "sh",
-            &args,
-            &HashMap::new(),
-            &[0],
-            Some(&[
+        let res = Exec::builder("sh")
+            .args(args)
+            .ignore_stderr([
                 Regex::new("will not match").unwrap(),
                 Regex::new("some.+output is ok").unwrap(),
-            ]),
-            None,
-        );
+            ])
+            .build()
+            .run();
         assert!(res.is_err(), "run returned Err");
         match error_from_run(res)? {
             Error::UnexpectedStderr {
@@ -357,16 +1300,11 @@ mod tests {
     #[parallel]
     fn run_with_env() -> Result<()> {
         let env_key = "PRECIOUS_ENV_TEST";
-        let mut env = HashMap::new();
-        env.insert(String::from(env_key), String::from("foo"));
-        let res = super::run(
-            "sh",
-            &["-c", &format!("echo ${env_key}")],
-            &env,
-            &[0],
-            None,
-            None,
-        )?;
+        let res = Exec::builder("sh")
+            .args(["-c", &format!("echo ${env_key}")])
+            .env(env_key, "foo")
+            .build()
+            .run()?;
         assert_eq!(res.exit_code, 0, "process exits 0");
         assert!(res.stdout.is_some(), "process has stdout output");
         assert_eq!(
@@ -389,7 +1327,7 @@ mod tests {
     #[test]
     #[parallel]
     fn run_exit_32() -> Result<()> {
-        let res = super::run("sh", &["-c", "exit 32"], &HashMap::new(), &[0], None, None);
+        let res = Exec::builder("sh").args(["-c", "exit 32"]).build().run();
         assert!(res.is_err(), "process exits non-zero");
         match error_from_run(res)? {
             Error::UnexpectedExitCode {
@@ -411,14 +1349,10 @@ mod tests {
     #[test]
     #[parallel]
     fn run_exit_32_with_stdout() -> Result<()> {
-        let res = super::run(
-            "sh",
-            &["-c", r#"echo "STDOUT" && exit 32"#],
-            &HashMap::new(),
-            &[0],
-            None,
-            None,
-        );
+        let res = Exec::builder("sh")
+            .args(["-c", r#"echo "STDOUT" && exit 32"#])
+            .build()
+            .run();
         assert!(res.is_err(), "process exits non-zero");
         let e = error_from_run(res)?;
         let expect = r#"Got unexpected exit code 32 from `sh -c echo "STDOUT" && exit 32`.
@@ -449,14 +1383,10 @@ Stderr was empty.
     #[test]
     #[parallel]
     fn run_exit_32_with_stderr() -> Result<()> {
-        let res = super::run(
-            "sh",
-            &["-c", r#"echo "STDERR" 1>&2 && exit 32"#],
-            &HashMap::new(),
-            &[0],
-            None,
-            None,
-        );
+        let res = Exec::builder("sh")
+            .args(["-c", r#"echo "STDERR" 1>&2 && exit 32"#])
+            .build()
+            .run();
         assert!(res.is_err(), "process exits non-zero");
         let e = error_from_run(res)?;
         let expect = r#"Got unexpected exit code 32 from `sh -c echo "STDERR" 1>&2 && exit 32`.
@@ -491,14 +1421,10 @@ STDERR
     #[test]
     #[parallel]
     fn run_exit_32_with_stdout_and_stderr() -> Result<()> {
-        let res = super::run(
-            "sh",
-            &["-c", r#"echo "STDOUT" && echo "STDERR" 1>&2 && exit 32"#],
-            &HashMap::new(),
-            &[0],
-            None,
-            None,
-        );
+        let res = Exec::builder("sh")
+            .args(["-c", r#"echo "STDOUT" && echo "STDERR" 1>&2 && exit 32"#])
+            .build()
+            .run();
         assert!(res.is_err(), "process exits non-zero");
 
         let e = error_from_run(res)?;
@@ -547,7 +1473,7 @@ STDERR
         let td = tempdir()?;
         let td_path = maybe_canonicalize(td.path())?;
 
-        let res = super::run("pwd", &[], &HashMap::new(), &[0], None, Some(&td_path))?;
+        let res = Exec::builder("pwd").in_dir(&td_path).build().run()?;
         assert_eq!(res.exit_code, 0, "process exits 0");
         assert!(res.stdout.is_some(), "process produced stdout output");
 
@@ -567,7 +1493,7 @@ STDERR
     fn executable_does_not_exist() {
         let exe = "I hope this binary does not exist on any system!";
         let args = ["--arg", "42"];
-        let res = super::run(exe, &args, &HashMap::new(), &[0], None, None);
+        let res = Exec::builder(exe).args(args).build().run();
         assert!(res.is_err());
         if let Err(e) = res {
             assert!(e.to_string().contains(
@@ -1 +1,18 @@
+//! Helpers for running external commands, used by precious to run linters,
+//! tidiers, and VCS commands. The [`exec::Exec`] API is stable and can be
+//! used by other programs.
+//!
+//! ```no_run
+//! use precious_helpers::exec::Exec;
+//! use std::time::Duration;
+//!
+//! let output = Exec::builder("git")
+//!     .args(["status", "--porcelain"])
+//!     .in_dir("/path/to/repo")
+//!     .timeout(Duration::from_secs(30))
+//!     .build()
+//!     .run()?;
+//! println!("{}", output.stdout.unwrap_or_default());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
 pub mod exec;
@@ -1,13 +1,11 @@
 use crate::shared::{compile_precious, precious_path};
 use anyhow::Result;
-use precious_helpers::exec::{self, Output};
+use precious_helpers::exec::{Exec, Output};
 use pushd::Pushd;
-use regex::Regex;
 use serial_test::serial;
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::PermissionsExt;
 use std::{
-    collections::HashMap,
     fs::{self, File},
     path::Path,
 };
@@ -88,7 +86,7 @@ fn init_does_not_overwrite_existing_file() -> Result<()> {
     File::create("precious.toml")?;
     let output = init_with_components(&["rust"], None)?;
 
-    assert_eq!(output.exit_code, 42);
+    assert_eq!(output.exit_code, 3);
     assert!(output.stderr.is_some());
     assert!(output
         .stderr
@@ -107,7 +105,7 @@ fn init_does_not_overwrite_existing_file_with_nonstandard_name() -> Result<()> {
     File::create("my-precious.toml")?;
     let output = init_with_components(&["rust"], Some("my-precious.toml"))?;
 
-    assert_eq!(output.exit_code, 42);
+    assert_eq!(output.exit_code, 3);
     assert!(output.stderr.is_some());
     assert!(output
         .stderr
@@ -154,7 +152,6 @@ fn chdir_to_tempdir() -> Result<(TempDir, Pushd)> {
 
 fn init_with_components(components: &[&str], init_path: Option<&str>) -> Result<Output> {
     let precious = precious_path()?;
-    let env = HashMap::new();
     let mut args = vec!["config", "init"];
     for c in components {
         args.push("--component");
@@ -164,27 +161,22 @@ fn init_with_components(components: &[&str], init_path: Option<&str>) -> Result<
         args.push("--path");
         args.push(p);
     }
-    exec::run(
-        &precious,
-        &args,
-        &env,
-        &[0, 42],
-        Some(&[Regex::new(".*")?]),
-        None,
-    )
+    Exec::builder(&precious)
+        .args(&args)
+        .ok_exit_codes(&[0, 3])
+        .ignore_all_stderr()
+        .build()
+        .run()
 }
 
 fn init_with_auto() -> Result<Output> {
     let precious = precious_path()?;
-    let env = HashMap::new();
-    exec::run(
-        &precious,
-        &["config", "init", "--auto"],
-        &env,
-        &[0, 42],
-        Some(&[Regex::new(".*")?]),
-        None,
-    )
+    Exec::builder(&precious)
+        .args(["config", "init", "--auto"])
+        .ok_exit_codes(&[0, 3])
+        .ignore_all_stderr()
+        .build()
+        .run()
 }
 
 fn assert_file_exists(path: impl AsRef<Path>) -> Result<()> {
@@ -1,7 +1,7 @@
 use crate::shared::{compile_precious, precious_path};
 use anyhow::{Context, Result};
 use itertools::Itertools;
-use precious_helpers::exec;
+use precious_helpers::exec::Exec;
 use precious_testhelper::TestHelper;
 use pretty_assertions::{assert_eq, assert_str_eq};
 use regex::{Captures, Regex};
@@ -50,23 +50,16 @@ fn all() -> Result<()> {
     let helper = set_up_for_tests()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
-    exec::run(
-        &precious,
-        &["lint", "--all"],
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
-    exec::run(
-        &precious,
-        &["tidy", "--all"],
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
+    Exec::builder(&precious)
+        .args(["lint", "--all"])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
+    Exec::builder(&precious)
+        .args(["tidy", "--all"])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -78,23 +71,16 @@ fn git() -> Result<()> {
     helper.modify_files()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
-    exec::run(
-        &precious,
-        &["lint", "--git"],
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
-    exec::run(
-        &precious,
-        &["tidy", "--git"],
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
+    Exec::builder(&precious)
+        .args(["lint", "--git"])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
+    Exec::builder(&precious)
+        .args(["tidy", "--git"])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -107,23 +93,16 @@ fn staged() -> Result<()> {
     helper.stage_all()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
-    exec::run(
-        &precious,
-        &["lint", "--staged"],
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
-    exec::run(
-        &precious,
-        &["tidy", "--staged"],
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
+    Exec::builder(&precious)
+        .args(["lint", "--staged"])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
+    Exec::builder(&precious)
+        .args(["tidy", "--staged"])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -135,28 +114,21 @@ fn cli_paths() -> Result<()> {
     let files = helper.modify_files()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
     let mut args = vec!["lint"];
     args.append(&mut files.iter().map(|p| p.to_str().unwrap()).collect());
-    exec::run(
-        &precious,
-        &args,
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
+    Exec::builder(&precious)
+        .args(&args)
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
 
     let mut args = vec!["tidy"];
     args.append(&mut files.iter().map(|p| p.to_str().unwrap()).collect());
-    exec::run(
-        &precious,
-        &args,
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
+    Exec::builder(&precious)
+        .args(&args)
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -167,13 +139,20 @@ fn all_in_subdir() -> Result<()> {
     let helper = set_up_for_tests()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
 
     let mut cwd = helper.precious_root();
     cwd.push("src");
 
-    exec::run(&precious, &["lint", "--all"], &env, &[0], None, Some(&cwd))?;
-    exec::run(&precious, &["tidy", "--all"], &env, &[0], None, Some(&cwd))?;
+    Exec::builder(&precious)
+        .args(["lint", "--all"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
+    Exec::builder(&precious)
+        .args(["tidy", "--all"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -185,13 +164,20 @@ fn git_in_subdir() -> Result<()> {
     helper.modify_files()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
 
     let mut cwd = helper.precious_root();
     cwd.push("src");
 
-    exec::run(&precious, &["lint", "--git"], &env, &[0], None, Some(&cwd))?;
-    exec::run(&precious, &["tidy", "--git"], &env, &[0], None, Some(&cwd))?;
+    Exec::builder(&precious)
+        .args(["lint", "--git"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
+    Exec::builder(&precious)
+        .args(["tidy", "--git"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -204,27 +190,20 @@ fn staged_in_subdir() -> Result<()> {
     helper.stage_all()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
 
     let mut cwd = helper.precious_root();
     cwd.push("src");
 
-    exec::run(
-        &precious,
-        &["lint", "--staged"],
-        &env,
-        &[0],
-        None,
-        Some(&cwd),
-    )?;
-    exec::run(
-        &precious,
-        &["tidy", "--staged"],
-        &env,
-        &[0],
-        None,
-        Some(&cwd),
-    )?;
+    Exec::builder(&precious)
+        .args(["lint", "--staged"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
+    Exec::builder(&precious)
+        .args(["tidy", "--staged"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -236,27 +215,20 @@ fn cli_paths_in_subdir() -> Result<()> {
     helper.modify_files()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
 
     let mut cwd = helper.precious_root();
     cwd.push("src");
 
-    exec::run(
-        &precious,
-        &["lint", "module.rs", "../README.md", "../tests/data/foo.txt"],
-        &env,
-        &[0],
-        None,
-        Some(&cwd),
-    )?;
-    exec::run(
-        &precious,
-        &["tidy", "module.rs", "../README.md", "../tests/data/foo.txt"],
-        &env,
-        &[0],
-        None,
-        Some(&cwd),
-    )?;
+    Exec::builder(&precious)
+        .args(["lint", "module.rs", "../README.md", "../tests/data/foo.txt"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
+    Exec::builder(&precious)
+        .args(["tidy", "module.rs", "../README.md", "../tests/data/foo.txt"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -273,29 +245,23 @@ fn foo() -> u8   {
     helper.write_file("src/module.rs", content)?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
 
     let mut cwd = helper.precious_root();
     cwd.push("src");
 
     // This succeeds because we're not checking with rustfmt.
-    exec::run(
-        &precious,
-        &["lint", "--command", "true", "module.rs"],
-        &env,
-        &[0],
-        None,
-        Some(&cwd),
-    )?;
+    Exec::builder(&precious)
+        .args(["lint", "--command", "true", "module.rs"])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
     // This fails now that we check with rustfmt.
-    exec::run(
-        &precious,
-        &["lint", "module.rs"],
-        &env,
-        &[1],
-        None,
-        Some(&cwd),
-    )?;
+    Exec::builder(&precious)
+        .args(["lint", "module.rs"])
+        .ok_exit_codes(&[1])
+        .in_dir(&cwd)
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -309,59 +275,65 @@ fn exit_codes() -> Result<()> {
     let match_all_re = Regex::new(".*")?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
-    let out = exec::run(
-        &precious,
-        &["lint", "--all"],
-        &env,
-        &all_codes,
-        Some(&[match_all_re.clone()]),
-        Some(&helper.precious_root()),
-    )?;
+    let out = Exec::builder(&precious)
+        .args(["lint", "--all"])
+        .ok_exit_codes(&all_codes)
+        .ignore_stderr([match_all_re.clone()])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
     assert_eq!(out.exit_code, 0);
 
-    helper.write_file("src/good.rs", "this is not valid rust")?;
+    helper.write_file("src/good.rs", "fn   good_func( ) {}\n")?;
 
-    let out = exec::run(
-        &precious,
-        &["lint", "--all"],
-        &env,
-        &all_codes,
-        Some(&[match_all_re.clone()]),
-        Some(&helper.precious_root()),
-    )?;
+    let out = Exec::builder(&precious)
+        .args(["lint", "--all"])
+        .ok_exit_codes(&all_codes)
+        .ignore_stderr([match_all_re.clone()])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
     assert_eq!(out.exit_code, 1);
 
-    let out = exec::run(
-        &precious,
-        &["foo", "--all"],
-        &env,
-        &all_codes,
-        Some(&[match_all_re.clone()]),
-        Some(&helper.precious_root()),
-    )?;
+    // rustfmt prints the parse error to stderr, which is not expected.
+    helper.write_file("src/good.rs", "this is not valid rust")?;
+
+    let out = Exec::builder(&precious)
+        .args(["lint", "--all"])
+        .ok_exit_codes(&all_codes)
+        .ignore_stderr([match_all_re.clone()])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
+    assert_eq!(out.exit_code, 3);
+
+    let out = Exec::builder(&precious)
+        .args(["foo", "--all"])
+        .ok_exit_codes(&all_codes)
+        .ignore_stderr([match_all_re.clone()])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
     assert_eq!(out.exit_code, 2);
 
-    let out = exec::run(
-        &precious,
-        &["lint", "--foo"],
-        &env,
-        &all_codes,
-        Some(&[match_all_re.clone()]),
-        Some(&helper.precious_root()),
-    )?;
+    let out = Exec::builder(&precious)
+        .args(["lint", "--foo"])
+        .ok_exit_codes(&all_codes)
+        .ignore_stderr([match_all_re.clone()])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
     assert_eq!(out.exit_code, 2);
 
     helper.write_file("precious.toml", "this is not valid config")?;
-    let out = exec::run(
-        &precious,
-        &["lint", "--all"],
-        &env,
-        &all_codes,
-        Some(&[match_all_re.clone()]),
-        Some(&helper.precious_root()),
-    )?;
-    assert_eq!(out.exit_code, 42);
+    let out = Exec::builder(&precious)
+        .args(["lint", "--all"])
+        .ok_exit_codes(&all_codes)
+        .ignore_stderr([match_all_re.clone()])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
+    assert_eq!(out.exit_code, 2);
 
     let config_missing_key = r#"
 [commands.rustfmt]
@@ -372,15 +344,14 @@ ok-exit-codes = 0
 lint-failure-exit-codes = 1
 "#;
     helper.write_file("precious.toml", config_missing_key)?;
-    let out = exec::run(
-        &precious,
-        &["lint", "--all"],
-        &env,
-        &all_codes,
-        Some(&[match_all_re.clone()]),
-        Some(&helper.precious_root()),
-    )?;
-    assert_eq!(out.exit_code, 42);
+    let out = Exec::builder(&precious)
+        .args(["lint", "--all"])
+        .ok_exit_codes(&all_codes)
+        .ignore_stderr([match_all_re.clone()])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
+    assert_eq!(out.exit_code, 2);
 
     Ok(())
 }
@@ -431,15 +402,11 @@ fn fix_is_tidy() -> Result<()> {
     let helper = set_up_for_tests()?;
 
     let precious = precious_path()?;
-    let env = HashMap::new();
-    exec::run(
-        &precious,
-        &["fix", "--all"],
-        &env,
-        &[0],
-        None,
-        Some(&helper.precious_root()),
-    )?;
+    Exec::builder(&precious)
+        .args(["fix", "--all"])
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
 
     Ok(())
 }
@@ -558,17 +525,16 @@ ok-exit-codes = 0
             helper.precious_root().to_string_lossy().to_string(),
         ),
     ]);
-    let _result = exec::run(
-        &precious,
-        &[
+    let _result = Exec::builder(&precious)
+        .args([
             //"--debug",
             "lint", "--all",
-        ],
-        &env,
-        &[0],
-        None, // Some(&[Regex::new(".*")?]),
-        Some(&helper.precious_root()),
-    )?;
+        ])
+        .envs(&env)
+        // .ignore_all_stderr()
+        .in_dir(helper.precious_root())
+        .build()
+        .run()?;
     // println!("STDERR");
     // println!("{}", _result.stderr.as_deref().unwrap_or(""));
 
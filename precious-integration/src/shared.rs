@@ -1,19 +1,16 @@
 use anyhow::Result;
-use precious_helpers::exec;
+use precious_helpers::exec::Exec;
 use regex::Regex;
-use std::{collections::HashMap, env, fs, path::PathBuf};
+use std::{env, fs, path::PathBuf};
 
 pub(crate) fn compile_precious() -> Result<()> {
     let cargo_build_re = Regex::new("Finished.+dev.+target")?;
-    let env = HashMap::new();
-    exec::run(
-        "cargo",
-        &["build", "--package", "precious"],
-        &env,
-        &[0],
-        Some(&[cargo_build_re]),
-        Some(&PathBuf::from("..")),
-    )?;
+    Exec::builder("cargo")
+        .args(["build", "--package", "precious"])
+        .ignore_stderr([cargo_build_re])
+        .in_dir("..")
+        .build()
+        .run()?;
     Ok(())
 }
 
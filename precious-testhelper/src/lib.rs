@@ -1,11 +1,10 @@
 use anyhow::{Context, Result};
 use log::debug;
 use once_cell::sync::{Lazy, OnceCell};
-use precious_helpers::exec;
+use precious_helpers::exec::Exec;
 use pushd::Pushd;
 use regex::Regex;
 use std::{
-    collections::HashMap,
     env,
     ffi::OsString,
     fs,
@@ -184,14 +183,11 @@ generated.*
             args.push("-b");
         }
         args.push(branch);
-        exec::run(
-            "git",
-            &args,
-            &HashMap::new(),
-            &[0],
-            None,
-            Some(&self.git_root),
-        )?;
+        Exec::builder("git")
+            .args(&args)
+            .in_dir(&self.git_root)
+            .build()
+            .run()?;
         Ok(())
     }
 
@@ -201,15 +197,14 @@ generated.*
             expect_codes.push(1);
         }
 
-        exec::run(
-            "git",
-            &["merge", "--quiet", "--no-ff", "--no-commit", "master"],
-            &HashMap::new(),
-            &expect_codes,
+        Exec::builder("git")
+            .args(["merge", "--quiet", "--no-ff", "--no-commit", "master"])
+            .ok_exit_codes(&expect_codes)
             // If rerere is enabled, it prints to stderr.
-            Some(&[RERERE_RE.clone()]),
-            Some(&self.git_root),
-        )?;
+            .ignore_stderr([RERERE_RE.clone()])
+            .in_dir(&self.git_root)
+            .build()
+            .run()?;
         Ok(())
     }
 
@@ -224,14 +219,29 @@ generated.*
     }
 
     fn run_git(&self, args: &[&str]) -> Result<()> {
-        exec::run(
-            "git",
-            args,
-            &HashMap::new(),
-            &[0],
-            None,
-            Some(&self.git_root),
-        )?;
+        Exec::builder("git")
+            .args(args)
+            .in_dir(&self.git_root)
+            .build()
+            .run()?;
+        Ok(())
+    }
+
+    // Adds a new git repo, with the same files as this one, as a submodule
+    // at the given path, and commits that.
+    pub fn add_submodule(&self, path: &str) -> Result<()> {
+        let source = TestHelper::new()?.with_git_repo()?;
+        self.run_git(&[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            "--quiet",
+            &source.git_root.to_string_lossy(),
+            path,
+        ])?;
+        self.run_git(&["-C", path, "config", "user.email", "precious@example.com"])?;
+        self.run_git(&["commit", "-m", "add submodule"])?;
         Ok(())
     }
 
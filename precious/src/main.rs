@@ -1,19 +1,19 @@
 #![recursion_limit = "1024"]
 
 use log::error;
-use precious_core::precious;
+use precious_core::precious::{self, ExitCode};
 
 fn main() {
     let app = precious::app();
     if let Err(e) = app.init_logger() {
         eprintln!("Error creating logger: {e}");
-        std::process::exit(42);
+        std::process::exit(i32::from(u8::from(ExitCode::InvocationError)));
     }
     let status = match app.run() {
         Ok(s) => s,
         Err(e) => {
             error!("{e}");
-            42
+            ExitCode::for_error(&e).into()
         }
     };
     std::process::exit(i32::from(status));